@@ -0,0 +1,224 @@
+//! Async access to an Erigon database.
+//!
+//! mdbx calls block the calling thread, so every read is executed on tokio's
+//! blocking thread pool via [`tokio::task::spawn_blocking`] against a shared
+//! [`ErigonDb`].
+use crate::{
+    erigon::{models::*, ChainEvent, Erigon, ErigonDb},
+    kv::traits::Mode,
+    Result,
+};
+use ethereum_types::{Address, H256, U256};
+use mdbx::RO;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+
+/// The number of items buffered by streams returned from [`AsyncErigon`].
+const STREAM_BUFFER: usize = 1024;
+
+/// An async wrapper around [`ErigonDb`].
+#[derive(Debug)]
+pub struct AsyncErigon<M> {
+    db: ErigonDb<M>,
+}
+
+impl<M> Clone for AsyncErigon<M> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+        }
+    }
+}
+
+impl<M> From<ErigonDb<M>> for AsyncErigon<M> {
+    fn from(db: ErigonDb<M>) -> Self {
+        Self { db }
+    }
+}
+
+impl<M> AsyncErigon<M>
+where
+    M: Mode + Send + Sync + 'static,
+{
+    pub fn new(db: ErigonDb<M>) -> Self {
+        Self { db }
+    }
+
+    pub fn db(&self) -> &ErigonDb<M> {
+        &self.db
+    }
+
+    /// Runs `f` against a read-only transaction on the blocking thread pool.
+    pub async fn view<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Erigon<'_, RO>) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || db.view(f)).await?
+    }
+
+    /// Runs `f` against a read-only transaction on the blocking thread pool,
+    /// streaming each item passed to the provided sender. The transaction is
+    /// held open until `f` returns or the stream is dropped.
+    pub fn stream<T, F>(&self, f: F) -> impl Stream<Item = Result<T>>
+    where
+        F: FnOnce(&Erigon<'_, RO>, &mpsc::Sender<Result<T>>) -> Result<()> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = db.view(|erigon| f(erigon, &tx)) {
+                // the receiver may already be gone, in which case there is
+                // no one left to report the error to
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+
+    pub async fn read_head_header_hash(&self) -> Result<Option<H256>> {
+        self.view(|db| db.read_head_header_hash()).await
+    }
+
+    pub async fn read_head_block_number(&self) -> Result<Option<BlockNumber>> {
+        self.view(|db| db.read_head_block_number()).await
+    }
+
+    pub async fn read_header_number(&self, hash: H256) -> Result<Option<BlockNumber>> {
+        self.view(move |db| db.read_header_number(hash)).await
+    }
+
+    pub async fn read_canonical_hash(&self, num: BlockNumber) -> Result<Option<H256>> {
+        self.view(move |db| db.read_canonical_hash(num)).await
+    }
+
+    pub async fn read_header(&self, key: HeaderKey) -> Result<Option<BlockHeader>> {
+        self.view(move |db| db.read_header(key)).await
+    }
+
+    pub async fn read_body_for_storage(&self, key: HeaderKey) -> Result<Option<BodyForStorage>> {
+        self.view(move |db| db.read_body_for_storage(key)).await
+    }
+
+    pub async fn read_transactions(&self, key: HeaderKey) -> Result<Option<Vec<Transaction>>> {
+        self.view(move |db| db.read_transactions(key)).await
+    }
+
+    pub async fn read_senders(&self, key: HeaderKey) -> Result<Option<Vec<Address>>> {
+        self.view(move |db| db.read_senders(key)).await
+    }
+
+    pub async fn read_account(&self, adr: Address) -> Result<Option<Account>> {
+        self.view(move |db| db.read_account(adr)).await
+    }
+
+    pub async fn read_account_hist(
+        &self,
+        adr: Address,
+        block: BlockNumber,
+    ) -> Result<Option<Account>> {
+        self.view(move |db| db.read_account_hist(adr, block)).await
+    }
+
+    pub async fn read_storage(
+        &self,
+        adr: Address,
+        inc: Incarnation,
+        slot: H256,
+    ) -> Result<Option<U256>> {
+        self.view(move |db| db.read_storage(adr, inc, slot)).await
+    }
+
+    pub async fn read_storage_hist(
+        &self,
+        adr: Address,
+        inc: Incarnation,
+        slot: H256,
+        block: BlockNumber,
+    ) -> Result<Option<U256>> {
+        self.view(move |db| db.read_storage_hist(adr, inc, slot, block))
+            .await
+    }
+
+    pub async fn read_code(&self, codehash: H256) -> Result<Option<Bytecode>> {
+        self.view(move |db| db.read_code(codehash)).await
+    }
+
+    /// Collects all of the storage (key, value) pairs for the given address
+    /// and incarnation.
+    pub async fn walk_storage(
+        &self,
+        adr: Address,
+        inc: Incarnation,
+        start_slot: Option<H256>,
+    ) -> Result<Vec<(H256, U256)>> {
+        self.view(move |db| db.walk_storage(adr, inc, start_slot)?.collect())
+            .await
+    }
+
+    /// Streams all of the storage (key, value) pairs for the given address
+    /// and incarnation.
+    pub fn stream_storage(
+        &self,
+        adr: Address,
+        inc: Incarnation,
+        start_slot: Option<H256>,
+    ) -> impl Stream<Item = Result<(H256, U256)>> {
+        self.stream(move |db, tx| {
+            for read in db.walk_storage(adr, inc, start_slot)? {
+                if tx.blocking_send(read).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Streams canonical transactions beginning at `start_key`.
+    pub fn stream_txs_canonical(
+        &self,
+        start_key: Option<TxIndex>,
+    ) -> impl Stream<Item = Result<(TxIndex, Transaction)>> {
+        self.stream(move |db, tx| {
+            for read in db.walk_txs_canonical(start_key)? {
+                if tx.blocking_send(read).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Streams the changes to the canonical chain, like
+    /// [`ErigonDb::follow`]. The db is polled on the blocking thread pool,
+    /// which stops within a poll interval of the stream being dropped.
+    pub fn follow(&self, poll_interval: Duration) -> impl Stream<Item = Result<ChainEvent>> {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut follow = match db.follow(poll_interval) {
+                Ok(follow) => follow,
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(e));
+                    return;
+                }
+            };
+            // checked before every poll, not only when there is an event to
+            // send, so an idle chain doesn't keep the thread alive
+            while !tx.is_closed() {
+                match follow.try_next() {
+                    Some(event) => {
+                        if tx.blocking_send(event).is_err() {
+                            break;
+                        }
+                    }
+                    None => std::thread::sleep(poll_interval),
+                }
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+}
@@ -0,0 +1,125 @@
+use clap::{Parser, Subcommand};
+use erigon_db::{env_open_with_max_dbs, models::HeaderKey, Erigon, NUM_TABLES};
+use ethereum_types::{Address, H256, U256};
+use eyre::{eyre, Result};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+/// Ad-hoc queries against an Erigon database. Results are printed as json.
+#[derive(Parser)]
+#[clap(name = "erigon-db")]
+struct Cli {
+    /// Path to Erigon's chaindata directory
+    #[clap(long, env = "ERIGON_CHAINDATA")]
+    chaindata: PathBuf,
+    /// The maximum number of tables the database can be opened with
+    #[clap(long, default_value_t = NUM_TABLES)]
+    max_dbs: usize,
+    #[clap(subcommand)]
+    cmd: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the canonical header at a block number
+    Header { num: u64 },
+    /// Print an account, optionally as of a past block
+    Account {
+        address: Address,
+        #[clap(long)]
+        block: Option<u64>,
+    },
+    /// Print the value of a storage slot, optionally as of a past block
+    Storage {
+        address: Address,
+        #[clap(parse(try_from_str = parse_slot))]
+        slot: H256,
+        #[clap(long)]
+        block: Option<u64>,
+    },
+    /// Print a canonical transaction by its hash
+    Tx { hash: H256 },
+    /// Print the size of every table in the database
+    Tables,
+    /// Print raw (key, value) pairs from a table as hex
+    Dump {
+        table: String,
+        #[clap(long, default_value = "10")]
+        limit: usize,
+        #[clap(long, default_value = "0")]
+        offset: usize,
+    },
+}
+
+// Accepts slots as hex quantities, e.g. 0x1, rather than requiring 32 bytes.
+fn parse_slot(s: &str) -> Result<H256, String> {
+    let slot = U256::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|e| e.to_string())?;
+    let mut buf = [0; 32];
+    slot.to_big_endian(&mut buf);
+    Ok(H256(buf))
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let env = env_open_with_max_dbs(&cli.chaindata, cli.max_dbs)?;
+    let db = Erigon::begin(&env)?;
+    db.check_db_version()?;
+
+    let out: Value = match cli.cmd {
+        Command::Header { num } => {
+            let hash = db
+                .read_canonical_hash(num)?
+                .ok_or_else(|| eyre!("No canonical block {}", num))?;
+            json!({ "hash": hash, "header": db.read_header(HeaderKey(num.into(), hash))? })
+        }
+        Command::Account { address, block } => match block {
+            Some(block) => json!(db.account_at(address, block)?),
+            None => json!(db.read_account(address)?),
+        },
+        Command::Storage {
+            address,
+            slot,
+            block,
+        } => {
+            let value = match block {
+                Some(block) => {
+                    let inc = db
+                        .account_at(address, block)?
+                        .unwrap_or_default()
+                        .incarnation;
+                    db.storage_at(address, inc, slot, block)?
+                }
+                None => {
+                    let inc = db.read_account(address)?.unwrap_or_default().incarnation;
+                    db.read_storage(address, inc, slot)?.unwrap_or_default()
+                }
+            };
+            json!(value)
+        }
+        Command::Tx { hash } => match db.read_transaction_by_hash(hash)? {
+            Some((key, index, tx)) => json!({
+                "blockNumber": key.0,
+                "blockHash": key.1,
+                "index": index,
+                "transaction": tx,
+            }),
+            None => Value::Null,
+        },
+        Command::Tables => json!(db.table_sizes()?),
+        Command::Dump {
+            table,
+            limit,
+            offset,
+        } => db
+            .dump_table_by_name(&table, limit, offset)?
+            .into_iter()
+            .map(|(k, v)| json!([to_hex(&k), to_hex(&v)]))
+            .collect(),
+    };
+    println!("{}", serde_json::to_string_pretty(&out)?);
+    Ok(())
+}
+
+fn to_hex(b: &[u8]) -> String {
+    format!("0x{}", hex::encode(b))
+}
@@ -0,0 +1,200 @@
+//! A timeline of the blocks in which an account was active, for following an
+//! address's funds without an external indexer.
+use crate::{
+    erigon::{
+        models::{transaction::TxAction, *},
+        tables::*,
+        Erigon,
+    },
+    error::Error,
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+use ethereum_types::{Address, H256, U256};
+use roaring::RoaringTreemap;
+use serde::Serialize;
+use std::ops::Range;
+
+/// A block in which an account changed, with the account's state around the
+/// block and the block's txs that involve the account.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountActivity {
+    pub block: BlockNumber,
+    /// The account before the block, `None` if it didn't exist.
+    pub before: Option<Account>,
+    /// The account after the block, `None` if it didn't exist.
+    pub after: Option<Account>,
+    /// The txs in the block sent by or to the account, with their index in
+    /// the block. Empty if the account only changed through internal calls,
+    /// rewards or withdrawals.
+    pub txs: Vec<(u32, Transaction)>,
+}
+
+impl AccountActivity {
+    pub fn balance_before(&self) -> U256 {
+        self.before.map(|acct| acct.balance).unwrap_or_default()
+    }
+
+    pub fn balance_after(&self) -> U256 {
+        self.after.map(|acct| acct.balance).unwrap_or_default()
+    }
+}
+
+/// Why an address appears in a block, see [`Erigon::appearance_reasons`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum AppearanceReason {
+    /// The account's balance, nonce or code changed.
+    AccountChange,
+    /// A slot of the account's storage changed.
+    StorageChange,
+    /// The address made or received a call, including internal calls. Only
+    /// found with the `otterscan` feature.
+    Call,
+    /// The address logged an event.
+    Log,
+}
+
+/// A block in which an address appears, with every reason it does.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Appearance {
+    pub block: BlockNumber,
+    pub reasons: Vec<AppearanceReason>,
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Returns every block in which `adr` appears, according to Erigon's
+    /// indexes: the AccountHistory, StorageHistory and LogAddressIndex
+    /// tables, and, with the `otterscan` feature, the call indexes. Erigon
+    /// only fills the indexes up to the progress of their stages, and not at
+    /// all for pruned blocks.
+    pub fn appearances(&self, adr: Address) -> Result<RoaringTreemap> {
+        Ok(self
+            .appearance_bitmaps(adr)?
+            .into_iter()
+            .fold(RoaringTreemap::new(), |acc, (_, map)| acc | map))
+    }
+
+    /// Like [`Erigon::appearances`], but returns each block in ascending
+    /// order with the reasons `adr` appears in it.
+    pub fn appearance_reasons(&self, adr: Address) -> Result<impl Iterator<Item = Appearance>> {
+        let maps = self.appearance_bitmaps(adr)?;
+        let all = maps
+            .iter()
+            .fold(RoaringTreemap::new(), |acc, (_, map)| acc | map);
+        Ok(all.into_iter().map(move |n| Appearance {
+            block: BlockNumber(n),
+            reasons: maps
+                .iter()
+                .filter(|(_, map)| map.contains(n))
+                .map(|(reason, _)| *reason)
+                .collect(),
+        }))
+    }
+
+    // Reads the bitmap of blocks for each reason `adr` can appear.
+    fn appearance_bitmaps(&self, adr: Address) -> Result<Vec<(AppearanceReason, RoaringTreemap)>> {
+        #[allow(unused_mut)]
+        let mut maps = vec![
+            (
+                AppearanceReason::AccountChange,
+                self.read_address_bitmap::<AccountHistory>(adr)?,
+            ),
+            (
+                AppearanceReason::StorageChange,
+                self.read_storage_blocks(adr)?,
+            ),
+            (AppearanceReason::Log, self.read_log_blocks(adr)?),
+        ];
+        #[cfg(feature = "otterscan")]
+        maps.push((
+            AppearanceReason::Call,
+            self.read_call_from_blocks(adr)? | self.read_call_to_blocks(adr)?,
+        ));
+        Ok(maps)
+    }
+
+    /// Returns the blocks in which any storage slot of `adr` changed, by
+    /// merging the StorageHistory bitmaps of all of its slots.
+    pub fn read_storage_blocks(&self, adr: Address) -> Result<RoaringTreemap> {
+        let mut map = RoaringTreemap::new();
+        for read in
+            self.cursor::<StorageHistory>()?
+                .walk(StorageHistKey(adr, H256::zero(), 0.into()))?
+        {
+            let (StorageHistKey(k, _, _), shard) = read?;
+            if k != adr {
+                break;
+            }
+            map |= shard;
+        }
+        Ok(map)
+    }
+
+    /// Returns the blocks in which `adr` logged an event, from the
+    /// LogAddressIndex table.
+    pub fn read_log_blocks(&self, adr: Address) -> Result<RoaringTreemap> {
+        let mut map = RoaringTreemap::new();
+        for read in self
+            .cursor::<LogAddressIndex>()?
+            .walk(LogAddressKey(adr, 0))?
+        {
+            let (LogAddressKey(k, _), shard) = read?;
+            if k != adr {
+                break;
+            }
+            map.extend(shard.into_iter().map(u64::from));
+        }
+        Ok(map)
+    }
+
+    /// Returns the blocks in `blocks` in which the balance, nonce or code of
+    /// `adr` changed, according to the AccountHistory table, in ascending
+    /// order. Each is joined with the txs in the block that `adr` sent or
+    /// was the direct recipient of. With the `otterscan` feature, blocks in
+    /// which `adr` made or received an internal call are included too, even
+    /// if the account didn't change.
+    pub fn account_activity(
+        &self,
+        adr: Address,
+        blocks: Range<u64>,
+    ) -> Result<Vec<AccountActivity>> {
+        #[allow(unused_mut)]
+        let mut map = self.read_address_bitmap::<AccountHistory>(adr)?;
+        #[cfg(feature = "otterscan")]
+        {
+            map |= self.read_call_from_blocks(adr)? | self.read_call_to_blocks(adr)?;
+        }
+        map.iter()
+            .skip_while(|n| *n < blocks.start)
+            .take_while(|n| *n < blocks.end)
+            .map(|n| {
+                let block = BlockNumber(n);
+                Ok(AccountActivity {
+                    block,
+                    before: self.account_at(adr, block)?,
+                    after: self.account_at(adr, n + 1)?,
+                    txs: self.txs_involving(adr, block)?,
+                })
+            })
+            .collect()
+    }
+
+    // Returns the txs of canonical block `block` sent by or to `adr`.
+    fn txs_involving(&self, adr: Address, block: BlockNumber) -> Result<Vec<(u32, Transaction)>> {
+        let key = match self.read_canonical_hash(block)? {
+            Some(hash) => HeaderKey(block, hash),
+            None => return Ok(vec![]),
+        };
+        let txs = self.read_transactions(key)?.unwrap_or_default();
+        let senders = self
+            .read_senders(key)?
+            .ok_or(Error::MissingValue { what: "senders" })?;
+        Ok(txs
+            .into_iter()
+            .zip(senders)
+            .enumerate()
+            .filter(|(_, (tx, sender))| *sender == adr || tx.to() == TxAction::Call(adr))
+            .map(|(idx, (tx, _))| (idx as u32, tx))
+            .collect())
+    }
+}
@@ -0,0 +1,102 @@
+//! ERC-20 transfers, decoded from the Transfer logs in the TransactionLog
+//! table.
+//!
+//! ```ignore
+//! for transfer in tx.erc20_transfers(15_000_000..15_001_000)? {
+//!     let Transfer { token, from, to, amount, .. } = transfer?;
+//! }
+//! ```
+use crate::{
+    erigon::{models::*, tables::*, Erigon},
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+use ethereum_types::{Address, H256, U256};
+use hex_literal::hex;
+use std::ops::Range;
+
+/// The topic of the Transfer event, keccak256("Transfer(address,address,uint256)").
+pub const TRANSFER_TOPIC: H256 = H256(hex!(
+    "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+));
+
+/// An ERC-20 transfer, and the tx that logged it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    pub block: BlockNumber,
+    /// The index of the tx in its block.
+    pub tx_index: u32,
+    /// The contract that logged the transfer.
+    pub token: Address,
+    pub from: Address,
+    pub to: Address,
+    pub amount: U256,
+}
+
+impl Transfer {
+    /// Decodes `log` as an ERC-20 Transfer, or returns `None` if it isn't
+    /// one. ERC-721 transfers share the topic but index the token id as a
+    /// fourth topic, so they aren't matched.
+    pub fn from_log(block: BlockNumber, tx_index: u32, log: &CborLog) -> Option<Self> {
+        match &log.topics[..] {
+            [topic, from, to] if *topic == TRANSFER_TOPIC && log.data.len() == 32 => Some(Self {
+                block,
+                tx_index,
+                token: log.address,
+                from: Address::from(*from),
+                to: Address::from(*to),
+                amount: U256::from_big_endian(&log.data),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Returns an iterator over the ERC-20 transfers logged in the blocks in
+    /// `blocks`, in the order they were logged.
+    pub fn erc20_transfers(
+        &self,
+        blocks: Range<u64>,
+    ) -> Result<impl Iterator<Item = Result<Transfer>> + '_> {
+        let range = LogsKey(blocks.start.into(), 0)..LogsKey(blocks.end.into(), 0);
+        Ok(self
+            .cursor::<TransactionLog>()?
+            .walk_range(range)?
+            .flat_map(|read| match read {
+                Ok((LogsKey(block, idx), CborLogs(logs))) => logs
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|log| Transfer::from_log(block, idx, &log))
+                    .map(Ok)
+                    .collect::<Vec<_>>(),
+                Err(e) => vec![Err(e)],
+            }))
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<M: Mode> crate::erigon::ErigonDb<M> {
+    /// Collects the ERC-20 transfers logged in the blocks in `blocks` on
+    /// rayon's thread pool, splitting the range into chunks of `chunk_size`
+    /// blocks that are each read in their own read-only transaction. The
+    /// transfers are returned in the order they were logged.
+    pub fn par_erc20_transfers(
+        &self,
+        blocks: Range<u64>,
+        chunk_size: u64,
+    ) -> Result<Vec<Transfer>> {
+        use rayon::prelude::*;
+
+        let chunk_size = chunk_size.max(1);
+        let chunks = (blocks.start..blocks.end)
+            .step_by(chunk_size as usize)
+            .map(|start| start..blocks.end.min(start.saturating_add(chunk_size)))
+            .collect::<Vec<_>>();
+        let chunks = chunks
+            .into_par_iter()
+            .map(|chunk| self.view(|tx| tx.erc20_transfers(chunk)?.collect()))
+            .collect::<Result<Vec<Vec<_>>>>()?;
+        Ok(chunks.into_iter().flatten().collect())
+    }
+}
@@ -0,0 +1,130 @@
+//! Gas usage and base fee statistics, read from the canonical headers.
+//!
+//! ```ignore
+//! let stats = tx.gas_stats(17_000_000..17_100_000)?;
+//! println!("median base fee: {:?}", stats.base_fee.map(|fee| fee.p50));
+//! stats.write_csv(std::fs::File::create("gas.csv")?)?;
+//! ```
+use crate::{
+    erigon::{models::*, tables::*, Erigon},
+    error::Error,
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+use serde::Serialize;
+use std::{io::Write, ops::Range};
+
+/// The gas figures of one block's header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct BlockGas {
+    pub block: BlockNumber,
+    pub gas_used: u64,
+    pub gas_limit: u64,
+    /// The base fee in wei, `None` before London. Base fees above `u64::MAX`
+    /// are saturated.
+    pub base_fee: Option<u64>,
+}
+
+/// The distribution of a series of values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Distribution {
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    pub p10: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+impl Distribution {
+    // Returns `None` for an empty series.
+    fn new(mut values: Vec<u64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable();
+        let at = |q: f64| values[((values.len() - 1) as f64 * q).round() as usize];
+        Some(Self {
+            min: values[0],
+            max: values[values.len() - 1],
+            mean: values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64,
+            p10: at(0.1),
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+        })
+    }
+}
+
+/// The result of [`Erigon::gas_stats`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GasStats {
+    /// The gas figures of each block, in order.
+    pub blocks: Vec<BlockGas>,
+    pub gas_used: Option<Distribution>,
+    pub gas_limit: Option<Distribution>,
+    /// The distribution of the base fee over the blocks that have one.
+    pub base_fee: Option<Distribution>,
+    /// The total gas used over the total gas limit of the blocks.
+    pub utilization: f64,
+}
+
+impl GasStats {
+    pub const CSV_HEADER: &'static str = "block,gas_used,gas_limit,base_fee";
+
+    /// Writes the per-block series as CSV with a header row. Blocks without
+    /// a base fee leave its column empty.
+    pub fn write_csv<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(w, "{}", Self::CSV_HEADER)?;
+        for b in &self.blocks {
+            let base_fee = b.base_fee.map(|fee| fee.to_string()).unwrap_or_default();
+            writeln!(
+                w,
+                "{},{},{},{}",
+                *b.block, b.gas_used, b.gas_limit, base_fee
+            )?;
+        }
+        w.flush()
+    }
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Reads the gas used, gas limit and base fee of each canonical block in
+    /// `blocks` in a single pass over the headers, and summarizes them.
+    pub fn gas_stats(&self, blocks: Range<u64>) -> Result<GasStats> {
+        let mut series = Vec::new();
+        let range = BlockNumber(blocks.start)..BlockNumber(blocks.end);
+        for read in self.cursor::<CanonicalHeader>()?.walk_range(range)? {
+            let (num, hash) = read?;
+            let header = self
+                .read_header(HeaderKey(num, hash))?
+                .ok_or(Error::MissingValue {
+                    what: "canonical header",
+                })?;
+            series.push(BlockGas {
+                block: num,
+                gas_used: header.gas_used,
+                gas_limit: header.gas_limit,
+                base_fee: header
+                    .base_fee
+                    .map(|fee| u64::try_from(fee).unwrap_or(u64::MAX)),
+            });
+        }
+
+        let (used, limit) = series.iter().fold((0u128, 0u128), |(used, limit), b| {
+            (used + b.gas_used as u128, limit + b.gas_limit as u128)
+        });
+        Ok(GasStats {
+            gas_used: Distribution::new(series.iter().map(|b| b.gas_used).collect()),
+            gas_limit: Distribution::new(series.iter().map(|b| b.gas_limit).collect()),
+            base_fee: Distribution::new(series.iter().filter_map(|b| b.base_fee).collect()),
+            utilization: if limit == 0 {
+                0.0
+            } else {
+                used as f64 / limit as f64
+            },
+            blocks: series,
+        })
+    }
+}
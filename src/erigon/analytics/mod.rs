@@ -0,0 +1,6 @@
+//! Scans that derive higher-level records from the raw tables, e.g. token
+//! transfers from logs, gas statistics from headers, or the largest accounts
+//! in the state.
+pub mod erc20;
+pub mod gas;
+pub mod top;
@@ -0,0 +1,121 @@
+//! The largest accounts in the current state, by balance or by number of
+//! storage slots, found in one pass over the PlainState table.
+use crate::{
+    erigon::{
+        export::PROGRESS_INTERVAL,
+        models::Account,
+        progress::{Progress, Ticker},
+        tables::PlainState,
+        utils::consts::ADDRESS_LENGTH,
+        Erigon,
+    },
+    kv::traits::{KvCursor, KvTx, Mode, TableDecode},
+    Result,
+};
+use ethereum_types::{Address, U256};
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+// Keeps the `n` largest (value, address) pairs pushed to it.
+struct TopN<V> {
+    n: usize,
+    heap: BinaryHeap<Reverse<(V, Address)>>,
+}
+
+impl<V: Ord> TopN<V> {
+    fn new(n: usize) -> Self {
+        Self {
+            n,
+            heap: BinaryHeap::with_capacity(n + 1),
+        }
+    }
+
+    fn push(&mut self, value: V, adr: Address) {
+        if self.n == 0 {
+            return;
+        }
+        if self.heap.len() < self.n {
+            self.heap.push(Reverse((value, adr)));
+        } else if matches!(self.heap.peek(), Some(Reverse((min, _))) if value > *min) {
+            self.heap.pop();
+            self.heap.push(Reverse((value, adr)));
+        }
+    }
+
+    // Returns the pairs, largest value first.
+    fn into_sorted(self) -> Vec<(Address, V)> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((value, adr))| (adr, value))
+            .collect()
+    }
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Returns the `n` accounts with the largest balances, largest first.
+    /// Only the accounts of the PlainState table are read, skipping their
+    /// storage. `progress` ticks every [`PROGRESS_INTERVAL`] accounts.
+    pub fn top_balances<P: Progress>(
+        &self,
+        n: usize,
+        mut progress: P,
+    ) -> Result<Vec<(Address, U256)>> {
+        let mut ticker = Ticker::new(&mut progress, "top_balances", None);
+        let mut top = TopN::new(n);
+        let mut cur = self.cursor::<PlainState>()?;
+        let mut count = 0;
+        let mut next = cur.inner.first()?;
+        while let Some((k, v)) = next {
+            if k.len() == ADDRESS_LENGTH {
+                let acct: Account = TableDecode::decode(&v)?;
+                top.push(acct.balance, TableDecode::decode(&k)?);
+                count += 1;
+                if count % PROGRESS_INTERVAL == 0 {
+                    ticker.tick(count, Some(&k[..]));
+                }
+            }
+            // storage is dupsorted under address||incarnation
+            next = cur.inner.next_nodup()?;
+        }
+        ticker.finish();
+        Ok(top.into_sorted())
+    }
+
+    /// Returns the `n` contracts with the most storage slots in the current
+    /// state, most first. Every slot of the PlainState table is visited.
+    /// `progress` ticks every [`PROGRESS_INTERVAL`] slots.
+    pub fn top_storage_users<P: Progress>(
+        &self,
+        n: usize,
+        mut progress: P,
+    ) -> Result<Vec<(Address, u64)>> {
+        let mut ticker = Ticker::new(&mut progress, "top_storage_users", None);
+        let mut top = TopN::new(n);
+        let mut cur = self.cursor::<PlainState>()?;
+        let (mut count, mut slots) = (0, 0);
+        let mut current = None;
+        let mut next = cur.inner.first()?;
+        while let Some((k, _)) = next {
+            if k.len() > ADDRESS_LENGTH {
+                let adr: Address = TableDecode::decode(&k[..ADDRESS_LENGTH])?;
+                if current != Some(adr) {
+                    if let Some(prev) = current.replace(adr) {
+                        top.push(slots, prev);
+                    }
+                    slots = 0;
+                }
+                slots += 1;
+                count += 1;
+                if count % PROGRESS_INTERVAL == 0 {
+                    ticker.tick(count, Some(&k[..]));
+                }
+            }
+            next = cur.inner.next()?;
+        }
+        if let Some(adr) = current {
+            top.push(slots, adr);
+        }
+        ticker.finish();
+        Ok(top.into_sorted())
+    }
+}
@@ -0,0 +1,323 @@
+//! Consistency checks of the data written by Erigon's stages, for detecting
+//! corruption without relying on Erigon's own integrity checks.
+use crate::{
+    erigon::{
+        models::*,
+        progress::{Progress, Ticker},
+        tables::*,
+        Erigon,
+    },
+    error::ensure,
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+use ethereum_types::{Address, H256};
+use eyre::eyre;
+use serde::Serialize;
+use std::ops::Range;
+
+/// The checks run by [`Erigon::audit`]. The default runs every check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AuditFlags {
+    /// Each canonical hash has a header that hashes to it, and the
+    /// HeaderNumber table maps the hash back to the block.
+    pub canonical_chain: bool,
+    /// Each header follows its parent, see [`validate_parent`].
+    pub headers: bool,
+    /// Each body matches its header, see [`Erigon::verify_body`].
+    pub bodies: bool,
+    /// The TxSender table matches the signers of the txs. Needs the
+    /// `secp256k1` feature, and is skipped without it.
+    pub senders: bool,
+    /// Every account and storage changeset entry is in the AccountHistory
+    /// and StorageHistory bitmaps.
+    pub history: bool,
+    /// Every tx is in the BlockTransactionLookup table, under its block.
+    pub tx_lookup: bool,
+}
+
+impl AuditFlags {
+    pub const ALL: Self = Self {
+        canonical_chain: true,
+        headers: true,
+        bodies: true,
+        senders: true,
+        history: true,
+        tx_lookup: true,
+    };
+    pub const NONE: Self = Self {
+        canonical_chain: false,
+        headers: false,
+        bodies: false,
+        senders: false,
+        history: false,
+        tx_lookup: false,
+    };
+}
+
+impl Default for AuditFlags {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// The check that found an [`AuditIssue`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum AuditCheck {
+    CanonicalChain,
+    Headers,
+    Bodies,
+    Senders,
+    History,
+    TxLookup,
+}
+
+/// A problem found by [`Erigon::audit`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AuditIssue {
+    pub block: BlockNumber,
+    pub check: AuditCheck,
+    pub message: String,
+}
+
+/// The result of [`Erigon::audit`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct AuditReport {
+    /// The number of blocks audited.
+    pub blocks: u64,
+    pub issues: Vec<AuditIssue>,
+}
+
+impl AuditReport {
+    /// Returns true if no issues were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    // Records the error of a failed check, if any.
+    fn check(&mut self, block: BlockNumber, check: AuditCheck, res: Result<()>) {
+        if let Err(e) = res {
+            self.issues.push(AuditIssue {
+                block,
+                check,
+                message: e.to_string(),
+            });
+        }
+    }
+}
+
+/// A tx whose sender in the TxSender table is not the signer of the tx.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct SenderMismatch {
+    pub block: BlockNumber,
+    /// The index of the tx in the block.
+    pub index: u32,
+    pub hash: H256,
+    /// The sender in the TxSender table, `None` if the block's entry has too
+    /// few senders.
+    pub stored: Option<Address>,
+    /// The signer recovered from the tx's signature, `None` if the signature
+    /// is invalid.
+    pub recovered: Option<Address>,
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Runs `checks` over the canonical blocks in `blocks`, collecting every
+    /// issue found rather than stopping at the first. A failed read, e.g. of
+    /// a value that doesn't decode, is reported as an issue of the check that
+    /// made it. Blocks without a canonical hash or header are reported under
+    /// [`AuditCheck::CanonicalChain`] whatever `checks` is, since no other
+    /// check can run on them.
+    ///
+    /// The tx-lookup check reports missing entries for blocks Erigon has
+    /// pruned the BlockTransactionLookup table of. `progress` ticks after
+    /// every block with the number of blocks audited.
+    pub fn audit<P: Progress>(
+        &self,
+        blocks: Range<u64>,
+        checks: AuditFlags,
+        mut progress: P,
+    ) -> Result<AuditReport> {
+        let total = blocks.end.saturating_sub(blocks.start);
+        let mut ticker = Ticker::new(&mut progress, "audit", Some(total));
+        let mut report = AuditReport::default();
+        let mut parent = match blocks.start.checked_sub(1) {
+            Some(num) if checks.headers => self.read_canonical_header(num)?,
+            _ => None,
+        };
+        for num in blocks {
+            let block = BlockNumber(num);
+            report.blocks += 1;
+            let (key, header) = match self.canonical_header(block) {
+                Ok(found) => found,
+                Err(e) => {
+                    report.check(block, AuditCheck::CanonicalChain, Err(e));
+                    parent = None;
+                    ticker.tick(report.blocks, None);
+                    continue;
+                }
+            };
+
+            if checks.canonical_chain {
+                let res = self.check_canonical(key, &header);
+                report.check(block, AuditCheck::CanonicalChain, res);
+            }
+            if checks.headers {
+                if let Some(parent) = &parent {
+                    report.check(block, AuditCheck::Headers, validate_parent(&header, parent));
+                }
+            }
+            if checks.bodies {
+                report.check(block, AuditCheck::Bodies, self.verify_body(key));
+            }
+            #[cfg(feature = "secp256k1")]
+            if checks.senders {
+                match self.verify_senders(num..num + 1, 1) {
+                    Ok(mismatches) => {
+                        for m in mismatches {
+                            let res = Err(eyre!(
+                                "Tx {} ({:?}) has sender {:?} in the TxSender table, but was signed by {:?}",
+                                m.index,
+                                m.hash,
+                                m.stored,
+                                m.recovered,
+                            )
+                            .into());
+                            report.check(block, AuditCheck::Senders, res);
+                        }
+                    }
+                    Err(e) => report.check(block, AuditCheck::Senders, Err(e)),
+                }
+            }
+            if checks.history {
+                report.check(block, AuditCheck::History, self.check_history(block));
+            }
+            if checks.tx_lookup {
+                report.check(block, AuditCheck::TxLookup, self.check_tx_lookup(key));
+            }
+            parent = Some(header);
+            ticker.tick(report.blocks, None);
+        }
+        ticker.finish();
+        Ok(report)
+    }
+
+    // Reads the key and header of canonical block `block`.
+    fn canonical_header(&self, block: BlockNumber) -> Result<(HeaderKey, BlockHeader)> {
+        let hash = self
+            .read_canonical_hash(block)?
+            .ok_or_else(|| eyre!("Block {} has no canonical hash", *block))?;
+        let key = HeaderKey(block, hash);
+        let header = self
+            .read_header(key)?
+            .ok_or_else(|| eyre!("Block {} has no header for hash {:?}", *block, hash))?;
+        Ok((key, header))
+    }
+
+    // Checks that the canonical header hashes to its key and is indexed by
+    // the HeaderNumber table.
+    fn check_canonical(&self, key: HeaderKey, header: &BlockHeader) -> Result<()> {
+        let HeaderKey(block, hash) = key;
+        ensure!(
+            header.hash() == hash && header.number == (*block).into(),
+            "Block {} has canonical hash {:?}, but its header is block {} with hash {:?}",
+            *block,
+            hash,
+            header.number,
+            header.hash(),
+        );
+        let num = self.read_header_number(hash)?;
+        ensure!(
+            num == Some(block),
+            "Block {} has canonical hash {:?}, but the HeaderNumber table has {:?} for it",
+            *block,
+            hash,
+            num,
+        );
+        Ok(())
+    }
+
+    // Checks that every account and slot in the changesets of `block` has
+    // `block` in its history bitmap.
+    fn check_history(&self, block: BlockNumber) -> Result<()> {
+        for read in self.cursor::<AccountChangeSet>()?.walk(block)? {
+            let (cs_block, AccountCSVal(adr, _)) = read?;
+            if cs_block != block {
+                break;
+            }
+            ensure!(
+                self.next_account_change(adr, block)? == Some(block),
+                "Account {:?} changed in block {}, but its AccountHistory doesn't include it",
+                adr,
+                *block,
+            );
+        }
+        let start = StorageCSKey(block, Default::default());
+        for read in self.cursor::<StorageChangeSet>()?.walk(start)? {
+            let (StorageCSKey(cs_block, StorageKey(adr, _)), StorageCSVal(slot, _)) = read?;
+            if cs_block != block {
+                break;
+            }
+            ensure!(
+                self.next_storage_change(adr, slot, block)? == Some(block),
+                "Slot {:?} of {:?} changed in block {}, but its StorageHistory doesn't include it",
+                slot,
+                adr,
+                *block,
+            );
+        }
+        Ok(())
+    }
+
+    // Checks that every tx of the block maps to the block in the
+    // BlockTransactionLookup table.
+    fn check_tx_lookup(&self, key: HeaderKey) -> Result<()> {
+        for tx in self.read_transactions(key)?.unwrap_or_default() {
+            let hash = tx.tx_hash();
+            let num = self.read_transaction_block_number(hash)?;
+            ensure!(
+                num == Some((*key.0).into()),
+                "Tx {:?} is in block {}, but the BlockTransactionLookup table has {:?} for it",
+                hash,
+                *key.0,
+                num,
+            );
+        }
+        Ok(())
+    }
+
+    /// Recovers the signers of the txs of the canonical blocks in `blocks`
+    /// and compares them with the TxSender table, returning the txs that
+    /// don't match. Only every `every`-th tx of each block is checked, so 1
+    /// checks every tx. Blocks without an entry in the TxSender table, e.g.
+    /// because the Senders stage hasn't reached them yet, are skipped.
+    #[cfg(feature = "secp256k1")]
+    pub fn verify_senders(&self, blocks: Range<u64>, every: usize) -> Result<Vec<SenderMismatch>> {
+        let mut mismatches = Vec::new();
+        for num in blocks {
+            let key = match self.read_canonical_hash(num)? {
+                Some(hash) => HeaderKey(num.into(), hash),
+                None => continue,
+            };
+            let senders = match self.read_senders(key)? {
+                Some(senders) => senders,
+                None => continue,
+            };
+            let txs = self.read_transactions(key)?.unwrap_or_default();
+            for (index, tx) in txs.iter().enumerate().step_by(every.max(1)) {
+                let stored = senders.get(index).copied();
+                let recovered = tx.recover_sender().ok();
+                if stored.is_none() || stored != recovered {
+                    mismatches.push(SenderMismatch {
+                        block: key.0,
+                        index: index as u32,
+                        hash: tx.tx_hash(),
+                        stored,
+                        recovered,
+                    });
+                }
+            }
+        }
+        Ok(mismatches)
+    }
+}
@@ -0,0 +1,90 @@
+//! Helpers for the roaring bitmaps of block numbers stored in the
+//! AccountHistory and StorageHistory tables.
+//!
+//! Erigon splits the bitmap of each account (or storage slot) into shards once
+//! it grows past a chunk size. Each shard is keyed by the largest block number
+//! it contains, except the last, which is keyed by `u64::MAX`, so a seek to
+//! `(address, block)` lands on the shard that would contain `block`.
+use crate::Result;
+use roaring::RoaringTreemap;
+use std::ops::RangeBounds;
+
+/// The key suffix of the last shard of a bitmap.
+pub const LAST_SHARD: u64 = u64::MAX;
+
+/// Returns the smallest block number in `map` that is `>= n`.
+// https://github.com/ledgerwatch/erigon/blob/f9d7cb5ca9e8a135a76ddcb6fa4ee526ea383554/ethdb/bitmapdb/dbutils.go#L313
+pub fn seek_in_bitmap(map: &RoaringTreemap, n: u64) -> Option<u64> {
+    if n == 0 {
+        return map.min();
+    }
+    // rank() returns the number of integers in the map <= n - 1, which is the
+    // index of the first integer >= n
+    map.select(map.rank(n - 1))
+}
+
+/// Merges the shards of a bitmap, e.g. as read from a cursor walk over the
+/// shards of one account, into a single bitmap.
+pub fn merge_shards<I>(shards: I) -> Result<RoaringTreemap>
+where
+    I: IntoIterator<Item = Result<RoaringTreemap>>,
+{
+    shards
+        .into_iter()
+        .try_fold(RoaringTreemap::new(), |mut acc, shard| {
+            acc |= shard?;
+            Ok(acc)
+        })
+}
+
+/// Removes the block numbers outside of `keep` from the (key suffix, bitmap)
+/// shards of one account or slot, dropping shards that end up empty. The
+/// last remaining shard is rekeyed to [`LAST_SHARD`].
+pub fn retain_in_shards<R>(
+    shards: Vec<(u64, RoaringTreemap)>,
+    keep: R,
+) -> Vec<(u64, RoaringTreemap)>
+where
+    R: RangeBounds<u64>,
+{
+    let mut out: Vec<_> = shards
+        .into_iter()
+        .filter_map(|(n, map)| {
+            let map: RoaringTreemap = map.into_iter().filter(|b| keep.contains(b)).collect();
+            (!map.is_empty()).then_some((n, map))
+        })
+        .collect();
+    if let Some(last) = out.last_mut() {
+        last.0 = LAST_SHARD;
+    }
+    out
+}
+
+/// The size in bytes that Erigon lets a shard grow to before starting a new
+/// one.
+// https://github.com/ledgerwatch/erigon/blob/f9d7cb5ca9e8a135a76ddcb6fa4ee526ea383554/ethdb/bitmapdb/dbutils.go#L24
+pub const CHUNK_LIMIT: usize = 1950;
+
+/// Splits `map` into (key suffix, bitmap) shards the way Erigon does: each
+/// shard holds as many of the remaining blocks as fit in `limit` bytes when
+/// serialized, and at least one. Every shard is keyed by its largest block,
+/// except the last, which is keyed by [`LAST_SHARD`].
+pub fn split_into_shards(map: &RoaringTreemap, limit: usize) -> Vec<(u64, RoaringTreemap)> {
+    let mut shards = vec![];
+    let mut shard = RoaringTreemap::new();
+    // blocks come in ascending order, so a shard only grows as it's filled and
+    // can be cut as soon as the next block would push it over the limit
+    for block in map.iter() {
+        shard.insert(block);
+        if shard.len() > 1 && shard.serialized_size() > limit {
+            shard.remove(block);
+            let last = shard.max().expect("shard holds at least one block");
+            shards.push((last, std::mem::replace(&mut shard, RoaringTreemap::new())));
+            shard.insert(block);
+        }
+    }
+    if !shard.is_empty() {
+        shards.push((LAST_SHARD, shard));
+    }
+    shards
+}
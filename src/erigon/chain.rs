@@ -0,0 +1,89 @@
+use ethereum_types::H256;
+use hex_literal::hex;
+
+use crate::{
+    erigon::{tables::*, Erigon},
+    error::Error,
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+
+const MAINNET_GENESIS: H256 = H256(hex!(
+    "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"
+));
+const SEPOLIA_GENESIS: H256 = H256(hex!(
+    "25a5cc106eea7138acab33231d7160d69cb777ee0c2c553fcddf5138993e6dd9"
+));
+const HOLESKY_GENESIS: H256 = H256(hex!(
+    "b5f7f912443c940f21fd611f12828d75b534364ed9e95ca4e307729a4661bde4"
+));
+const GNOSIS_GENESIS: H256 = H256(hex!(
+    "4f1dd23188aab3a76b463e4af801b52b1248ef073c648cbdc4c9333d3da79756"
+));
+const POLYGON_GENESIS: H256 = H256(hex!(
+    "a9c28ce2141b56c474f1dc504bee9b01eb1bd7d1a507580d5519d4437a97de1b"
+));
+
+/// A chain, identified by the hash of its genesis block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Chain {
+    Mainnet,
+    Sepolia,
+    Holesky,
+    Gnosis,
+    /// Polygon PoS mainnet, whose blocks are produced by bor.
+    Polygon,
+    /// A chain not known to this crate, with the given genesis hash.
+    Unknown(H256),
+}
+
+impl Chain {
+    pub fn from_genesis_hash(hash: H256) -> Self {
+        [
+            (MAINNET_GENESIS, Self::Mainnet),
+            (SEPOLIA_GENESIS, Self::Sepolia),
+            (HOLESKY_GENESIS, Self::Holesky),
+            (GNOSIS_GENESIS, Self::Gnosis),
+            (POLYGON_GENESIS, Self::Polygon),
+        ]
+        .into_iter()
+        .find_map(|(genesis, chain)| (genesis == hash).then_some(chain))
+        .unwrap_or(Self::Unknown(hash))
+    }
+
+    /// Returns the EIP-155 chain id, if the chain is known.
+    pub fn chain_id(&self) -> Option<u64> {
+        match self {
+            Self::Mainnet => Some(1),
+            Self::Sepolia => Some(11155111),
+            Self::Holesky => Some(17000),
+            Self::Gnosis => Some(100),
+            Self::Polygon => Some(137),
+            Self::Unknown(_) => None,
+        }
+    }
+
+    /// Whether the chain's blocks are produced by bor, and so its db has the
+    /// Polygon tables.
+    pub fn is_bor(&self) -> bool {
+        matches!(self, Self::Polygon)
+    }
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Returns the chain the db holds, as identified by its genesis hash.
+    pub fn chain(&self) -> Result<Chain> {
+        let genesis = self.read_canonical_hash(0)?.ok_or(Error::MissingValue {
+            what: "genesis hash",
+        })?;
+        Ok(Chain::from_genesis_hash(genesis))
+    }
+
+    /// Returns the chain config Erigon stored for the chain with genesis
+    /// `genesis`, as JSON, e.g. to look up fork activation blocks.
+    pub fn read_chain_config(&self, genesis: H256) -> Result<Option<serde_json::Value>> {
+        self.read::<ChainConfig>(genesis)?
+            .map(|config| serde_json::from_slice(&config).map_err(From::from))
+            .transpose()
+    }
+}
@@ -0,0 +1,113 @@
+//! Comparison of the state held by two databases, e.g. to verify a copy of a
+//! datadir or to check two Erigon versions against each other.
+use crate::{
+    erigon::{models::*, tables::*, utils::consts::ADDRESS_LENGTH, Erigon},
+    kv::traits::{KvCursor, KvTx, Mode, RawPair, TableDecode},
+    Result,
+};
+use ethereum_types::{Address, H256, U256};
+use serde::Serialize;
+use std::cmp::Ordering;
+
+/// An account or storage slot whose value differs between two databases.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum StateMismatch {
+    /// `None` indicates the account does not exist in that db.
+    Account {
+        address: Address,
+        a: Option<Account>,
+        b: Option<Account>,
+    },
+    /// Slots that aren't stored are zero.
+    Storage {
+        address: Address,
+        incarnation: Incarnation,
+        slot: H256,
+        a: U256,
+        b: U256,
+    },
+}
+
+/// Walks the PlainState tables of `db_a` and `db_b` side by side, returning up
+/// to `limit` accounts and storage slots whose values differ, in key order.
+///
+/// Values are compared as stored, so e.g. an account whose code hash is only
+/// recorded in one of the dbs is reported as a mismatch.
+pub fn diff_state<KA, TA, KB, TB>(
+    db_a: &Erigon<'_, KA, TA>,
+    db_b: &Erigon<'_, KB, TB>,
+    limit: usize,
+) -> Result<Vec<StateMismatch>>
+where
+    KA: Mode,
+    TA: KvTx,
+    KB: Mode,
+    TB: KvTx,
+{
+    let mut out = vec![];
+    let mut cur_a = db_a.0.cursor_raw::<PlainState>()?;
+    let mut cur_b = db_b.0.cursor_raw::<PlainState>()?;
+    let (mut a, mut b) = (cur_a.first()?, cur_b.first()?);
+    while out.len() < limit {
+        let ord = match (&a, &b) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(a), Some(b)) => sort_key(a).cmp(&sort_key(b)),
+        };
+        match ord {
+            Ordering::Less => {
+                out.push(mismatch(a.as_ref(), None)?);
+                a = cur_a.next()?;
+            }
+            Ordering::Greater => {
+                out.push(mismatch(None, b.as_ref())?);
+                b = cur_b.next()?;
+            }
+            Ordering::Equal => {
+                if a.as_ref().map(|kv| &kv.1) != b.as_ref().map(|kv| &kv.1) {
+                    out.push(mismatch(a.as_ref(), b.as_ref())?);
+                }
+                a = cur_a.next()?;
+                b = cur_b.next()?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+// PlainState is dupsorted, and the value of each storage entry starts with its
+// slot, so (key, slot) orders entries the way the cursors walk them.
+fn sort_key<'a>((k, v): &'a RawPair<'_>) -> (&'a [u8], &'a [u8]) {
+    if k.len() == ADDRESS_LENGTH {
+        (&k[..], &[])
+    } else {
+        (&k[..], &v[..v.len().min(32)])
+    }
+}
+
+// Decodes the entries at the same position in each db, at least one of which
+// must be present.
+fn mismatch(a: Option<&RawPair<'_>>, b: Option<&RawPair<'_>>) -> Result<StateMismatch> {
+    let (k, _) = a.or(b).expect("an entry in either db");
+    if k.len() == ADDRESS_LENGTH {
+        let acct = |kv: Option<&RawPair<'_>>| kv.map(|(_, v)| Account::decode(v)).transpose();
+        return Ok(StateMismatch::Account {
+            address: Address::decode(k)?,
+            a: acct(a)?,
+            b: acct(b)?,
+        });
+    }
+    let StorageKey(address, incarnation) = StorageKey::decode(k)?;
+    let slot_val =
+        |kv: Option<&RawPair<'_>>| kv.map(|(_, v)| <(H256, U256)>::decode(v)).transpose();
+    let (a, b) = (slot_val(a)?, slot_val(b)?);
+    let slot = a.or(b).map(|(slot, _)| slot).unwrap_or_default();
+    Ok(StateMismatch::Storage {
+        address,
+        incarnation,
+        slot,
+        a: a.map(|(_, v)| v).unwrap_or_default(),
+        b: b.map(|(_, v)| v).unwrap_or_default(),
+    })
+}
@@ -0,0 +1,303 @@
+#[cfg(feature = "rayon")]
+use crate::kv::traits::{DefaultFlags, KvCursor, KvTx, Table, TableDecode};
+use crate::{
+    erigon::{env_open, Erigon},
+    error::Error,
+    kv::{traits::Mode, MdbxEnv},
+    Result,
+};
+use mdbx::{RO, RW};
+use std::{
+    path::Path,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+/// The default number of read-only transactions an [`ErigonDb`] will allow
+/// to be open at the same time.
+pub const DEFAULT_MAX_READERS: usize = 64;
+
+/// An owned, cheaply cloneable handle to an Erigon database.
+///
+/// [`Erigon::begin`] ties the lifetime of a transaction to a borrow of the
+/// environment, which is awkward to hold onto in long-lived services. `ErigonDb`
+/// instead owns the environment behind an `Arc` and hands out transactions
+/// scoped to a closure, so it can be cloned freely across threads.
+///
+/// Each read-only transaction is begun fresh, but only once it claims one of
+/// a fixed number of reader slots. When every slot is in use,
+/// [`ErigonDb::view`] blocks until one is released rather than failing with
+/// `MDBX_READERS_FULL`.
+///
+/// Transactions that fail to begin because another process, e.g. Erigon,
+/// grew the db file or left stale reader slots behind are restarted according
+/// to the db's [`RetryPolicy`].
+#[derive(Debug)]
+pub struct ErigonDb<M> {
+    env: Arc<MdbxEnv<M>>,
+    readers: Arc<ReaderLimit>,
+    retry: RetryPolicy,
+}
+
+impl<M> Clone for ErigonDb<M> {
+    fn clone(&self) -> Self {
+        Self {
+            env: self.env.clone(),
+            readers: self.readers.clone(),
+            retry: self.retry,
+        }
+    }
+}
+
+/// How an [`ErigonDb`] recovers when a transaction fails to begin with
+/// MDBX_UNABLE_EXTEND_MAPSIZE (formerly MDBX_MAP_RESIZED), because another
+/// process grew the db beyond this process's memory map, or with
+/// MDBX_BAD_RSLOT, because of a stale reader slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of times to retry beginning a transaction. 0 disables
+    /// retries.
+    pub max_retries: u32,
+    /// The time to wait before the first retry, doubled for each retry after.
+    pub backoff: Duration,
+    /// Whether to resize this process's memory map to the db's current
+    /// geometry after a map resize, rather than waiting for mdbx to remap on
+    /// its own. See [`MdbxEnv::sync_geometry`].
+    pub remap: bool,
+}
+
+impl RetryPolicy {
+    /// Fails on the first error, as mdbx does.
+    pub const NEVER: Self = Self {
+        max_retries: 0,
+        backoff: Duration::ZERO,
+        remap: false,
+    };
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff: Duration::from_millis(10),
+            remap: true,
+        }
+    }
+}
+
+// Whether `e` is a failure to begin a transaction that can be recovered from
+// by retrying.
+fn is_stale_env(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Mdbx(mdbx::Error::UnableExtendMapsize | mdbx::Error::BadRslot)
+    )
+}
+
+impl<M: Mode> ErigonDb<M> {
+    /// Opens an mdbx env at `path` with Erigon-specific configuration. Fails
+    /// if the db was written with a schema version this crate can't read; see
+    /// [`Erigon::check_db_version`].
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = Self::new(env_open(path)?);
+        db.view(|tx| tx.check_db_version())?;
+        Ok(db)
+    }
+
+    pub fn new(env: MdbxEnv<M>) -> Self {
+        Self::with_max_readers(env, DEFAULT_MAX_READERS)
+    }
+
+    /// Wraps the environment, allowing at most `max_readers` concurrent
+    /// read-only transactions.
+    pub fn with_max_readers(env: MdbxEnv<M>, max_readers: usize) -> Self {
+        Self {
+            env: Arc::new(env),
+            readers: Arc::new(ReaderLimit::new(max_readers)),
+            retry: Default::default(),
+        }
+    }
+
+    /// Returns the db with `policy` for recovering from transactions that
+    /// fail to begin.
+    pub fn with_retry(self, policy: RetryPolicy) -> Self {
+        Self {
+            retry: policy,
+            ..self
+        }
+    }
+
+    pub fn env(&self) -> &MdbxEnv<M> {
+        &self.env
+    }
+
+    /// Calls `begin` until it succeeds, recovering from failures to begin a
+    /// transaction as the db's [`RetryPolicy`] allows.
+    pub(crate) fn retry<T>(&self, mut begin: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            match begin() {
+                Err(e) if attempt < self.retry.max_retries && is_stale_env(&e) => {
+                    self.recover(&e, attempt)?;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    // Recovers from `e`, a failure to begin a transaction, before the
+    // `attempt`th retry.
+    fn recover(&self, e: &Error, attempt: u32) -> Result<()> {
+        std::thread::sleep(self.retry.backoff.saturating_mul(1 << attempt.min(16)));
+        match e {
+            Error::Mdbx(mdbx::Error::BadRslot) => self.env.reader_check().map(drop),
+            _ if self.retry.remap => self.env.sync_geometry(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs `f` against a read-only transaction. Blocks until a reader slot
+    /// is available.
+    pub fn view<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Erigon<'_, RO>) -> Result<T>,
+    {
+        let _slot = self.readers.acquire();
+        let tx = self.retry(|| self.env.begin_ro())?;
+        f(&Erigon::new(tx))
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<M: Mode> ErigonDb<M> {
+    /// Scans table `T` on rayon's thread pool, splitting its keyspace into
+    /// `shards` ranges that are each walked in their own read-only
+    /// transaction. Each range folds its entries into an accumulator created
+    /// by `init`, and the accumulators are returned in key order.
+    ///
+    /// The keyspace is split evenly over the first 8 bytes of the keys
+    /// between the table's first and last keys, so shards are balanced for
+    /// tables keyed by address or hash, and by block number.
+    pub fn par_walk<T, A, I, F>(&self, shards: usize, init: I, f: F) -> Result<Vec<A>>
+    where
+        T: for<'tx> Table<'tx> + DefaultFlags,
+        <T as Table<'static>>::Key: TableDecode,
+        A: Send,
+        I: Fn() -> A + Sync,
+        F: Fn(&mut A, <T as Table<'static>>::Key, <T as Table<'static>>::Value) -> Result<()>
+            + Sync,
+    {
+        use rayon::prelude::*;
+
+        let bounds = self.view(|tx| {
+            let mut cur = tx.0.cursor_raw::<T>()?;
+            let first = cur.first()?.map(|(k, _)| key_prefix(&k));
+            let last = cur.last()?.map(|(k, _)| key_prefix(&k));
+            Ok(first.zip(last))
+        })?;
+        let (lo, hi) = match bounds {
+            Some(bounds) => bounds,
+            None => return Ok(vec![]),
+        };
+        // the start of each range, then the end of the last
+        let shards = shards.max(1) as u128;
+        let mut starts = (1..shards)
+            .map(|i| (lo as u128 + (hi - lo) as u128 * i / shards) as u64)
+            .filter(|&start| start > lo)
+            .collect::<Vec<_>>();
+        starts.dedup();
+        let ranges = std::iter::once(None)
+            .chain(starts.iter().copied().map(Some))
+            .zip(
+                starts
+                    .iter()
+                    .copied()
+                    .map(Some)
+                    .chain(std::iter::once(None)),
+            )
+            .collect::<Vec<_>>();
+
+        ranges
+            .into_par_iter()
+            .map(|(start, end)| {
+                self.view(|tx| {
+                    let mut acc = init();
+                    let mut cur = tx.0.cursor_raw::<T>()?;
+                    let mut next = match start {
+                        Some(start) => cur.seek(&u64::to_be_bytes(start))?,
+                        None => cur.first()?,
+                    };
+                    while let Some((k, v)) = next {
+                        if matches!(end, Some(end) if k[..] >= u64::to_be_bytes(end)[..]) {
+                            break;
+                        }
+                        f(&mut acc, TableDecode::decode(&k)?, TableDecode::decode(&v)?)?;
+                        next = cur.next()?;
+                    }
+                    Ok(acc)
+                })
+            })
+            .collect()
+    }
+}
+
+// The first 8 bytes of a key as a big-endian integer, padded with zeros.
+#[cfg(feature = "rayon")]
+fn key_prefix(key: &[u8]) -> u64 {
+    let mut prefix = [0; 8];
+    let len = key.len().min(8);
+    prefix[..len].copy_from_slice(&key[..len]);
+    u64::from_be_bytes(prefix)
+}
+
+impl ErigonDb<RW> {
+    /// Runs `f` against a read-write transaction, committing the transaction
+    /// if `f` returns `Ok`. Blocks if another read-write transaction is open.
+    pub fn update<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Erigon<'_, RW>) -> Result<T>,
+    {
+        let tx = self.retry(|| Erigon::begin_rw(&self.env))?;
+        let res = f(&tx)?;
+        tx.commit()?;
+        Ok(res)
+    }
+}
+
+/// Bounds the number of read-only transactions open at once.
+#[derive(Debug)]
+struct ReaderLimit {
+    max: usize,
+    open: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl ReaderLimit {
+    fn new(max: usize) -> Self {
+        Self {
+            max: max.max(1),
+            open: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) -> ReaderSlot<'_> {
+        let mut open = self.open.lock().unwrap();
+        while *open >= self.max {
+            open = self.freed.wait(open).unwrap();
+        }
+        *open += 1;
+        ReaderSlot(self)
+    }
+}
+
+/// A claimed reader slot, released on drop.
+struct ReaderSlot<'a>(&'a ReaderLimit);
+
+impl Drop for ReaderSlot<'_> {
+    fn drop(&mut self) {
+        *self.0.open.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
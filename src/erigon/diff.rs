@@ -0,0 +1,103 @@
+use ethereum_types::{Address, H256, U256};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{
+    erigon::{models::*, tables::*, Erigon},
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+
+/// The accounts and storage slots changed over a range of blocks, with their
+/// values before and after the range.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StateDiff {
+    pub accounts: BTreeMap<Address, AccountDiff>,
+    pub storage: BTreeMap<(Address, Incarnation), BTreeMap<H256, StorageDiff>>,
+}
+
+/// The state of an account before and after a range of blocks. `None`
+/// indicates that the account did not exist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct AccountDiff {
+    pub before: Option<Account>,
+    pub after: Option<Account>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StorageDiff {
+    pub before: U256,
+    pub after: U256,
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Replays the AccountChangeSet and StorageChangeSet tables over the blocks
+    /// `[from, to)`, returning the value of every changed account and storage
+    /// slot as of block `from` and as of block `to`.
+    ///
+    /// As with [`Erigon::read_account_hist`], the state "as of" block `N` is the
+    /// state before block `N` was executed.
+    pub fn state_diff(
+        &self,
+        from: impl Into<BlockNumber>,
+        to: impl Into<BlockNumber>,
+    ) -> Result<StateDiff> {
+        let (from, to) = (from.into(), to.into());
+        let mut diff = StateDiff::default();
+
+        // The changeset at block N holds the value before block N changed it,
+        // so the first entry we see for each key is its value as of `from`.
+        for read in self.cursor::<AccountChangeSet>()?.walk(from)? {
+            let (block, AccountCSVal(adr, acct)) = read?;
+            if block >= to {
+                break;
+            }
+            if !diff.accounts.contains_key(&adr) {
+                let before = exists(self.recover_codehash(adr, acct)?);
+                diff.accounts.insert(
+                    adr,
+                    AccountDiff {
+                        before,
+                        after: None,
+                    },
+                );
+            }
+        }
+        for (adr, acct_diff) in diff.accounts.iter_mut() {
+            acct_diff.after = self.account_at(*adr, to)?.and_then(exists);
+        }
+
+        let start = StorageCSKey(from, Default::default());
+        for read in self.cursor::<StorageChangeSet>()?.walk(start)? {
+            let (StorageCSKey(block, StorageKey(adr, inc)), StorageCSVal(slot, before)) = read?;
+            if block >= to {
+                break;
+            }
+            diff.storage
+                .entry((adr, inc))
+                .or_default()
+                .entry(slot)
+                .or_insert(StorageDiff {
+                    before,
+                    after: U256::zero(),
+                });
+        }
+        for ((adr, inc), slots) in diff.storage.iter_mut() {
+            for (slot, slot_diff) in slots.iter_mut() {
+                slot_diff.after = self.storage_at(*adr, *inc, *slot, to)?;
+            }
+        }
+
+        Ok(diff)
+    }
+}
+
+// An empty changeset entry decodes to the default account, indicating the
+// account did not exist before the change.
+fn exists(acct: Account) -> Option<Account> {
+    if acct == Account::default() {
+        None
+    } else {
+        Some(acct)
+    }
+}
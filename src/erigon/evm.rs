@@ -0,0 +1,455 @@
+//! Execution against historical state with revm, enabled by the `evm` feature.
+//!
+//! [`HistoricalState`] serves the state after a given block to revm from the
+//! history tables, and [`Erigon::call`] uses it to execute calls the way
+//! `eth_call` does:
+//!
+//! ```ignore
+//! let req = CallRequest { to: Some(token), data: calldata, ..Default::default() };
+//! let res = db.call(15_000_000, req)?;
+//! ```
+use crate::{
+    erigon::{
+        models::{
+            logs_bloom, transaction::TxAction, BlockHeader, BlockNumber, CborLog, CborReceipt,
+            FromAlloy, HeaderKey, ToAlloy, Transaction,
+        },
+        trie::{ordered_trie_root, receipt_leaf},
+        Erigon,
+    },
+    error::{ensure, Error},
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+use bytes::Bytes;
+use ethereum_types::{Address, H256, U256};
+use eyre::eyre;
+use revm::{
+    db::CacheDB,
+    primitives::{
+        AccountInfo, BlobExcessGasAndPrice, BlockEnv, Env, ExecutionResult, SpecId, TransactTo,
+        TxEnv, B256, KECCAK_EMPTY,
+    },
+    Database, DatabaseCommit, DatabaseRef, Evm,
+};
+use serde_json::Value;
+
+// Forks activated by block number, latest first, keyed as in the chain config.
+const BLOCK_FORKS: [(&str, SpecId); 9] = [
+    ("londonBlock", SpecId::LONDON),
+    ("berlinBlock", SpecId::BERLIN),
+    ("istanbulBlock", SpecId::ISTANBUL),
+    ("petersburgBlock", SpecId::PETERSBURG),
+    ("constantinopleBlock", SpecId::CONSTANTINOPLE),
+    ("byzantiumBlock", SpecId::BYZANTIUM),
+    ("eip158Block", SpecId::SPURIOUS_DRAGON),
+    ("eip150Block", SpecId::TANGERINE),
+    ("homesteadBlock", SpecId::HOMESTEAD),
+];
+// Forks activated by timestamp, latest first.
+const TIME_FORKS: [(&str, SpecId); 2] = [
+    ("cancunTime", SpecId::CANCUN),
+    ("shanghaiTime", SpecId::SHANGHAI),
+];
+
+/// Returns the revm spec in effect for `header`, given the chain config as
+/// returned by [`Erigon::read_chain_config`]. The merge is recognized by a
+/// header with zero difficulty after London.
+pub fn spec_id(config: &Value, header: &BlockHeader) -> SpecId {
+    let activation = |key: &str| config.get(key).and_then(Value::as_u64);
+    if let Some((_, spec)) = TIME_FORKS
+        .iter()
+        .find(|(key, _)| activation(key).map_or(false, |t| header.time >= t))
+    {
+        return *spec;
+    }
+    let num = header.number.as_u64();
+    let spec = BLOCK_FORKS
+        .iter()
+        .find(|(key, _)| activation(key).map_or(false, |b| num >= b))
+        .map_or(SpecId::FRONTIER, |(_, spec)| *spec);
+    if spec == SpecId::LONDON && header.difficulty.is_zero() {
+        return SpecId::MERGE;
+    }
+    spec
+}
+
+/// Returns the block env revm executes the transactions of `header` in.
+pub fn block_env(header: &BlockHeader) -> BlockEnv {
+    // after the merge, the mix digest field holds prevrandao
+    let prevrandao = header
+        .difficulty
+        .is_zero()
+        .then(|| header.mix_digest.to_alloy());
+    BlockEnv {
+        number: header.number.to_alloy(),
+        coinbase: header.coinbase.to_alloy(),
+        timestamp: revm::primitives::U256::from(header.time),
+        gas_limit: revm::primitives::U256::from(header.gas_limit),
+        basefee: header.base_fee.unwrap_or_default().to_alloy(),
+        difficulty: header.difficulty.to_alloy(),
+        prevrandao,
+        blob_excess_gas_and_price: header.excess_blob_gas.map(BlobExcessGasAndPrice::new),
+    }
+}
+
+/// The state after a block was applied, read from the history tables, as a
+/// revm database. Block hashes are read from the canonical chain.
+pub struct HistoricalState<'db, 'env, K: Mode, Tx: KvTx> {
+    db: &'db Erigon<'env, K, Tx>,
+    block: BlockNumber,
+}
+
+impl<K: Mode, Tx: KvTx> DatabaseRef for HistoricalState<'_, '_, K, Tx> {
+    type Error = Error;
+
+    fn basic_ref(
+        &self,
+        address: revm::primitives::Address,
+    ) -> Result<Option<AccountInfo>, Self::Error> {
+        let acct = match self
+            .db
+            .account_after(Address::from_alloy(address), self.block)?
+        {
+            Some(acct) => acct,
+            None => return Ok(None),
+        };
+        // Erigon stores no codehash for accounts without code
+        let code_hash = match acct.codehash {
+            hash if hash.is_zero() => KECCAK_EMPTY,
+            hash => hash.to_alloy(),
+        };
+        Ok(Some(AccountInfo {
+            balance: acct.balance.to_alloy(),
+            nonce: acct.nonce,
+            code_hash,
+            code: None,
+        }))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<revm::primitives::Bytecode, Self::Error> {
+        let code = self
+            .db
+            .read_code(H256::from_alloy(code_hash))?
+            .unwrap_or_default();
+        Ok(revm::primitives::Bytecode::new_raw(code.0.to_alloy()))
+    }
+
+    fn storage_ref(
+        &self,
+        address: revm::primitives::Address,
+        index: revm::primitives::U256,
+    ) -> Result<revm::primitives::U256, Self::Error> {
+        let adr = Address::from_alloy(address);
+        let inc = match self.db.account_after(adr, self.block)? {
+            Some(acct) => acct.incarnation,
+            None => return Ok(Default::default()),
+        };
+        let slot = H256(index.to_be_bytes());
+        let val = self.db.storage_at(adr, inc, slot, *self.block + 1)?;
+        Ok(val.to_alloy())
+    }
+
+    fn block_hash_ref(&self, number: revm::primitives::U256) -> Result<B256, Self::Error> {
+        let hash = self.db.read_canonical_hash(number.saturating_to::<u64>())?;
+        Ok(hash.unwrap_or_default().to_alloy())
+    }
+}
+
+/// A message call, with the fields taken by `eth_call`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallRequest {
+    pub from: Address,
+    /// The callee, or `None` to execute `data` as initcode.
+    pub to: Option<Address>,
+    /// Defaults to the block's gas limit.
+    pub gas: Option<u64>,
+    pub gas_price: Option<U256>,
+    pub value: U256,
+    pub data: Bytes,
+}
+
+/// The outcome of executing a [`CallRequest`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallResult {
+    /// False if the call reverted or halted.
+    pub success: bool,
+    /// The return data, or the revert data if the call reverted.
+    pub output: Bytes,
+    pub gas_used: u64,
+    pub logs: Vec<CborLog>,
+}
+
+impl From<ExecutionResult> for CallResult {
+    fn from(res: ExecutionResult) -> Self {
+        Self {
+            success: res.is_success(),
+            output: res.output().cloned().map(|out| out.0).unwrap_or_default(),
+            gas_used: res.gas_used(),
+            logs: res
+                .logs()
+                .iter()
+                .cloned()
+                .map(CborLog::from_alloy)
+                .collect(),
+        }
+    }
+}
+
+/// Returns the tx env revm executes `tx`, signed by `sender`, in.
+pub fn tx_env(tx: &Transaction, sender: Address) -> TxEnv {
+    let access_list = tx.access_list().map(|list| {
+        list.iter()
+            .map(|tuple| {
+                let slots = tuple
+                    .slots
+                    .iter()
+                    .map(|slot| revm::primitives::U256::from_be_bytes(slot.0))
+                    .collect();
+                (tuple.address.to_alloy(), slots)
+            })
+            .collect()
+    });
+    TxEnv {
+        caller: sender.to_alloy(),
+        gas_limit: tx.gas(),
+        // for dynamic fee txs, revm takes the fee cap as the gas price
+        gas_price: tx
+            .gas_price()
+            .or(tx.fee_cap())
+            .unwrap_or_default()
+            .to_alloy(),
+        gas_priority_fee: tx.tip().map(|tip| tip.to_alloy()),
+        transact_to: match tx.to() {
+            TxAction::Call(to) => TransactTo::Call(to.to_alloy()),
+            TxAction::Create => TransactTo::Create,
+        },
+        value: tx.value().to_alloy(),
+        data: tx.data().to_alloy(),
+        nonce: Some(tx.nonce()),
+        chain_id: tx.chain_id().map(|id| id.as_u64()),
+        access_list: access_list.unwrap_or_default(),
+        blob_hashes: tx
+            .blob_versioned_hashes()
+            .map(|hashes| hashes.iter().map(ToAlloy::to_alloy).collect())
+            .unwrap_or_default(),
+        max_fee_per_blob_gas: tx.blob_fee_cap().map(|cap| cap.to_alloy()),
+        ..Default::default()
+    }
+}
+
+/// The env shared by the txs of a block: its spec, chain id, and block env.
+#[derive(Clone, Debug)]
+pub struct BlockContext {
+    pub header: BlockHeader,
+    pub spec: SpecId,
+    pub chain_id: u64,
+}
+
+impl BlockContext {
+    /// Returns the env to execute `tx` in as part of the block.
+    pub fn env(&self, tx: TxEnv) -> Box<Env> {
+        let mut env = Env::default();
+        env.cfg.chain_id = self.chain_id;
+        env.block = block_env(&self.header);
+        env.tx = tx;
+        Box::new(env)
+    }
+
+    /// Executes `tx`, signed by `sender`, as part of the block and commits its
+    /// changes to `db`.
+    pub fn transact_commit<DB>(
+        &self,
+        db: DB,
+        tx: &Transaction,
+        sender: Address,
+    ) -> Result<ExecutionResult>
+    where
+        DB: Database + DatabaseCommit,
+        DB::Error: std::fmt::Display,
+    {
+        let mut evm = Evm::builder()
+            .with_db(db)
+            .with_env(self.env(tx_env(tx, sender)))
+            .with_spec_id(self.spec)
+            .build();
+        evm.transact_commit()
+            .map_err(|e| eyre!("tx {:?} failed: {}", tx.tx_hash(), e).into())
+    }
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Returns the state after block `block` was applied, as a revm database.
+    pub fn state_after(&self, block: impl Into<BlockNumber>) -> HistoricalState<'_, 'env, K, Tx> {
+        HistoricalState {
+            db: self,
+            block: block.into(),
+        }
+    }
+
+    /// Returns the context the txs of the block with header `header` execute
+    /// in, which is read from the chain config.
+    pub fn block_context(&self, header: BlockHeader) -> Result<BlockContext> {
+        let genesis = self.read_canonical_hash(0)?.ok_or(Error::MissingValue {
+            what: "genesis hash",
+        })?;
+        let config = self
+            .read_chain_config(genesis)?
+            .ok_or(Error::MissingValue {
+                what: "chain config",
+            })?;
+        let chain_id = config
+            .get("chainId")
+            .and_then(Value::as_u64)
+            .ok_or(Error::MissingValue { what: "chain id" })?;
+        Ok(BlockContext {
+            spec: spec_id(&config, &header),
+            chain_id,
+            header,
+        })
+    }
+
+    // Reads the context and the (tx, sender) pairs of the block with key
+    // `key`, to re-execute it.
+    pub(crate) fn block_txs(
+        &self,
+        key: HeaderKey,
+    ) -> Result<(BlockContext, Vec<(Transaction, Address)>)> {
+        let header = self
+            .read_header(key)?
+            .ok_or(Error::MissingValue { what: "header" })?;
+        let txs = self.read_transactions(key)?.unwrap_or_default();
+        let senders = self
+            .read_senders(key)?
+            .ok_or(Error::MissingValue { what: "senders" })?;
+        ensure!(
+            txs.len() == senders.len(),
+            "Block {} has {} txs but {} senders",
+            *key.0,
+            txs.len(),
+            senders.len(),
+        );
+        Ok((
+            self.block_context(header)?,
+            txs.into_iter().zip(senders).collect(),
+        ))
+    }
+
+    /// Re-executes the txs of canonical block `block` against the state
+    /// before it, returning the receipt and logs of each tx. Receipts never
+    /// carry a post-state root, as intermediate state roots aren't computed.
+    pub fn execute_block(
+        &self,
+        block: impl Into<BlockNumber>,
+    ) -> Result<Vec<(CborReceipt, Vec<CborLog>)>> {
+        self.execute(block.into()).map(|(_, receipts)| receipts)
+    }
+
+    fn execute(
+        &self,
+        block: BlockNumber,
+    ) -> Result<(BlockContext, Vec<(CborReceipt, Vec<CborLog>)>)> {
+        let hash = self
+            .read_canonical_hash(block)?
+            .ok_or(Error::MissingValue {
+                what: "canonical hash",
+            })?;
+        let (ctx, txs) = self.block_txs(HeaderKey(block, hash))?;
+        let mut state = CacheDB::new(self.state_after(block.saturating_sub(1)));
+        let mut cumulative_gas_used = 0;
+        let mut receipts = Vec::with_capacity(txs.len());
+        for (tx, sender) in txs {
+            let res = ctx.transact_commit(&mut state, &tx, sender)?;
+            cumulative_gas_used += res.gas_used();
+            let receipt = CborReceipt {
+                tx_type: tx.tx_type().unwrap_or_default(),
+                post_state: None,
+                status: res.is_success() as u64,
+                cumulative_gas_used,
+            };
+            let logs = res.into_logs().into_iter().map(CborLog::from_alloy);
+            receipts.push((receipt, logs.collect()));
+        }
+        Ok((ctx, receipts))
+    }
+
+    /// Re-executes canonical block `block` with [`Self::execute_block`] and
+    /// checks the gas used, logs bloom and receipts root of its header against
+    /// the results, failing on the first mismatch. Before Byzantium, receipts
+    /// commit to intermediate state roots, so the receipts root isn't checked.
+    pub fn verify_block_execution(&self, block: impl Into<BlockNumber>) -> Result<()> {
+        let block = block.into();
+        let (ctx, receipts) = self.execute(block)?;
+        let header = &ctx.header;
+        let gas_used = receipts
+            .last()
+            .map_or(0, |(receipt, _)| receipt.cumulative_gas_used);
+        ensure!(
+            gas_used == header.gas_used,
+            "Block {} used {} gas on re-execution, but its header says {}",
+            *block,
+            gas_used,
+            header.gas_used,
+        );
+        let bloom = logs_bloom(receipts.iter().flat_map(|(_, logs)| logs));
+        ensure!(
+            bloom == header.bloom,
+            "Block {} has a different logs bloom on re-execution",
+            *block,
+        );
+        if ctx.spec >= SpecId::BYZANTIUM {
+            let root = ordered_trie_root(
+                receipts
+                    .iter()
+                    .map(|(receipt, logs)| receipt_leaf(receipt, logs)),
+            );
+            ensure!(
+                root == header.receipts_hash,
+                "Block {} has receipts root {:?} on re-execution, but its header says {:?}",
+                *block,
+                root,
+                header.receipts_hash,
+            );
+        }
+        Ok(())
+    }
+
+    /// Executes `req` against the state after canonical block `block`, in the
+    /// env of that block, like `eth_call`. As with `eth_call`, the caller's
+    /// balance and the base fee are not checked. Nothing is written to the db.
+    pub fn call(&self, block: impl Into<BlockNumber>, req: CallRequest) -> Result<CallResult> {
+        let block = block.into();
+        let header = self
+            .read_canonical_header(block)?
+            .ok_or(Error::MissingValue {
+                what: "canonical header",
+            })?;
+        let ctx = self.block_context(header)?;
+        let tx = TxEnv {
+            caller: req.from.to_alloy(),
+            gas_limit: req.gas.unwrap_or(ctx.header.gas_limit),
+            gas_price: req.gas_price.unwrap_or_default().to_alloy(),
+            transact_to: match req.to {
+                Some(to) => TransactTo::Call(to.to_alloy()),
+                None => TransactTo::Create,
+            },
+            value: req.value.to_alloy(),
+            data: req.data.to_alloy(),
+            ..Default::default()
+        };
+        let mut evm = Evm::builder()
+            .with_ref_db(self.state_after(block))
+            .with_env(ctx.env(tx))
+            .with_spec_id(ctx.spec)
+            .modify_cfg_env(|cfg| {
+                cfg.disable_balance_check = true;
+                cfg.disable_base_fee = true;
+                cfg.disable_eip3607 = true;
+            })
+            .build();
+        let res = evm
+            .transact()
+            .map_err(|e| eyre!("call at block {} failed: {}", *block, e))?;
+        Ok(res.result.into())
+    }
+}
@@ -0,0 +1,129 @@
+//! Streaming export of the current state (the PlainState table).
+use ethereum_types::{Address, H256, U256};
+use serde::Serialize;
+use std::io::Write;
+
+use crate::{
+    erigon::{
+        models::*,
+        progress::{Progress, Ticker},
+        tables::PlainState,
+        utils::consts::ADDRESS_LENGTH,
+        Erigon,
+    },
+    kv::traits::{KvCursor, KvTx, Mode, TableDecode},
+    Result,
+};
+
+/// The number of records written between progress ticks.
+pub const PROGRESS_INTERVAL: u64 = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON object per line.
+    JsonLines,
+    /// Comma-separated values with a header row. Account rows leave the
+    /// storage columns empty and storage rows leave the account columns empty.
+    Csv,
+}
+
+/// A single entry in the PlainState table.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StateRecord {
+    Account {
+        address: Address,
+        #[serde(flatten)]
+        account: Account,
+    },
+    Storage {
+        address: Address,
+        incarnation: Incarnation,
+        slot: H256,
+        value: U256,
+    },
+}
+
+impl StateRecord {
+    pub const CSV_HEADER: &'static str =
+        "type,address,nonce,balance,incarnation,codehash,slot,value";
+
+    fn write_csv<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        match self {
+            Self::Account { address, account } => writeln!(
+                w,
+                "account,{:?},{},{},{},{:?},,",
+                address, account.nonce, account.balance, account.incarnation, account.codehash
+            ),
+            Self::Storage {
+                address,
+                incarnation,
+                slot,
+                value,
+            } => writeln!(
+                w,
+                "storage,{:?},,,{},,{:?},{}",
+                address, incarnation, slot, value
+            ),
+        }
+    }
+}
+
+/// Streams every account and storage slot in the PlainState table to `writer`
+/// in the requested format, returning the number of records written.
+/// `progress` ticks every [`PROGRESS_INTERVAL`] records with the number of
+/// records written so far and the PlainState key being exported.
+pub fn export_state<K, Tx, W, P>(
+    db: &Erigon<'_, K, Tx>,
+    mut writer: W,
+    format: ExportFormat,
+    mut progress: P,
+) -> Result<u64>
+where
+    K: Mode,
+    Tx: KvTx,
+    W: Write,
+    P: Progress,
+{
+    let mut ticker = Ticker::new(&mut progress, "export_state", None);
+    if format == ExportFormat::Csv {
+        writeln!(writer, "{}", StateRecord::CSV_HEADER)?;
+    }
+    let mut cur = db.cursor::<PlainState>()?;
+    let mut count = 0;
+    let mut next = cur.inner.first()?;
+    while let Some((k, v)) = next {
+        let record = if k.len() == ADDRESS_LENGTH {
+            StateRecord::Account {
+                address: TableDecode::decode(&k)?,
+                account: TableDecode::decode(&v)?,
+            }
+        } else {
+            let StorageKey(address, incarnation) = TableDecode::decode(&k)?;
+            let (slot, value) = TableDecode::decode(&v)?;
+            StateRecord::Storage {
+                address,
+                incarnation,
+                slot,
+                value,
+            }
+        };
+
+        match format {
+            ExportFormat::JsonLines => {
+                serde_json::to_writer(&mut writer, &record)?;
+                writeln!(writer)?;
+            }
+            ExportFormat::Csv => record.write_csv(&mut writer)?,
+        }
+
+        count += 1;
+        if count % PROGRESS_INTERVAL == 0 {
+            ticker.tick(count, Some(&k[..]));
+        }
+        next = cur.inner.next()?;
+    }
+    writer.flush()?;
+    ticker.finish();
+    Ok(count)
+}
@@ -0,0 +1,185 @@
+//! Extraction of small, self-contained test fixtures from a full chaindata
+//! directory.
+use ethereum_types::{Address, H256};
+use eyre::eyre;
+use mdbx::{RO, RW};
+use roaring::RoaringTreemap;
+use std::{ops::Range, path::Path};
+
+use crate::{
+    erigon::{env_open, models::*, strip_system_txs, tables::*, writer::BlockWriter, Erigon},
+    kv::{
+        traits::{DefaultFlags, KvCursor, KvTx, KvTxMut, Mode, Table, TableDecode, TableEncode},
+        MdbxEnv,
+    },
+    Result,
+};
+
+/// Copies the rows needed to serve the canonical blocks in `blocks` and the
+/// state of `addresses` from `src_env` into a new mdbx database at `dst_path`.
+///
+/// For each block, the canonical hash, header, total difficulty, body (with
+/// its system txs), transactions, senders, receipts, logs, and tx lookups are
+/// copied. For each address, its current account and storage, code, and
+/// history bitmaps are copied, along with its changesets from `blocks.start`
+/// onward, so historical reads of these addresses are exact for any block
+/// `>= blocks.start`. The head of the new database is set to the last block
+/// in the range.
+pub fn extract_subset<M: Mode>(
+    src_env: &MdbxEnv<M>,
+    dst_path: &Path,
+    blocks: Range<u64>,
+    addresses: &[Address],
+) -> Result<()> {
+    let src = Erigon::<RO>::new(src_env.begin_ro()?);
+    let dst_env = env_open(dst_path)?;
+    let dst = Erigon::begin_rw(&dst_env)?;
+
+    let mut head = None;
+    for num in blocks.clone() {
+        let hash = src
+            .read_canonical_hash(num)?
+            .ok_or_else(|| eyre!("No canonical block {}", num))?;
+        copy_block(&src.0, &dst, HeaderKey(num.into(), hash))?;
+        head = Some(hash);
+    }
+    for &adr in addresses {
+        copy_account(&src.0, &dst.0, adr, blocks.start)?;
+    }
+    if let Some(hash) = head {
+        dst.write_head_header_hash(hash)?;
+        dst.write_head_block_hash(hash)?;
+    }
+    dst.commit()?;
+    Ok(())
+}
+
+fn copy_block<S: KvTx, D: KvTxMut>(src: &S, dst: &Erigon<RW, D>, key: HeaderKey) -> Result<()> {
+    let HeaderKey(num, hash) = key;
+    copy_key::<CanonicalHeader, _, _>(src, &dst.0, num)?;
+    copy_key::<HeaderNumber, _, _>(src, &dst.0, hash)?;
+    copy_key::<Header, _, _>(src, &dst.0, key)?;
+    copy_key::<HeadersTotalDifficulty, _, _>(src, &dst.0, key)?;
+    copy_key::<TxSender, _, _>(src, &dst.0, key)?;
+    copy_key::<Receipt, _, _>(src, &dst.0, num)?;
+    copy_prefix::<TransactionLog, _, _>(src, &dst.0, num.encode().as_ref())?;
+
+    // The stored body counts a system tx slot at either end of the block.
+    // Those slots hold no transactions, so only the user txs between them are
+    // copied, and the body is renumbered into ids allocated in the new
+    // database the same way the BlockWriter allocates them.
+    let raw = src
+        .get_raw::<BlockBody>(key.encode().as_ref())?
+        .ok_or_else(|| eyre!("No body for block {:?}", key))?;
+    let mut body = BodyForStorage::decode(&raw)?;
+    let user = strip_system_txs(body.clone(), key)?;
+    let base_tx_id = BlockWriter::new(dst).next_tx_ids(body.tx_amount.into())?;
+    for i in 0..user.tx_amount as u64 {
+        let id = TxIndex(user.base_tx_id + i).encode();
+        let tx = src
+            .get_raw::<BlockTransaction>(id.as_ref())?
+            .ok_or_else(|| eyre!("Missing transaction {} in block {:?}", i, key))?;
+        let new_id = TxIndex(base_tx_id + 1 + i).encode();
+        dst.0.put_raw::<BlockTransaction>(new_id.as_ref(), &tx)?;
+        let tx_hash = Transaction::decode(&tx)?.tx_hash();
+        copy_key::<BlockTransactionLookup, _, _>(src, &dst.0, tx_hash)?;
+    }
+    body.base_tx_id = base_tx_id;
+    dst.write_body_for_storage(key, body)?;
+    Ok(())
+}
+
+fn copy_account<S: KvTx, D: KvTxMut>(src: &S, dst: &D, adr: Address, from: u64) -> Result<()> {
+    // PlainState holds both the account (keyed by address) and its storage
+    // (keyed by address||incarnation). Copying through the dupsorted Storage
+    // view of the table creates it with the right flags.
+    copy_prefix::<Storage, _, _>(src, dst, adr.as_bytes())?;
+    copy_key::<IncarnationMap, _, _>(src, dst, adr)?;
+    for (_, codehash) in copy_prefix::<PlainCodeHash, _, _>(src, dst, adr.as_bytes())? {
+        copy_key::<Code, _, _>(src, dst, H256::decode(&codehash)?)?;
+    }
+
+    for (_, bitmap) in copy_prefix::<AccountHistory, _, _>(src, dst, adr.as_bytes())? {
+        for block in RoaringTreemap::decode(&bitmap)?
+            .iter()
+            .filter(|&b| b >= from)
+        {
+            let key = BlockNumber(block).encode();
+            copy_dup::<AccountChangeSet, _, _>(src, dst, key.as_ref(), adr.as_bytes())?;
+        }
+    }
+
+    // key: address||slot||shard
+    for (key, bitmap) in copy_prefix::<StorageHistory, _, _>(src, dst, adr.as_bytes())? {
+        let slot = &key[Address::len_bytes()..Address::len_bytes() + H256::len_bytes()];
+        for block in RoaringTreemap::decode(&bitmap)?
+            .iter()
+            .filter(|&b| b >= from)
+        {
+            // changeset keys are block||address||incarnation, and the
+            // incarnation the slot changed under isn't known up front
+            let mut prefix = BlockNumber(block).encode().as_ref().to_vec();
+            prefix.extend_from_slice(adr.as_bytes());
+            let mut cur = src.cursor_raw::<StorageChangeSet>()?;
+            let mut kv = cur.seek(&prefix)?;
+            while let Some((k, _)) = kv {
+                if !k.starts_with(&prefix) {
+                    break;
+                }
+                copy_dup::<StorageChangeSet, _, _>(src, dst, &k, slot)?;
+                kv = cur.next_nodup()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Copies the row at `key`, if any, returning its raw value.
+fn copy_key<T, S, D>(src: &S, dst: &D, key: impl TableEncode) -> Result<Option<Vec<u8>>>
+where
+    T: for<'tx> Table<'tx> + DefaultFlags,
+    S: KvTx,
+    D: KvTxMut,
+{
+    let key = key.encode();
+    let val = src.get_raw::<T>(key.as_ref())?;
+    if let Some(val) = &val {
+        dst.put_raw::<T>(key.as_ref(), val)?;
+    }
+    Ok(val.map(|v| v.into_owned()))
+}
+
+// Copies every row whose key begins with `prefix`, returning the copied rows.
+fn copy_prefix<T, S, D>(src: &S, dst: &D, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>>
+where
+    T: for<'tx> Table<'tx> + DefaultFlags,
+    S: KvTx,
+    D: KvTxMut,
+{
+    let mut rows = vec![];
+    let mut cur = src.cursor_raw::<T>()?;
+    let mut kv = cur.seek(prefix)?;
+    while let Some((k, v)) = kv {
+        if !k.starts_with(prefix) {
+            break;
+        }
+        dst.put_raw::<T>(&k, &v)?;
+        rows.push((k.into_owned(), v.into_owned()));
+        kv = cur.next()?;
+    }
+    Ok(rows)
+}
+
+// Copies the duplicate at `key` whose value begins with `subkey`, if any.
+fn copy_dup<T, S, D>(src: &S, dst: &D, key: &[u8], subkey: &[u8]) -> Result<()>
+where
+    T: for<'tx> Table<'tx> + DefaultFlags,
+    S: KvTx,
+    D: KvTxMut,
+{
+    let mut cur = src.cursor_raw::<T>()?;
+    match cur.seek_both_range(key, subkey)? {
+        Some(val) if val.starts_with(subkey) => dst.put_raw::<T>(key, &val),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,194 @@
+//! Following the canonical chain as Erigon extends it, by polling the db.
+use crate::{
+    erigon::{models::*, Erigon, ErigonDb},
+    error::bail,
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+use ethereum_types::H256;
+use std::{collections::VecDeque, ops::Range, time::Duration};
+
+/// The number of recent blocks a [`Follow`] remembers to detect reorgs. A
+/// reorg that retracts more blocks than this is raised as an error.
+pub const FOLLOW_DEPTH: usize = 256;
+
+/// A change to the canonical chain, as yielded by [`Follow`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChainEvent {
+    /// The block became canonical.
+    Added(HeaderKey),
+    /// A block previously yielded as added is no longer canonical.
+    /// Retractions are yielded newest first, before the blocks that replace
+    /// them are added.
+    Retracted(HeaderKey),
+}
+
+/// How the canonical chain changed since a snapshot of it, as found by
+/// [`Erigon::detect_reorg`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reorg {
+    /// The newest block of the snapshot that is still canonical. `None` if
+    /// none is, i.e. the reorg is deeper than the snapshot.
+    pub ancestor: Option<HeaderKey>,
+    /// The numbers of the blocks of the snapshot that are no longer
+    /// canonical. Empty if there was no reorg.
+    pub retracted: Range<u64>,
+    /// The numbers of the canonical blocks after the ancestor, up to the head
+    /// block: the blocks that replace the retracted ones and any new blocks.
+    pub added: Range<u64>,
+}
+
+impl Reorg {
+    /// Returns true if any block of the snapshot was retracted.
+    pub fn is_reorg(&self) -> bool {
+        !self.retracted.is_empty()
+    }
+}
+
+/// An endless iterator over the changes to the canonical chain, returned by
+/// [`ErigonDb::follow`]. Each poll reads the db in a new read-only
+/// transaction, and the iterator sleeps between polls that find nothing new.
+#[derive(Debug)]
+pub struct Follow<M> {
+    db: ErigonDb<M>,
+    poll_interval: Duration,
+    // the next block number to add
+    next: u64,
+    // the most recently added blocks, oldest first
+    seen: VecDeque<HeaderKey>,
+    pending: VecDeque<ChainEvent>,
+}
+
+impl<M: Mode> ErigonDb<M> {
+    /// Follows the canonical chain from the block after the current head
+    /// block, checking for new blocks every `poll_interval`. The head is read
+    /// from the LastBlock table, which Erigon updates once a block has gone
+    /// through every stage, so added blocks are fully executed and indexed.
+    pub fn follow(&self, poll_interval: Duration) -> Result<Follow<M>> {
+        let head = self.view(|tx| tx.read_head_block_key())?;
+        Ok(Follow {
+            db: self.clone(),
+            poll_interval,
+            next: head.map_or(0, |HeaderKey(num, _)| *num + 1),
+            seen: head.into_iter().collect(),
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    // Returns the key of the head block.
+    fn read_head_block_key(&self) -> Result<Option<HeaderKey>> {
+        let hash = match self.read_head_block_hash()? {
+            Some(hash) => hash,
+            None => return Ok(None),
+        };
+        Ok(self
+            .read_header_number(hash)?
+            .map(|num| HeaderKey(num, hash)))
+    }
+
+    /// Compares `prev_snapshot`, canonical (number, hash) pairs observed
+    /// earlier, with the CanonicalHeader table. The snapshot's newest block
+    /// that is still canonical is the common ancestor, and every block of the
+    /// snapshot after it was retracted. The snapshot may be in any order, and
+    /// needn't be contiguous, but a reorg is only noticed at the numbers it
+    /// covers.
+    pub fn detect_reorg(&self, prev_snapshot: &[(BlockNumber, H256)]) -> Result<Reorg> {
+        let mut snapshot = prev_snapshot.to_vec();
+        snapshot.sort_unstable_by_key(|(num, _)| std::cmp::Reverse(*num));
+        let mut ancestor = None;
+        for &(num, hash) in &snapshot {
+            if self.read_canonical_hash(num)? == Some(hash) {
+                ancestor = Some(HeaderKey(num, hash));
+                break;
+            }
+        }
+        let newest = snapshot.first().map_or(0, |(num, _)| **num + 1);
+        let start = match ancestor {
+            Some(HeaderKey(num, _)) => *num + 1,
+            None => snapshot.last().map_or(0, |(num, _)| **num),
+        };
+        let head = self
+            .read_head_block_key()?
+            .map_or(0, |HeaderKey(num, _)| *num + 1);
+        Ok(Reorg {
+            ancestor,
+            retracted: start..newest.max(start),
+            added: start..head.max(start),
+        })
+    }
+}
+
+impl<M: Mode> Follow<M> {
+    // Queues the retractions and additions since the last poll.
+    fn poll(&mut self) -> Result<()> {
+        let Self {
+            db,
+            next,
+            seen,
+            pending,
+            ..
+        } = self;
+        db.view(|tx| {
+            let snapshot = seen.iter().map(|&HeaderKey(num, hash)| (num, hash));
+            let reorg = tx.detect_reorg(&snapshot.collect::<Vec<_>>())?;
+            if reorg.is_reorg() {
+                while let Some(&key) = seen.back() {
+                    if *key.0 < reorg.retracted.start {
+                        break;
+                    }
+                    seen.pop_back();
+                    pending.push_back(ChainEvent::Retracted(key));
+                }
+                *next = reorg.retracted.start;
+                if reorg.ancestor.is_none() {
+                    bail!(
+                        "Reorg retracted more than the {} most recent blocks",
+                        FOLLOW_DEPTH
+                    );
+                }
+            }
+            while *next < reorg.added.end {
+                let key = match tx.read_canonical_hash(*next)? {
+                    Some(hash) => HeaderKey(BlockNumber(*next), hash),
+                    None => break,
+                };
+                pending.push_back(ChainEvent::Added(key));
+                seen.push_back(key);
+                if seen.len() > FOLLOW_DEPTH {
+                    seen.pop_front();
+                }
+                *next += 1;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<M: Mode> Follow<M> {
+    /// Returns the next queued event, polling the db once if none is queued.
+    /// Unlike [`Iterator::next`], this never sleeps, and returns `None` if
+    /// the poll found nothing new.
+    pub fn try_next(&mut self) -> Option<Result<ChainEvent>> {
+        if self.pending.is_empty() {
+            if let Err(e) = self.poll() {
+                return Some(Err(e));
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+impl<M: Mode> Iterator for Follow<M> {
+    type Item = Result<ChainEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.try_next() {
+                return Some(event);
+            }
+            std::thread::sleep(self.poll_interval);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use bytes::Bytes;
+use ethereum_types::{Address, H256, U256, U64};
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    erigon::{models::*, tables::*, utils::consts::ADDRESS_LENGTH, Erigon},
+    kv::traits::{KvCursor, KvTx, Mode, TableDecode, TableEncode},
+    Result,
+};
+
+/// A single entry in the `alloc` field of a geth-style genesis file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct GenesisAccount {
+    pub balance: U256,
+    #[serde(skip_serializing_if = "U64::is_zero")]
+    pub nonce: U64,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_code"
+    )]
+    pub code: Option<Bytes>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<H256, H256>,
+}
+
+fn serialize_code<S: Serializer>(code: &Option<Bytes>, s: S) -> Result<S::Ok, S::Error> {
+    let code = code.as_deref().unwrap_or_default();
+    let hex: String = code.iter().map(|b| format!("{:02x}", b)).collect();
+    s.serialize_str(&format!("0x{}", hex))
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Builds a geth-style genesis `alloc` map containing the balance, nonce,
+    /// code, and storage of every account as of block `block`.
+    ///
+    /// As with [`Erigon::read_account_hist`], the state as of block `N` is the
+    /// state *before* block `N` was executed, so pass `N + 1` to capture the
+    /// state after block `N`.
+    pub fn generate_genesis_alloc(
+        &self,
+        block: impl Into<BlockNumber>,
+    ) -> Result<BTreeMap<Address, GenesisAccount>> {
+        let block = block.into();
+
+        // Every account or slot changed since `block` has its value as of
+        // `block` stored in the first changeset entry >= `block`. Everything
+        // else is unchanged since, and can be read from PlainState. An account
+        // value left empty, rather than an empty account, means the account
+        // didn't exist yet.
+        let mut changed_accounts = HashMap::new();
+        let mut cur = self.0.cursor_raw::<AccountChangeSet>()?;
+        let mut kv = cur.seek(block.encode().as_ref())?;
+        while let Some((_, v)) = kv {
+            let AccountCSVal(adr, acct) = AccountCSVal::decode(&v)?;
+            let acct = (v.len() > ADDRESS_LENGTH).then_some(acct);
+            changed_accounts.entry(adr).or_insert(acct);
+            kv = cur.next()?;
+        }
+        let mut changed_storage: HashMap<(Address, Incarnation), HashMap<H256, U256>> =
+            HashMap::new();
+        let start = StorageCSKey(block, Default::default());
+        for read in self.cursor::<StorageChangeSet>()?.walk(start)? {
+            let (StorageCSKey(_, StorageKey(adr, inc)), StorageCSVal(slot, val)) = read?;
+            changed_storage
+                .entry((adr, inc))
+                .or_default()
+                .entry(slot)
+                .or_insert(val);
+        }
+
+        let mut accounts = BTreeMap::new();
+        for read in self.walk_accounts(None)? {
+            let (adr, acct) = read?;
+            if !changed_accounts.contains_key(&adr) {
+                accounts.insert(adr, acct);
+            }
+        }
+        for (adr, acct) in changed_accounts {
+            if let Some(acct) = acct {
+                accounts.insert(adr, self.recover_codehash(adr, acct)?);
+            }
+        }
+
+        let mut alloc = BTreeMap::new();
+        for (adr, acct) in accounts {
+            let mut storage = BTreeMap::new();
+            let changed = changed_storage.remove(&(adr, acct.incarnation));
+            for read in self.walk_storage(adr, acct.incarnation, None)? {
+                let (slot, val) = read?;
+                if changed.as_ref().map_or(true, |c| !c.contains_key(&slot)) {
+                    storage.insert(slot, val);
+                }
+            }
+            storage.extend(changed.into_iter().flatten());
+
+            let code = match self.read_code(acct.codehash)? {
+                Some(code) if !code.is_empty() => Some(code.0),
+                _ => None,
+            };
+            alloc.insert(
+                adr,
+                GenesisAccount {
+                    balance: acct.balance,
+                    nonce: acct.nonce.into(),
+                    code,
+                    storage: storage
+                        .into_iter()
+                        .filter(|(_, val)| !val.is_zero())
+                        .map(|(slot, val)| (slot, u256_to_h256(val)))
+                        .collect(),
+                },
+            );
+        }
+        Ok(alloc)
+    }
+}
+
+fn u256_to_h256(val: U256) -> H256 {
+    let mut buf = [0; 32];
+    val.to_big_endian(&mut buf);
+    H256(buf)
+}
@@ -0,0 +1,97 @@
+use crate::{
+    erigon::{bitmap, models::*, tables::*, Erigon},
+    kv::traits::KvTxMut,
+    Result,
+};
+use ethereum_types::{Address, H256};
+use mdbx::RW;
+use roaring::RoaringTreemap;
+use std::{collections::BTreeMap, ops::Range};
+
+impl<'env, Tx: KvTxMut> Erigon<'env, RW, Tx> {
+    /// Regenerates the AccountHistory bitmaps over the blocks in `blocks` from
+    /// the AccountChangeSet table. For every account with a changeset entry in
+    /// `blocks`, the blocks of its bitmap in that range are replaced with the
+    /// ones it changed in, and its shards are split again at
+    /// [`bitmap::CHUNK_LIMIT`]. Returns the number of accounts rewritten.
+    ///
+    /// Accounts without a changeset entry in `blocks` are left untouched, so
+    /// to rebuild the table from scratch, clear it first.
+    pub fn rebuild_account_history(&self, blocks: Range<u64>) -> Result<usize> {
+        let mut changed = BTreeMap::<Address, RoaringTreemap>::new();
+        for read in self
+            .cursor::<AccountChangeSet>()?
+            .walk(blocks.start.into())?
+        {
+            let (block, AccountCSVal(adr, _)) = read?;
+            if *block >= blocks.end {
+                break;
+            }
+            changed.entry(adr).or_default().insert(*block);
+        }
+
+        for (&adr, map) in &changed {
+            let shards = self
+                .cursor::<AccountHistory>()?
+                .walk(AccountHistKey(adr, 0.into()))?
+                .take_while(|res| !matches!(res, Ok((AccountHistKey(k, _), _)) if *k != adr))
+                .map(|res| res.map(|(AccountHistKey(_, n), map)| (n.0, map)))
+                .collect::<Result<Vec<_>>>()?;
+            for (n, _) in &shards {
+                self.delete::<AccountHistory>(AccountHistKey(adr, (*n).into()))?;
+            }
+            for (n, map) in rebuilt_shards(shards, map, &blocks) {
+                self.write::<AccountHistory>(AccountHistKey(adr, n.into()), map)?;
+            }
+        }
+        Ok(changed.len())
+    }
+
+    /// Like [`Erigon::rebuild_account_history`], for the StorageHistory
+    /// bitmaps, regenerated from the StorageChangeSet table. Returns the number
+    /// of storage slots rewritten.
+    pub fn rebuild_storage_history(&self, blocks: Range<u64>) -> Result<usize> {
+        let mut changed = BTreeMap::<(Address, H256), RoaringTreemap>::new();
+        let start = StorageCSKey(blocks.start.into(), Default::default());
+        for read in self.cursor::<StorageChangeSet>()?.walk(start)? {
+            let (StorageCSKey(block, StorageKey(adr, _)), StorageCSVal(slot, _)) = read?;
+            if *block >= blocks.end {
+                break;
+            }
+            changed.entry((adr, slot)).or_default().insert(*block);
+        }
+
+        for (&(adr, slot), map) in &changed {
+            let shards = self
+                .cursor::<StorageHistory>()?
+                .walk(StorageHistKey(adr, slot, 0.into()))?
+                .take_while(
+                    |res| !matches!(res, Ok((StorageHistKey(k, s, _), _)) if *k != adr || *s != slot),
+                )
+                .map(|res| res.map(|(StorageHistKey(_, _, n), map)| (n.0, map)))
+                .collect::<Result<Vec<_>>>()?;
+            for (n, _) in &shards {
+                self.delete::<StorageHistory>(StorageHistKey(adr, slot, (*n).into()))?;
+            }
+            for (n, map) in rebuilt_shards(shards, map, &blocks) {
+                self.write::<StorageHistory>(StorageHistKey(adr, slot, n.into()), map)?;
+            }
+        }
+        Ok(changed.len())
+    }
+}
+
+// Merges the existing shards of one account or slot, replaces the blocks in
+// `blocks` with `changed`, and splits the result into new shards.
+fn rebuilt_shards(
+    shards: Vec<(u64, RoaringTreemap)>,
+    changed: &RoaringTreemap,
+    blocks: &Range<u64>,
+) -> Vec<(u64, RoaringTreemap)> {
+    let mut map = shards
+        .into_iter()
+        .fold(RoaringTreemap::new(), |acc, (_, shard)| acc | shard);
+    map.remove_range(blocks.clone());
+    map |= changed;
+    bitmap::split_into_shards(&map, bitmap::CHUNK_LIMIT)
+}
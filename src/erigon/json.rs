@@ -0,0 +1,247 @@
+//! Views of the models that serialize to the shapes used by the eth JSON-RPC
+//! API: camelCase field names, quantities as 0x-prefixed hex, and byte strings
+//! as 0x-prefixed hex data.
+//!
+//! The models themselves serialize field by field, which suits round-tripping
+//! them but not handing them to tools that expect RPC output:
+//!
+//! ```ignore
+//! let json = serde_json::to_string(&HeaderJson::from(&header))?;
+//! ```
+use crate::erigon::models::{
+    transaction::{AccessTuple, TransactionWithSigner},
+    Account, BlockHeader, CborLog, CborReceipt, Transaction,
+};
+use bytes::Bytes;
+use ethereum_types::{Address, Bloom, H256, H64, U256, U64};
+use serde::{Serialize, Serializer};
+
+// Serializes bytes as 0x-prefixed hex data.
+pub(crate) fn hex_data<S: Serializer>(data: &Bytes, s: S) -> Result<S::Ok, S::Error> {
+    let hex = data
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    s.serialize_str(&format!("0x{}", hex))
+}
+
+/// A header as returned by `eth_getBlockByNumber`, without the body fields.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeaderJson {
+    pub hash: H256,
+    pub parent_hash: H256,
+    pub sha3_uncles: H256,
+    pub miner: Address,
+    pub state_root: H256,
+    pub transactions_root: H256,
+    pub receipts_root: H256,
+    pub logs_bloom: Bloom,
+    pub difficulty: U256,
+    pub number: U256,
+    pub gas_limit: U64,
+    pub gas_used: U64,
+    pub timestamp: U64,
+    #[serde(serialize_with = "hex_data")]
+    pub extra_data: Bytes,
+    pub mix_hash: H256,
+    pub nonce: H64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_fee_per_gas: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub withdrawals_root: Option<H256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob_gas_used: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excess_blob_gas: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_beacon_block_root: Option<H256>,
+}
+
+impl From<&BlockHeader> for HeaderJson {
+    fn from(h: &BlockHeader) -> Self {
+        Self {
+            hash: h.hash(),
+            parent_hash: h.parent_hash,
+            sha3_uncles: h.uncle_hash,
+            miner: h.coinbase,
+            state_root: h.root,
+            transactions_root: h.tx_hash,
+            receipts_root: h.receipts_hash,
+            logs_bloom: h.bloom,
+            difficulty: h.difficulty,
+            number: h.number,
+            gas_limit: h.gas_limit.into(),
+            gas_used: h.gas_used.into(),
+            timestamp: h.time.into(),
+            extra_data: h.extra.clone(),
+            mix_hash: h.mix_digest,
+            nonce: h.nonce,
+            base_fee_per_gas: h.base_fee,
+            withdrawals_root: h.withdrawals_root,
+            blob_gas_used: h.blob_gas_used.map(Into::into),
+            excess_blob_gas: h.excess_blob_gas.map(Into::into),
+            parent_beacon_block_root: h.parent_beacon_block_root,
+        }
+    }
+}
+
+/// The fields of an account returned by `eth_getProof`, without the proofs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountJson {
+    pub nonce: U64,
+    pub balance: U256,
+    pub code_hash: H256,
+}
+
+impl From<&Account> for AccountJson {
+    fn from(acct: &Account) -> Self {
+        Self {
+            nonce: acct.nonce.into(),
+            balance: acct.balance,
+            code_hash: acct.codehash,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessTupleJson {
+    pub address: Address,
+    pub storage_keys: Vec<H256>,
+}
+
+impl From<&AccessTuple> for AccessTupleJson {
+    fn from(tuple: &AccessTuple) -> Self {
+        Self {
+            address: tuple.address,
+            storage_keys: tuple.slots.clone(),
+        }
+    }
+}
+
+/// A transaction as returned by `eth_getTransactionByHash`, without the
+/// fields locating it in a block. `from` is only set when converting from a
+/// [`TransactionWithSigner`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionJson {
+    pub hash: H256,
+    #[serde(rename = "type")]
+    pub tx_type: U64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub nonce: U64,
+    pub value: U256,
+    pub gas: U64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_price: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_fee_per_gas: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_priority_fee_per_gas: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_fee_per_blob_gas: Option<U256>,
+    #[serde(serialize_with = "hex_data")]
+    pub input: Bytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_list: Option<Vec<AccessTupleJson>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob_versioned_hashes: Option<Vec<H256>>,
+    pub v: U256,
+    pub r: U256,
+    pub s: U256,
+}
+
+impl From<&Transaction> for TransactionJson {
+    fn from(tx: &Transaction) -> Self {
+        // legacy txs report v as signed, with the chain id packed in
+        let v = match tx {
+            Transaction::Legacy(tx) => *tx.v,
+            tx => tx.v(),
+        };
+        Self {
+            hash: tx.tx_hash(),
+            tx_type: tx.tx_type().unwrap_or_default().into(),
+            from: None,
+            to: tx.to().into(),
+            nonce: tx.nonce().into(),
+            value: tx.value(),
+            gas: tx.gas().into(),
+            gas_price: tx.gas_price(),
+            max_fee_per_gas: tx.fee_cap(),
+            max_priority_fee_per_gas: tx.tip(),
+            max_fee_per_blob_gas: tx.blob_fee_cap(),
+            input: tx.data().clone(),
+            chain_id: tx.chain_id(),
+            access_list: tx
+                .access_list()
+                .map(|list| list.iter().map(Into::into).collect()),
+            blob_versioned_hashes: tx.blob_versioned_hashes().map(<[H256]>::to_vec),
+            v,
+            r: tx.r(),
+            s: tx.s(),
+        }
+    }
+}
+
+impl From<&TransactionWithSigner> for TransactionJson {
+    fn from(tx: &TransactionWithSigner) -> Self {
+        Self {
+            from: Some(tx.signer),
+            ..Self::from(&tx.msg)
+        }
+    }
+}
+
+/// A log as returned in a receipt, without the fields locating it in a block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogJson {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    #[serde(serialize_with = "hex_data")]
+    pub data: Bytes,
+}
+
+impl From<&CborLog> for LogJson {
+    fn from(log: &CborLog) -> Self {
+        Self {
+            address: log.address,
+            topics: log.topics.clone(),
+            data: log.data.clone(),
+        }
+    }
+}
+
+/// The fields of a receipt that Erigon stores, as returned by
+/// `eth_getTransactionReceipt`. Erigon stores a tx's logs apart from its
+/// receipt, so `logs` starts out empty.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiptJson {
+    #[serde(rename = "type")]
+    pub tx_type: U64,
+    /// The post-state root, for receipts from before Byzantium.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<H256>,
+    pub status: U64,
+    pub cumulative_gas_used: U64,
+    pub logs: Vec<LogJson>,
+}
+
+impl From<&CborReceipt> for ReceiptJson {
+    fn from(receipt: &CborReceipt) -> Self {
+        Self {
+            tx_type: receipt.tx_type.into(),
+            root: receipt.post_state,
+            status: receipt.status.into(),
+            cumulative_gas_used: receipt.cumulative_gas_used.into(),
+            logs: vec![],
+        }
+    }
+}
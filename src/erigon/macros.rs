@@ -73,7 +73,7 @@ pub(crate) use impl_encode_tuple;
 macro_rules! impl_decode_tuple {
     ($name:ident($($t:ty),+), $n:literal) => {
         impl $crate::kv::traits::TableDecode for $name {
-            fn decode(b: &[u8]) -> ::eyre::Result<Self> {
+            fn decode(b: &[u8]) -> $crate::Result<Self> {
                 if b.len() > Self::SIZE {
                     return Err(
                         $crate::kv::tables::TooLong::<{ Self::SIZE }> { got: b.len() }.into(),
@@ -163,7 +163,7 @@ macro_rules! rlp_table_value {
             }
         }
         impl $crate::kv::traits::TableDecode for $t {
-            fn decode(mut b: &[u8]) -> ::eyre::Result<Self> {
+            fn decode(mut b: &[u8]) -> $crate::Result<Self> {
                 ::fastrlp::Decodable::decode(&mut b).map_err(From::from)
             }
         }
@@ -211,7 +211,7 @@ macro_rules! bytes_wrapper {
         }
 
         impl $crate::kv::traits::TableDecode for $name {
-            fn decode(b: &[u8]) -> ::eyre::Result<Self> {
+            fn decode(b: &[u8]) -> $crate::Result<Self> {
                 $crate::kv::traits::TableDecode::decode(b).map(Self)
             }
         }
@@ -263,7 +263,7 @@ macro_rules! u64_table_key {
         }
 
         impl $crate::kv::traits::TableDecode for $ty {
-            fn decode(b: &[u8]) -> ::eyre::Result<Self> {
+            fn decode(b: &[u8]) -> $crate::Result<Self> {
                 match b.len() {
                     8 => Ok(u64::from_be_bytes(*::arrayref::array_ref!(&*b, 0, 8)).into()),
                     other => Err($crate::kv::tables::InvalidLength::<8> { got: other }.into()),
@@ -341,7 +341,7 @@ macro_rules! cbor_wrapper {
         }
 
         impl $crate::kv::traits::TableDecode for $name {
-            fn decode(b: &[u8]) -> ::eyre::Result<Self> {
+            fn decode(b: &[u8]) -> $crate::Result<Self> {
                 ::serde_cbor::from_slice(b).map_err(From::from).map(Self)
             }
         }
@@ -1,22 +1,89 @@
-use crate::kv::{
-    traits::{DefaultFlags, Mode, Table},
-    EnvFlags, MdbxCursor, MdbxEnv, MdbxTx,
+use crate::{
+    error::{decode_err, ensure, Error},
+    kv::{
+        decode_one,
+        mem::{MemDb, MemTx},
+        metrics,
+        trace::db_span,
+        traits::{
+            DbName, DefaultFlags, DupSort, KvCursor, KvTx, KvTxMut, Mode, Table, TableDecode,
+            TableDecodeBorrowed, TableEncode,
+        },
+        EnvFlags, MdbxEnv, MdbxEnvBuilder, MdbxTx, Snapshot, TableCursor, TableStat,
+    },
+    Result,
 };
+use bytes::Bytes;
 use ethereum_types::{Address, H256, U256};
-use eyre::{eyre, Result};
+use eyre::eyre;
 use mdbx::{TransactionKind, RO, RW};
+use std::{collections::BTreeMap, iter::Peekable, marker::PhantomData};
 
+mod activity;
+pub mod analytics;
+mod audit;
+pub mod bitmap;
+mod chain;
+pub mod compare;
+mod db;
+mod diff;
+#[cfg(feature = "evm")]
+pub mod evm;
+pub mod export;
+pub mod fixtures;
+mod follow;
+mod genesis;
+mod history;
+pub mod json;
 mod macros;
 pub mod models;
+#[cfg(feature = "otterscan")]
+mod ots;
+pub mod progress;
+mod prune;
+mod rewards;
+mod sample;
+pub mod slots;
+mod stream;
 pub mod tables;
-mod utils;
+mod tiered;
+#[cfg(feature = "evm")]
+pub mod tracer;
+pub mod trie;
+mod txlookup;
+mod unwind;
+pub(crate) mod utils;
+pub mod writer;
 
 use utils::consts as C;
 
+pub use activity::{AccountActivity, Appearance, AppearanceReason};
+pub use audit::{AuditCheck, AuditFlags, AuditIssue, AuditReport, SenderMismatch};
+pub use chain::Chain;
+pub use db::{ErigonDb, RetryPolicy, DEFAULT_MAX_READERS};
+pub use diff::{AccountDiff, StateDiff, StorageDiff};
+pub use follow::{ChainEvent, Follow, Reorg, FOLLOW_DEPTH};
+pub use genesis::GenesisAccount;
+pub use prune::{PruneDistance, PruneMode, PruneProgress};
+pub use rewards::{static_reward, BlockReward};
+pub use sample::{SizeStats, TableSample};
+pub use stream::{AccountWalker, TxStream};
+pub use tiered::{TieredErigon, TieredTx};
+pub use unwind::UNWOUND_STAGES;
+
 use models::*;
 use tables::*;
 
-pub const NUM_TABLES: usize = 50;
+/// The default limit on the number of named tables an env can open, mdbx's
+/// `max_dbs`. Erigon adds tables between versions, so this is set well above
+/// the number of tables declared here. Opening more tables than the limit
+/// fails with MDBX_DBS_FULL; use [`env_open_with_max_dbs`] to raise it.
+pub const NUM_TABLES: usize = 256;
+/// The major versions of Erigon's db schema whose layout this crate reads.
+/// 5.0 added the system txs around each block's txs in BlockTransaction, and
+/// 6.0 moved the txs of non-canonical blocks out to their own table, which
+/// doesn't change how canonical blocks are read.
+pub const SUPPORTED_DB_VERSIONS: std::ops::RangeInclusive<u32> = 5..=6;
 // https://github.com/ledgerwatch/erigon-lib/blob/625c9f5385d209dc2abfadedf6e4b3914a26ed3e/kv/mdbx/kv_mdbx.go#L154
 pub const ENV_FLAGS: EnvFlags = EnvFlags {
     no_rdahead: true,
@@ -33,42 +100,305 @@ pub fn env_open<M: Mode>(path: &std::path::Path) -> Result<MdbxEnv<M>> {
     MdbxEnv::<M>::open(path, NUM_TABLES, ENV_FLAGS)
 }
 
-/// Erigon wraps an `MdbxTx` and provides Erigon-specific access methods.
-pub struct Erigon<'env, K: TransactionKind>(pub MdbxTx<'env, K>);
+/// Returns an env builder preconfigured with Erigon-specific configuration.
+pub fn env_builder<M: Mode>() -> MdbxEnvBuilder<M> {
+    MdbxEnv::builder().max_dbs(NUM_TABLES).flags(ENV_FLAGS)
+}
+
+/// Open an mdbx env with Erigon-specific configuration and room for up to
+/// `max_dbs` named tables.
+pub fn env_open_with_max_dbs<M: Mode>(
+    path: &std::path::Path,
+    max_dbs: usize,
+) -> Result<MdbxEnv<M>> {
+    MdbxEnv::<M>::open(path, max_dbs, ENV_FLAGS)
+}
+
+/// Open an mdbx env with Erigon-specific configuration, but with readahead
+/// enabled. Better suited to processes that mostly walk tables end to end,
+/// e.g. exports and analytics, than to point lookups.
+pub fn env_open_for_scans<M: Mode>(path: &std::path::Path) -> Result<MdbxEnv<M>> {
+    MdbxEnv::<M>::open(path, NUM_TABLES, ENV_FLAGS.readahead(true))
+}
+
+// Maps a read from a table that doesn't exist to `None`.
+fn none_if_no_table<T>(res: Result<Option<T>>) -> Result<Option<T>> {
+    match res {
+        Err(Error::TableNotFound(_)) => Ok(None),
+        res => res,
+    }
+}
+
+// Whether `e` is mdbx running out of map space.
+fn is_map_full(e: &Error) -> bool {
+    matches!(e, Error::Mdbx(mdbx::Error::MapFull))
+}
+
+// Skip 1 system tx at the beginning of the block and 1 at the end
+// https://github.com/ledgerwatch/erigon/blob/f56d4c5881822e70f65927ade76ef05bfacb1df4/core/rawdb/accessors_chain.go#L602-L605
+// https://github.com/ledgerwatch/erigon-lib/blob/625c9f5385d209dc2abfadedf6e4b3914a26ed3e/kv/tables.go#L28
+pub(crate) fn strip_system_txs(
+    mut body: BodyForStorage,
+    key: impl std::fmt::Debug,
+) -> Result<BodyForStorage> {
+    body.base_tx_id += 1;
+    body.tx_amount = body.tx_amount.checked_sub(2).ok_or_else(|| {
+        eyre!(
+            "Block body has too few txs: {}. Key: {:?}",
+            body.tx_amount,
+            key,
+        )
+    })?;
+    Ok(body)
+}
+
+/// Erigon wraps a transaction against a KV backend and provides Erigon-specific
+/// access methods. The backend defaults to mdbx.
+pub struct Erigon<'env, K: TransactionKind, Tx = MdbxTx<'env, K>>(
+    pub Tx,
+    PhantomData<(&'env (), K)>,
+);
 
 impl<'env> Erigon<'env, RO> {
     pub fn begin(env: &'env MdbxEnv<RO>) -> Result<Self> {
-        env.begin().map(Self)
+        env.begin().map(Self::new)
     }
 }
 impl<'env> Erigon<'env, RW> {
     pub fn begin_rw(env: &'env MdbxEnv<RW>) -> Result<Self> {
-        env.begin_rw().map(Self)
+        env.begin_rw().map(Self::new)
+    }
+
+    /// Creates an mdbx env at `path` holding an empty table for every table in
+    /// [`all_tables`](crate::kv::tables::all_tables), with the flags Erigon
+    /// creates them with. Tables that already exist are left untouched.
+    pub fn init_database(path: &std::path::Path) -> Result<MdbxEnv<RW>> {
+        std::fs::create_dir_all(path)?;
+        let env = env_open::<RW>(path)?;
+        let tx = env.begin_rw()?;
+        // a db is dupsorted if any of the table types stored in it are, e.g.
+        // PlainState holds both accounts and dupsorted storage
+        let mut flags = BTreeMap::new();
+        for table in crate::kv::tables::all_tables() {
+            *flags
+                .entry(table.db_name)
+                .or_insert_with(mdbx::DatabaseFlags::empty) |= table.flags;
+        }
+        for (name, flags) in flags {
+            tx.create_table(name, flags)?;
+        }
+        tx.commit()?;
+        Ok(env)
+    }
+
+    /// Passes the items of `iter` to `f` in chunks of `chunk_size`, each in
+    /// its own read-write transaction that is committed before the next
+    /// begins, which bounds the dirty pages a long write holds in memory.
+    /// `progress` is called with the number of items committed so far after
+    /// each chunk. Returns the total number of items committed.
+    ///
+    /// If a chunk fills the memory map (MDBX_MAP_FULL), its transaction is
+    /// aborted, the map's upper bound is doubled, and the chunk is retried,
+    /// so `f` must be safe to run more than once on the same chunk.
+    pub fn update_batched<I, F, P>(
+        env: &'env MdbxEnv<RW>,
+        chunk_size: usize,
+        iter: I,
+        mut f: F,
+        mut progress: P,
+    ) -> Result<usize>
+    where
+        I: IntoIterator,
+        F: FnMut(&Erigon<'env, RW>, &[I::Item]) -> Result<()>,
+        P: FnMut(usize),
+    {
+        let mut iter = iter.into_iter();
+        let mut done = 0;
+        loop {
+            let chunk = iter.by_ref().take(chunk_size.max(1)).collect::<Vec<_>>();
+            if chunk.is_empty() {
+                return Ok(done);
+            }
+            loop {
+                let res = Erigon::begin_rw(env).and_then(|tx| {
+                    f(&tx, &chunk)?;
+                    tx.commit()
+                });
+                match res {
+                    Ok(_) => break,
+                    Err(e) if is_map_full(&e) => {
+                        env.set_max_map_size(env.info()?.map_size.saturating_mul(2))?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            done += chunk.len();
+            progress(done);
+        }
+    }
+}
+impl<'env> Erigon<'env, RO, Snapshot<'env>> {
+    /// Begins a read-only transaction from an env of either mode. See
+    /// [`Snapshot`].
+    pub fn begin_snapshot<M: Mode>(env: &'env MdbxEnv<M>) -> Result<Self> {
+        env.begin_ro().map(Self::new)
+    }
+}
+impl<'env> Erigon<'env, RO, MemTx<'env, RO>> {
+    pub fn begin_mem(db: &'env MemDb) -> Result<Self> {
+        db.begin().map(Self::new)
     }
 }
-impl<'env, K: TransactionKind> Erigon<'env, K> {
-    pub fn new(inner: MdbxTx<'env, K>) -> Self {
-        Self(inner)
+impl<'env> Erigon<'env, RW, MemTx<'env, RW>> {
+    pub fn begin_mem_rw(db: &'env MemDb) -> Result<Self> {
+        db.begin_rw().map(Self::new)
+    }
+}
+impl<'env, K: TransactionKind, Tx> Erigon<'env, K, Tx> {
+    pub fn new(inner: Tx) -> Self {
+        Self(inner, PhantomData)
     }
 }
 
 impl<'env, K: Mode> Erigon<'env, K> {
+    /// Returns mdbx's statistics (entries, pages, size in bytes) for every
+    /// table in the database, keyed by table name.
+    pub fn table_sizes(&self) -> Result<BTreeMap<String, TableStat>> {
+        self.0
+            .table_names()?
+            .into_iter()
+            .map(|name| {
+                let stat = self.0.table_stat(&name)?;
+                Ok((name, stat))
+            })
+            .collect()
+    }
+
+    /// Returns up to `limit` undecoded (key, value) pairs from the named table,
+    /// skipping the first `offset` entries.
+    pub fn dump_table_by_name(
+        &self,
+        name: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.0.dump_named(name, limit, offset)
+    }
+
+    /// Returns up to `limit` undecoded (key, value) pairs from the table,
+    /// skipping the first `offset` entries.
+    pub fn dump_table<'tx, T>(
+        &'tx self,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        self.0
+            .dump_raw(self.0.open_db::<T::Name, T::Flags>()?, limit, offset)
+    }
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
     /// Opens and reads from the db table with the table's default flags
     pub fn read<'tx, T>(&'tx self, key: T::Key) -> Result<Option<T::Value>>
     where
         T: Table<'tx> + DefaultFlags,
     {
-        self.0.get::<T, T::Flags>(self.0.open_db()?, key)
+        let key = key.encode();
+        db_span!("read", <T::Name as DbName>::NAME, key.as_ref());
+        metrics::timed_read(<T::Name as DbName>::NAME, || {
+            self.0
+                .get_raw::<T>(key.as_ref())?
+                .map(decode_one::<T>)
+                .transpose()
+        })
+    }
+    /// Like [`Erigon::read`], but decodes the value as `V`, which may borrow
+    /// from the db rather than copy out of it, e.g. `Cow<[u8]>` for the raw
+    /// value.
+    pub fn read_borrowed<'tx, T, V>(&'tx self, key: T::Key) -> Result<Option<V>>
+    where
+        T: Table<'tx> + DefaultFlags,
+        V: TableDecodeBorrowed<'tx>,
+    {
+        let key = key.encode();
+        db_span!("read", <T::Name as DbName>::NAME, key.as_ref());
+        metrics::timed_read(<T::Name as DbName>::NAME, || {
+            let res = self
+                .0
+                .get_raw::<T>(key.as_ref())?
+                .map(V::decode_borrowed)
+                .transpose()
+                .map_err(|e| decode_err(<T::Name as DbName>::NAME, Some(key.as_ref()), e));
+            metrics::check_decode(<T::Name as DbName>::NAME, res)
+        })
+    }
+    /// Reads the value at each of `keys`, returned in the same order as the
+    /// keys. The keys are sorted and looked up with a single cursor, which is
+    /// much faster than a [`Erigon::read`] per key when there are many.
+    pub fn multi_read<'tx, T, I>(&'tx self, keys: I) -> Result<Vec<Option<T::Value>>>
+    where
+        T: Table<'tx> + DefaultFlags,
+        I: IntoIterator<Item = T::Key>,
+    {
+        let keys = keys.into_iter().map(|k| k.encode()).collect::<Vec<_>>();
+        db_span!("multi_read", <T::Name as DbName>::NAME);
+        let mut order = (0..keys.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| keys[a].as_ref().cmp(keys[b].as_ref()));
+
+        let mut vals = keys.iter().map(|_| None).collect::<Vec<_>>();
+        let mut cur = self.0.cursor_raw::<T>()?;
+        for i in order {
+            vals[i] = metrics::timed_read(<T::Name as DbName>::NAME, || {
+                cur.seek_exact(keys[i].as_ref())?
+                    .map(|(_, v)| decode_one::<T>(v))
+                    .transpose()
+            })?;
+        }
+        Ok(vals)
     }
     /// Opens a table with the table's default flags and creates a cursor into
     /// the opened table.
-    pub fn cursor<'tx, T>(&'tx self) -> Result<MdbxCursor<'tx, K, T>>
+    pub fn cursor<'tx, T>(&'tx self) -> Result<TableCursor<'tx, Tx::Cursor<'tx>, T>>
     where
         T: Table<'tx> + DefaultFlags,
     {
-        self.0.cursor::<T, T::Flags>(self.0.open_db()?)
+        db_span!("cursor", <T::Name as DbName>::NAME);
+        self.0.cursor_raw::<T>().map(TableCursor::new)
+    }
+    /// Returns the first (key, value) pair of table `T`, e.g. the earliest
+    /// block still in a table Erigon prunes from the front.
+    pub fn first<'tx, T>(&'tx self) -> Result<Option<(T::Key, T::Value)>>
+    where
+        T: Table<'tx> + DefaultFlags,
+        T::Key: TableDecode,
+    {
+        self.cursor::<T>()?.first()
+    }
+    /// Returns the last (key, value) pair of table `T`, e.g. the latest
+    /// block written to it. For dupsorted tables, the value is the last
+    /// duplicate at the last key.
+    pub fn last<'tx, T>(&'tx self) -> Result<Option<(T::Key, T::Value)>>
+    where
+        T: Table<'tx> + DefaultFlags,
+        T::Key: TableDecode,
+    {
+        self.cursor::<T>()?.last()
+    }
+    /// Returns the undecoded value stored at `key` in the named table. An
+    /// escape hatch for tables that have no typed definition in this crate.
+    pub fn read_raw(&self, table_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        db_span!("read", table_name, key);
+        Ok(self.0.get_named(table_name, key)?.map(|v| v.into_owned()))
+    }
+    /// Creates a cursor into the named table that returns undecoded (key,
+    /// value) pairs.
+    pub fn cursor_raw(&self, table_name: &str) -> Result<Tx::Cursor<'_>> {
+        db_span!("cursor", table_name);
+        self.0.cursor_named(table_name)
     }
-
     /// Returns the hash of the current canonical head header.
     pub fn read_head_header_hash(&self) -> Result<Option<H256>> {
         self.read::<LastHeader>(LastHeaderKey)
@@ -79,6 +409,50 @@ impl<'env, K: Mode> Erigon<'env, K> {
         self.read::<LastBlock>(LastBlockKey)
     }
 
+    /// Returns the version of the db schema, which Erigon writes to the DbInfo
+    /// table when it creates or migrates the db. Returns `None` for dbs
+    /// without one, e.g. those created by [`Erigon::init_database`].
+    pub fn db_version(&self) -> Result<Option<DbVersion>> {
+        let key = Bytes::from_static(C::DB_SCHEMA_VERSION_KEY);
+        none_if_no_table(self.read_borrowed::<DbInfo, DbVersion>(key))
+    }
+
+    /// Returns the version of the PlainState layout that older versions of
+    /// Erigon recorded in the DbInfo table, if any.
+    pub fn plain_state_version(&self) -> Result<Option<u64>> {
+        let key = Bytes::from_static(C::PLAIN_STATE_VERSION_KEY);
+        let val = match none_if_no_table(self.read::<DbInfo>(key))? {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        if val.len() > 8 {
+            return Err(Error::Incompatible(format!(
+                "PlainStateVersion is {} bytes long",
+                val.len()
+            ))
+            .into());
+        }
+        Ok(Some(val.iter().fold(0, |n, b| n << 8 | *b as u64)))
+    }
+
+    /// Fails with [`Error::Incompatible`] if the db's schema version isn't one
+    /// of [`SUPPORTED_DB_VERSIONS`], rather than letting its tables be
+    /// misdecoded. Dbs without a version pass.
+    pub fn check_db_version(&self) -> Result<()> {
+        match self.db_version()? {
+            Some(v) if !SUPPORTED_DB_VERSIONS.contains(&v.major) => {
+                Err(Error::Incompatible(format!(
+                    "db schema version {} is not supported, expected major version {}-{}",
+                    v,
+                    SUPPORTED_DB_VERSIONS.start(),
+                    SUPPORTED_DB_VERSIONS.end(),
+                ))
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Returns the incarnation of the account when it was last deleted.
     pub fn read_incarnation(&self, adr: Address) -> Result<Option<Incarnation>> {
         self.read::<IncarnationMap>(adr)
@@ -89,7 +463,30 @@ impl<'env, K: Mode> Erigon<'env, K> {
         self.read::<PlainState>(adr)
     }
 
+    /// Returns an iterator over all of the accounts in the PlainState table,
+    /// beginning at the smallest address >= `start`. Storage entries, which
+    /// share the PlainState table, are skipped.
+    pub fn walk_accounts<'tx>(
+        &'tx self,
+        start: Option<Address>,
+    ) -> Result<AccountWalker<'tx, Tx::Cursor<'tx>>> {
+        let start = start.unwrap_or_default().encode().to_vec();
+        Ok(AccountWalker::new(self.cursor()?, start, Vec::new()))
+    }
+
+    /// Returns an iterator over all of the accounts in the PlainState table
+    /// whose address begins with the given bytes.
+    pub fn walk_accounts_with_prefix<'tx>(
+        &'tx self,
+        prefix: impl AsRef<[u8]>,
+    ) -> Result<AccountWalker<'tx, Tx::Cursor<'tx>>> {
+        let prefix = prefix.as_ref().to_vec();
+        Ok(AccountWalker::new(self.cursor()?, prefix.clone(), prefix))
+    }
+
     /// Returns the number of the block containing the specified transaction.
+    /// Returns `None` as well for txs whose lookup entry Erigon pruned, see
+    /// [`PruneMode::tx_index`].
     pub fn read_transaction_block_number(&self, hash: H256) -> Result<Option<U256>> {
         self.read::<BlockTransactionLookup>(hash)
     }
@@ -99,6 +496,25 @@ impl<'env, K: Mode> Erigon<'env, K> {
         self.read::<Header>(key.into())
     }
 
+    /// Returns every header stored for block `num`, canonical or not, with
+    /// its hash. Erigon keeps the headers of forks it has seen until they
+    /// are pruned, so this includes reorged-out blocks still in the db.
+    pub fn read_headers_at(&self, num: impl Into<BlockNumber>) -> Result<Vec<(H256, BlockHeader)>> {
+        let num = num.into();
+        let mut headers = Vec::new();
+        self.cursor::<Header>()?.walk_while(
+            HeaderKey(num, H256::zero()),
+            |HeaderKey(n, hash), header| {
+                if *n != num {
+                    return std::ops::ControlFlow::Break(());
+                }
+                headers.push((*hash, header.clone()));
+                std::ops::ControlFlow::Continue(())
+            },
+        )?;
+        Ok(headers)
+    }
+
     /// Returns header total difficulty
     pub fn read_total_difficulty(
         &self,
@@ -114,23 +530,53 @@ impl<'env, K: Mode> Erigon<'env, K> {
     ) -> Result<Option<BodyForStorage>> {
         let key = key.into();
         self.read::<BlockBody>(key)?
-            .map(|mut body| {
-                // Skip 1 system tx at the beginning of the block and 1 at the end
-                // https://github.com/ledgerwatch/erigon/blob/f56d4c5881822e70f65927ade76ef05bfacb1df4/core/rawdb/accessors_chain.go#L602-L605
-                // https://github.com/ledgerwatch/erigon-lib/blob/625c9f5385d209dc2abfadedf6e4b3914a26ed3e/kv/tables.go#L28
-                body.base_tx_id += 1;
-                body.tx_amount = body.tx_amount.checked_sub(2).ok_or_else(|| {
-                    eyre!(
-                        "Block body has too few txs: {}. HeaderKey: {:?}",
-                        body.tx_amount,
-                        key,
-                    )
-                })?;
-                Ok(body)
-            })
+            .map(|body| strip_system_txs(body, key))
             .transpose()
     }
 
+    /// Returns the uncle headers of the block, empty if it has none or no
+    /// body.
+    pub fn read_uncles(&self, key: impl Into<HeaderKey>) -> Result<Vec<BlockHeader>> {
+        Ok(self
+            .read_body_for_storage(key)?
+            .map(|body| body.uncles)
+            .unwrap_or_default())
+    }
+
+    /// Returns the withdrawals of the block, `None` if it has no body or
+    /// predates Shanghai.
+    pub fn read_withdrawals(&self, key: impl Into<HeaderKey>) -> Result<Option<Vec<Withdrawal>>> {
+        Ok(self
+            .read_body_for_storage(key)?
+            .and_then(|body| body.withdrawals))
+    }
+
+    /// Returns the withdrawals credited to `address` in the canonical blocks
+    /// in `blocks`, with their block numbers, and their total in wei.
+    /// Withdrawal amounts are stored in gwei.
+    pub fn withdrawals_for(
+        &self,
+        address: Address,
+        blocks: std::ops::Range<u64>,
+    ) -> Result<(Vec<(BlockNumber, Withdrawal)>, U256)> {
+        let mut found = Vec::new();
+        let mut total = U256::zero();
+        let range = BlockNumber(blocks.start)..BlockNumber(blocks.end);
+        for read in self.cursor::<CanonicalHeader>()?.walk_range(range)? {
+            let (num, hash) = read?;
+            for w in self
+                .read_withdrawals(HeaderKey(num, hash))?
+                .unwrap_or_default()
+            {
+                if w.address == address {
+                    total += U256::from(w.amount) * C::GWEI;
+                    found.push((num, w));
+                }
+            }
+        }
+        Ok((found, total))
+    }
+
     /// Returns the header number assigned to a hash.
     pub fn read_header_number(&self, hash: H256) -> Result<Option<BlockNumber>> {
         self.read::<HeaderNumber>(hash)
@@ -138,24 +584,258 @@ impl<'env, K: Mode> Erigon<'env, K> {
 
     /// Returns the number of the current canonical block header.
     pub fn read_head_block_number(&self) -> Result<Option<BlockNumber>> {
-        let hash = self.read_head_header_hash()?.ok_or(eyre!("No value"))?;
+        let hash = self.read_head_header_hash()?.ok_or(Error::MissingValue {
+            what: "head header hash",
+        })?;
         self.read_header_number(hash)
     }
 
+    /// Returns the block the named sync stage, e.g. "Execution", has
+    /// processed up to.
+    pub fn read_stage_progress(&self, stage: &str) -> Result<Option<BlockNumber>> {
+        self.read::<SyncStage>(Bytes::copy_from_slice(stage.as_bytes()))
+    }
+
     /// Returns the signers of each transaction in the block.
     pub fn read_senders(&self, key: impl Into<HeaderKey>) -> Result<Option<Vec<Address>>> {
         self.read::<TxSender>(key.into())
     }
 
+    /// Returns the transactions in the block, as stored in the BlockTransaction table.
+    pub fn read_transactions(&self, key: impl Into<HeaderKey>) -> Result<Option<Vec<Transaction>>> {
+        let body = match self.read_body_for_storage(key)? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        self.cursor::<BlockTransaction>()?
+            .walk(body.base_tx_id.into())?
+            .take(body.tx_amount as usize)
+            .map(|res| res.map(|(_, tx)| tx))
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+
+    /// Finds a canonical transaction by its hash, returning the key of the block
+    /// containing it, its index in the block, and the transaction itself.
+    pub fn read_transaction_by_hash(
+        &self,
+        hash: H256,
+    ) -> Result<Option<(HeaderKey, u32, Transaction)>> {
+        let num = match self.read_transaction_block_number(hash)? {
+            Some(num) => BlockNumber(num.as_u64()),
+            None => return Ok(None),
+        };
+        let key = match self.read_canonical_hash(num)? {
+            Some(block_hash) => HeaderKey(num, block_hash),
+            None => return Ok(None),
+        };
+        let txs = self.read_transactions(key)?.unwrap_or_default();
+        Ok(txs
+            .into_iter()
+            .enumerate()
+            .find(|(_, tx)| tx.tx_hash() == hash)
+            .map(|(idx, tx)| (key, idx as u32, tx)))
+    }
+
+    /// Returns the logs emitted by each transaction in the block, keyed by the
+    /// index of the transaction in the block. Transactions that emitted no logs
+    /// are omitted. Fails with [`Error::Pruned`] if Erigon pruned the block's
+    /// receipts.
+    pub fn read_logs(&self, num: impl Into<BlockNumber>) -> Result<Vec<(u32, Vec<CborLog>)>> {
+        let num = num.into();
+        self.ensure_not_pruned::<TransactionLog>(C::PRUNE_RECEIPTS_KEY, "Execution", num)?;
+        let range = LogsKey(num, 0)..LogsKey(BlockNumber(num.0.saturating_add(1)), 0);
+        self.cursor::<TransactionLog>()?
+            .walk_range(range)?
+            .map(|read| {
+                let (LogsKey(_, idx), CborLogs(tx_logs)) = read?;
+                Ok((idx, tx_logs.unwrap_or_default()))
+            })
+            .collect()
+    }
+
+    /// Returns the keys of the canonical blocks in `blocks` whose header's logs
+    /// bloom may include a log from one of `addresses` with `topics`, as
+    /// checked by [`bloom_matches`]. Only headers are read, so this is a cheap
+    /// first pass before reading the logs of the candidates with
+    /// [`Erigon::read_logs`], which may still hold no matching log. Blocks
+    /// whose header is missing are skipped.
+    pub fn blocks_matching_bloom(
+        &self,
+        blocks: std::ops::Range<u64>,
+        addresses: &[Address],
+        topics: &[Vec<H256>],
+    ) -> Result<Vec<HeaderKey>> {
+        let mut candidates = Vec::new();
+        let range = BlockNumber(blocks.start)..BlockNumber(blocks.end);
+        for read in self.cursor::<CanonicalHeader>()?.walk_range(range)? {
+            let (num, hash) = read?;
+            let key = HeaderKey(num, hash);
+            match self.read_header(key)? {
+                Some(header) if bloom_matches(&header.bloom, addresses, topics) => {
+                    candidates.push(key)
+                }
+                _ => continue,
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Returns the receipt of the state-sync transaction bor appends to the
+    /// block, if it has one.
+    #[cfg(feature = "polygon")]
+    pub fn read_bor_receipt(&self, num: impl Into<BlockNumber>) -> Result<Option<BorReceipt>> {
+        self.read::<BorReceipts>(num.into())
+    }
+
+    /// Returns the number of the block containing the specified state-sync
+    /// transaction.
+    #[cfg(feature = "polygon")]
+    pub fn read_bor_transaction_block_number(&self, hash: H256) -> Result<Option<U256>> {
+        self.read::<BlockBorTransactionLookup>(hash)
+    }
+
+    /// Returns the heimdall span with the given id.
+    #[cfg(feature = "polygon")]
+    pub fn read_span(&self, span_id: impl Into<SpanId>) -> Result<Option<Span>> {
+        self.read::<BorSpans>(span_id.into())
+    }
+
+    /// Returns the heimdall checkpoint with the given id.
+    #[cfg(feature = "polygon")]
+    pub fn read_checkpoint(&self, id: impl Into<CheckpointId>) -> Result<Option<Checkpoint>> {
+        self.read::<BorCheckpoints>(id.into())
+    }
+
+    /// Returns the root of the canonical beacon block at `slot`. Like the
+    /// other Caplin reads, this must be made on Caplin's db.
+    #[cfg(feature = "caplin")]
+    pub fn read_canonical_block_root(&self, slot: impl Into<Slot>) -> Result<Option<H256>> {
+        self.read::<CanonicalBlockRoots>(slot.into())
+    }
+
+    /// Returns the slot of the beacon block with root `root`.
+    #[cfg(feature = "caplin")]
+    pub fn read_beacon_slot(&self, root: H256) -> Result<Option<Slot>> {
+        self.read::<BlockRootToSlot>(root)
+    }
+
+    /// Returns the root of the parent of the beacon block with root `root`.
+    #[cfg(feature = "caplin")]
+    pub fn read_beacon_parent_root(&self, root: H256) -> Result<Option<H256>> {
+        self.read::<BlockRootToParentRoot>(root)
+    }
+
+    /// Returns the state root of the beacon block with root `root`.
+    #[cfg(feature = "caplin")]
+    pub fn read_beacon_state_root(&self, root: H256) -> Result<Option<H256>> {
+        self.read::<BlockRootToStateRoot>(root)
+    }
+
+    /// Returns the key of the execution block in the payload of the beacon
+    /// block with root `root`, to be read from chaindata.
+    #[cfg(feature = "caplin")]
+    pub fn read_beacon_execution_block(&self, root: H256) -> Result<Option<HeaderKey>> {
+        let num = match self.read::<BlockRootToBlockNumber>(root)? {
+            Some(num) => BlockNumber(*num),
+            None => return Ok(None),
+        };
+        Ok(self
+            .read::<BlockRootToBlockHash>(root)?
+            .map(|hash| HeaderKey(num, hash)))
+    }
+
+    /// Recovers the signers of each transaction in the block from the transaction
+    /// signatures. Useful when the Senders stage has not yet processed the block
+    /// and `read_senders` returns `None`.
+    #[cfg(feature = "secp256k1")]
+    pub fn recover_senders(&self, key: impl Into<HeaderKey>) -> Result<Option<Vec<Address>>> {
+        self.read_transactions(key)?
+            .map(|txs| txs.iter().map(Transaction::recover_sender).collect())
+            .transpose()
+    }
+
     /// Returns the hash assigned to a canonical block number.
     pub fn read_canonical_hash(&self, num: impl Into<BlockNumber>) -> Result<Option<H256>> {
         self.read::<CanonicalHeader>(num.into())
     }
 
+    /// Returns the header of the canonical block numbered `num`.
+    pub fn read_canonical_header(
+        &self,
+        num: impl Into<BlockNumber>,
+    ) -> Result<Option<BlockHeader>> {
+        let num = num.into();
+        match self.read_canonical_hash(num)? {
+            Some(hash) => self.read_header(HeaderKey(num, hash)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the newest canonical block with a timestamp at or before
+    /// `ts`, i.e. the block that was live at time `ts`, by binary search over
+    /// the canonical headers. Errors if `ts` is before the genesis block.
+    pub fn block_by_timestamp(&self, ts: u64) -> Result<BlockNumber> {
+        let time = |num: u64| -> Result<u64> {
+            let header = self.read_canonical_header(num)?;
+            Ok(header
+                .ok_or(Error::MissingValue {
+                    what: "canonical header",
+                })?
+                .time)
+        };
+        let (last, _) = self.last::<CanonicalHeader>()?.ok_or(Error::MissingValue {
+            what: "canonical header",
+        })?;
+        let genesis = time(0)?;
+        ensure!(
+            genesis <= ts,
+            "Timestamp {} is before the genesis block at {}",
+            ts,
+            genesis
+        );
+        // time(lo) <= ts, and every block after hi is after ts
+        let (mut lo, mut hi) = (0, *last);
+        while lo < hi {
+            let mid = lo + (hi - lo + 1) / 2;
+            if time(mid)? <= ts {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        Ok(BlockNumber(lo))
+    }
+
+    /// Checks each canonical header in `blocks` against its parent with
+    /// [`validate_parent`], failing on the first header that doesn't follow
+    /// or is missing.
+    pub fn validate_header_chain(&self, blocks: std::ops::Range<u64>) -> Result<()> {
+        let mut parent = None;
+        for num in blocks.start.saturating_sub(1)..blocks.end {
+            let header = self
+                .read_canonical_header(num)?
+                .ok_or(Error::MissingValue {
+                    what: "canonical header",
+                })?;
+            if let Some(parent) = &parent {
+                if num >= blocks.start {
+                    validate_parent(&header, parent)?;
+                }
+            }
+            parent = Some(header);
+        }
+        Ok(())
+    }
+
     /// Determines whether a header with the given hash is on the canonical chain.
     pub fn is_canonical_hash(&self, hash: H256) -> Result<bool> {
-        let num = self.read_header_number(hash)?.ok_or(eyre!("No value"))?;
-        let canon = self.read_canonical_hash(num)?.ok_or(eyre!("No value"))?;
+        let num = self.read_header_number(hash)?.ok_or(Error::MissingValue {
+            what: "header number",
+        })?;
+        let canon = self.read_canonical_hash(num)?.ok_or(Error::MissingValue {
+            what: "canonical hash",
+        })?;
         Ok(canon != Default::default() && canon == hash)
     }
 
@@ -168,8 +848,29 @@ impl<'env, K: Mode> Erigon<'env, K> {
     ) -> Result<Option<U256>> {
         let bucket = StorageKey(adr, inc.into());
         let mut cur = self.cursor::<Storage>()?;
-        cur.seek_dup(bucket, slot)
-            .map(|kv| kv.and_then(|(k, v)| if k == slot { Some(v) } else { None }))
+        Ok(cur.seek_both_exact(bucket, slot)?.map(|(_, v)| v))
+    }
+
+    /// Returns the values of `slots` in the storage of account `adr`, in the
+    /// order of `slots`. Like [`Self::multi_read`], the slots are sorted and
+    /// looked up with a single cursor, so the reads move forward through the
+    /// account's storage rather than each starting from the root.
+    pub fn read_storage_many(
+        &self,
+        adr: Address,
+        inc: impl Into<Incarnation>,
+        slots: &[H256],
+    ) -> Result<Vec<Option<U256>>> {
+        let bucket = StorageKey(adr, inc.into());
+        let mut order = (0..slots.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| slots[i]);
+
+        let mut vals = vec![None; slots.len()];
+        let mut cur = self.cursor::<Storage>()?;
+        for i in order {
+            vals[i] = cur.seek_both_exact(bucket, slots[i])?.map(|(_, v)| v);
+        }
+        Ok(vals)
     }
 
     /// Returns an iterator over all of the storage (key, value) pairs for the
@@ -185,6 +886,188 @@ impl<'env, K: Mode> Erigon<'env, K> {
         self.cursor::<Storage>()?.walk_dup(key, start_slot.unwrap_or_default())
     }
 
+    /// Returns the number of storage slots of the given account incarnation
+    /// in the PlainState table. With mdbx, the slots aren't read at all.
+    pub fn storage_slot_count(&self, adr: Address, inc: impl Into<Incarnation>) -> Result<u64> {
+        let key = StorageKey(adr, inc.into()).encode();
+        let mut cur = self.0.cursor_raw::<Storage>()?;
+        if cur.seek_exact(key.as_ref())?.is_none() {
+            return Ok(0);
+        }
+        cur.dup_count()
+    }
+
+    /// Returns the number of bytes the storage of the given account
+    /// incarnation takes in the PlainState table: the key once, and the
+    /// encoded (slot, value) pair of each slot. mdbx's page overhead isn't
+    /// included.
+    pub fn storage_size_bytes(&self, adr: Address, inc: impl Into<Incarnation>) -> Result<u64> {
+        let key = StorageKey(adr, inc.into()).encode();
+        let mut cur = self.0.cursor_raw::<Storage>()?;
+        let mut next = cur.seek_exact(key.as_ref())?;
+        if next.is_none() {
+            return Ok(0);
+        }
+        // the values are walked raw, without decoding
+        let mut size = key.as_ref().len() as u64;
+        while let Some((_, v)) = next {
+            size += v.len() as u64;
+            next = cur.next_dup()?;
+        }
+        Ok(size)
+    }
+
+    /// Returns the account stored under `keccak256(address)` in the HashedAccount table.
+    pub fn read_hashed_account(&self, hashed_adr: H256) -> Result<Option<Account>> {
+        self.read::<HashedAccount>(hashed_adr)
+    }
+
+    /// Returns an iterator over the (keccak256(address), account) pairs in the
+    /// HashedAccount table, beginning at the smallest key >= `start`.
+    pub fn walk_hashed_accounts(
+        &self,
+        start: Option<H256>,
+    ) -> Result<impl Iterator<Item = Result<(H256, Account)>>> {
+        self.cursor::<HashedAccount>()?
+            .walk(start.unwrap_or_default())
+    }
+
+    /// Returns the value of a storage slot from the HashedStorage table.
+    pub fn read_hashed_storage(&self, key: HashStorageKey) -> Result<Option<U256>> {
+        let (key, hashed_slot) = key.split();
+        let mut cur = self.cursor::<HashedStorage>()?;
+        Ok(cur.seek_both_exact(key, hashed_slot)?.map(|(_, v)| v))
+    }
+
+    /// Returns an iterator over all of the (keccak256(slot), value) pairs in the
+    /// HashedStorage table for the given hashed address and incarnation.
+    pub fn walk_hashed_storage(
+        &self,
+        hashed_adr: H256,
+        inc: impl Into<Incarnation>,
+    ) -> Result<impl Iterator<Item = Result<(H256, U256)>>> {
+        let key = HashedStorageKey(hashed_adr, inc.into());
+        self.cursor::<HashedStorage>()?
+            .walk_dup(key, Default::default())
+    }
+
+    /// Returns the codehash stored in the HashedCodeHash table.
+    pub fn read_hashed_codehash(&self, key: ContractCodeKey) -> Result<Option<H256>> {
+        self.read::<HashedCodeHash>(key)
+    }
+
+    /// Returns the node of the account trie at the given path from the
+    /// TrieAccount intermediate hash table.
+    pub fn read_trie_account_node(&self, prefix: impl Into<Nibbles>) -> Result<Option<TrieNode>> {
+        self.read::<TrieAccount>(prefix.into())
+    }
+
+    /// Returns the node of an account's storage trie at the given path from
+    /// the TrieStorage intermediate hash table.
+    pub fn read_trie_storage_node(
+        &self,
+        hashed_adr: H256,
+        inc: impl Into<Incarnation>,
+        prefix: impl Into<Nibbles>,
+    ) -> Result<Option<TrieNode>> {
+        self.read::<TrieStorage>(TrieStorageKey {
+            hashed_adr,
+            incarnation: inc.into(),
+            nibbles: prefix.into(),
+        })
+    }
+
+    /// Returns the state root as of the last run of Erigon's intermediate
+    /// hashes stage, which is stored in the root node of the account trie.
+    pub fn read_trie_root(&self) -> Result<Option<H256>> {
+        Ok(self
+            .read_trie_account_node(Nibbles::default())?
+            .and_then(|node| node.root_hash))
+    }
+
+    /// Returns the total amount of ether issued as of the given block.
+    pub fn read_issuance(&self, num: impl Into<BlockNumber>) -> Result<Option<U256>> {
+        self.read::<Issuance>(num.into())
+    }
+
+    /// Returns the total amount of ether burnt as of the given block.
+    pub fn read_burnt(&self, num: impl Into<BlockNumber>) -> Result<Option<U256>> {
+        self.read::<Burnt>(BurntKey(num.into()))
+    }
+
+    /// Returns an iterator over `(block number, total issued, total burnt)` for
+    /// the blocks in `[from, to]` with an entry in the Issuance table. A
+    /// block with only one of the two amounts reports the other as zero.
+    pub fn walk_issuance<'tx>(
+        &'tx self,
+        from: impl Into<BlockNumber>,
+        to: impl Into<BlockNumber>,
+    ) -> Result<impl Iterator<Item = Result<(BlockNumber, U256, U256)>> + 'tx> {
+        // Burnt amounts share the Issuance table under a "burnt" prefix, so
+        // each is walked with its own cursor and the two are merged by block.
+        type Amount = Result<(BlockNumber, U256)>;
+        // The block of the next entry, or its decode error.
+        fn head<I: Iterator<Item = Amount>>(it: &mut Peekable<I>) -> Result<Option<BlockNumber>> {
+            match it.peek() {
+                Some(Ok((num, _))) => Ok(Some(*num)),
+                Some(Err(_)) => Err(it.next().unwrap().unwrap_err()),
+                None => Ok(None),
+            }
+        }
+        // The amount of the next entry if it's for `num`, and zero otherwise.
+        fn take<I: Iterator<Item = Amount>>(it: &mut Peekable<I>, num: BlockNumber) -> U256 {
+            match it.next_if(|res| matches!(res, Ok((n, _)) if *n == num)) {
+                Some(Ok((_, amount))) => amount,
+                _ => U256::zero(),
+            }
+        }
+
+        let (from, to) = (from.into(), to.into());
+        let end = BlockNumber(to.0.saturating_add(1));
+        // block keys sort before the burnt ones, so the issued walk has to
+        // stop short of the prefix no matter how far `to` reaches
+        let burnt_start = BlockNumber(u64::from_be_bytes(*b"burnt\0\0\0"));
+        let mut issued = self
+            .cursor::<Issuance>()?
+            .walk_range(from..end.min(burnt_start))?
+            .peekable();
+        let mut burnt = self
+            .cursor::<Burnt>()?
+            .walk_range(BurntKey(from)..BurntKey(end))?
+            .map(|res| res.map(|(BurntKey(num), amount)| (num, amount)))
+            .peekable();
+        Ok(std::iter::from_fn(move || {
+            let next = (|| {
+                let num = match (head(&mut issued)?, head(&mut burnt)?) {
+                    (Some(i), Some(b)) => i.min(b),
+                    (Some(num), None) | (None, Some(num)) => num,
+                    (None, None) => return Ok(None),
+                };
+                Ok(Some((num, take(&mut issued, num), take(&mut burnt, num))))
+            })();
+            next.transpose()
+        }))
+    }
+
+    /// Returns the epoch transition proof stored for the block, used by
+    /// consensus engines with validator set transitions.
+    pub fn read_epoch(&self, key: impl Into<HeaderKey>) -> Result<Option<Bytes>> {
+        self.read::<Epoch>(key.into())
+    }
+
+    /// Returns the pending epoch transition proof stored for the block.
+    pub fn read_pending_epoch(&self, key: impl Into<HeaderKey>) -> Result<Option<Bytes>> {
+        self.read::<PendingEpoch>(key.into())
+    }
+
+    /// Returns the Clique signer snapshot stored for the block, if any.
+    pub fn read_clique_snapshot(
+        &self,
+        key: impl Into<HeaderKey>,
+    ) -> Result<Option<CliqueSnapshot>> {
+        self.read::<CliqueSeparate>(key.into())
+    }
+
     /// Returns the code associated with the given codehash.
     pub fn read_code(&self, codehash: H256) -> Result<Option<Bytecode>> {
         if codehash == C::EMPTY_HASH {
@@ -193,6 +1076,37 @@ impl<'env, K: Mode> Erigon<'env, K> {
         self.read::<Code>(codehash)
     }
 
+    /// Returns the length of the code associated with the given codehash,
+    /// without copying the code out of the db.
+    pub fn read_code_len(&self, codehash: H256) -> Result<Option<usize>> {
+        if codehash == C::EMPTY_HASH {
+            return Ok(Some(0));
+        }
+        db_span!("read_code_len", <Code as DbName>::NAME, codehash.as_bytes());
+        metrics::timed_read(<Code as DbName>::NAME, || {
+            Ok(self
+                .0
+                .get_raw::<Code>(codehash.as_bytes())?
+                .map(|code| code.len()))
+        })
+    }
+
+    /// Returns the size of the code currently deployed at `adr`, like
+    /// `EXTCODESIZE`. Accounts without code, and missing accounts, have size 0.
+    pub fn read_code_size(&self, adr: Address) -> Result<usize> {
+        let acct = match self.read_account(adr)? {
+            Some(acct) if acct.codehash != H256::zero() => acct,
+            _ => return Ok(0),
+        };
+        Ok(self.read_code_len(acct.codehash)?.unwrap_or_default())
+    }
+
+    /// Returns an iterator over all of the deployed code in the Code table,
+    /// as `(codehash, code)` pairs in order of codehash.
+    pub fn walk_code(&self) -> Result<impl Iterator<Item = Result<(H256, Bytecode)>> + '_> {
+        self.cursor::<Code>()?.walk(H256::zero())
+    }
+
     /// Returns the codehash at the `adr` with incarnation `inc`
     pub fn read_codehash(&self, adr: Address, inc: impl Into<Incarnation>) -> Result<Option<H256>> {
         let key = PlainCodeKey(adr, inc.into());
@@ -215,6 +1129,25 @@ impl<'env, K: Mode> Erigon<'env, K> {
             .walk(start_key.unwrap_or_default())
     }
 
+    /// Returns an iterator over every transaction in the canonical blocks
+    /// `[from, to]`, yielding `(block number, index in block, transaction, sender)`.
+    /// The BlockBody, BlockTransaction, and TxSender tables are walked in
+    /// lockstep with cursors rather than read block by block.
+    pub fn stream_transactions<'tx>(
+        &'tx self,
+        from: impl Into<BlockNumber>,
+        to: impl Into<BlockNumber>,
+    ) -> Result<TxStream<'tx, Tx::Cursor<'tx>>> {
+        Ok(TxStream::new(
+            self.cursor()?,
+            self.cursor()?,
+            self.cursor()?,
+            self.cursor()?,
+            from.into(),
+            to.into(),
+        ))
+    }
+
     // The `AccountChangeSet` table at block `N` stores the state of all accounts
     // changed in block `N` *before* block `N` changed them.
     //
@@ -250,29 +1183,179 @@ impl<'env, K: Mode> Erigon<'env, K> {
         block: impl Into<BlockNumber>,
     ) -> Result<Option<Account>> {
         let block = block.into();
+        self.ensure_not_pruned::<AccountHistory>(C::PRUNE_HISTORY_KEY, "Execution", block)?;
+        match self.next_account_change(adr, block)? {
+            Some(cs_block) => self.read_account_changeset(adr, cs_block),
+            None => Ok(None),
+        }
+    }
+
+    // Merges the shards of the bitmap of `adr` in `T`, a table of block
+    // bitmaps sharded by address like AccountHistory.
+    fn read_address_bitmap<'tx, T>(&'tx self, adr: Address) -> Result<roaring::RoaringTreemap>
+    where
+        T: Table<'tx, Key = AccountHistKey, Value = roaring::RoaringTreemap> + DefaultFlags,
+    {
+        bitmap::merge_shards(
+            self.cursor::<T>()?
+                .walk(AccountHistKey(adr, 0.into()))?
+                .take_while(|res| !matches!(res, Ok((AccountHistKey(k, _), _)) if *k != adr))
+                .map(|res| res.map(|(_, map)| map)),
+        )
+    }
+
+    // Returns the first block >= `block` in which the account changed. The
+    // seek lands on the first shard whose largest block is >= `block`, but
+    // the next change can still be in a later shard of the same account, so
+    // the shards are walked until one has a block >= `block`.
+    fn next_account_change(&self, adr: Address, block: BlockNumber) -> Result<Option<BlockNumber>> {
         let mut hist_cur = self.cursor::<AccountHistory>()?;
-        let (_, bitmap) = hist_cur
-            .seek((adr, block).into())?
-            .ok_or(eyre!("No value"))?;
-        let cs_block = match utils::find_gte(bitmap, *block) {
-            Some(changeset) => BlockNumber(changeset),
-            _ => return Ok(None),
-        };
-        let mut cs_cur = self.cursor::<AccountChangeSet>()?;
-        if let Some(AccountCSVal(k, mut acct)) = cs_cur.seek_dup(cs_block, adr)? {
-            if k == adr {
-                // recover the codehash
-                if *acct.incarnation > 0 && acct.codehash == Default::default() {
-                    acct.codehash = self
-                        .read_codehash(adr, acct.incarnation)?
-                        .ok_or(eyre!("No value"))?
-                }
-                return Ok(Some(acct));
+        let mut shard = hist_cur.seek_range((adr, block).into())?;
+        while let Some((AccountHistKey(k, _), map)) = shard {
+            if k != adr {
+                break;
+            }
+            if let Some(cs_block) = bitmap::seek_in_bitmap(&map, *block) {
+                return Ok(Some(BlockNumber(cs_block)));
+            }
+            shard = hist_cur.next()?;
+        }
+        Ok(None)
+    }
+
+    // Like `next_account_change`, for a storage slot.
+    fn next_storage_change(
+        &self,
+        adr: Address,
+        slot: H256,
+        block: BlockNumber,
+    ) -> Result<Option<BlockNumber>> {
+        let mut hist_cur = self.cursor::<StorageHistory>()?;
+        let mut shard = hist_cur.seek_range((adr, slot, block).into())?;
+        while let Some((StorageHistKey(k, s, _), map)) = shard {
+            if k != adr || s != slot {
+                break;
             }
+            if let Some(cs_block) = bitmap::seek_in_bitmap(&map, *block) {
+                return Ok(Some(BlockNumber(cs_block)));
+            }
+            shard = hist_cur.next()?;
         }
         Ok(None)
     }
 
+    /// Returns the state of account `adr` immediately before it was changed in
+    /// block `block`, as stored in the AccountChangeSet table.
+    pub fn read_account_changeset(
+        &self,
+        adr: Address,
+        block: impl Into<BlockNumber>,
+    ) -> Result<Option<Account>> {
+        let mut cs_cur = self.cursor::<AccountChangeSet>()?;
+        match cs_cur.seek_both_exact(block.into(), adr)? {
+            Some(AccountCSVal(_, acct)) => self.recover_codehash(adr, acct).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Changeset entries omit the codehash of contract accounts, so we recover
+    // it from the PlainCodeHash table.
+    fn recover_codehash(&self, adr: Address, mut acct: Account) -> Result<Account> {
+        if *acct.incarnation > 0 && acct.codehash == Default::default() {
+            acct.codehash = self
+                .read_codehash(adr, acct.incarnation)?
+                .ok_or(Error::MissingValue { what: "codehash" })?
+        }
+        Ok(acct)
+    }
+
+    /// Returns the value of the storage slot immediately before it was changed
+    /// in block `block`, as stored in the StorageChangeSet table.
+    pub fn read_storage_changeset(
+        &self,
+        adr: Address,
+        inc: impl Into<Incarnation>,
+        slot: H256,
+        block: impl Into<BlockNumber>,
+    ) -> Result<Option<U256>> {
+        let cs_key = (block.into(), adr, inc.into()).into();
+        let mut cs_cur = self.cursor::<StorageChangeSet>()?;
+        Ok(cs_cur
+            .seek_both_exact(cs_key, slot)?
+            .map(|StorageCSVal(_, v)| v))
+    }
+
+    /// Returns the state of the account as of block `block`, falling back to
+    /// the PlainState table if the account has not changed since. Fails with
+    /// [`Error::Pruned`] if Erigon pruned the history of the block.
+    pub fn account_at(
+        &self,
+        adr: Address,
+        block: impl Into<BlockNumber>,
+    ) -> Result<Option<Account>> {
+        let block = block.into();
+        self.ensure_not_pruned::<AccountHistory>(C::PRUNE_HISTORY_KEY, "Execution", block)?;
+        match self.next_account_change(adr, block)? {
+            Some(cs_block) => self.read_account_changeset(adr, cs_block),
+            None => self.read_account(adr),
+        }
+    }
+
+    /// Returns the value of the storage slot as of block `block`, falling back
+    /// to the PlainState table if the slot has not changed since. Fails with
+    /// [`Error::Pruned`] if Erigon pruned the history of the block.
+    pub fn storage_at(
+        &self,
+        adr: Address,
+        inc: impl Into<Incarnation>,
+        slot: H256,
+        block: impl Into<BlockNumber>,
+    ) -> Result<U256> {
+        let (inc, block) = (inc.into(), block.into());
+        self.ensure_not_pruned::<StorageHistory>(C::PRUNE_HISTORY_KEY, "Execution", block)?;
+        let val = match self.next_storage_change(adr, slot, block)? {
+            Some(cs_block) => self.read_storage_changeset(adr, inc, slot, cs_block)?,
+            None => self.read_storage(adr, inc, slot)?,
+        };
+        Ok(val.unwrap_or_default())
+    }
+
+    // `account_at(adr, n)` is the state before block `n` is applied, whereas
+    // the eth_getBalance family reads the state after it. Erigon's rpcdaemon
+    // makes the same adjustment when it reads history for a block.
+    fn account_after(&self, adr: Address, block: BlockNumber) -> Result<Option<Account>> {
+        self.account_at(adr, BlockNumber(block.saturating_add(1)))
+    }
+
+    /// Returns the balance of `adr` after block `block` was applied, like
+    /// `eth_getBalance`. Accounts that don't exist have a balance of zero.
+    pub fn balance_at(&self, adr: Address, block: impl Into<BlockNumber>) -> Result<U256> {
+        Ok(self
+            .account_after(adr, block.into())?
+            .map(|acct| acct.balance)
+            .unwrap_or_default())
+    }
+
+    /// Returns the nonce of `adr` after block `block` was applied, like
+    /// `eth_getTransactionCount`.
+    pub fn nonce_at(&self, adr: Address, block: impl Into<BlockNumber>) -> Result<u64> {
+        Ok(self
+            .account_after(adr, block.into())?
+            .map(|acct| acct.nonce)
+            .unwrap_or_default())
+    }
+
+    /// Returns the code of `adr` after block `block` was applied, like
+    /// `eth_getCode`. The code is looked up by the codehash of the account's
+    /// incarnation at that block, so a contract that was destroyed and
+    /// redeployed at the same address returns the code live at the time.
+    pub fn code_at(&self, adr: Address, block: impl Into<BlockNumber>) -> Result<Bytecode> {
+        let acct = match self.account_after(adr, block.into())? {
+            Some(acct) if acct.codehash != H256::zero() => acct,
+            _ => return Ok(Default::default()),
+        };
+        Ok(self.read_code(acct.codehash)?.unwrap_or_default())
+    }
 
     /// Returns the value of an address's storage at the given block number. Returns `None` if the state
     /// is not found in history (e.g., if it's in the PlainState table instead).
@@ -284,32 +1367,63 @@ impl<'env, K: Mode> Erigon<'env, K> {
         block: impl Into<BlockNumber>,
     ) -> Result<Option<U256>> {
         let block = block.into();
-        let mut hist_cur = self.cursor::<StorageHistory>()?;
-        let (_, bitmap) = hist_cur
-            .seek((adr, slot, block).into())?
-            .ok_or(eyre!("No value"))?;
-        let cs_block = match utils::find_gte(bitmap, *block) {
-            Some(changeset) => BlockNumber(changeset),
-            _ => return Ok(None),
-        };
-        let cs_key = (cs_block, adr, inc.into()).into();
-        let mut cs_cur = self.cursor::<StorageChangeSet>()?;
-        if let Some(StorageCSVal(k, v)) = cs_cur.seek_dup(cs_key, slot)? {
-            if k == slot {
-                return Ok(Some(v));
-            }
+        self.ensure_not_pruned::<StorageHistory>(C::PRUNE_HISTORY_KEY, "Execution", block)?;
+        match self.next_storage_change(adr, slot, block)? {
+            Some(cs_block) => self.read_storage_changeset(adr, inc, slot, cs_block),
+            None => Ok(None),
         }
-        Ok(None)
     }
 }
 
-impl<'env> Erigon<'env, mdbx::RW> {
+impl<'env, Tx: KvTxMut> Erigon<'env, RW, Tx> {
+    /// Commits the transaction.
+    pub fn commit(self) -> Result<bool> {
+        self.0.commit()
+    }
+
     /// Opens and writes to the db table with the table's default flags.
     pub fn write<'tx, T>(&'tx self, key: T::Key, val: T::Value) -> Result<()>
     where
         T: Table<'tx> + DefaultFlags,
     {
-        self.0.put::<T, T::Flags>(self.0.open_db()?, key, val)
+        let key = key.encode();
+        db_span!("write", <T::Name as DbName>::NAME, key.as_ref());
+        metrics::record_write(<T::Name as DbName>::NAME);
+        self.0.put_raw::<T>(key.as_ref(), val.encode().as_ref())
+    }
+
+    /// Deletes `key` from the table, including all of its duplicates if the
+    /// table is dupsorted. Returns `true` if the key existed.
+    pub fn delete<'tx, T>(&'tx self, key: T::Key) -> Result<bool>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let key = key.encode();
+        db_span!("delete", <T::Name as DbName>::NAME, key.as_ref());
+        metrics::record_write(<T::Name as DbName>::NAME);
+        self.0.delete_raw::<T>(key.as_ref(), None)
+    }
+
+    /// Deletes a single (key, subkey) entry from a dupsorted table without
+    /// touching the other duplicates stored at `key`. Returns `true` if the
+    /// entry existed.
+    pub fn delete_dup<'tx, T>(&'tx self, key: T::Key, subkey: T::Subkey) -> Result<bool>
+    where
+        T: DupSort<'tx> + DefaultFlags,
+    {
+        let (key, subkey) = (key.encode(), subkey.encode());
+        db_span!("delete_dup", <T::Name as DbName>::NAME, key.as_ref());
+        // the backend deletes exact duplicates, so find the full value first
+        let val = match self
+            .0
+            .cursor_raw::<T>()?
+            .seek_both_range(key.as_ref(), subkey.as_ref())?
+        {
+            Some(val) if val.starts_with(subkey.as_ref()) => val.into_owned(),
+            _ => return Ok(false),
+        };
+        metrics::record_write(<T::Name as DbName>::NAME);
+        self.0.delete_raw::<T>(key.as_ref(), Some(&val))
     }
 
     pub fn write_head_header_hash(&self, v: H256) -> Result<()> {
@@ -336,4 +1450,26 @@ impl<'env> Erigon<'env, mdbx::RW> {
     pub fn write_body_for_storage(&self, k: HeaderKey, v: BodyForStorage) -> Result<()> {
         self.write::<BlockBody>(k, v)
     }
+    pub fn write_senders(&self, k: HeaderKey, v: Vec<Address>) -> Result<()> {
+        self.write::<TxSender>(k, v)
+    }
+    pub fn write_stage_progress(&self, stage: &str, v: BlockNumber) -> Result<()> {
+        self.write::<SyncStage>(Bytes::copy_from_slice(stage.as_bytes()), v)
+    }
+
+    /// Returns the signers of each transaction in the block, recovering them
+    /// and writing them to the TxSender table if they are not already present
+    /// (as Erigon's Senders stage would).
+    #[cfg(feature = "secp256k1")]
+    pub fn backfill_senders(&self, key: impl Into<HeaderKey>) -> Result<Option<Vec<Address>>> {
+        let key = key.into();
+        if let Some(senders) = self.read_senders(key)? {
+            return Ok(Some(senders));
+        }
+        let senders = self.recover_senders(key)?;
+        if let Some(senders) = &senders {
+            self.write_senders(key, senders.clone())?;
+        }
+        Ok(senders)
+    }
 }
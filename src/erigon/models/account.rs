@@ -1,6 +1,5 @@
 use bytes::Buf;
 use ethereum_types::{H256, U256};
-use eyre::Result;
 use fastrlp::{RlpDecodable, RlpEncodable};
 use serde::{Deserialize, Serialize};
 
@@ -9,7 +8,9 @@ use crate::{
         utils::{consts::*, *},
         Incarnation,
     },
+    error::bail,
     kv::traits::{TableDecode, TableEncode},
+    Result,
 };
 
 #[derive(
@@ -53,7 +54,7 @@ impl TableDecode for Account {
         if fieldset & 8 > 0 {
             let len: usize = buf.get_u8().into();
             if len != KECCAK_LENGTH {
-                eyre::bail!(
+                bail!(
                     "codehash should be {} bytes long. Got {} instead",
                     KECCAK_LENGTH,
                     len
@@ -65,11 +66,38 @@ impl TableDecode for Account {
         Ok(acct)
     }
 }
-//TODO: dummy impl as we only need to decode for now, but need the trait bound
+// The inverse of decode: a fieldset byte, then each present field as its
+// length and its big-endian bytes without leading zeroes. Like Erigon, an
+// empty codehash is left out.
 impl TableEncode for Account {
     type Encoded = Vec<u8>;
     fn encode(self) -> Self::Encoded {
-        unreachable!("Can't encode Account")
+        fn put(buf: &mut Vec<u8>, be: &[u8]) {
+            let be = &be[be.iter().take_while(|b| **b == 0).count()..];
+            buf.push(be.len() as u8);
+            buf.extend_from_slice(be);
+        }
+        let mut buf = vec![0];
+        if self.nonce > 0 {
+            buf[0] |= 1;
+            put(&mut buf, &self.nonce.to_be_bytes());
+        }
+        if !self.balance.is_zero() {
+            buf[0] |= 2;
+            let mut bal = [0; 32];
+            self.balance.to_big_endian(&mut bal);
+            put(&mut buf, &bal);
+        }
+        if *self.incarnation > 0 {
+            buf[0] |= 4;
+            put(&mut buf, &self.incarnation.to_be_bytes());
+        }
+        if !self.codehash.is_zero() && self.codehash != EMPTY_HASH {
+            buf[0] |= 8;
+            buf.push(KECCAK_LENGTH as u8);
+            buf.extend_from_slice(self.codehash.as_bytes());
+        }
+        buf
     }
 }
 
@@ -0,0 +1,121 @@
+//! Conversions to and from the types of alloy-primitives, enabled by the
+//! `alloy` feature.
+//!
+//! Both sides of each conversion are foreign to this crate, so they are
+//! provided through [`ToAlloy`] and [`FromAlloy`] rather than `From`:
+//!
+//! ```ignore
+//! let hash: B256 = header.hash().to_alloy();
+//! let hash = H256::from_alloy(hash);
+//! ```
+//!
+//! alloy-primitives has no header, transaction or account types; of the
+//! models, only [`CborLog`] has a counterpart.
+//!
+//! The feature provides these conversions, and alloy table keys and values
+//! (see `kv::tables`), which are stored exactly as their ethereum-types
+//! counterparts. The models hold ethereum-types values either way.
+//!
+//! TODO: alloy-primitives as the native types of the models. Their rlp codecs
+//! are derived with fastrlp, which only implements its traits for
+//! ethereum-types, so this needs a type alias layer in `models` and fastrlp
+//! impls through a newtype.
+use super::CborLog;
+use bytes::Bytes;
+use ethereum_types::{Address, Bloom, H256, U256};
+
+/// Converts a value into its alloy-primitives counterpart.
+pub trait ToAlloy {
+    type Alloy;
+    fn to_alloy(&self) -> Self::Alloy;
+}
+
+/// Converts a value from its alloy-primitives counterpart.
+pub trait FromAlloy<T> {
+    fn from_alloy(value: T) -> Self;
+}
+
+impl ToAlloy for Address {
+    type Alloy = alloy_primitives::Address;
+    fn to_alloy(&self) -> Self::Alloy {
+        alloy_primitives::Address::new(self.0)
+    }
+}
+impl FromAlloy<alloy_primitives::Address> for Address {
+    fn from_alloy(value: alloy_primitives::Address) -> Self {
+        Self(value.0 .0)
+    }
+}
+
+impl ToAlloy for H256 {
+    type Alloy = alloy_primitives::B256;
+    fn to_alloy(&self) -> Self::Alloy {
+        alloy_primitives::B256::new(self.0)
+    }
+}
+impl FromAlloy<alloy_primitives::B256> for H256 {
+    fn from_alloy(value: alloy_primitives::B256) -> Self {
+        Self(value.0)
+    }
+}
+
+// Both are little-endian arrays of four u64 limbs.
+impl ToAlloy for U256 {
+    type Alloy = alloy_primitives::U256;
+    fn to_alloy(&self) -> Self::Alloy {
+        alloy_primitives::U256::from_limbs(self.0)
+    }
+}
+impl FromAlloy<alloy_primitives::U256> for U256 {
+    fn from_alloy(value: alloy_primitives::U256) -> Self {
+        Self(value.into_limbs())
+    }
+}
+
+impl ToAlloy for Bloom {
+    type Alloy = alloy_primitives::Bloom;
+    fn to_alloy(&self) -> Self::Alloy {
+        alloy_primitives::Bloom::new(self.0)
+    }
+}
+impl FromAlloy<alloy_primitives::Bloom> for Bloom {
+    fn from_alloy(value: alloy_primitives::Bloom) -> Self {
+        Self(value.0 .0)
+    }
+}
+
+impl ToAlloy for Bytes {
+    type Alloy = alloy_primitives::Bytes;
+    fn to_alloy(&self) -> Self::Alloy {
+        alloy_primitives::Bytes(self.clone())
+    }
+}
+impl FromAlloy<alloy_primitives::Bytes> for Bytes {
+    fn from_alloy(value: alloy_primitives::Bytes) -> Self {
+        value.0
+    }
+}
+
+impl ToAlloy for CborLog {
+    type Alloy = alloy_primitives::Log;
+    fn to_alloy(&self) -> Self::Alloy {
+        alloy_primitives::Log::new_unchecked(
+            self.address.to_alloy(),
+            self.topics.iter().map(ToAlloy::to_alloy).collect(),
+            self.data.to_alloy(),
+        )
+    }
+}
+impl FromAlloy<alloy_primitives::Log> for CborLog {
+    fn from_alloy(value: alloy_primitives::Log) -> Self {
+        Self {
+            address: Address::from_alloy(value.address),
+            topics: value
+                .topics()
+                .iter()
+                .map(|t| H256::from_alloy(*t))
+                .collect(),
+            data: Bytes::from_alloy(value.data.data),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Models for the tables of Caplin, Erigon's consensus client. Caplin keeps
+//! them in its own mdbx db in the datadir, `caplin/indexing/beacon_indicies`,
+//! rather than in chaindata.
+use crate::{
+    erigon::macros::*,
+    kv::{
+        tables::InvalidLength,
+        traits::{TableDecode, TableEncode},
+    },
+    Result,
+};
+
+// Caplin encodes slots and block numbers as 4 big-endian bytes.
+macro_rules! u64_bytes4_wrapper {
+    ($ty:ident) => {
+        decl_u64_wrapper!($ty);
+
+        impl TableEncode for $ty {
+            type Encoded = [u8; 4];
+
+            fn encode(self) -> Self::Encoded {
+                (self.0 as u32).to_be_bytes()
+            }
+        }
+
+        impl TableDecode for $ty {
+            fn decode(b: &[u8]) -> Result<Self> {
+                match b.len() {
+                    4 => Ok(Self(
+                        u32::from_be_bytes(*arrayref::array_ref!(b, 0, 4)).into(),
+                    )),
+                    other => Err(InvalidLength::<4> { got: other }.into()),
+                }
+            }
+        }
+    };
+}
+
+u64_bytes4_wrapper!(Slot);
+// the execution block number of a beacon block, in the BlockRootToBlockNumber
+// table
+u64_bytes4_wrapper!(ExecutionBlockNumber);
@@ -1,19 +1,92 @@
+use crate::{
+    error::{bail, ensure},
+    Result,
+};
 use bytes::Bytes;
 use ethereum_types::{Address, Bloom, H256, H64, U256};
-use eyre::Result;
 use fastrlp::{BufMut, Decodable, DecodeError, Encodable, RlpDecodable, RlpEncodable};
 use serde::{Deserialize, Serialize};
 
-use crate::erigon::{macros::*, utils::consts::*, Rlp};
+use crate::erigon::{
+    macros::*,
+    utils::{consts::*, keccak256},
+    Rlp,
+};
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, RlpEncodable, RlpDecodable)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BodyForStorage {
     pub base_tx_id: u64,
     pub tx_amount: u32,
     pub uncles: Vec<BlockHeader>,
+    // None before Shanghai
+    pub withdrawals: Option<Vec<Withdrawal>>,
 }
 rlp_table_value!(BodyForStorage);
 
+impl BodyForStorage {
+    fn rlp_header(&self) -> fastrlp::Header {
+        let mut payload_length =
+            self.base_tx_id.length() + self.tx_amount.length() + self.uncles.length();
+        if let Some(withdrawals) = &self.withdrawals {
+            payload_length += withdrawals.length();
+        }
+        fastrlp::Header {
+            list: true,
+            payload_length,
+        }
+    }
+}
+
+impl Encodable for BodyForStorage {
+    fn encode(&self, out: &mut dyn BufMut) {
+        self.rlp_header().encode(out);
+        Encodable::encode(&self.base_tx_id, out);
+        Encodable::encode(&self.tx_amount, out);
+        Encodable::encode(&self.uncles, out);
+        if let Some(withdrawals) = &self.withdrawals {
+            Encodable::encode(withdrawals, out);
+        }
+    }
+    fn length(&self) -> usize {
+        let rlp_head = self.rlp_header();
+        fastrlp::length_of_length(rlp_head.payload_length) + rlp_head.payload_length
+    }
+}
+
+// withdrawals are an optional trailing field, present from Shanghai on
+impl Decodable for BodyForStorage {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        let rlp_head = fastrlp::Header::decode(buf)?;
+        if !rlp_head.list {
+            return Err(DecodeError::UnexpectedString);
+        }
+        let rest = buf.len() - rlp_head.payload_length;
+        let base_tx_id = Decodable::decode(buf)?;
+        let tx_amount = Decodable::decode(buf)?;
+        let uncles = Decodable::decode(buf)?;
+        let withdrawals = decode_optional(buf, rest)?;
+        Ok(Self {
+            base_tx_id,
+            tx_amount,
+            uncles,
+            withdrawals,
+        })
+    }
+}
+
+/// A validator withdrawal from the consensus layer (EIP-4895).
+#[derive(
+    Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize, RlpEncodable, RlpDecodable,
+)]
+pub struct Withdrawal {
+    pub index: u64,
+    pub validator: u64,
+    pub address: Address,
+    // in gwei
+    pub amount: u64,
+}
+rlp_table_value!(Withdrawal);
+
 #[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct BlockHeader {
     pub parent_hash: H256,
@@ -32,10 +105,249 @@ pub struct BlockHeader {
     pub mix_digest: H256,
     pub nonce: H64,
     pub base_fee: Option<U256>,
+    // EIP-4895, from Shanghai on
+    pub withdrawals_root: Option<H256>,
+    // EIP-4844, from Cancun on
+    pub blob_gas_used: Option<u64>,
+    pub excess_blob_gas: Option<u64>,
+    // EIP-4788, from Cancun on
+    pub parent_beacon_block_root: Option<H256>,
+    // the raw rlp of the seal fields, in place of mix_digest and nonce, for
+    // engines like AuRa that don't use them
     pub seal: Option<Rlp>,
 }
 rlp_table_value!(BlockHeader);
 
+/// How the seal of a header is laid out, which depends on the chain's
+/// consensus engine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// Detects the format the way erigon does: a 32 byte field after `extra`
+    /// is taken as ethash's mix_digest, otherwise the header is AuRa's.
+    #[default]
+    Auto,
+    /// mix_digest and nonce, as in ethash, clique, and post-merge headers.
+    Ethash,
+    /// The given number of seal fields in place of mix_digest and nonce.
+    Seal(usize),
+}
+
+impl HeaderFormat {
+    /// The step and signature of AuRa headers, e.g. on Gnosis Chain.
+    pub const AURA: Self = Self::Seal(2);
+}
+
+impl BlockHeader {
+    /// Computes the block hash, the keccak256 hash of the rlp-encoded header.
+    pub fn hash(&self) -> H256 {
+        let mut buf = vec![];
+        self.encode(&mut buf);
+        keccak256(buf).into()
+    }
+
+    /// Decodes a header whose seal is laid out according to `format`. The
+    /// header's [`Decodable`] impl uses [`HeaderFormat::Auto`].
+    // https://github.com/ledgerwatch/erigon/blob/156da607e7495d709c141aec40f66a2556d35dc0/core/types/block.go#L430
+    pub fn decode_with_format(buf: &mut &[u8], format: HeaderFormat) -> Result<Self, DecodeError> {
+        let rlp_head = fastrlp::Header::decode(buf)?;
+        if !rlp_head.list {
+            return Err(DecodeError::UnexpectedString);
+        }
+        let rest = buf.len() - rlp_head.payload_length;
+        let parent_hash = Decodable::decode(buf)?;
+        let uncle_hash = Decodable::decode(buf)?;
+        let coinbase = Decodable::decode(buf)?;
+        let root = Decodable::decode(buf)?;
+        let tx_hash = Decodable::decode(buf)?;
+        let receipts_hash = Decodable::decode(buf)?;
+        let bloom = Decodable::decode(buf)?;
+        let difficulty = Decodable::decode(buf)?;
+        let number = Decodable::decode(buf)?;
+        let gas_limit = Decodable::decode(buf)?;
+        let gas_used = Decodable::decode(buf)?;
+        let time = Decodable::decode(buf)?;
+        let extra = Decodable::decode(buf)?;
+
+        let seal_fields = match format {
+            HeaderFormat::Auto => {
+                let mut next = *buf;
+                let field = fastrlp::Header::decode(&mut next)?;
+                if !field.list && field.payload_length == KECCAK_LENGTH {
+                    0
+                } else {
+                    2
+                }
+            }
+            HeaderFormat::Ethash => 0,
+            HeaderFormat::Seal(n) => n,
+        };
+        let (mix_digest, nonce, seal) = if seal_fields == 0 {
+            (Decodable::decode(buf)?, Decodable::decode(buf)?, None)
+        } else {
+            let start = *buf;
+            for _ in 0..seal_fields {
+                let field = fastrlp::Header::decode(buf)?;
+                if buf.len() < field.payload_length {
+                    return Err(DecodeError::InputTooShort);
+                }
+                *buf = &buf[field.payload_length..];
+            }
+            let raw = &start[..start.len() - buf.len()];
+            (
+                H256::zero(),
+                H64::zero(),
+                Some(Rlp(Bytes::copy_from_slice(raw))),
+            )
+        };
+        // each fork appends optional fields to the end of the header
+        let base_fee = decode_optional(buf, rest)?;
+        let withdrawals_root = decode_optional(buf, rest)?;
+        let blob_gas_used = decode_optional(buf, rest)?;
+        let excess_blob_gas = decode_optional(buf, rest)?;
+        let parent_beacon_block_root = decode_optional(buf, rest)?;
+
+        Ok(Self {
+            parent_hash,
+            uncle_hash,
+            coinbase,
+            root,
+            tx_hash,
+            receipts_hash,
+            bloom,
+            difficulty,
+            number,
+            gas_limit,
+            gas_used,
+            time,
+            extra,
+            mix_digest,
+            nonce,
+            base_fee,
+            withdrawals_root,
+            blob_gas_used,
+            excess_blob_gas,
+            parent_beacon_block_root,
+            seal,
+        })
+    }
+
+    /// Returns the seal fields of the header as byte strings, e.g. the step
+    /// and signature of an AuRa header, or `None` for ethash-style headers.
+    pub fn seal_fields(&self) -> Result<Option<Vec<Bytes>>, DecodeError> {
+        self.seal
+            .as_ref()
+            .map(|seal| {
+                let mut buf = &seal[..];
+                let mut fields = vec![];
+                while !buf.is_empty() {
+                    fields.push(Decodable::decode(&mut buf)?);
+                }
+                Ok(fields)
+            })
+            .transpose()
+    }
+
+    /// Returns the base fee a child of this header must have under EIP-1559,
+    /// or the initial base fee if this header predates London.
+    // https://eips.ethereum.org/EIPS/eip-1559
+    pub fn next_base_fee(&self) -> U256 {
+        let base_fee = match self.base_fee {
+            Some(base_fee) => base_fee,
+            None => return INITIAL_BASE_FEE.into(),
+        };
+        let target = self.gas_limit / ELASTICITY_MULTIPLIER;
+        if target == 0 || self.gas_used == target {
+            return base_fee;
+        }
+        let (used, target) = (U256::from(self.gas_used), U256::from(target));
+        if used > target {
+            let delta = base_fee * (used - target) / target / BASE_FEE_CHANGE_DENOMINATOR;
+            base_fee + delta.max(1.into())
+        } else {
+            let delta = base_fee * (target - used) / target / BASE_FEE_CHANGE_DENOMINATOR;
+            base_fee.saturating_sub(delta)
+        }
+    }
+}
+
+const INITIAL_BASE_FEE: u64 = 1_000_000_000;
+const ELASTICITY_MULTIPLIER: u64 = 2;
+const BASE_FEE_CHANGE_DENOMINATOR: u64 = 8;
+const GAS_LIMIT_BOUND_DIVISOR: u64 = 1024;
+const MIN_GAS_LIMIT: u64 = 5000;
+
+/// Checks that `child` can follow `parent` under Ethereum's rules: numbers
+/// and parent hash link up, time moves forward, the gas limit moves by less
+/// than 1/1024 of the parent's, the base fee follows EIP-1559, and the chain
+/// doesn't return to proof of work after the merge. Seals aren't verified.
+pub fn validate_parent(child: &BlockHeader, parent: &BlockHeader) -> Result<()> {
+    ensure!(
+        child.number == parent.number + 1,
+        "Block {} does not follow block {}",
+        child.number,
+        parent.number,
+    );
+    let parent_hash = parent.hash();
+    ensure!(
+        child.parent_hash == parent_hash,
+        "Block {} has parent hash {:?}, expected {:?}",
+        child.number,
+        child.parent_hash,
+        parent_hash,
+    );
+    ensure!(
+        child.time > parent.time,
+        "Block {} has timestamp {} <= parent timestamp {}",
+        child.number,
+        child.time,
+        parent.time,
+    );
+    ensure!(
+        child.gas_used <= child.gas_limit,
+        "Block {} uses {} gas, over its limit of {}",
+        child.number,
+        child.gas_used,
+        child.gas_limit,
+    );
+
+    // the gas target doubles the limit at the London fork block
+    let parent_limit = match (parent.base_fee, child.base_fee) {
+        (None, Some(_)) => parent.gas_limit * ELASTICITY_MULTIPLIER,
+        _ => parent.gas_limit,
+    };
+    let max_change = parent_limit / GAS_LIMIT_BOUND_DIVISOR;
+    ensure!(
+        child.gas_limit.abs_diff(parent_limit) < max_change && child.gas_limit >= MIN_GAS_LIMIT,
+        "Block {} has gas limit {}, out of bounds of parent limit {}",
+        child.number,
+        child.gas_limit,
+        parent_limit,
+    );
+
+    match (parent.base_fee, child.base_fee) {
+        (_, Some(base_fee)) => {
+            let expected = parent.next_base_fee();
+            ensure!(
+                base_fee == expected,
+                "Block {} has base fee {}, expected {}",
+                child.number,
+                base_fee,
+                expected,
+            );
+        }
+        (Some(_), None) => bail!("Block {} is missing a base fee", child.number),
+        (None, None) => {}
+    }
+
+    ensure!(
+        !(parent.difficulty.is_zero() && !child.difficulty.is_zero()),
+        "Block {} has difficulty {} after the merge",
+        child.number,
+        child.difficulty,
+    );
+    Ok(())
+}
+
 impl BlockHeader {
     fn rlp_header(&self) -> fastrlp::Header {
         let mut rlp_head = fastrlp::Header {
@@ -57,12 +369,29 @@ impl BlockHeader {
         rlp_head.payload_length += self.time.length(); // timestamp
         rlp_head.payload_length += self.extra.length(); // extra_data
 
-        rlp_head.payload_length += KECCAK_LENGTH + 1; // mix_hash
-        rlp_head.payload_length += 8 + 1; // nonce
+        match &self.seal {
+            Some(seal) => rlp_head.payload_length += seal.len(),
+            None => {
+                rlp_head.payload_length += KECCAK_LENGTH + 1; // mix_hash
+                rlp_head.payload_length += 8 + 1; // nonce
+            }
+        }
 
         if let Some(base_fee) = self.base_fee {
             rlp_head.payload_length += base_fee.length();
         }
+        if self.withdrawals_root.is_some() {
+            rlp_head.payload_length += KECCAK_LENGTH + 1;
+        }
+        if let Some(blob_gas_used) = self.blob_gas_used {
+            rlp_head.payload_length += blob_gas_used.length();
+        }
+        if let Some(excess_blob_gas) = self.excess_blob_gas {
+            rlp_head.payload_length += excess_blob_gas.length();
+        }
+        if self.parent_beacon_block_root.is_some() {
+            rlp_head.payload_length += KECCAK_LENGTH + 1;
+        }
 
         rlp_head
     }
@@ -84,11 +413,28 @@ impl Encodable for BlockHeader {
         Encodable::encode(&self.gas_used, out);
         Encodable::encode(&self.time, out);
         Encodable::encode(&self.extra, out);
-        Encodable::encode(&self.mix_digest, out);
-        Encodable::encode(&self.nonce, out);
+        match &self.seal {
+            Some(seal) => out.put_slice(seal),
+            None => {
+                Encodable::encode(&self.mix_digest, out);
+                Encodable::encode(&self.nonce, out);
+            }
+        }
         if let Some(base_fee) = self.base_fee {
             Encodable::encode(&base_fee, out);
         }
+        if let Some(withdrawals_root) = self.withdrawals_root {
+            Encodable::encode(&withdrawals_root, out);
+        }
+        if let Some(blob_gas_used) = self.blob_gas_used {
+            Encodable::encode(&blob_gas_used, out);
+        }
+        if let Some(excess_blob_gas) = self.excess_blob_gas {
+            Encodable::encode(&excess_blob_gas, out);
+        }
+        if let Some(parent_beacon_block_root) = self.parent_beacon_block_root {
+            Encodable::encode(&parent_beacon_block_root, out);
+        }
     }
     fn length(&self) -> usize {
         let rlp_head = self.rlp_header();
@@ -96,56 +442,18 @@ impl Encodable for BlockHeader {
     }
 }
 
-// https://github.com/ledgerwatch/erigon/blob/156da607e7495d709c141aec40f66a2556d35dc0/core/types/block.go#L430
 impl Decodable for BlockHeader {
     fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
-        let rlp_head = fastrlp::Header::decode(buf)?;
-        if !rlp_head.list {
-            return Err(DecodeError::UnexpectedString);
-        }
-        let rest = buf.len() - rlp_head.payload_length;
-        let parent_hash = Decodable::decode(buf)?;
-        let uncle_hash = Decodable::decode(buf)?;
-        let coinbase = Decodable::decode(buf)?;
-        let root = Decodable::decode(buf)?;
-        let tx_hash = Decodable::decode(buf)?;
-        let receipts_hash = Decodable::decode(buf)?;
-        let bloom = Decodable::decode(buf)?;
-        let difficulty = Decodable::decode(buf)?;
-        let number = Decodable::decode(buf)?;
-        let gas_limit = Decodable::decode(buf)?;
-        let gas_used = Decodable::decode(buf)?;
-        let time = Decodable::decode(buf)?;
-        let extra = Decodable::decode(buf)?;
-
-        // TODO: seal fields
-        let seal = None;
-        let mix_digest = Decodable::decode(buf)?;
-        let nonce = Decodable::decode(buf)?;
-        let base_fee = if buf.len() > rest {
-            Some(Decodable::decode(buf)?)
-        } else {
-            None
-        };
+        Self::decode_with_format(buf, HeaderFormat::Auto)
+    }
+}
 
-        Ok(Self {
-            parent_hash,
-            uncle_hash,
-            coinbase,
-            root,
-            tx_hash,
-            receipts_hash,
-            bloom,
-            difficulty,
-            number,
-            gas_limit,
-            gas_used,
-            time,
-            extra,
-            mix_digest,
-            nonce,
-            base_fee,
-            seal,
-        })
+// Decodes an optional trailing field of an rlp list, which is present only if
+// the list has bytes left before `rest`.
+fn decode_optional<T: Decodable>(buf: &mut &[u8], rest: usize) -> Result<Option<T>, DecodeError> {
+    if buf.len() > rest {
+        Decodable::decode(buf).map(Some)
+    } else {
+        Ok(None)
     }
 }
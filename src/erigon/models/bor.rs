@@ -0,0 +1,94 @@
+//! Models for the tables only present in Polygon (bor) datadirs.
+use bytes::Bytes;
+use ethereum_types::{Address, H256};
+use fastrlp::{RlpDecodable, RlpEncodable};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    erigon::macros::*,
+    kv::traits::{TableDecode, TableEncode},
+    Result,
+};
+
+u64_wrapper!(SpanId);
+u64_wrapper!(CheckpointId);
+
+/// The receipt of the state-sync transaction bor appends to each sprint-end
+/// block, stored as rlp in the BorReceipt table. Unlike regular receipts, the
+/// logs are stored inline.
+#[derive(
+    Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize, RlpEncodable, RlpDecodable,
+)]
+pub struct BorReceipt {
+    /// Empty for a failed tx, `[1]` for a successful one.
+    pub status: Bytes,
+    pub cumulative_gas_used: u64,
+    pub logs: Vec<BorLog>,
+}
+rlp_table_value!(BorReceipt);
+
+impl BorReceipt {
+    pub fn succeeded(&self) -> bool {
+        self.status[..] == [1]
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize, RlpEncodable, RlpDecodable,
+)]
+pub struct BorLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: Bytes,
+}
+
+/// A heimdall span, the range of blocks produced by a validator set, stored as
+/// json in the BorSpans table.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Span {
+    #[serde(rename = "span_id")]
+    pub id: u64,
+    pub start_block: u64,
+    pub end_block: u64,
+    /// The validator set, left undecoded.
+    #[serde(default)]
+    pub validator_set: serde_json::Value,
+    /// The block producers selected for this span, left undecoded.
+    #[serde(default)]
+    pub selected_producers: Vec<serde_json::Value>,
+    #[serde(rename = "bor_chain_id", default)]
+    pub chain_id: String,
+}
+
+/// A heimdall checkpoint of a range of bor blocks to Ethereum, stored as json
+/// in the BorCheckpoints table.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub proposer: Address,
+    pub start_block: u64,
+    pub end_block: u64,
+    pub root_hash: H256,
+    #[serde(rename = "bor_chain_id", default)]
+    pub chain_id: String,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+macro_rules! json_table_value {
+    ($t:ty) => {
+        impl TableEncode for $t {
+            type Encoded = Vec<u8>;
+            fn encode(self) -> Self::Encoded {
+                serde_json::to_vec(&self).expect(concat!("failed to encode ", stringify!($t)))
+            }
+        }
+
+        impl TableDecode for $t {
+            fn decode(b: &[u8]) -> Result<Self> {
+                serde_json::from_slice(b).map_err(From::from)
+            }
+        }
+    };
+}
+json_table_value!(Span);
+json_table_value!(Checkpoint);
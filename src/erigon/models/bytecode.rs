@@ -0,0 +1,102 @@
+//! Disassembly helpers for contract code stored in the Code table.
+use super::Bytecode;
+
+const JUMPDEST: u8 = 0x5b;
+const PUSH1: u8 = 0x60;
+const PUSH4: u8 = 0x63;
+const PUSH32: u8 = 0x7f;
+const EQ: u8 = 0x14;
+
+// selectors of totalSupply(), balanceOf(address), transfer(address,uint256),
+// transferFrom(address,address,uint256), approve(address,uint256) and
+// allowance(address,address)
+const ERC20_SELECTORS: [[u8; 4]; 6] = [
+    [0x18, 0x16, 0x0d, 0xdd],
+    [0x70, 0xa0, 0x82, 0x31],
+    [0xa9, 0x05, 0x9c, 0xbb],
+    [0x23, 0xb8, 0x72, 0xdd],
+    [0x09, 0x5e, 0xa7, 0xb3],
+    [0xdd, 0x62, 0xed, 0x3e],
+];
+
+/// A single instruction of EVM bytecode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction<'a> {
+    /// The offset of the opcode in the code.
+    pub pc: usize,
+    pub opcode: u8,
+    /// The immediate bytes of a PUSH instruction, empty for other opcodes.
+    /// Shorter than the push size if the code ends mid-push.
+    pub immediate: &'a [u8],
+}
+
+impl Instruction<'_> {
+    pub fn is_push(&self) -> bool {
+        (PUSH1..=PUSH32).contains(&self.opcode)
+    }
+}
+
+impl Bytecode {
+    /// Returns an iterator over the instructions of the code, skipping over
+    /// the immediate bytes of PUSH instructions.
+    pub fn iter_opcodes(&self) -> impl Iterator<Item = Instruction<'_>> {
+        let code = &self.0[..];
+        let mut pc = 0;
+        std::iter::from_fn(move || {
+            let opcode = *code.get(pc)?;
+            let start = pc + 1;
+            let end = match opcode {
+                PUSH1..=PUSH32 => (start + (opcode - PUSH1 + 1) as usize).min(code.len()),
+                _ => start,
+            };
+            let ins = Instruction {
+                pc,
+                opcode,
+                immediate: &code[start..end],
+            };
+            pc = end;
+            Some(ins)
+        })
+    }
+
+    /// Returns the offsets of the valid jump destinations in the code, in
+    /// ascending order. JUMPDEST bytes inside PUSH data are not included.
+    pub fn jumpdests(&self) -> Vec<usize> {
+        self.iter_opcodes()
+            .filter(|ins| ins.opcode == JUMPDEST)
+            .map(|ins| ins.pc)
+            .collect()
+    }
+
+    /// Returns the function selectors found in the code's dispatcher, in order
+    /// of appearance. A selector is a PUSH4 whose value is compared with EQ,
+    /// the pattern emitted by solc; dispatchers compiled otherwise may be
+    /// missed.
+    pub fn selectors(&self) -> Vec<[u8; 4]> {
+        let mut out = Vec::new();
+        let mut prev: Option<Instruction<'_>> = None;
+        for ins in self.iter_opcodes() {
+            if let Some(push) = prev.filter(|p| p.opcode == PUSH4 && p.immediate.len() == 4) {
+                let sel = [
+                    push.immediate[0],
+                    push.immediate[1],
+                    push.immediate[2],
+                    push.immediate[3],
+                ];
+                if ins.opcode == EQ && !out.contains(&sel) {
+                    out.push(sel);
+                }
+            }
+            prev = Some(ins);
+        }
+        out
+    }
+
+    /// Returns true if the code dispatches on all six ERC-20 functions. This
+    /// is a heuristic over [`Self::selectors`]: proxies and non-solc
+    /// contracts may implement ERC-20 without matching.
+    pub fn is_erc20_like(&self) -> bool {
+        let selectors = self.selectors();
+        ERC20_SELECTORS.iter().all(|sel| selectors.contains(sel))
+    }
+}
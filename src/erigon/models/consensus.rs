@@ -0,0 +1,47 @@
+use crate::Result;
+use ethereum_types::{Address, H256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::kv::traits::{TableDecode, TableEncode};
+
+/// A Clique signer snapshot, stored as json in the CliqueSeparate table.
+///
+/// https://github.com/ledgerwatch/erigon/blob/f9d7cb5ca9e8a135a76ddcb6fa4ee526ea383554/consensus/clique/snapshot.go#L49
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CliqueSnapshot {
+    pub number: u64,
+    pub hash: H256,
+    /// The set of authorized signers at this block.
+    #[serde(default)]
+    pub signers: BTreeMap<Address, serde_json::Value>,
+    /// Recent signers, keyed by the block number they signed.
+    #[serde(default)]
+    pub recents: BTreeMap<u64, Address>,
+    /// Votes cast in chronological order, left undecoded.
+    #[serde(default)]
+    pub votes: Vec<serde_json::Value>,
+    /// Current vote tally, left undecoded.
+    #[serde(default)]
+    pub tally: BTreeMap<Address, serde_json::Value>,
+}
+
+impl CliqueSnapshot {
+    /// Returns the addresses of the authorized signers.
+    pub fn signer_addresses(&self) -> Vec<Address> {
+        self.signers.keys().copied().collect()
+    }
+}
+
+impl TableEncode for CliqueSnapshot {
+    type Encoded = Vec<u8>;
+    fn encode(self) -> Self::Encoded {
+        serde_json::to_vec(&self).expect("failed to encode CliqueSnapshot")
+    }
+}
+
+impl TableDecode for CliqueSnapshot {
+    fn decode(b: &[u8]) -> Result<Self> {
+        serde_json::from_slice(b).map_err(From::from)
+    }
+}
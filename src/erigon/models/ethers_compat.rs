@@ -0,0 +1,241 @@
+//! Conversions between the models and the types of ethers-core, enabled by
+//! the `ethers-types` feature. ethers-core has no counterpart to
+//! [`Account`](super::Account).
+//!
+//! ethers-core has no fields for the blob fee cap and versioned hashes of a
+//! blob tx, so they are carried in the `other` fields of the ethers
+//! [`Transaction`], under their json-rpc names.
+use super::{
+    transaction::{
+        self, AccessListTx, AccessTuple, BlobTx, DynamicFeeTx, LegacyTx, TransactionWithSigner,
+        TxAction, VPackChainId,
+    },
+    BlockHeader, CborLog,
+};
+use ethers::types::{transaction::eip2930::AccessListItem, Block, Log, Transaction, H256, U256};
+
+const BLOB_FEE_CAP: &str = "maxFeePerBlobGas";
+const BLOB_HASHES: &str = "blobVersionedHashes";
+
+impl From<TransactionWithSigner> for Transaction {
+    fn from(tx: TransactionWithSigner) -> Self {
+        let mut out = Self {
+            hash: tx.msg.tx_hash(),
+            nonce: tx.msg.nonce().into(),
+            from: tx.signer,
+            to: tx.msg.to().into(),
+            value: tx.msg.value(),
+            gas_price: tx.msg.gas_price(),
+            gas: tx.msg.gas().into(),
+            input: tx.msg.data().clone().into(),
+            transaction_type: tx.msg.tx_type().map(From::from),
+            access_list: tx.msg.access_list().map(|al| {
+                al.iter()
+                    .map(|it| it.clone().into())
+                    .collect::<Vec<_>>()
+                    .into()
+            }),
+            chain_id: tx.msg.chain_id(),
+            max_fee_per_gas: tx.msg.fee_cap(),
+            max_priority_fee_per_gas: tx.msg.tip(),
+            v: tx.msg.v().as_u64().into(),
+            r: tx.msg.r(),
+            s: tx.msg.s(),
+            ..Default::default()
+        };
+        if let Some(fee_cap) = tx.msg.blob_fee_cap() {
+            out.other
+                .insert(BLOB_FEE_CAP.into(), serde_json::json!(fee_cap));
+        }
+        if let Some(hashes) = tx.msg.blob_versioned_hashes() {
+            out.other
+                .insert(BLOB_HASHES.into(), serde_json::json!(hashes));
+        }
+        out
+    }
+}
+
+/// Converts the tx to the model of its type. A legacy tx's `v` may be given
+/// either packed with its chain id, as in json-rpc, or as the parity alone
+/// with the chain id alongside, as the conversion above produces.
+impl From<&Transaction> for transaction::Transaction {
+    fn from(tx: &Transaction) -> Self {
+        let access_list = tx
+            .access_list
+            .as_ref()
+            .map(|al| al.0.iter().cloned().map(AccessTuple::from).collect())
+            .unwrap_or_default();
+        let chain_id = tx.chain_id.unwrap_or_default();
+        let v = U256::from(tx.v.as_u64());
+        let (nonce, gas) = (tx.nonce.as_u64(), tx.gas.as_u64());
+        let (to, data) = (TxAction::from(tx.to), tx.input.0.clone());
+        let tip = tx.max_priority_fee_per_gas.unwrap_or_default();
+        let fee_cap = tx.max_fee_per_gas.unwrap_or_default();
+        match tx.transaction_type.map(|t| t.as_u64()) {
+            Some(1) => Self::AccessList(AccessListTx {
+                chain_id,
+                nonce,
+                gas_price: tx.gas_price.unwrap_or_default(),
+                gas,
+                to,
+                value: tx.value,
+                data,
+                access_list,
+                v,
+                r: tx.r,
+                s: tx.s,
+            }),
+            Some(2) => Self::DynamicFee(DynamicFeeTx {
+                chain_id,
+                nonce,
+                tip,
+                fee_cap,
+                gas,
+                to,
+                value: tx.value,
+                data,
+                access_list,
+                v,
+                r: tx.r,
+                s: tx.s,
+            }),
+            Some(3) => Self::Blob(BlobTx {
+                chain_id,
+                nonce,
+                tip,
+                fee_cap,
+                gas,
+                to: tx.to.unwrap_or_default(),
+                value: tx.value,
+                data,
+                access_list,
+                blob_fee_cap: tx
+                    .other
+                    .get_deserialized(BLOB_FEE_CAP)
+                    .and_then(Result::ok)
+                    .unwrap_or_default(),
+                blob_versioned_hashes: tx
+                    .other
+                    .get_deserialized(BLOB_HASHES)
+                    .and_then(Result::ok)
+                    .unwrap_or_default(),
+                v,
+                r: tx.r,
+                s: tx.s,
+            }),
+            _ => Self::Legacy(LegacyTx {
+                nonce,
+                gas_price: tx.gas_price.unwrap_or_default(),
+                gas,
+                to,
+                value: tx.value,
+                data,
+                v: VPackChainId(match tx.chain_id {
+                    Some(id) if v < 2.into() => v + id * 2 + 35,
+                    _ => v,
+                }),
+                r: tx.r,
+                s: tx.s,
+            }),
+        }
+    }
+}
+
+impl From<AccessTuple> for AccessListItem {
+    fn from(src: AccessTuple) -> Self {
+        Self {
+            address: src.address,
+            storage_keys: src.slots,
+        }
+    }
+}
+
+impl From<AccessListItem> for AccessTuple {
+    fn from(src: AccessListItem) -> Self {
+        Self {
+            address: src.address,
+            slots: src.storage_keys,
+        }
+    }
+}
+
+/// Converts the header into a block without transactions or uncles.
+impl From<&BlockHeader> for Block<H256> {
+    fn from(h: &BlockHeader) -> Self {
+        Self {
+            hash: Some(h.hash()),
+            parent_hash: h.parent_hash,
+            uncles_hash: h.uncle_hash,
+            author: Some(h.coinbase),
+            state_root: h.root,
+            transactions_root: h.tx_hash,
+            receipts_root: h.receipts_hash,
+            number: Some(h.number.as_u64().into()),
+            gas_used: h.gas_used.into(),
+            gas_limit: h.gas_limit.into(),
+            extra_data: h.extra.clone().into(),
+            logs_bloom: Some(h.bloom),
+            timestamp: h.time.into(),
+            difficulty: h.difficulty,
+            mix_hash: Some(h.mix_digest),
+            nonce: Some(h.nonce),
+            base_fee_per_gas: h.base_fee,
+            withdrawals_root: h.withdrawals_root,
+            blob_gas_used: h.blob_gas_used.map(From::from),
+            excess_blob_gas: h.excess_blob_gas.map(From::from),
+            parent_beacon_block_root: h.parent_beacon_block_root,
+            ..Default::default()
+        }
+    }
+}
+
+/// Converts the header fields of the block. The seal fields of engines like
+/// AuRa are not carried over.
+impl<T> From<&Block<T>> for BlockHeader {
+    fn from(b: &Block<T>) -> Self {
+        Self {
+            parent_hash: b.parent_hash,
+            uncle_hash: b.uncles_hash,
+            coinbase: b.author.unwrap_or_default(),
+            root: b.state_root,
+            tx_hash: b.transactions_root,
+            receipts_hash: b.receipts_root,
+            bloom: b.logs_bloom.unwrap_or_default(),
+            difficulty: b.difficulty,
+            number: b.number.unwrap_or_default().as_u64().into(),
+            gas_limit: b.gas_limit.as_u64(),
+            gas_used: b.gas_used.as_u64(),
+            time: b.timestamp.as_u64(),
+            extra: b.extra_data.0.clone(),
+            mix_digest: b.mix_hash.unwrap_or_default(),
+            nonce: b.nonce.unwrap_or_default(),
+            base_fee: b.base_fee_per_gas,
+            withdrawals_root: b.withdrawals_root,
+            blob_gas_used: b.blob_gas_used.map(|n| n.as_u64()),
+            excess_blob_gas: b.excess_blob_gas.map(|n| n.as_u64()),
+            parent_beacon_block_root: b.parent_beacon_block_root,
+            seal: None,
+        }
+    }
+}
+
+impl From<&CborLog> for Log {
+    fn from(log: &CborLog) -> Self {
+        Self {
+            address: log.address,
+            topics: log.topics.clone(),
+            data: log.data.clone().into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&Log> for CborLog {
+    fn from(log: &Log) -> Self {
+        Self {
+            address: log.address,
+            topics: log.topics.clone(),
+            data: log.data.0.clone(),
+        }
+    }
+}
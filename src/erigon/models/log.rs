@@ -3,20 +3,24 @@ use crate::erigon::{
     models::BlockNumber,
 };
 use bytes::Bytes;
-use ethereum_types::{Address, H256};
+use ethereum_types::{Address, Bloom, BloomInput, H256};
 use serde::{Deserialize, Serialize};
 
 cbor_wrapper!(CborReceipts(Option<Vec<CborReceipt>>));
 
 // blocknum||log_index_in_tx
 tuple_key!(LogsKey(BlockNumber, u32));
+// address||shard_id_u32, the key of the LogAddressIndex table
+tuple_key!(LogAddressKey(Address, u32));
+// topic||shard_id_u32, the key of the LogTopicIndex table
+tuple_key!(LogTopicKey(H256, u32));
 cbor_wrapper!(CborLogs(Option<Vec<CborLog>>));
 
 #[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct CborLog {
-    address: Address,
-    topics: Vec<H256>,
-    data: Bytes,
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: Bytes,
     // block_number: u64,
     // tx_hash: H256,
     // tx_index: usize,
@@ -25,10 +29,39 @@ pub struct CborLog {
     // removed: bool,
 }
 
+/// Returns the bloom filter over the addresses and topics of `logs`, as
+/// stored in receipts and headers.
+pub fn logs_bloom<'a>(logs: impl IntoIterator<Item = &'a CborLog>) -> Bloom {
+    let mut bloom = Bloom::zero();
+    for log in logs {
+        bloom.accrue(BloomInput::Raw(log.address.as_bytes()));
+        for topic in &log.topics {
+            bloom.accrue(BloomInput::Raw(topic.as_bytes()));
+        }
+    }
+    bloom
+}
+
+/// Returns true if `bloom` may include a log from one of `addresses` whose
+/// topics match `topics`. An empty set of addresses, or of topics at a
+/// position, matches any. Blooms don't record topic positions, so this only
+/// checks that each position has a topic in the bloom, and like any bloom
+/// check it can give false positives but never false negatives.
+pub fn bloom_matches(bloom: &Bloom, addresses: &[Address], topics: &[Vec<H256>]) -> bool {
+    let any = |inputs: &mut dyn Iterator<Item = &[u8]>| {
+        let mut inputs = inputs.peekable();
+        inputs.peek().is_none() || inputs.any(|input| bloom.contains_input(BloomInput::Raw(input)))
+    };
+    any(&mut addresses.iter().map(|adr| adr.as_bytes()))
+        && topics
+            .iter()
+            .all(|set| any(&mut set.iter().map(|topic| topic.as_bytes())))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct CborReceipt {
-    tx_type: u8, //omitempty
-    post_state: Option<H256>,
-    status: u64,
-    cumulative_gas_used: u64,
+    pub tx_type: u8, //omitempty
+    pub post_state: Option<H256>,
+    pub status: u64,
+    pub cumulative_gas_used: u64,
 }
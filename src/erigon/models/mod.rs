@@ -1,13 +1,14 @@
 use bytes::Bytes;
 use ethereum_types::{Address, H256, U256};
-use eyre::Result;
 
 use crate::{
     erigon::{macros::*, utils::*},
+    error::bail,
     kv::{
         tables::VariableVec,
         traits::{TableDecode, TableEncode},
     },
+    Result,
 };
 
 pub mod transaction;
@@ -18,6 +19,26 @@ pub mod account;
 pub use account::*;
 pub mod log;
 pub use log::*;
+pub mod consensus;
+pub use consensus::*;
+pub mod trie;
+pub use trie::*;
+pub mod bytecode;
+pub use bytecode::Instruction;
+#[cfg(feature = "polygon")]
+pub mod bor;
+#[cfg(feature = "polygon")]
+pub use bor::*;
+#[cfg(feature = "caplin")]
+pub mod beacon;
+#[cfg(feature = "caplin")]
+pub use beacon::*;
+#[cfg(feature = "alloy")]
+mod alloy_compat;
+#[cfg(feature = "ethers-types")]
+mod ethers_compat;
+#[cfg(feature = "alloy")]
+pub use alloy_compat::{FromAlloy, ToAlloy};
 
 use crate::erigon::utils::consts::*;
 
@@ -25,11 +46,54 @@ use crate::erigon::utils::consts::*;
 constant_key!(LastHeaderKey, LastHeader);
 // the LastBlock table stores only one key, bytes("LastBlock")
 constant_key!(LastBlockKey, LastBlock);
+// the Sequence table's key for the next id in the BlockTransaction table
+constant_key!(TxSequenceKey, EthTx);
+
+/// The version of Erigon's db schema, stored in the DbInfo table as three
+/// big-endian u32s.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DbVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl std::fmt::Display for DbVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl TableEncode for DbVersion {
+    type Encoded = [u8; 12];
+    fn encode(self) -> Self::Encoded {
+        let mut buf = [0; 12];
+        buf[..4].copy_from_slice(&self.major.to_be_bytes());
+        buf[4..8].copy_from_slice(&self.minor.to_be_bytes());
+        buf[8..].copy_from_slice(&self.patch.to_be_bytes());
+        buf
+    }
+}
+
+impl TableDecode for DbVersion {
+    fn decode(b: &[u8]) -> Result<Self> {
+        if b.len() != 12 {
+            bail!("Invalid db version length: {}", b.len());
+        }
+        let word = |i: usize| u32::from_be_bytes(*arrayref::array_ref!(b, i, 4));
+        Ok(Self {
+            major: word(0),
+            minor: word(4),
+            patch: word(8),
+        })
+    }
+}
 
 // u64 newtype aliases
 u64_wrapper!(BlockNumber);
 u64_wrapper!(Incarnation);
 u64_wrapper!(TxIndex);
+u64_wrapper!(TxNum);
 
 // blocknum||blockhash
 tuple_key!(HeaderKey(BlockNumber, H256));
@@ -72,6 +136,18 @@ impl HashStorageKey {
     pub fn make(who: Address, inc: impl Into<Incarnation>, key: H256) -> Self {
         Self(keccak256(who).into(), inc.into(), keccak256(key).into())
     }
+    /// Splits the key into the HashedStorage table key and the dupsort subkey.
+    pub fn split(self) -> (HashedStorageKey, H256) {
+        (HashedStorageKey(self.0, self.1), self.2)
+    }
+}
+
+// keccak(address)||incarnation
+tuple_key!(HashedStorageKey(H256, Incarnation));
+impl HashedStorageKey {
+    pub fn make(who: Address, inc: impl Into<Incarnation>) -> Self {
+        Self(keccak256(who).into(), inc.into())
+    }
 }
 
 // The Issuance table also stores the amount burnt, prefixing the encoded block number with "burnt"
@@ -88,6 +164,14 @@ impl TableEncode for BurntKey {
         out
     }
 }
+impl TableDecode for BurntKey {
+    fn decode(b: &[u8]) -> Result<Self> {
+        match b.strip_prefix(b"burnt") {
+            Some(num) => Ok(Self(TableDecode::decode(num)?)),
+            None => bail!("Burnt key is missing its prefix"),
+        }
+    }
+}
 
 bytes_wrapper!(Rlp(Bytes));
 bytes_wrapper!(Bytecode(Bytes));
@@ -111,7 +195,7 @@ impl TableEncode for Vec<Address> {
 impl TableDecode for Vec<Address> {
     fn decode(b: &[u8]) -> Result<Self> {
         if b.len() % ADDRESS_LENGTH != 0 {
-            eyre::bail!("Slice len should be divisible by {}", ADDRESS_LENGTH);
+            bail!("Slice len should be divisible by {}", ADDRESS_LENGTH);
         }
 
         let mut v = Vec::with_capacity(b.len() / ADDRESS_LENGTH);
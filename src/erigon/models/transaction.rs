@@ -166,14 +166,63 @@ pub struct DynamicFeeTx {
     pub s: U256,
 }
 
+// Eip4844 transaction
+// 0x03 || rlp([chain_id, nonce, max_priority_fee_per_gas, max_fee_per_gas, gas_limit, to, value, data, access_list, max_fee_per_blob_gas, blob_versioned_hashes, sig_y_parity, sig_r, sig_s])
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, RlpDecodable, RlpEncodable)]
+pub struct BlobTx {
+    pub chain_id: U256,
+    pub nonce: u64,
+    pub tip: U256,
+    pub fee_cap: U256,
+    pub gas: u64,
+    // blob txs can't create contracts
+    pub to: Address,
+    pub value: U256,
+    pub data: Bytes,
+    pub access_list: AccessList,
+    pub blob_fee_cap: U256,
+    pub blob_versioned_hashes: Vec<H256>,
+    pub v: U256,
+    pub r: U256,
+    pub s: U256,
+}
+
 crate::erigon::macros::rlp_table_value!(Transaction);
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Transaction {
     Legacy(LegacyTx),
     AccessList(AccessListTx),
     DynamicFee(DynamicFeeTx),
+    Blob(BlobTx),
 }
 
+impl BlobTx {
+    pub const TYPE: u8 = 0x03;
+
+    // Decodes either the canonical form of the tx or the network form, which
+    // wraps the tx with its blobs: rlp([tx_payload_body, blobs, commitments, proofs]).
+    // The blobs aren't part of the tx itself, so they are skipped.
+    fn decode_network(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut payload = *buf;
+        let rlp_head = fastrlp::Header::decode(&mut payload)?;
+        if !rlp_head.list {
+            return Err(DecodeError::UnexpectedString);
+        }
+        // the canonical form begins with the chain id, never a list
+        if payload
+            .first()
+            .map_or(true, |b| *b < fastrlp::EMPTY_LIST_CODE)
+        {
+            return Decodable::decode(buf);
+        }
+        let rest = payload
+            .get(rlp_head.payload_length..)
+            .ok_or(DecodeError::InputTooShort)?;
+        let tx = Decodable::decode(&mut payload)?;
+        *buf = rest;
+        Ok(tx)
+    }
+}
 impl DynamicFeeTx {
     pub const TYPE: u8 = 0x02;
 }
@@ -195,18 +244,35 @@ impl Decodable for Transaction {
         match buf.get_u8() {
             AccessListTx::TYPE => Decodable::decode(buf).map(Self::AccessList),
             DynamicFeeTx::TYPE => Decodable::decode(buf).map(Self::DynamicFee),
+            BlobTx::TYPE => BlobTx::decode_network(buf).map(Self::Blob),
             _ => Err(DecodeError::Custom("Unknown transaction type")),
         }
     }
 }
 
 impl Encodable for Transaction {
+    fn length(&self) -> usize {
+        let payload_length = match self {
+            Self::Legacy(tx) => return tx.length(),
+            Self::AccessList(tx) => tx.length(),
+            Self::DynamicFee(tx) => tx.length(),
+            Self::Blob(tx) => tx.length(),
+        } + 1;
+        fastrlp::length_of_length(payload_length) + payload_length
+    }
+    // Mirrors the decoder: typed transactions are wrapped in an rlp string
+    // holding TransactionType || TransactionPayload.
     fn encode(&self, out: &mut dyn BufMut) {
-        match self {
-            Self::Legacy(tx) => tx.encode(out),
-            Self::AccessList(tx) => tx.encode(out),
-            Self::DynamicFee(tx) => tx.encode(out),
+        if let Self::Legacy(tx) = self {
+            return tx.encode(out);
         }
+        let signed = self.encode_signed();
+        fastrlp::Header {
+            list: false,
+            payload_length: signed.len(),
+        }
+        .encode(out);
+        out.put_slice(&signed);
     }
 }
 
@@ -215,6 +281,7 @@ impl Transaction {
         match self {
             Self::AccessList(_) => Some(AccessListTx::TYPE),
             Self::DynamicFee(_) => Some(DynamicFeeTx::TYPE),
+            Self::Blob(_) => Some(BlobTx::TYPE),
             Self::Legacy(_) => None,
         }
     }
@@ -223,13 +290,41 @@ impl Transaction {
             Self::Legacy(tx) => tx.hash(),
             Self::AccessList(tx) => tx.hash(),
             Self::DynamicFee(tx) => tx.hash(),
+            Self::Blob(tx) => tx.hash(),
+        }
+    }
+    /// Returns the encoding of the signed transaction, as it is hashed and
+    /// stored in the transactions trie: the rlp of the transaction, prefixed
+    /// by the type for typed transactions.
+    pub fn encode_signed(&self) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        match self {
+            Self::Legacy(tx) => tx.encode(&mut buf),
+            Self::AccessList(tx) => {
+                buf.put_u8(AccessListTx::TYPE);
+                tx.encode(&mut buf);
+            }
+            Self::DynamicFee(tx) => {
+                buf.put_u8(DynamicFeeTx::TYPE);
+                tx.encode(&mut buf);
+            }
+            Self::Blob(tx) => {
+                buf.put_u8(BlobTx::TYPE);
+                tx.encode(&mut buf);
+            }
         }
+        buf.to_vec()
+    }
+    /// Computes the hash of the signed transaction, i.e. the transaction hash.
+    pub fn tx_hash(&self) -> H256 {
+        keccak256(self.encode_signed()).into()
     }
     pub fn nonce(&self) -> u64 {
         match self {
             Self::Legacy(tx) => tx.nonce,
             Self::AccessList(tx) => tx.nonce,
             Self::DynamicFee(tx) => tx.nonce,
+            Self::Blob(tx) => tx.nonce,
         }
     }
     pub fn to(&self) -> TxAction {
@@ -237,6 +332,7 @@ impl Transaction {
             Self::Legacy(tx) => tx.to,
             Self::AccessList(tx) => tx.to,
             Self::DynamicFee(tx) => tx.to,
+            Self::Blob(tx) => TxAction::Call(tx.to),
         }
     }
     pub fn value(&self) -> U256 {
@@ -244,13 +340,14 @@ impl Transaction {
             Self::Legacy(tx) => tx.value,
             Self::AccessList(tx) => tx.value,
             Self::DynamicFee(tx) => tx.value,
+            Self::Blob(tx) => tx.value,
         }
     }
     pub fn gas_price(&self) -> Option<U256> {
         match self {
             Self::Legacy(tx) => Some(tx.gas_price),
             Self::AccessList(tx) => Some(tx.gas_price),
-            Self::DynamicFee(_) => None,
+            Self::DynamicFee(_) | Self::Blob(_) => None,
         }
     }
     pub fn chain_id(&self) -> Option<U256> {
@@ -258,17 +355,32 @@ impl Transaction {
             Self::Legacy(tx) => tx.v.derive_chain_id(),
             Self::AccessList(tx) => Some(tx.chain_id),
             Self::DynamicFee(tx) => Some(tx.chain_id),
+            Self::Blob(tx) => Some(tx.chain_id),
         }
     }
     pub fn tip(&self) -> Option<U256> {
         match self {
             Self::DynamicFee(tx) => Some(tx.tip),
+            Self::Blob(tx) => Some(tx.tip),
             _ => None,
         }
     }
     pub fn fee_cap(&self) -> Option<U256> {
         match self {
             Self::DynamicFee(tx) => Some(tx.fee_cap),
+            Self::Blob(tx) => Some(tx.fee_cap),
+            _ => None,
+        }
+    }
+    pub fn blob_fee_cap(&self) -> Option<U256> {
+        match self {
+            Self::Blob(tx) => Some(tx.blob_fee_cap),
+            _ => None,
+        }
+    }
+    pub fn blob_versioned_hashes(&self) -> Option<&[H256]> {
+        match self {
+            Self::Blob(tx) => Some(tx.blob_versioned_hashes.as_slice()),
             _ => None,
         }
     }
@@ -277,6 +389,7 @@ impl Transaction {
             Self::Legacy(tx) => tx.gas,
             Self::AccessList(tx) => tx.gas,
             Self::DynamicFee(tx) => tx.gas,
+            Self::Blob(tx) => tx.gas,
         }
     }
     pub fn data(&self) -> &Bytes {
@@ -284,6 +397,7 @@ impl Transaction {
             Self::Legacy(tx) => &tx.data,
             Self::AccessList(tx) => &tx.data,
             Self::DynamicFee(tx) => &tx.data,
+            Self::Blob(tx) => &tx.data,
         }
     }
     pub fn r(&self) -> U256 {
@@ -291,6 +405,7 @@ impl Transaction {
             Self::Legacy(tx) => tx.r,
             Self::AccessList(tx) => tx.r,
             Self::DynamicFee(tx) => tx.r,
+            Self::Blob(tx) => tx.r,
         }
     }
     pub fn s(&self) -> U256 {
@@ -298,6 +413,7 @@ impl Transaction {
             Self::Legacy(tx) => tx.s,
             Self::AccessList(tx) => tx.s,
             Self::DynamicFee(tx) => tx.s,
+            Self::Blob(tx) => tx.s,
         }
     }
     //TODO
@@ -306,6 +422,7 @@ impl Transaction {
             Self::Legacy(tx) => tx.v.derive_v(),
             Self::AccessList(tx) => tx.v,
             Self::DynamicFee(tx) => tx.v,
+            Self::Blob(tx) => tx.v,
         }
     }
 
@@ -313,6 +430,7 @@ impl Transaction {
         match self {
             Self::AccessList(tx) => Some(Cow::Borrowed(&tx.access_list)),
             Self::DynamicFee(tx) => Some(Cow::Borrowed(&tx.access_list)),
+            Self::Blob(tx) => Some(Cow::Borrowed(&tx.access_list)),
             Self::Legacy(_) => None,
         }
     }
@@ -443,47 +561,76 @@ impl DynamicFeeTx {
     }
 }
 
-pub struct TransactionWithSigner {
-    pub msg: Transaction,
-    pub signer: Address,
-}
+impl BlobTx {
+    /// Computes the (signing) hash of the transaction
+    pub fn hash(&self) -> H256 {
+        #[derive(RlpEncodable)]
+        struct AsHash<'a> {
+            chain_id: U256,
+            nonce: u64,
+            tip: &'a U256,
+            fee_cap: &'a U256,
+            gas: u64,
+            to: &'a Address,
+            value: &'a U256,
+            data: &'a Bytes,
+            access_list: &'a AccessList,
+            blob_fee_cap: &'a U256,
+            blob_versioned_hashes: &'a Vec<H256>,
+        }
 
-#[cfg(feature = "ethers-types")]
-impl From<TransactionWithSigner> for ethers::types::Transaction {
-    fn from(tx: TransactionWithSigner) -> Self {
-        Self {
-            hash: tx.msg.hash(),
-            nonce: tx.msg.nonce().into(),
-            from: tx.signer,
-            to: tx.msg.to().into(),
-            value: tx.msg.value(),
-            gas_price: tx.msg.gas_price(),
-            gas: tx.msg.gas().into(),
-            input: tx.msg.data().clone().into(),
-            transaction_type: tx.msg.tx_type().map(From::from),
-            access_list: tx.msg.access_list().map(|al| {
-                al.iter()
-                    .map(|it| it.clone().into())
-                    .collect::<Vec<_>>()
-                    .into()
-            }),
-            chain_id: tx.msg.chain_id(),
-            max_fee_per_gas: tx.msg.fee_cap(),
-            max_priority_fee_per_gas: tx.msg.tip(),
-            v: tx.msg.v().as_u64().into(),
-            r: tx.msg.r(),
-            s: tx.msg.s(),
-            ..Default::default()
+        let mut buf = BytesMut::new();
+        buf.put_u8(Self::TYPE);
+
+        AsHash {
+            chain_id: self.chain_id,
+            nonce: self.nonce,
+            tip: &self.tip,
+            fee_cap: &self.fee_cap,
+            gas: self.gas,
+            to: &self.to,
+            value: &self.value,
+            data: &self.data,
+            access_list: &self.access_list,
+            blob_fee_cap: &self.blob_fee_cap,
+            blob_versioned_hashes: &self.blob_versioned_hashes,
         }
+        .encode(&mut buf);
+
+        keccak256(buf).into()
     }
 }
 
-#[cfg(feature = "ethers-types")]
-impl From<AccessTuple> for ethers::types::transaction::eip2930::AccessListItem {
-    fn from(src: AccessTuple) -> Self {
-        Self {
-            address: src.address,
-            storage_keys: src.slots,
-        }
+#[cfg(feature = "secp256k1")]
+impl Transaction {
+    /// Recovers the address of the account that signed the transaction.
+    pub fn recover_sender(&self) -> crate::Result<Address> {
+        use crate::error::bail;
+        use secp256k1::{
+            ecdsa::{RecoverableSignature, RecoveryId},
+            Message, SECP256K1,
+        };
+        // legacy txs without an eip155 chain id use v = {0,1} + 27
+        let v = self.v();
+        let parity = if v >= U256::from(27) { v - 27 } else { v };
+        if parity > U256::one() {
+            bail!("Invalid signature parity: {}", v);
+        }
+        let recid = RecoveryId::from_i32(parity.as_u32() as i32)?;
+
+        let mut sig = [0; 64];
+        self.r().to_big_endian(&mut sig[..32]);
+        self.s().to_big_endian(&mut sig[32..]);
+        let sig = RecoverableSignature::from_compact(&sig, recid)?;
+        let msg = Message::from_slice(self.hash().as_bytes())?;
+
+        let pubkey = SECP256K1.recover_ecdsa(&msg, &sig)?;
+        let hash = keccak256(&pubkey.serialize_uncompressed()[1..]);
+        Ok(Address::from_slice(&hash[12..]))
     }
 }
+
+pub struct TransactionWithSigner {
+    pub msg: Transaction,
+    pub signer: Address,
+}
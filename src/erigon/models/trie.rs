@@ -0,0 +1,140 @@
+use ethereum_types::H256;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    erigon::{
+        macros::bytes_wrapper,
+        models::Incarnation,
+        utils::consts::{KECCAK_LENGTH, U64_LENGTH},
+    },
+    error::bail,
+    kv::{
+        tables::TooShort,
+        traits::{TableDecode, TableEncode},
+    },
+    Result,
+};
+
+// A path in the trie, stored one nibble per byte.
+bytes_wrapper!(Nibbles(Vec<u8>));
+impl Nibbles {
+    /// Unpacks each byte of `b` into two nibbles.
+    pub fn unpack(b: &[u8]) -> Self {
+        Self(b.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect())
+    }
+}
+
+/// Key for the TrieStorage table. keccak(address)||incarnation||nibbles
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TrieStorageKey {
+    pub hashed_adr: H256,
+    pub incarnation: Incarnation,
+    pub nibbles: Nibbles,
+}
+
+impl TableEncode for TrieStorageKey {
+    type Encoded = Vec<u8>;
+    fn encode(self) -> Self::Encoded {
+        let mut out = Vec::with_capacity(KECCAK_LENGTH + U64_LENGTH + self.nibbles.len());
+        out.extend_from_slice(&self.hashed_adr.encode());
+        out.extend_from_slice(&self.incarnation.encode());
+        out.extend_from_slice(&self.nibbles);
+        out
+    }
+}
+
+impl TableDecode for TrieStorageKey {
+    fn decode(b: &[u8]) -> Result<Self> {
+        if b.len() < KECCAK_LENGTH + U64_LENGTH {
+            return Err(TooShort::<{ KECCAK_LENGTH + U64_LENGTH }> { got: b.len() }.into());
+        }
+        let (hashed_adr, rest) = b.split_at(KECCAK_LENGTH);
+        let (incarnation, nibbles) = rest.split_at(U64_LENGTH);
+        Ok(Self {
+            hashed_adr: TableDecode::decode(hashed_adr)?,
+            incarnation: TableDecode::decode(incarnation)?,
+            nibbles: Nibbles(nibbles.to_vec()),
+        })
+    }
+}
+
+/// A branch node of the state or storage trie, as stored in Erigon's
+/// intermediate hash tables.
+///
+/// Each bitset is indexed by the nibble of the child:
+/// - `has_state`: the child subtrie contains at least one account or slot.
+/// - `has_tree`: the child is itself stored in the table.
+/// - `has_hash`: the hash of the child is stored in `hashes`, in nibble order.
+///
+/// https://github.com/ledgerwatch/erigon/blob/f9d7cb5ca9e8a135a76ddcb6fa4ee526ea383554/turbo/trie/trie_root.go#L1605
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TrieNode {
+    pub has_state: u16,
+    pub has_tree: u16,
+    pub has_hash: u16,
+    /// The hash of the node itself. Only stored for the root node.
+    pub root_hash: Option<H256>,
+    pub hashes: Vec<H256>,
+}
+
+impl TrieNode {
+    /// Returns the stored hash of the child at `nibble`, if any.
+    pub fn child_hash(&self, nibble: u8) -> Option<H256> {
+        let bit = 1u16.checked_shl(nibble.into())?;
+        if self.has_hash & bit == 0 {
+            return None;
+        }
+        let idx = (self.has_hash & (bit - 1)).count_ones() as usize;
+        self.hashes.get(idx).copied()
+    }
+}
+
+impl TableEncode for TrieNode {
+    type Encoded = Vec<u8>;
+    fn encode(self) -> Self::Encoded {
+        let num_hashes = self.hashes.len() + usize::from(self.root_hash.is_some());
+        let mut out = Vec::with_capacity(6 + num_hashes * KECCAK_LENGTH);
+        out.extend_from_slice(&self.has_state.to_be_bytes());
+        out.extend_from_slice(&self.has_tree.to_be_bytes());
+        out.extend_from_slice(&self.has_hash.to_be_bytes());
+        if let Some(root) = self.root_hash {
+            out.extend_from_slice(root.as_bytes());
+        }
+        for hash in self.hashes {
+            out.extend_from_slice(hash.as_bytes());
+        }
+        out
+    }
+}
+
+impl TableDecode for TrieNode {
+    fn decode(b: &[u8]) -> Result<Self> {
+        if b.len() < 6 {
+            return Err(TooShort::<6> { got: b.len() }.into());
+        }
+        let has_state = u16::from_be_bytes([b[0], b[1]]);
+        let has_tree = u16::from_be_bytes([b[2], b[3]]);
+        let has_hash = u16::from_be_bytes([b[4], b[5]]);
+        let mut rest = &b[6..];
+        if rest.len() % KECCAK_LENGTH != 0 {
+            bail!(
+                "Trie node hashes should be a multiple of {} bytes. Got {}",
+                KECCAK_LENGTH,
+                rest.len()
+            );
+        }
+        // the root node stores its own hash ahead of the child hashes
+        let mut root_hash = None;
+        if rest.len() / KECCAK_LENGTH == has_hash.count_ones() as usize + 1 {
+            root_hash = Some(H256::from_slice(&rest[..KECCAK_LENGTH]));
+            rest = &rest[KECCAK_LENGTH..];
+        }
+        Ok(Self {
+            has_state,
+            has_tree,
+            has_hash,
+            root_hash,
+            hashes: rest.chunks(KECCAK_LENGTH).map(H256::from_slice).collect(),
+        })
+    }
+}
@@ -0,0 +1,106 @@
+//! Queries over the CallFromIndex and CallToIndex tables, which Otterscan
+//! uses to page through the transactions of an address.
+//!
+//! Like the history tables, each index holds a bitmap of block numbers per
+//! address, split into shards as described in
+//! [`bitmap`](crate::erigon::bitmap). A block is in the bitmap if the address
+//! made or received any call in it, including internal calls.
+use crate::{
+    erigon::{models::*, tables::*, Erigon},
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+use ethereum_types::Address;
+use roaring::RoaringTreemap;
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Returns the blocks in which `adr` made a call.
+    pub fn read_call_from_blocks(&self, adr: Address) -> Result<RoaringTreemap> {
+        self.read_address_bitmap::<CallFromIndex>(adr)
+    }
+
+    /// Returns the blocks in which `adr` received a call.
+    pub fn read_call_to_blocks(&self, adr: Address) -> Result<RoaringTreemap> {
+        self.read_address_bitmap::<CallToIndex>(adr)
+    }
+
+    /// Returns up to `limit` of the blocks before block `before` in which
+    /// `adr` made or received a call, latest first. To fetch the next page,
+    /// pass the last block returned as `before`.
+    pub fn search_blocks_before(
+        &self,
+        adr: Address,
+        before: impl Into<BlockNumber>,
+        limit: usize,
+    ) -> Result<Vec<BlockNumber>> {
+        let map = self.read_call_from_blocks(adr)? | self.read_call_to_blocks(adr)?;
+        Ok(blocks_before(&map, *before.into())
+            .take(limit)
+            .map(BlockNumber)
+            .collect())
+    }
+
+    /// Returns up to `limit` of the blocks after block `after` in which `adr`
+    /// made or received a call, earliest first. To fetch the next page, pass
+    /// the last block returned as `after`.
+    pub fn search_blocks_after(
+        &self,
+        adr: Address,
+        after: impl Into<BlockNumber>,
+        limit: usize,
+    ) -> Result<Vec<BlockNumber>> {
+        let map = self.read_call_from_blocks(adr)? | self.read_call_to_blocks(adr)?;
+        let start = map.rank(*after.into());
+        Ok((start..map.len())
+            .take(limit)
+            .filter_map(|i| map.select(i))
+            .map(BlockNumber)
+            .collect())
+    }
+
+    /// Returns the transactions sent by `adr` in the canonical blocks before
+    /// block `before`, latest first, with the block and index of each. Blocks
+    /// are read until at least `page_size` transactions are found, so a page
+    /// always ends at a block boundary and may hold more than `page_size`
+    /// transactions. To fetch the next page, pass the block of the last
+    /// transaction returned as `before`.
+    pub fn transactions_sent_before(
+        &self,
+        adr: Address,
+        before: impl Into<BlockNumber>,
+        page_size: usize,
+    ) -> Result<Vec<(HeaderKey, u32, Transaction)>> {
+        let map = self.read_call_from_blocks(adr)?;
+        let mut page = vec![];
+        for num in blocks_before(&map, *before.into()) {
+            if page.len() >= page_size {
+                break;
+            }
+            let hash = match self.read_canonical_hash(num)? {
+                Some(hash) => hash,
+                None => continue,
+            };
+            let key = HeaderKey(num.into(), hash);
+            let (senders, txs) = match (self.read_senders(key)?, self.read_transactions(key)?) {
+                (Some(senders), Some(txs)) => (senders, txs),
+                _ => continue,
+            };
+            let sent = senders
+                .into_iter()
+                .zip(txs)
+                .enumerate()
+                .filter(|(_, (sender, _))| *sender == adr);
+            // latest first within the block, too
+            let sent = sent.collect::<Vec<_>>().into_iter().rev();
+            page.extend(sent.map(|(idx, (_, tx))| (key, idx as u32, tx)));
+        }
+        Ok(page)
+    }
+}
+
+// Iterates over the blocks in `map` that are < `before`, latest first.
+fn blocks_before(map: &RoaringTreemap, before: u64) -> impl Iterator<Item = u64> + '_ {
+    // rank() returns the number of blocks <= before - 1
+    let n = before.checked_sub(1).map_or(0, |b| map.rank(b));
+    (0..n).rev().filter_map(|i| map.select(i))
+}
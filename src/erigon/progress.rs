@@ -0,0 +1,151 @@
+//! Progress reporting for long-running operations, such as exports, audits,
+//! rebuilds and prunes. Operations report to a [`Progress`], which is
+//! implemented for closures taking a [`Tick`], for [`NoProgress`], and, with
+//! the `progress` feature, for [`ConsoleProgress`], a console progress bar.
+use std::time::{Duration, Instant};
+
+/// A report of how far a long-running operation has got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tick<'a> {
+    /// The operation, or the step of it under way, e.g. the table being
+    /// pruned.
+    pub op: &'static str,
+    /// The number of items, e.g. blocks or records, processed so far in `op`.
+    pub done: u64,
+    /// The number of items `op` will process, if known in advance.
+    pub total: Option<u64>,
+    /// The raw key being processed, for operations that walk a table.
+    pub key: Option<&'a [u8]>,
+    /// The time since `op` started.
+    pub elapsed: Duration,
+}
+
+impl Tick<'_> {
+    /// Estimates the time left in `op` from the rate so far. `None` if the
+    /// total is unknown or nothing has been processed yet.
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.total?;
+        if self.done == 0 {
+            return None;
+        }
+        let left = total.saturating_sub(self.done) as f64 / self.done as f64;
+        Some(self.elapsed.mul_f64(left))
+    }
+}
+
+/// Receives the progress of long-running operations.
+pub trait Progress {
+    /// Called periodically while an operation runs.
+    fn tick(&mut self, tick: &Tick<'_>);
+    /// Called once `op` is done.
+    fn finish(&mut self, _op: &'static str) {}
+}
+
+impl<F: FnMut(&Tick<'_>)> Progress for F {
+    fn tick(&mut self, tick: &Tick<'_>) {
+        self(tick)
+    }
+}
+
+/// Ignores all progress.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn tick(&mut self, _: &Tick<'_>) {}
+}
+
+// Times an operation to fill in the elapsed time of its ticks.
+pub(crate) struct Ticker<'p, P> {
+    progress: &'p mut P,
+    op: &'static str,
+    total: Option<u64>,
+    started: Instant,
+}
+
+impl<'p, P: Progress> Ticker<'p, P> {
+    pub(crate) fn new(progress: &'p mut P, op: &'static str, total: Option<u64>) -> Self {
+        Self {
+            progress,
+            op,
+            total,
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn tick(&mut self, done: u64, key: Option<&[u8]>) {
+        self.progress.tick(&Tick {
+            op: self.op,
+            done,
+            total: self.total,
+            key,
+            elapsed: self.started.elapsed(),
+        });
+    }
+
+    pub(crate) fn finish(self) {
+        self.progress.finish(self.op);
+    }
+}
+
+#[cfg(feature = "progress")]
+pub use console::ConsoleProgress;
+
+#[cfg(feature = "progress")]
+mod console {
+    use super::{Progress, Tick};
+    use indicatif::{ProgressBar, ProgressStyle};
+
+    /// Draws a progress bar on stderr, or a spinner for operations of
+    /// unknown length, with a new bar for each step of an operation.
+    #[derive(Debug, Default)]
+    pub struct ConsoleProgress {
+        bar: Option<(&'static str, ProgressBar)>,
+    }
+
+    impl ConsoleProgress {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        fn bar(&mut self, op: &'static str, total: Option<u64>) -> &ProgressBar {
+            if !matches!(&self.bar, Some((current, _)) if *current == op) {
+                let bar = match total {
+                    Some(total) => ProgressBar::new(total).with_style(
+                        ProgressStyle::with_template(
+                            "{prefix} [{elapsed_precise}] {wide_bar} {pos}/{len} eta {eta} {msg}",
+                        )
+                        .expect("template is valid"),
+                    ),
+                    None => ProgressBar::new_spinner().with_style(
+                        ProgressStyle::with_template(
+                            "{prefix} [{elapsed_precise}] {spinner} {pos} {msg}",
+                        )
+                        .expect("template is valid"),
+                    ),
+                };
+                bar.set_prefix(op);
+                self.bar = Some((op, bar));
+            }
+            &self.bar.as_ref().expect("bar was just set").1
+        }
+    }
+
+    impl Progress for ConsoleProgress {
+        fn tick(&mut self, tick: &Tick<'_>) {
+            let bar = self.bar(tick.op, tick.total);
+            bar.set_position(tick.done);
+            if let Some(key) = tick.key {
+                bar.set_message(key.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+            }
+        }
+
+        fn finish(&mut self, op: &'static str) {
+            if let Some((current, bar)) = &self.bar {
+                if *current == op {
+                    bar.finish();
+                }
+            }
+        }
+    }
+}
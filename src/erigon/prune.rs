@@ -0,0 +1,330 @@
+use crate::{
+    erigon::{
+        models::*,
+        none_if_no_table,
+        progress::{Progress, Ticker},
+        tables::*,
+        utils::consts as C,
+        Erigon, ErigonDb,
+    },
+    error::Error,
+    kv::traits::{
+        DbName, DefaultFlags, KvCursor, KvTx, KvTxMut, Mode, Table, TableDecode, TableEncode,
+    },
+    Result,
+};
+use bytes::Bytes;
+use mdbx::RW;
+use roaring::RoaringTreemap;
+use serde::Serialize;
+
+/// The result of pruning one table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PruneProgress {
+    pub table: &'static str,
+    /// The number of keys visited.
+    pub visited: u64,
+    /// The number of keys deleted.
+    pub deleted: u64,
+}
+
+/// How much of one kind of data Erigon keeps, as configured by its `--prune`
+/// flags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum PruneDistance {
+    /// Kept for every block.
+    #[default]
+    Archive,
+    /// Kept for the given number of blocks behind the head.
+    Older(u64),
+    /// Kept from the given block on.
+    Before(u64),
+}
+
+impl PruneDistance {
+    /// Returns the first block whose data is kept, once the stage that
+    /// prunes the data has reached `head`.
+    pub fn horizon(&self, head: u64) -> u64 {
+        match *self {
+            Self::Archive => 0,
+            Self::Older(distance) => head.saturating_sub(distance),
+            Self::Before(block) => block,
+        }
+    }
+
+    /// Returns true if the data of `block` is kept once the stage that
+    /// prunes the data has reached `head`.
+    pub fn keeps(&self, block: u64, head: u64) -> bool {
+        block >= self.horizon(head)
+    }
+}
+
+/// The prune configuration Erigon recorded in the DbInfo table (Erigon's
+/// DatabaseInfo) when the db was created. Each kind of data that isn't
+/// configured is kept for every block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PruneMode {
+    /// The AccountHistory, StorageHistory and changeset tables.
+    pub history: PruneDistance,
+    /// The Receipt and TransactionLog tables.
+    pub receipts: PruneDistance,
+    /// The BlockTransactionLookup table.
+    pub tx_index: PruneDistance,
+    /// The CallTraceSet table and the call indexes.
+    pub call_traces: PruneDistance,
+}
+
+impl PruneMode {
+    /// Returns true if no data is pruned.
+    pub fn is_archive(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Returns the prune configuration of the db. Dbs without a DbInfo
+    /// table, e.g. those created by [`Erigon::init_database`], are treated as
+    /// archives.
+    pub fn read_prune_mode(&self) -> Result<PruneMode> {
+        Ok(PruneMode {
+            history: self.read_prune_distance(C::PRUNE_HISTORY_KEY)?,
+            receipts: self.read_prune_distance(C::PRUNE_RECEIPTS_KEY)?,
+            tx_index: self.read_prune_distance(C::PRUNE_TX_INDEX_KEY)?,
+            call_traces: self.read_prune_distance(C::PRUNE_CALL_TRACES_KEY)?,
+        })
+    }
+
+    // Fails with `Error::Pruned` if `block` is before the horizon of the data
+    // pruned by `prune_key` in table `T`, given the progress of `stage`,
+    // which prunes it.
+    pub(crate) fn ensure_not_pruned<'tx, T: Table<'tx>>(
+        &'tx self,
+        prune_key: &'static [u8],
+        stage: &str,
+        block: BlockNumber,
+    ) -> Result<()> {
+        let distance = self.read_prune_distance(prune_key)?;
+        if distance == PruneDistance::Archive {
+            return Ok(());
+        }
+        let head = self.read_stage_progress(stage)?.unwrap_or_default();
+        let horizon = distance.horizon(*head);
+        if *block < horizon {
+            return Err(Error::Pruned {
+                table: <T::Name as DbName>::NAME,
+                needed_block: *block,
+                prune_horizon: horizon,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    // Reads the distance under `key` and its type under `key` + "Type".
+    // Erigon writes u64::MAX as the distance of data it keeps for every block.
+    fn read_prune_distance(&self, key: &'static [u8]) -> Result<PruneDistance> {
+        let val = match none_if_no_table(self.read::<DbInfo>(Bytes::from_static(key)))? {
+            Some(val) => val,
+            None => return Ok(PruneDistance::Archive),
+        };
+        let amount = match <[u8; 8]>::try_from(&val[..]) {
+            Ok(be) => u64::from_be_bytes(be),
+            Err(_) => {
+                return Err(Error::Incompatible(format!(
+                    "{} is {} bytes long",
+                    String::from_utf8_lossy(key),
+                    val.len()
+                ))
+                .into())
+            }
+        };
+        let type_key = Bytes::from([key, C::PRUNE_TYPE_SUFFIX].concat());
+        let kind = self.read::<DbInfo>(type_key)?;
+        Ok(match kind {
+            Some(kind) if kind[..] == *C::PRUNE_TYPE_BEFORE => PruneDistance::Before(amount),
+            _ if amount == u64::MAX => PruneDistance::Archive,
+            _ => PruneDistance::Older(amount),
+        })
+    }
+}
+
+impl<'env, Tx: KvTxMut> Erigon<'env, RW, Tx> {
+    // Deletes up to `limit` keys of table `T` that sort before `end`,
+    // including all of their duplicates. Returns the number of keys deleted.
+    fn delete_keys_before<'tx, T>(&'tx self, end: &[u8], limit: usize) -> Result<usize>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let mut keys = vec![];
+        let mut cur = self.0.cursor_raw::<T>()?;
+        let mut kv = cur.first()?;
+        while let Some((k, _)) = kv {
+            if k[..] >= *end || keys.len() >= limit {
+                break;
+            }
+            keys.push(k.into_owned());
+            kv = cur.next_nodup()?;
+        }
+        drop(cur);
+        for k in &keys {
+            self.0.delete_raw::<T>(k, None)?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Deletes the AccountChangeSet entries of up to `limit` blocks before
+    /// `before`. Returns the number of blocks deleted.
+    pub fn prune_account_changesets(&self, before: BlockNumber, limit: usize) -> Result<usize> {
+        self.delete_keys_before::<AccountChangeSet>(before.encode().as_ref(), limit)
+    }
+
+    /// Deletes up to `limit` StorageChangeSet keys, i.e. (block, address,
+    /// incarnation) triples, before block `before`. Returns the number of
+    /// keys deleted.
+    pub fn prune_storage_changesets(&self, before: BlockNumber, limit: usize) -> Result<usize> {
+        let end = StorageCSKey(before, Default::default()).encode();
+        self.delete_keys_before::<StorageChangeSet>(end.as_ref(), limit)
+    }
+
+    /// Removes the blocks before `before` from up to `limit` shards of
+    /// history table `T`, starting at the raw key `from`. Shards left empty
+    /// are deleted. Returns the number of shards visited and deleted, and
+    /// the key to resume from, if the end of the table wasn't reached.
+    pub fn prune_history_shards<'tx, T>(
+        &'tx self,
+        before: BlockNumber,
+        from: &[u8],
+        limit: usize,
+    ) -> Result<(usize, usize, Option<Vec<u8>>)>
+    where
+        T: Table<'tx, Value = RoaringTreemap> + DefaultFlags,
+    {
+        let mut updates = vec![];
+        let mut visited = 0;
+        let mut cur = self.0.cursor_raw::<T>()?;
+        let mut kv = cur.seek(from)?;
+        while let Some((k, v)) = kv {
+            if visited >= limit {
+                break;
+            }
+            visited += 1;
+            // every block in a shard is <= the block in its key suffix
+            let suffix = k.len().checked_sub(8).map(|i| &k[i..]);
+            if suffix.map_or(false, |n| n < &before.encode()[..]) {
+                updates.push((k.into_owned(), None));
+            } else {
+                let mut map = RoaringTreemap::decode(&v)?;
+                if map.min().map_or(false, |min| min < *before) {
+                    map.remove_range(..*before);
+                    let map = (!map.is_empty()).then_some(map);
+                    updates.push((k.into_owned(), map));
+                }
+            }
+            kv = cur.next()?;
+        }
+        let resume = kv.map(|(k, _)| k.into_owned());
+        drop(cur);
+
+        let mut deleted = 0;
+        for (k, map) in updates {
+            match map {
+                Some(map) => self.0.put_raw::<T>(&k, map.encode().as_ref())?,
+                None => {
+                    self.0.delete_raw::<T>(&k, None)?;
+                    deleted += 1;
+                }
+            }
+        }
+        Ok((visited, deleted, resume))
+    }
+}
+
+impl ErigonDb<RW> {
+    /// Deletes the account and storage changesets of every block before
+    /// `before`, committing after every `batch_size` keys and ticking
+    /// `progress`, with each table as the op, after every commit. Returns
+    /// the counts for each table.
+    ///
+    /// History can't be read for blocks whose changesets are gone, so prune
+    /// the history bitmaps to the same block with [`ErigonDb::prune_history`].
+    pub fn prune_changesets<P>(
+        &self,
+        before: impl Into<BlockNumber>,
+        batch_size: usize,
+        mut progress: P,
+    ) -> Result<Vec<PruneProgress>>
+    where
+        P: Progress,
+    {
+        let before = before.into();
+        let batch_size = batch_size.max(1);
+        let mut pruned = vec![];
+        for table in [AccountChangeSet::NAME, StorageChangeSet::NAME] {
+            let mut ticker = Ticker::new(&mut progress, table, None);
+            let mut p = PruneProgress {
+                table,
+                ..Default::default()
+            };
+            loop {
+                let deleted = self.update(|tx| {
+                    if table == AccountChangeSet::NAME {
+                        tx.prune_account_changesets(before, batch_size)
+                    } else {
+                        tx.prune_storage_changesets(before, batch_size)
+                    }
+                })?;
+                p.visited += deleted as u64;
+                p.deleted += deleted as u64;
+                ticker.tick(p.visited, None);
+                if deleted < batch_size {
+                    break;
+                }
+            }
+            ticker.finish();
+            pruned.push(p);
+        }
+        Ok(pruned)
+    }
+
+    /// Removes every block before `before` from the AccountHistory and
+    /// StorageHistory bitmaps, committing after every `batch_size` shards
+    /// and ticking `progress` after every commit with the number of shards
+    /// visited and the key to resume from. Returns the counts for each table.
+    pub fn prune_history<P>(
+        &self,
+        before: impl Into<BlockNumber>,
+        batch_size: usize,
+        mut progress: P,
+    ) -> Result<Vec<PruneProgress>>
+    where
+        P: Progress,
+    {
+        let before = before.into();
+        let batch_size = batch_size.max(1);
+        let mut pruned = vec![];
+        for table in [AccountHistory::NAME, StorageHistory::NAME] {
+            let mut ticker = Ticker::new(&mut progress, table, None);
+            let mut p = PruneProgress {
+                table,
+                ..Default::default()
+            };
+            let mut from = Some(vec![]);
+            while let Some(start) = from {
+                let (visited, deleted, resume) = self.update(|tx| {
+                    if table == AccountHistory::NAME {
+                        tx.prune_history_shards::<AccountHistory>(before, &start, batch_size)
+                    } else {
+                        tx.prune_history_shards::<StorageHistory>(before, &start, batch_size)
+                    }
+                })?;
+                p.visited += visited as u64;
+                p.deleted += deleted as u64;
+                ticker.tick(p.visited, resume.as_deref());
+                from = resume;
+            }
+            ticker.finish();
+            pruned.push(p);
+        }
+        Ok(pruned)
+    }
+}
@@ -0,0 +1,105 @@
+//! Ethash block and uncle rewards, which are credited outside of any tx and
+//! so don't show up in the tx-derived tables.
+use crate::{
+    erigon::{models::*, Erigon},
+    error::Error,
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+use ethereum_types::{Address, U256};
+use serde::Serialize;
+use serde_json::Value;
+
+const ETHER: u64 = 1_000_000_000_000_000_000;
+// Consensus engines that pay no block reward, as keyed in the chain config.
+const UNREWARDED_ENGINES: [&str; 3] = ["clique", "aura", "bor"];
+
+/// The rewards credited for a block, as computed by [`Erigon::block_reward`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct BlockReward {
+    /// The static reward, in wei.
+    pub static_reward: U256,
+    /// The reward for including the block's uncles, 1/32 of the static
+    /// reward per uncle.
+    pub inclusion_reward: U256,
+    /// The reward credited to the coinbase of each uncle, in the block's
+    /// uncle order.
+    pub uncles: Vec<(Address, U256)>,
+}
+
+impl BlockReward {
+    /// The total credited to the block's coinbase.
+    pub fn miner_reward(&self) -> U256 {
+        self.static_reward + self.inclusion_reward
+    }
+
+    /// The total issued for the block, to its miner and its uncles' miners.
+    pub fn total(&self) -> U256 {
+        self.uncles
+            .iter()
+            .fold(self.miner_reward(), |total, (_, reward)| total + reward)
+    }
+}
+
+/// Returns the static reward of `header` under the chain `config`, as
+/// returned by [`Erigon::read_chain_config`]: 5 ether, 3 from Byzantium and 2
+/// from Constantinople, and nothing after the merge, recognized by a zero
+/// difficulty, or on chains with a proof-of-authority engine.
+pub fn static_reward(config: &Value, header: &BlockHeader) -> U256 {
+    if header.difficulty.is_zero() || UNREWARDED_ENGINES.iter().any(|e| config.get(e).is_some()) {
+        return U256::zero();
+    }
+    let num = header.number.as_u64();
+    let active = |key: &str| {
+        config
+            .get(key)
+            .and_then(Value::as_u64)
+            .map_or(false, |b| num >= b)
+    };
+    let ether = if active("constantinopleBlock") {
+        2
+    } else if active("byzantiumBlock") {
+        3
+    } else {
+        5
+    };
+    U256::from(ether) * ETHER
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Computes the rewards Ethash credited for the block: the static reward
+    /// and uncle inclusion reward to its coinbase, and to the coinbase of
+    /// each uncle, (8 + uncle number - block number) / 8 of the static
+    /// reward. Fee payments are not included.
+    pub fn block_reward(&self, key: impl Into<HeaderKey>) -> Result<BlockReward> {
+        let key = key.into();
+        let header = self
+            .read_header(key)?
+            .ok_or(Error::MissingValue { what: "header" })?;
+        let genesis = self.read_canonical_hash(0)?.ok_or(Error::MissingValue {
+            what: "genesis hash",
+        })?;
+        let config = self
+            .read_chain_config(genesis)?
+            .ok_or(Error::MissingValue {
+                what: "chain config",
+            })?;
+        let base = static_reward(&config, &header);
+        if base.is_zero() {
+            return Ok(BlockReward::default());
+        }
+        let uncles = self.read_uncles(key)?;
+        let uncle_rewards = uncles
+            .iter()
+            .map(|uncle| {
+                let depth = header.number.saturating_sub(uncle.number).min(8.into());
+                (uncle.coinbase, base * (U256::from(8) - depth) / 8)
+            })
+            .collect();
+        Ok(BlockReward {
+            static_reward: base,
+            inclusion_reward: base / 32 * uncles.len(),
+            uncles: uncle_rewards,
+        })
+    }
+}
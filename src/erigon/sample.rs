@@ -0,0 +1,147 @@
+//! Sampling of a table's keys and values, to see where a table's disk usage
+//! comes from. mdbx's stat reports only a table's entry and page counts.
+use crate::{
+    erigon::Erigon,
+    kv::traits::{DbFlags, DbName, DefaultFlags, KvCursor, KvTx, Mode, Table},
+    Result,
+};
+use mdbx::DatabaseFlags;
+use serde::Serialize;
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    hash::{BuildHasher, Hasher},
+};
+
+/// The distribution of a set of sizes in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct SizeStats {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub p50: usize,
+    pub p99: usize,
+}
+
+impl SizeStats {
+    fn new(mut sizes: Vec<usize>) -> Self {
+        if sizes.is_empty() {
+            return Self::default();
+        }
+        sizes.sort_unstable();
+        let at = |q: f64| sizes[((sizes.len() - 1) as f64 * q).round() as usize];
+        Self {
+            min: sizes[0],
+            max: sizes[sizes.len() - 1],
+            mean: sizes.iter().sum::<usize>() as f64 / sizes.len() as f64,
+            p50: at(0.5),
+            p99: at(0.99),
+        }
+    }
+}
+
+/// The result of [`Erigon::sample_table`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TableSample {
+    pub table: &'static str,
+    /// The number of entries in the table, as reported by mdbx. For dupsorted
+    /// tables, every duplicate is an entry.
+    pub entries: usize,
+    /// The number of entries sampled.
+    pub samples: usize,
+    pub key_sizes: SizeStats,
+    pub value_sizes: SizeStats,
+    /// The estimated number of entries whose key starts with each byte,
+    /// scaled from the share of the samples that did.
+    pub prefixes: BTreeMap<u8, usize>,
+}
+
+// A xorshift generator. The samples don't need to be unpredictable, only
+// spread over the key space.
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        // RandomState is seeded randomly per process
+        Self(RandomState::new().build_hasher().finish() | 1)
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(len + 8);
+        while out.len() < len {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            out.extend_from_slice(&self.0.to_be_bytes());
+        }
+        out.truncate(len);
+        out
+    }
+}
+
+impl<'env, K: Mode> Erigon<'env, K> {
+    /// Samples `n` entries of table `T` by seeking to random keys, and
+    /// reports the sizes of their keys and values and an estimate of the
+    /// entries under each leading key byte. For dupsorted tables, each sample
+    /// also seeks to a random duplicate at the key it lands on.
+    ///
+    /// Random keys are drawn uniformly from the byte space, so entries that
+    /// follow large gaps between keys are sampled more often than their share
+    /// of the table. The estimates are rough for tables with unevenly spread
+    /// keys, such as those keyed by block number.
+    pub fn sample_table<'tx, T>(&'tx self, n: usize) -> Result<TableSample>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let name = <T::Name as DbName>::NAME;
+        let dupsort = <T::Flags as DbFlags>::FLAGS.contains(DatabaseFlags::DUP_SORT);
+        let entries = self.0.table_stat(name)?.entries;
+        let mut cur = self.0.cursor_raw::<T>()?;
+        // random keys have the length of the first key, which is the length
+        // of every key in most tables. mdbx::Cursor has inherent methods of
+        // the same names, hence the qualified calls.
+        let key_len = match KvCursor::first(&mut cur)? {
+            Some((k, _)) if n > 0 => k.len(),
+            _ => {
+                return Ok(TableSample {
+                    table: name,
+                    entries,
+                    ..Default::default()
+                })
+            }
+        };
+
+        let mut rng = Rng::new();
+        let (mut key_sizes, mut value_sizes) = (Vec::with_capacity(n), Vec::with_capacity(n));
+        let mut counts = BTreeMap::<u8, usize>::new();
+        for _ in 0..n {
+            // past the last key, wrap around to the first
+            let (k, mut v) = match cur.seek(&rng.bytes(key_len))? {
+                Some(kv) => kv,
+                None => KvCursor::first(&mut cur)?.expect("table is not empty"),
+            };
+            if dupsort {
+                if let Some(dup) = cur.seek_both_range(&k, &rng.bytes(v.len()))? {
+                    v = dup;
+                }
+            }
+            key_sizes.push(k.len());
+            value_sizes.push(v.len());
+            *counts
+                .entry(k.first().copied().unwrap_or_default())
+                .or_default() += 1;
+        }
+
+        let prefixes = counts
+            .into_iter()
+            .map(|(b, count)| (b, entries * count / n))
+            .collect();
+        Ok(TableSample {
+            table: name,
+            entries,
+            samples: n,
+            key_sizes: SizeStats::new(key_sizes),
+            value_sizes: SizeStats::new(value_sizes),
+            prefixes,
+        })
+    }
+}
@@ -0,0 +1,75 @@
+//! Storage slots of Solidity state variables, for reading contract state
+//! without hashing slot keys by hand.
+//!
+//! Solidity stores the value of `mapping[key]`, where the mapping is declared
+//! at slot `p`, at `keccak256(key . p)`, and element `i` of a dynamic array
+//! declared at slot `p` at `keccak256(p) + i`. Value-type keys are left-padded
+//! to 32 bytes, so an address key is passed as `H256::from(adr)`:
+//!
+//! ```ignore
+//! // the balance of `holder` in a token whose balances mapping is at slot 0
+//! let bal = tx.read_mapping_value(token, H256::zero(), holder.into(), block)?;
+//! ```
+use crate::{
+    erigon::{
+        models::{BlockNumber, Incarnation},
+        utils::keccak256,
+        Erigon,
+    },
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+use ethereum_types::{Address, H256, U256};
+
+/// Returns the slot of `mapping[key]` for a mapping declared at `base_slot`.
+pub fn map_slot(base_slot: H256, key: H256) -> H256 {
+    let mut preimage = [0; 64];
+    preimage[..32].copy_from_slice(key.as_bytes());
+    preimage[32..].copy_from_slice(base_slot.as_bytes());
+    H256(keccak256(preimage))
+}
+
+/// Returns the slot of element `index` of a dynamic array declared at
+/// `base_slot`, for elements that take up one slot each.
+pub fn array_slot(base_slot: H256, index: U256) -> H256 {
+    let start = U256::from_big_endian(&keccak256(base_slot));
+    let mut slot = H256::zero();
+    start
+        .overflowing_add(index)
+        .0
+        .to_big_endian(slot.as_bytes_mut());
+    slot
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Returns the current value of `mapping[key]` in the storage of `adr`'s
+    /// incarnation `inc`, for a mapping declared at `base_slot`.
+    pub fn read_mapping(
+        &self,
+        adr: Address,
+        inc: impl Into<Incarnation>,
+        base_slot: H256,
+        key: H256,
+    ) -> Result<Option<U256>> {
+        self.read_storage(adr, inc, map_slot(base_slot, key))
+    }
+
+    /// Returns the value of `mapping[key]` in the storage of `adr` after block
+    /// `block` was applied, like `eth_getStorageAt`, for a mapping declared at
+    /// `base_slot`. The storage of the account's incarnation at that block is
+    /// read, from history if it has changed since.
+    pub fn read_mapping_value(
+        &self,
+        adr: Address,
+        base_slot: H256,
+        key: H256,
+        block: impl Into<BlockNumber>,
+    ) -> Result<U256> {
+        let block = block.into();
+        let inc = match self.account_after(adr, block)? {
+            Some(acct) => acct.incarnation,
+            None => return Ok(U256::zero()),
+        };
+        self.storage_at(adr, inc, map_slot(base_slot, key), *block + 1)
+    }
+}
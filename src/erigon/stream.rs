@@ -0,0 +1,197 @@
+use ethereum_types::Address;
+use eyre::eyre;
+
+use crate::{
+    erigon::{models::*, strip_system_txs, tables::*, utils::consts::ADDRESS_LENGTH},
+    error::bail,
+    kv::{decode, traits::KvCursor, TableCursor},
+    Result,
+};
+
+/// An iterator over the transactions in a range of canonical blocks, along
+/// with their senders. See [`Erigon::stream_transactions`].
+///
+/// [`Erigon::stream_transactions`]: crate::erigon::Erigon::stream_transactions
+pub struct TxStream<'tx, C> {
+    canonical: TableCursor<'tx, C, CanonicalHeader>,
+    bodies: TableCursor<'tx, C, BlockBody>,
+    txs: TableCursor<'tx, C, BlockTransaction>,
+    senders: TableCursor<'tx, C, TxSender>,
+    from: BlockNumber,
+    to: BlockNumber,
+    started: bool,
+    done: bool,
+    block: Option<BlockTxs>,
+}
+
+/// The position of the stream within the current block.
+struct BlockTxs {
+    num: BlockNumber,
+    base_tx_id: TxIndex,
+    senders: Vec<Address>,
+    pos: u32,
+    amount: u32,
+}
+
+impl<'tx, C: KvCursor<'tx>> TxStream<'tx, C> {
+    pub fn new(
+        canonical: TableCursor<'tx, C, CanonicalHeader>,
+        bodies: TableCursor<'tx, C, BlockBody>,
+        txs: TableCursor<'tx, C, BlockTransaction>,
+        senders: TableCursor<'tx, C, TxSender>,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Self {
+        Self {
+            canonical,
+            bodies,
+            txs,
+            senders,
+            from,
+            to,
+            started: false,
+            done: false,
+            block: None,
+        }
+    }
+
+    fn try_next(&mut self) -> Result<Option<(BlockNumber, u32, Transaction, Address)>> {
+        while !self.done {
+            if let Some(block) = self.block.as_mut() {
+                if block.pos < block.amount {
+                    // txs for a block are stored contiguously, so we only need
+                    // to seek once per block
+                    let id = TxIndex(block.base_tx_id.0 + block.pos as u64);
+                    let next = if block.pos == 0 {
+                        self.txs.seek_exact(id)?
+                    } else {
+                        self.txs.next()?
+                    };
+                    let tx = match next {
+                        Some((k, tx)) if k == id => tx,
+                        _ => bail!("Missing transaction {} in block {}", block.pos, block.num),
+                    };
+                    let sender = *block.senders.get(block.pos as usize).ok_or_else(|| {
+                        eyre!("Missing sender {} in block {}", block.pos, block.num)
+                    })?;
+                    let item = (block.num, block.pos, tx, sender);
+                    block.pos += 1;
+                    return Ok(Some(item));
+                }
+            }
+            self.block = self.next_block()?;
+        }
+        Ok(None)
+    }
+
+    fn next_block(&mut self) -> Result<Option<BlockTxs>> {
+        let next = if self.started {
+            self.canonical.next()?
+        } else {
+            self.started = true;
+            self.canonical.seek(self.from)?
+        };
+        let (num, hash) = match next {
+            Some((num, hash)) if num <= self.to => (num, hash),
+            _ => {
+                self.done = true;
+                return Ok(None);
+            }
+        };
+        let key = HeaderKey(num, hash);
+        let body = self.seek_body(key)?;
+        let senders = match self.senders.seek(key)? {
+            Some((k, senders)) if k == key => senders,
+            _ => bail!("No senders for block {:?}", key),
+        };
+        Ok(Some(BlockTxs {
+            num,
+            base_tx_id: body.base_tx_id.into(),
+            senders,
+            pos: 0,
+            amount: body.tx_amount,
+        }))
+    }
+
+    // The BlockBody table also contains non-canonical bodies, so we may need
+    // to step past other bodies at the same height.
+    fn seek_body(&mut self, key: HeaderKey) -> Result<BodyForStorage> {
+        let mut kv = self.bodies.seek(key.0)?;
+        while let Some((k, body)) = kv {
+            if k == key {
+                return strip_system_txs(body, key);
+            }
+            if k.0 != key.0 {
+                break;
+            }
+            kv = self.bodies.next()?;
+        }
+        bail!("No body for block {:?}", key)
+    }
+}
+
+impl<'tx, C: KvCursor<'tx>> Iterator for TxStream<'tx, C> {
+    type Item = Result<(BlockNumber, u32, Transaction, Address)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.try_next();
+        if res.is_err() {
+            self.done = true;
+        }
+        res.transpose()
+    }
+}
+
+/// An iterator over the accounts in the PlainState table, skipping over the
+/// storage entries that share the table. See [`Erigon::walk_accounts`].
+///
+/// [`Erigon::walk_accounts`]: crate::erigon::Erigon::walk_accounts
+pub struct AccountWalker<'tx, C> {
+    cur: TableCursor<'tx, C, PlainState>,
+    start: Option<Vec<u8>>,
+    prefix: Vec<u8>,
+    done: bool,
+}
+
+impl<'tx, C: KvCursor<'tx>> AccountWalker<'tx, C> {
+    /// Walks accounts beginning at the first key >= `start` for as long as
+    /// the keys begin with `prefix`.
+    pub fn new(cur: TableCursor<'tx, C, PlainState>, start: Vec<u8>, prefix: Vec<u8>) -> Self {
+        Self {
+            cur,
+            start: Some(start),
+            prefix,
+            done: false,
+        }
+    }
+
+    fn try_next(&mut self) -> Result<Option<(Address, Account)>> {
+        while !self.done {
+            // next_nodup skips every storage slot stored under an
+            // address||incarnation key in one step
+            let kv = match self.start.take() {
+                Some(start) => self.cur.inner.seek(&start)?,
+                None => self.cur.inner.next_nodup()?,
+            };
+            match kv {
+                Some((k, _)) if !k.starts_with(&self.prefix) => self.done = true,
+                Some(kv) if kv.0.len() == ADDRESS_LENGTH => {
+                    return decode::<PlainState>(kv).map(Some)
+                }
+                Some(_) => continue,
+                None => self.done = true,
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'tx, C: KvCursor<'tx>> Iterator for AccountWalker<'tx, C> {
+    type Item = Result<(Address, Account)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.try_next();
+        if res.is_err() {
+            self.done = true;
+        }
+        res.transpose()
+    }
+}
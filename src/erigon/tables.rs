@@ -1,7 +1,7 @@
-use crate::{dupsort_table, erigon::models::*, table};
+use crate::{dupsort_table, erigon::models::*, kv::tables::TableInfo, table};
 use bytes::Bytes;
 use ethereum_types::{Address, H256, U256};
-use roaring::RoaringTreemap;
+use roaring::{RoaringBitmap, RoaringTreemap};
 
 // --- Erigon db schema version 6.0.0 ---
 
@@ -32,6 +32,10 @@ table!(CanonicalHeader          => BlockNumber  => H256);
 table!(BlockTransaction         => TxIndex      => Transaction);
 // key: index. val: rlp(tx). erigon: NonCanonicalTxs
 table!(NonCanonicalTransaction  => TxIndex      => Transaction);
+// key: bytes(table_name). val: the next id to allocate in the table
+table!(Sequence                 => TxSequenceKey => TxIndex);
+// key: bytes(stage_name). val: the block the stage has processed up to
+table!(SyncStage                => Bytes        => BlockNumber);
 // key: address||shard_id_u64. val: bitmap of blocks w/ change. erigon: AccountsHistory
 table!(AccountHistory           => AccountHistKey => RoaringTreemap);
 // key: address||slot||shard_id_u64. val: bitmap of blocks w/ change.
@@ -51,15 +55,19 @@ dupsort_table!(
 
 // key: keccak(address). val: encode(account). erigon: HashedAcccounts
 table!(HashedAccount            => H256             => Account);
-//TODO: also dupsorted
-// key: keccak(address)||incarnation||keccak(slot). val: slot_value
-table!(HashedStorage            => HashStorageKey   => U256);
+// key: keccak(address)||incarnation. val: keccak(slot)||slot_value (dupsorted). erigon: HashedStorage
+dupsort_table!(
+    HashedStorage => HashedStorageKey => (H256, U256),
+    subkey = H256
+);
 // key: code_hash. val: contract code
 table!(Code                     => H256             => Bytecode);
 // key: keccak256(address)||incarnation. val: code_hash. erigon: ContractCode
 table!(HashedCodeHash           => ContractCodeKey  => H256);
 // key: bytestring. val: bytestring. erigon: DatabaseInfo
 table!(DbInfo                   => Bytes            => Bytes);
+// key: genesis_hash. val: json(chain_config). erigon: Config
+table!(ChainConfig              => H256             => Bytes, rename = Config);
 // key: blocknum||blockhash. val: rlp(total_difficulty big.Int). erigon: HeaderTD
 table!(HeadersTotalDifficulty   => HeaderKey        => TotalDifficulty);
 // key: blocknum. val: total_issued
@@ -74,12 +82,157 @@ table!(Receipt                  => BlockNumber      => CborReceipts);
 // key: blocknum||log_index_in_tx. val: cbor(log). erigon: Log
 table!(TransactionLog           => LogsKey          => CborLogs);
 
+// key: nibbles. val: encode(trie_node). erigon: TrieOfAccounts
+table!(TrieAccount              => Nibbles          => TrieNode);
+// key: keccak(address)||incarnation||nibbles. val: encode(trie_node). erigon: TrieOfStorage
+table!(TrieStorage              => TrieStorageKey   => TrieNode);
+
+// key: blocknum||blockhash. val: epoch transition proof. erigon: Epoch
+table!(Epoch                    => HeaderKey        => Bytes, rename = DevEpoch);
+// key: blocknum||blockhash. val: pending epoch transition proof. erigon: PendingEpoch
+table!(PendingEpoch             => HeaderKey        => Bytes, rename = DevPendingEpoch);
+// key: blocknum||blockhash. val: json(clique_snapshot)
+table!(CliqueSeparate           => HeaderKey        => CliqueSnapshot);
+
+// --- Erigon 3 tables ---
+
+// key: blocknum. val: the txnum of the block's last system tx. txnums count
+// every tx of the canonical chain, including a system tx on each side of the
+// txs of each block
+table!(MaxTxNum                 => BlockNumber      => TxNum);
+
+// --- Polygon (bor) tables ---
+
+// key: blocknum. val: rlp(receipt) of the block's state-sync tx
+#[cfg(feature = "polygon")]
+table!(BorReceipts              => BlockNumber      => BorReceipt, rename = BorReceipt);
+// key: state_sync_tx_hash. val: blocknum. erigon: BorTxLookup
+#[cfg(feature = "polygon")]
+table!(BlockBorTransactionLookup => H256            => U256);
+// key: span_id. val: json(span)
+#[cfg(feature = "polygon")]
+table!(BorSpans                 => SpanId           => Span);
+// key: checkpoint_id. val: json(checkpoint)
+#[cfg(feature = "polygon")]
+table!(BorCheckpoints           => CheckpointId     => Checkpoint);
+
+// --- Caplin tables, in Caplin's own db rather than chaindata ---
+
+// key: slot. val: root of the canonical beacon block at slot
+#[cfg(feature = "caplin")]
+table!(CanonicalBlockRoots      => Slot             => H256);
+// key: beacon_block_root. val: slot
+#[cfg(feature = "caplin")]
+table!(BlockRootToSlot          => H256             => Slot);
+// key: beacon_block_root. val: state root
+#[cfg(feature = "caplin")]
+table!(BlockRootToStateRoot     => H256             => H256);
+// key: beacon_block_root. val: parent beacon block root
+#[cfg(feature = "caplin")]
+table!(BlockRootToParentRoot    => H256             => H256);
+// key: beacon_block_root. val: hash of the execution payload's block
+#[cfg(feature = "caplin")]
+table!(BlockRootToBlockHash     => H256             => H256);
+// key: beacon_block_root. val: number of the execution payload's block
+#[cfg(feature = "caplin")]
+table!(BlockRootToBlockNumber   => H256             => ExecutionBlockNumber);
+
+// --- Otterscan indexes, filled by Erigon's CallTraces stage ---
+
+// key: address||shard_id_u64. val: bitmap of blocks with a call from address
+#[cfg(feature = "otterscan")]
+table!(CallFromIndex            => AccountHistKey   => RoaringTreemap);
+// key: address||shard_id_u64. val: bitmap of blocks with a call to address
+#[cfg(feature = "otterscan")]
+table!(CallToIndex              => AccountHistKey   => RoaringTreemap);
+
+// --- Log indexes, filled by Erigon's LogIndex stage ---
+
+// key: topic||shard_id_u32. val: bitmap of blocks with a log with topic
+table!(LogTopicIndex            => LogTopicKey      => RoaringBitmap);
+// key: address||shard_id_u32. val: bitmap of blocks with a log from address
+table!(LogAddressIndex          => LogAddressKey    => RoaringBitmap);
+
 type Todo = Bytes;
-// erigon: TrieOfAccounts
-table!(TrieAccount => Todo => Todo);
-// erigon: TrieOfStorage
-table!(TrieStorage => Todo => Todo);
-table!(LogTopicIndex => Todo => Todo);
-table!(LogAddressIndex => Todo => Todo);
 // key: blocknum||address.
 dupsort_table!(CallTraceSet => Todo => Todo, subkey = Todo);
+
+/// Returns metadata for every table declared in this module.
+pub fn all_tables() -> Vec<TableInfo> {
+    #[allow(unused_mut)]
+    let mut tables = vec![
+        TableInfo::of::<LastHeader>(),
+        TableInfo::of::<LastBlock>(),
+        TableInfo::of::<IncarnationMap>(),
+        TableInfo::of::<BlockTransactionLookup>(),
+        TableInfo::of::<HeaderNumber>(),
+        TableInfo::of::<Header>(),
+        TableInfo::of::<BlockBody>(),
+        TableInfo::of::<PlainCodeHash>(),
+        TableInfo::of::<TxSender>(),
+        TableInfo::of::<CanonicalHeader>(),
+        TableInfo::of::<BlockTransaction>(),
+        TableInfo::of::<NonCanonicalTransaction>(),
+        TableInfo::of::<Sequence>(),
+        TableInfo::of::<SyncStage>(),
+        TableInfo::of::<AccountHistory>(),
+        TableInfo::of::<StorageHistory>(),
+        TableInfo::of::<AccountChangeSet>(),
+        TableInfo::of::<StorageChangeSet>(),
+        TableInfo::of::<PlainState>(),
+        TableInfo::of::<Storage>(),
+        TableInfo::of::<HashedAccount>(),
+        TableInfo::of::<HashedStorage>(),
+        TableInfo::of::<Code>(),
+        TableInfo::of::<HashedCodeHash>(),
+        TableInfo::of::<DbInfo>(),
+        TableInfo::of::<ChainConfig>(),
+        TableInfo::of::<HeadersTotalDifficulty>(),
+        TableInfo::of::<Issuance>(),
+        TableInfo::of::<Burnt>(),
+        TableInfo::of::<TEVMCode>(),
+        TableInfo::of::<Receipt>(),
+        TableInfo::of::<TransactionLog>(),
+        TableInfo::of::<TrieAccount>(),
+        TableInfo::of::<TrieStorage>(),
+        TableInfo::of::<Epoch>(),
+        TableInfo::of::<PendingEpoch>(),
+        TableInfo::of::<CliqueSeparate>(),
+        TableInfo::of::<MaxTxNum>(),
+        TableInfo::of::<LogTopicIndex>(),
+        TableInfo::of::<LogAddressIndex>(),
+        TableInfo::of::<CallTraceSet>(),
+    ];
+    #[cfg(feature = "polygon")]
+    tables.extend([
+        TableInfo::of::<BorReceipts>(),
+        TableInfo::of::<BlockBorTransactionLookup>(),
+        TableInfo::of::<BorSpans>(),
+        TableInfo::of::<BorCheckpoints>(),
+    ]);
+    #[cfg(feature = "caplin")]
+    tables.extend([
+        TableInfo::of::<CanonicalBlockRoots>(),
+        TableInfo::of::<BlockRootToSlot>(),
+        TableInfo::of::<BlockRootToStateRoot>(),
+        TableInfo::of::<BlockRootToParentRoot>(),
+        TableInfo::of::<BlockRootToBlockHash>(),
+        TableInfo::of::<BlockRootToBlockNumber>(),
+    ]);
+    #[cfg(feature = "otterscan")]
+    tables.extend([
+        TableInfo::of::<CallFromIndex>(),
+        TableInfo::of::<CallToIndex>(),
+    ]);
+    tables
+}
+
+/// Returns the tables stored in the mdbx database named `db_name`. More than
+/// one table type may decode the same database, e.g. `PlainState` holds both
+/// [`PlainState`] and [`Storage`].
+pub fn find_tables(db_name: &str) -> Vec<TableInfo> {
+    all_tables()
+        .into_iter()
+        .filter(|t| t.db_name == db_name)
+        .collect()
+}
@@ -0,0 +1,211 @@
+use crate::{
+    erigon::{env_open, models::*, tables::MaxTxNum, Erigon},
+    kv::MdbxEnv,
+    snapshots::{
+        domains::{self, Domain, DomainReader, Domains},
+        BlockSnapshots,
+    },
+    Error, Result,
+};
+use ethereum_types::{Address, H256, U256};
+use mdbx::RO;
+use std::path::Path;
+
+/// An Erigon datadir read through both of its tiers: the mdbx chaindata and
+/// the frozen block segments and domain files.
+///
+/// Erigon deletes blocks from mdbx once they are frozen into segments, so
+/// [`Erigon::read_header`] and friends return `None` for old blocks. The
+/// transactions of a `TieredErigon` route block reads to the segments below
+/// the frozen boundary and to mdbx above it. On Erigon 3 datadirs, the
+/// history of accounts and storage is likewise read from the domain files
+/// before mdbx.
+#[derive(Debug)]
+pub struct TieredErigon {
+    env: MdbxEnv<RO>,
+    snapshots: BlockSnapshots,
+    accounts: DomainReader,
+    storage: DomainReader,
+}
+
+impl TieredErigon {
+    /// Opens `<datadir>/chaindata` and the segments and domain files in
+    /// `<datadir>/snapshots`. A datadir without a snapshots directory is read
+    /// from mdbx alone.
+    pub fn open(datadir: &Path) -> Result<Self> {
+        let env = env_open(&datadir.join("chaindata"))?;
+        let dir = datadir.join("snapshots");
+        if !dir.is_dir() {
+            return Ok(Self::new(env, BlockSnapshots::default()));
+        }
+        Self::new(env, BlockSnapshots::open(&dir)?).with_domains(&Domains::open(&dir)?)
+    }
+
+    pub fn new(env: MdbxEnv<RO>, snapshots: BlockSnapshots) -> Self {
+        Self {
+            env,
+            snapshots,
+            accounts: DomainReader::default(),
+            storage: DomainReader::default(),
+        }
+    }
+
+    /// Reads the history of accounts and storage from `domains` as well.
+    pub fn with_domains(mut self, domains: &Domains) -> Result<Self> {
+        self.accounts = domains.reader(Domain::Accounts)?;
+        self.storage = domains.reader(Domain::Storage)?;
+        Ok(self)
+    }
+
+    pub fn env(&self) -> &MdbxEnv<RO> {
+        &self.env
+    }
+
+    pub fn snapshots(&self) -> &BlockSnapshots {
+        &self.snapshots
+    }
+
+    /// Begins a read-only transaction.
+    pub fn begin(&self) -> Result<TieredTx<'_>> {
+        Ok(TieredTx {
+            db: Erigon::begin(&self.env)?,
+            snapshots: &self.snapshots,
+            accounts: &self.accounts,
+            storage: &self.storage,
+        })
+    }
+}
+
+/// A read-only transaction over both tiers of a [`TieredErigon`]. Only the
+/// reads below are routed through the tiers; everything else, e.g. the
+/// current state, is read from [`TieredTx::mdbx`].
+pub struct TieredTx<'env> {
+    db: Erigon<'env, RO>,
+    snapshots: &'env BlockSnapshots,
+    accounts: &'env DomainReader,
+    storage: &'env DomainReader,
+}
+
+impl<'env> TieredTx<'env> {
+    /// The mdbx tier. Block reads made through it miss frozen blocks.
+    pub fn mdbx(&self) -> &Erigon<'env, RO> {
+        &self.db
+    }
+
+    /// Returns true if block `num` is read from the segments.
+    pub fn is_frozen(&self, num: impl Into<BlockNumber>) -> bool {
+        num.into().0 < self.snapshots.frozen_blocks()
+    }
+
+    // Segments only hold canonical blocks, so a frozen key is only read from
+    // them if its hash matches. Other keys fall through to mdbx.
+    fn frozen_num(&self, key: HeaderKey) -> Result<Option<u64>> {
+        let HeaderKey(BlockNumber(num), hash) = key;
+        if !self.is_frozen(num) {
+            return Ok(None);
+        }
+        let frozen = self.snapshots.read_header_hash(num)?;
+        Ok((frozen == Some(hash)).then(|| num))
+    }
+
+    /// Returns the hash of the canonical block numbered `num`.
+    pub fn read_canonical_hash(&self, num: impl Into<BlockNumber>) -> Result<Option<H256>> {
+        let num = num.into();
+        if self.is_frozen(num) {
+            self.snapshots.read_header_hash(num.0)
+        } else {
+            self.db.read_canonical_hash(num)
+        }
+    }
+
+    /// Returns the block header identified by the (block number, block hash) key
+    pub fn read_header(&self, key: impl Into<HeaderKey>) -> Result<Option<BlockHeader>> {
+        let key = key.into();
+        match self.frozen_num(key)? {
+            Some(num) => self.snapshots.read_header(num),
+            None => self.db.read_header(key),
+        }
+    }
+
+    /// Returns the decoding of the body, excluding the system txs
+    pub fn read_body_for_storage(
+        &self,
+        key: impl Into<HeaderKey>,
+    ) -> Result<Option<BodyForStorage>> {
+        let key = key.into();
+        match self.frozen_num(key)? {
+            Some(num) => self.snapshots.read_body_for_storage(num),
+            None => self.db.read_body_for_storage(key),
+        }
+    }
+
+    /// Returns the transactions in the block.
+    pub fn read_transactions(&self, key: impl Into<HeaderKey>) -> Result<Option<Vec<Transaction>>> {
+        let key = key.into();
+        match self.frozen_num(key)? {
+            Some(num) => self.snapshots.read_transactions(num),
+            None => self.db.read_transactions(key),
+        }
+    }
+
+    /// Returns the signers of each transaction in the block.
+    pub fn read_senders(&self, key: impl Into<HeaderKey>) -> Result<Option<Vec<Address>>> {
+        let key = key.into();
+        match self.frozen_num(key)? {
+            Some(num) => self.snapshots.read_senders(num),
+            None => self.db.read_senders(key),
+        }
+    }
+
+    // The txnum of the first system tx of block `num`.
+    fn first_txnum(&self, num: BlockNumber) -> Result<u64> {
+        let prev = match num.0.checked_sub(1) {
+            Some(prev) => prev,
+            None => return Ok(0),
+        };
+        let max = self
+            .db
+            .read::<MaxTxNum>(prev.into())?
+            .ok_or(Error::MissingValue { what: "max txnum" })?;
+        Ok(max.0 + 1)
+    }
+
+    /// Returns the state of account `adr` as of block `block`, i.e. before the
+    /// block is applied. A change frozen into the accounts history files is
+    /// read from them, and later changes from mdbx, as in
+    /// [`Erigon::account_at`].
+    pub fn account_at(
+        &self,
+        adr: Address,
+        block: impl Into<BlockNumber>,
+    ) -> Result<Option<Account>> {
+        let block = block.into();
+        if !self.accounts.is_empty() {
+            let txnum = self.first_txnum(block)?;
+            if let Some(val) = self.accounts.before(adr.as_bytes(), txnum)? {
+                return domains::decode_account(&val);
+            }
+        }
+        self.db.account_at(adr, block)
+    }
+
+    /// Returns the value of the storage slot as of block `block`, read from
+    /// the storage history files before mdbx like [`TieredTx::account_at`].
+    pub fn storage_at(
+        &self,
+        adr: Address,
+        inc: impl Into<Incarnation>,
+        slot: H256,
+        block: impl Into<BlockNumber>,
+    ) -> Result<U256> {
+        let block = block.into();
+        if !self.storage.is_empty() {
+            let txnum = self.first_txnum(block)?;
+            let key = [adr.as_bytes(), slot.as_bytes()].concat();
+            if let Some(val) = self.storage.before(&key, txnum)? {
+                return domains::decode_storage(&val);
+            }
+        }
+        self.db.storage_at(adr, inc, slot, block)
+    }
+}
@@ -0,0 +1,257 @@
+//! Transaction tracing with revm, enabled by the `evm` feature. The traces
+//! have the shapes returned by `debug_traceTransaction` with geth's
+//! `callTracer` and `prestateTracer`.
+use crate::{
+    erigon::{
+        evm::tx_env,
+        json::hex_data,
+        models::{BlockNumber, FromAlloy},
+        Erigon,
+    },
+    error::Error,
+    kv::traits::{KvTx, Mode},
+    Result,
+};
+use bytes::Bytes;
+use ethereum_types::{Address, H256, U256, U64};
+use eyre::eyre;
+use revm::{
+    db::CacheDB,
+    inspector_handle_register,
+    interpreter::{
+        CallInputs, CallOutcome, CallScheme, CreateInputs, CreateOutcome, CreateScheme,
+        InterpreterResult,
+    },
+    Database, DatabaseRef, Evm, EvmContext, Inspector,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The tracers [`Erigon::trace_transaction`] can run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TracerKind {
+    /// The tree of calls the tx made, like geth's `callTracer`.
+    CallTracer,
+    /// The state the tx read, as it was before the tx, like geth's
+    /// `prestateTracer`.
+    Prestate,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum Trace {
+    Call(CallFrame),
+    Prestate(BTreeMap<Address, PrestateAccount>),
+}
+
+/// A call or contract creation, with the calls it made in turn.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallFrame {
+    /// The opcode that made the call, e.g. "CALL" or "CREATE2".
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub from: Address,
+    /// The callee, or the created contract. `None` if a creation failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<Address>,
+    pub value: U256,
+    pub gas: U64,
+    pub gas_used: U64,
+    #[serde(serialize_with = "hex_data")]
+    pub input: Bytes,
+    #[serde(serialize_with = "hex_data")]
+    pub output: Bytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<CallFrame>,
+}
+
+/// The state of an account before the traced tx.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PrestateAccount {
+    pub balance: U256,
+    pub nonce: u64,
+    #[serde(serialize_with = "hex_data", skip_serializing_if = "Bytes::is_empty")]
+    pub code: Bytes,
+    /// The slots the tx read or wrote, with their values before the tx.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<H256, H256>,
+}
+
+// Builds the call tree from revm's frame callbacks.
+#[derive(Default)]
+struct CallTracer {
+    stack: Vec<CallFrame>,
+    root: Option<CallFrame>,
+}
+
+impl CallTracer {
+    fn exit(&mut self, res: &InterpreterResult, created: Option<Address>) {
+        let mut frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        frame.gas_used = res.gas.spent().into();
+        frame.output = res.output.0.clone();
+        if frame.kind.starts_with("CREATE") {
+            frame.to = created;
+        }
+        if res.is_revert() {
+            frame.error = Some("execution reverted".into());
+        } else if !res.is_ok() {
+            frame.error = Some(format!("{:?}", res.result));
+        }
+        match self.stack.last_mut() {
+            Some(parent) => parent.calls.push(frame),
+            None => self.root = Some(frame),
+        }
+    }
+}
+
+impl<DB: Database> Inspector<DB> for CallTracer {
+    fn call(&mut self, _: &mut EvmContext<DB>, inputs: &mut CallInputs) -> Option<CallOutcome> {
+        let kind = match inputs.scheme {
+            CallScheme::Call => "CALL",
+            CallScheme::CallCode => "CALLCODE",
+            CallScheme::DelegateCall => "DELEGATECALL",
+            CallScheme::StaticCall => "STATICCALL",
+        };
+        self.stack.push(CallFrame {
+            kind,
+            from: Address::from_alloy(inputs.caller),
+            to: Some(Address::from_alloy(inputs.target_address)),
+            value: U256::from_alloy(inputs.call_value()),
+            gas: inputs.gas_limit.into(),
+            input: inputs.input.0.clone(),
+            ..Default::default()
+        });
+        None
+    }
+
+    fn call_end(
+        &mut self,
+        _: &mut EvmContext<DB>,
+        _: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        self.exit(&outcome.result, None);
+        outcome
+    }
+
+    fn create(
+        &mut self,
+        _: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        let kind = match inputs.scheme {
+            CreateScheme::Create => "CREATE",
+            CreateScheme::Create2 { .. } => "CREATE2",
+        };
+        self.stack.push(CallFrame {
+            kind,
+            from: Address::from_alloy(inputs.caller),
+            value: U256::from_alloy(inputs.value),
+            gas: inputs.gas_limit.into(),
+            input: inputs.init_code.0.clone(),
+            ..Default::default()
+        });
+        None
+    }
+
+    fn create_end(
+        &mut self,
+        _: &mut EvmContext<DB>,
+        _: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        self.exit(&outcome.result, outcome.address.map(Address::from_alloy));
+        outcome
+    }
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Re-executes the canonical tx with hash `hash` and traces it with
+    /// `kind`. The tx runs against the state before its block, with the txs
+    /// before it in the block applied. System calls made at the start of a
+    /// block, e.g. EIP-4788's beacon root update, are not applied.
+    ///
+    /// Returns `None` if the tx isn't in the BlockTransactionLookup table.
+    pub fn trace_transaction(&self, hash: H256, kind: TracerKind) -> Result<Option<Trace>> {
+        let (key, idx, tx) = match self.read_transaction_by_hash(hash)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let (ctx, txs) = self.block_txs(key)?;
+        let sender = txs
+            .get(idx as usize)
+            .map(|(_, sender)| *sender)
+            .ok_or(Error::MissingValue { what: "sender" })?;
+
+        let parent = BlockNumber(key.0.saturating_sub(1));
+        let mut state = CacheDB::new(self.state_after(parent));
+        for (tx, sender) in txs.iter().take(idx as usize) {
+            ctx.transact_commit(&mut state, tx, *sender)?;
+        }
+
+        let env = ctx.env(tx_env(&tx, sender));
+        match kind {
+            TracerKind::CallTracer => {
+                let mut evm = Evm::builder()
+                    .with_db(&mut state)
+                    .with_external_context(CallTracer::default())
+                    .with_env(env)
+                    .with_spec_id(ctx.spec)
+                    .append_handler_register(inspector_handle_register)
+                    .build();
+                let res = evm
+                    .transact()
+                    .map_err(|e| eyre!("tx {:?} failed: {}", hash, e))?;
+                let mut root = evm
+                    .context
+                    .external
+                    .root
+                    .take()
+                    .ok_or_else(|| eyre!("tx {:?} made no call", hash))?;
+                // the top frame accounts for the whole tx, including intrinsic gas
+                root.gas = tx.gas().into();
+                root.gas_used = res.result.gas_used().into();
+                Ok(Some(Trace::Call(root)))
+            }
+            TracerKind::Prestate => {
+                let res = Evm::builder()
+                    .with_db(&mut state)
+                    .with_env(env)
+                    .with_spec_id(ctx.spec)
+                    .build()
+                    .transact()
+                    .map_err(|e| eyre!("tx {:?} failed: {}", hash, e))?;
+                let mut prestate = BTreeMap::new();
+                for (adr, acct) in res.state {
+                    let info = match state.basic_ref(adr)? {
+                        Some(info) => info,
+                        None => continue,
+                    };
+                    let code = state.code_by_hash_ref(info.code_hash)?;
+                    let storage = acct
+                        .storage
+                        .iter()
+                        .map(|(slot, val)| {
+                            let slot = H256(slot.to_be_bytes());
+                            (slot, H256(val.original_value().to_be_bytes()))
+                        })
+                        .collect();
+                    let acct = PrestateAccount {
+                        balance: U256::from_alloy(info.balance),
+                        nonce: info.nonce,
+                        code: code.original_bytes().0,
+                        storage,
+                    };
+                    prestate.insert(Address::from_alloy(adr), acct);
+                }
+                Ok(Some(Trace::Prestate(prestate)))
+            }
+        }
+    }
+}
@@ -0,0 +1,511 @@
+//! Merkle Patricia trie root computation.
+//!
+//! Roots are computed by building the trie in memory from a sorted list of
+//! leaves, so computing the state root of a large chain requires a
+//! correspondingly large amount of memory. Storage roots of individual
+//! accounts are cheap. The `trie_*` roots avoid most of that by reusing the
+//! subtrie hashes Erigon keeps in its intermediate hash tables.
+use ethereum_types::{Address, H256, U256};
+use hex_literal::hex;
+
+use crate::{
+    erigon::{
+        models::*,
+        tables::{BlockTransaction, HashedStorage, TrieAccount, TrieStorage},
+        utils::{consts::EMPTY_HASH, keccak256},
+        Erigon,
+    },
+    error::{ensure, Error},
+    kv::traits::{KvTx, Mode, TableEncode},
+    Result,
+};
+
+/// The root of an empty trie, keccak256(rlp("")).
+pub const EMPTY_ROOT: H256 = H256(hex!(
+    "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"
+));
+
+/// Computes the root of the trie containing the given (key, rlp(value))
+/// leaves. Keys must be unique but need not be sorted, and one key may be a
+/// prefix of another.
+pub fn trie_root<I, K>(leaves: I) -> H256
+where
+    I: IntoIterator<Item = (K, Vec<u8>)>,
+    K: AsRef<[u8]>,
+{
+    root_of(
+        leaves
+            .into_iter()
+            .map(|(k, v)| (to_nibbles(k.as_ref()), v))
+            .collect(),
+    )
+}
+
+/// Computes the root of the trie keyed by the rlp encoding of each value's
+/// index, as used for the txs and receipts of a block.
+pub fn ordered_trie_root<I>(values: I) -> H256
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    root_of(
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (to_nibbles(&rlp_string(&U256::from(i as u64).encode())), v))
+            .collect(),
+    )
+}
+
+fn root_of(mut leaves: Vec<(Vec<u8>, Vec<u8>)>) -> H256 {
+    if leaves.is_empty() {
+        return EMPTY_ROOT;
+    }
+    leaves.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    keccak256(encode_node(&leaves, 0)).into()
+}
+
+/// Returns the rlp encoding of an account as it is stored in the state trie.
+pub fn account_leaf(acct: &Account, storage_root: H256) -> Vec<u8> {
+    // accounts with no code store no codehash in erigon
+    let codehash = if acct.codehash.is_zero() {
+        EMPTY_HASH
+    } else {
+        acct.codehash
+    };
+    rlp_list(&[
+        rlp_string(&U256::from(acct.nonce).encode()),
+        rlp_string(&acct.balance.encode()),
+        rlp_string(storage_root.as_bytes()),
+        rlp_string(codehash.as_bytes()),
+    ])
+}
+
+/// Returns the rlp encoding of a storage value as it is stored in a storage trie.
+pub fn storage_leaf(val: U256) -> Vec<u8> {
+    rlp_string(&val.encode())
+}
+
+/// Returns the encoding of a receipt as it is stored in the receipts trie:
+/// the rlp of its outcome, cumulative gas, bloom and logs, prefixed by the tx
+/// type for typed txs. Receipts from before Byzantium carry their post-state
+/// root in place of a status.
+pub fn receipt_leaf(receipt: &CborReceipt, logs: &[CborLog]) -> Vec<u8> {
+    let outcome = match receipt.post_state {
+        Some(root) => rlp_string(root.as_bytes()),
+        None => rlp_string(&U256::from(receipt.status).encode()),
+    };
+    let encoded_logs = logs
+        .iter()
+        .map(|log| {
+            let topics = log
+                .topics
+                .iter()
+                .map(|topic| rlp_string(topic.as_bytes()))
+                .collect::<Vec<_>>();
+            rlp_list(&[
+                rlp_string(log.address.as_bytes()),
+                rlp_list(&topics),
+                rlp_string(&log.data),
+            ])
+        })
+        .collect::<Vec<_>>();
+    let mut out = Vec::new();
+    if receipt.tx_type != 0 {
+        out.push(receipt.tx_type);
+    }
+    out.extend(rlp_list(&[
+        outcome,
+        rlp_string(&U256::from(receipt.cumulative_gas_used).encode()),
+        rlp_string(logs_bloom(logs).as_bytes()),
+        rlp_list(&encoded_logs),
+    ]));
+    out
+}
+
+/// Computes the transactions root of a block from its txs, in block order.
+pub fn compute_tx_root<'a, I>(txs: I) -> H256
+where
+    I: IntoIterator<Item = &'a Transaction>,
+{
+    ordered_trie_root(txs.into_iter().map(Transaction::encode_signed))
+}
+
+/// Computes the withdrawals root of a block from its withdrawals.
+pub fn compute_withdrawals_root(withdrawals: &[Withdrawal]) -> H256 {
+    ordered_trie_root(withdrawals.iter().map(|w| {
+        let mut buf = vec![];
+        fastrlp::Encodable::encode(w, &mut buf);
+        buf
+    }))
+}
+
+/// Computes the uncle hash of a block, keccak256(rlp(uncles)).
+pub fn compute_uncle_hash(uncles: &[BlockHeader]) -> H256 {
+    let mut buf = vec![];
+    fastrlp::encode_list::<BlockHeader, _>(uncles, &mut buf);
+    keccak256(buf).into()
+}
+
+impl<'env, K: Mode, Tx: KvTx> Erigon<'env, K, Tx> {
+    /// Checks the body of the block identified by `key` against its header:
+    /// the transactions root is recomputed from the BlockTransaction table,
+    /// and the uncle hash and withdrawals root from the BlockBody table.
+    /// Fails on the first mismatch, or with [`Error::MissingValue`] if a tx id
+    /// in the range the body claims is missing from BlockTransaction.
+    pub fn verify_body(&self, key: impl Into<HeaderKey>) -> Result<()> {
+        let key = key.into();
+        let header = self
+            .read_header(key)?
+            .ok_or(Error::MissingValue { what: "header" })?;
+        let body = self
+            .read_body_for_storage(key)?
+            .ok_or(Error::MissingValue { what: "body" })?;
+        // read each id by itself, so a gap isn't filled by the next block's txs
+        let mut cur = self.cursor::<BlockTransaction>()?;
+        let mut txs = Vec::with_capacity(body.tx_amount as usize);
+        for id in body.base_tx_id..body.base_tx_id + u64::from(body.tx_amount) {
+            let (_, tx) = cur.seek_exact(TxIndex(id))?.ok_or(Error::MissingValue {
+                what: "transactions",
+            })?;
+            txs.push(tx);
+        }
+
+        let tx_root = compute_tx_root(&txs);
+        ensure!(
+            tx_root == header.tx_hash,
+            "Block {} has transactions root {:?}, but its header says {:?}",
+            *key.0,
+            tx_root,
+            header.tx_hash,
+        );
+        let uncle_hash = compute_uncle_hash(&body.uncles);
+        ensure!(
+            uncle_hash == header.uncle_hash,
+            "Block {} has uncle hash {:?}, but its header says {:?}",
+            *key.0,
+            uncle_hash,
+            header.uncle_hash,
+        );
+        let withdrawals_root = body.withdrawals.as_deref().map(compute_withdrawals_root);
+        ensure!(
+            withdrawals_root == header.withdrawals_root,
+            "Block {} has withdrawals root {:?}, but its header says {:?}",
+            *key.0,
+            withdrawals_root,
+            header.withdrawals_root,
+        );
+        Ok(())
+    }
+
+    /// Computes the storage root of the account from the PlainState table.
+    pub fn storage_root(&self, adr: Address, inc: impl Into<Incarnation>) -> Result<H256> {
+        let leaves = self
+            .walk_storage(adr, inc, None)?
+            .map(|read| read.map(|(slot, val)| (keccak256(slot).into(), storage_leaf(val))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(trie_root(leaves))
+    }
+
+    /// Computes the state root from the PlainState table. This should match
+    /// the state root in the header of the block most recently executed.
+    pub fn state_root(&self) -> Result<H256> {
+        let mut leaves = Vec::new();
+        for read in self.walk_accounts(None)? {
+            let (adr, acct) = read?;
+            let storage_root = self.storage_root(adr, acct.incarnation)?;
+            leaves.push((keccak256(adr).into(), account_leaf(&acct, storage_root)));
+        }
+        Ok(trie_root(leaves))
+    }
+
+    /// Computes the storage root of the account from the HashedStorage table.
+    pub fn hashed_storage_root(
+        &self,
+        hashed_adr: H256,
+        inc: impl Into<Incarnation>,
+    ) -> Result<H256> {
+        let leaves = self
+            .walk_hashed_storage(hashed_adr, inc)?
+            .map(|read| read.map(|(slot, val)| (slot, storage_leaf(val))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(trie_root(leaves))
+    }
+
+    /// Computes the state root from the HashedAccount and HashedStorage tables,
+    /// which are maintained by Erigon's hashing stages.
+    pub fn hashed_state_root(&self) -> Result<H256> {
+        let mut leaves = Vec::new();
+        for read in self.walk_hashed_accounts(None)? {
+            let (hashed_adr, acct) = read?;
+            let storage_root = self.hashed_storage_root(hashed_adr, acct.incarnation)?;
+            leaves.push((hashed_adr, account_leaf(&acct, storage_root)));
+        }
+        Ok(trie_root(leaves))
+    }
+
+    /// Computes the storage root of the account like
+    /// [`Erigon::hashed_storage_root`], but reuses the subtrie hashes in the
+    /// TrieStorage table rather than reading every slot beneath them.
+    pub fn trie_storage_root(&self, hashed_adr: H256, inc: impl Into<Incarnation>) -> Result<H256> {
+        let inc = inc.into();
+        let node = |prefix: &[u8]| -> Result<Option<(Vec<u8>, TrieNode)>> {
+            let start = TrieStorageKey {
+                hashed_adr,
+                incarnation: inc,
+                nibbles: Nibbles(prefix.to_vec()),
+            };
+            let first = self
+                .cursor::<TrieStorage>()?
+                .walk(start)?
+                .next()
+                .transpose()?;
+            Ok(first.and_then(|(key, node)| {
+                let same_account = key.hashed_adr == hashed_adr && key.incarnation == inc;
+                (same_account && key.nibbles.starts_with(prefix)).then(|| (key.nibbles.0, node))
+            }))
+        };
+        let leaves = |prefix: &[u8]| -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+            let key = HashedStorageKey(hashed_adr, inc);
+            let mut leaves = Vec::new();
+            for read in self
+                .cursor::<HashedStorage>()?
+                .walk_dup(key, pack_nibbles(prefix))?
+            {
+                let (slot, val) = read?;
+                let nibbles = to_nibbles(slot.as_bytes());
+                if !nibbles.starts_with(prefix) {
+                    break;
+                }
+                leaves.push((nibbles, storage_leaf(val)));
+            }
+            Ok(leaves)
+        };
+        IntermediateHashes {
+            node: &node,
+            leaves: &leaves,
+        }
+        .root()
+    }
+
+    /// Computes the state root like [`Erigon::hashed_state_root`], but reuses
+    /// the subtrie hashes in the TrieAccount and TrieStorage tables rather
+    /// than reading every account and slot beneath them. Much faster on a
+    /// full node, but only as trustworthy as the tables, which are as of
+    /// Erigon's last run of its intermediate hashes stage.
+    pub fn trie_state_root(&self) -> Result<H256> {
+        let node = |prefix: &[u8]| -> Result<Option<(Vec<u8>, TrieNode)>> {
+            let start = Nibbles(prefix.to_vec());
+            let first = self
+                .cursor::<TrieAccount>()?
+                .walk(start)?
+                .next()
+                .transpose()?;
+            Ok(first
+                .and_then(|(Nibbles(path), node)| path.starts_with(prefix).then(|| (path, node))))
+        };
+        let leaves = |prefix: &[u8]| -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+            let mut leaves = Vec::new();
+            for read in self.walk_hashed_accounts(Some(pack_nibbles(prefix)))? {
+                let (hashed_adr, acct) = read?;
+                let nibbles = to_nibbles(hashed_adr.as_bytes());
+                if !nibbles.starts_with(prefix) {
+                    break;
+                }
+                let storage_root = self.trie_storage_root(hashed_adr, acct.incarnation)?;
+                leaves.push((nibbles, account_leaf(&acct, storage_root)));
+            }
+            Ok(leaves)
+        };
+        IntermediateHashes {
+            node: &node,
+            leaves: &leaves,
+        }
+        .root()
+    }
+}
+
+// A trie read through one of Erigon's intermediate hash tables. Its stored
+// branch nodes give the hashes of some subtries directly, and the leaves are
+// only read for the rest.
+struct IntermediateHashes<'a> {
+    // the first stored node whose path begins with the given nibbles
+    node: &'a dyn Fn(&[u8]) -> Result<Option<(Vec<u8>, TrieNode)>>,
+    // the leaves whose keys begin with the given nibbles, in order
+    leaves: &'a dyn Fn(&[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl<'a> IntermediateHashes<'a> {
+    fn root(&self) -> Result<H256> {
+        Ok(match (self.node)(&[])? {
+            Some((path, node)) => keccak256(self.stored_subtrie(&[], &path, &node)?).into(),
+            None => root_of((self.leaves)(&[])?),
+        })
+    }
+
+    // Encodes the node of the subtrie holding the keys that begin with
+    // `prefix`, which has a stored node in it.
+    fn subtrie(&self, prefix: &[u8]) -> Result<Vec<u8>> {
+        match (self.node)(prefix)? {
+            Some((path, node)) => self.stored_subtrie(prefix, &path, &node),
+            None => self.leaf_subtrie(prefix),
+        }
+    }
+
+    // Encodes the node of the subtrie holding the keys that begin with
+    // `prefix` from its leaves.
+    fn leaf_subtrie(&self, prefix: &[u8]) -> Result<Vec<u8>> {
+        let leaves = (self.leaves)(prefix)?;
+        ensure!(
+            !leaves.is_empty(),
+            "Trie node at {:?} has a child with no leaves",
+            prefix
+        );
+        Ok(encode_node(&leaves, prefix.len()))
+    }
+
+    // Encodes the subtrie at `prefix` whose first branch is the stored node
+    // at `path`. No other branch can sit between the two, since it would
+    // have been stored too, so any nibbles in between form an extension.
+    fn stored_subtrie(&self, prefix: &[u8], path: &[u8], node: &TrieNode) -> Result<Vec<u8>> {
+        let mut children = Vec::with_capacity(17);
+        let mut child = path.to_vec();
+        child.push(0);
+        for nibble in 0..16 {
+            *child.last_mut().unwrap() = nibble;
+            let bit = 1 << nibble;
+            children.push(if node.has_state & bit == 0 {
+                rlp_string(&[])
+            } else if let Some(hash) = node.child_hash(nibble) {
+                rlp_string(hash.as_bytes())
+            } else if node.has_tree & bit != 0 {
+                child_ref(self.subtrie(&child)?)
+            } else {
+                child_ref(self.leaf_subtrie(&child)?)
+            });
+        }
+        children.push(rlp_string(&[]));
+        let branch = rlp_list(&children);
+        if path.len() == prefix.len() {
+            return Ok(branch);
+        }
+        Ok(rlp_list(&[
+            rlp_string(&hex_prefix(&path[prefix.len()..], false)),
+            child_ref(branch),
+        ]))
+    }
+}
+
+// Packs nibbles into the smallest key that begins with them.
+fn pack_nibbles(nibbles: &[u8]) -> H256 {
+    let mut out = H256::zero();
+    for (i, nibble) in nibbles.iter().enumerate() {
+        out.0[i / 2] |= if i % 2 == 0 { nibble << 4 } else { *nibble };
+    }
+    out
+}
+
+fn to_nibbles(b: &[u8]) -> Vec<u8> {
+    b.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect()
+}
+
+// Encodes the node containing all of `leaves`, which are sorted and share
+// their first `depth` nibbles.
+fn encode_node(leaves: &[(Vec<u8>, Vec<u8>)], depth: usize) -> Vec<u8> {
+    let (first, val) = &leaves[0];
+    if leaves.len() == 1 {
+        return rlp_list(&[
+            rlp_string(&hex_prefix(&first[depth..], true)),
+            rlp_string(val),
+        ]);
+    }
+
+    // the leaves are sorted, so the prefix shared by the first and last
+    // leaves is shared by all of them
+    let last = &leaves[leaves.len() - 1].0;
+    let shared = first[depth..]
+        .iter()
+        .zip(&last[depth..])
+        .take_while(|(a, b)| a == b)
+        .count();
+    if shared > 0 {
+        let child = encode_node(leaves, depth + shared);
+        return rlp_list(&[
+            rlp_string(&hex_prefix(&first[depth..depth + shared], false)),
+            child_ref(child),
+        ]);
+    }
+
+    // a key that ends at the branch sorts first, and is the branch's value
+    let (value, mut rest) = if first.len() == depth {
+        (rlp_string(val), &leaves[1..])
+    } else {
+        (rlp_string(&[]), leaves)
+    };
+    let mut children = Vec::with_capacity(17);
+    for nibble in 0..16 {
+        let n = rest.iter().take_while(|(k, _)| k[depth] == nibble).count();
+        let (group, tail) = rest.split_at(n);
+        rest = tail;
+        children.push(if group.is_empty() {
+            rlp_string(&[])
+        } else {
+            child_ref(encode_node(group, depth + 1))
+        });
+    }
+    children.push(value);
+    rlp_list(&children)
+}
+
+// Nodes shorter than 32 bytes are embedded directly in their parent.
+fn child_ref(node: Vec<u8>) -> Vec<u8> {
+    if node.len() < 32 {
+        node
+    } else {
+        rlp_string(&keccak256(node))
+    }
+}
+
+// https://ethereum.org/en/developers/docs/data-structures-and-encoding/patricia-merkle-trie/#specification
+pub(crate) fn hex_prefix(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 };
+    let mut out = Vec::with_capacity(nibbles.len() / 2 + 1);
+    let rest = if nibbles.len() % 2 == 1 {
+        out.push(((flag + 1) << 4) | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        out.push(flag << 4);
+        nibbles
+    };
+    out.extend(rest.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    out
+}
+
+fn rlp_string(b: &[u8]) -> Vec<u8> {
+    if b.len() == 1 && b[0] < fastrlp::EMPTY_STRING_CODE {
+        return b.to_vec();
+    }
+    let mut out = rlp_length(b.len(), fastrlp::EMPTY_STRING_CODE);
+    out.extend_from_slice(b);
+    out
+}
+
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let len = items.iter().map(Vec::len).sum();
+    let mut out = rlp_length(len, fastrlp::EMPTY_LIST_CODE);
+    for item in items {
+        out.extend_from_slice(item);
+    }
+    out
+}
+
+fn rlp_length(len: usize, offset: u8) -> Vec<u8> {
+    if len < 56 {
+        vec![offset + len as u8]
+    } else {
+        let be = len.to_be_bytes();
+        let be = &be[be.iter().take_while(|&&b| b == 0).count()..];
+        let mut out = vec![offset + 55 + be.len() as u8];
+        out.extend_from_slice(be);
+        out
+    }
+}
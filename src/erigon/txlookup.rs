@@ -0,0 +1,69 @@
+use crate::{
+    erigon::{
+        models::*,
+        progress::{Progress, Ticker},
+        Erigon, ErigonDb,
+    },
+    kv::traits::KvTxMut,
+    Result,
+};
+use mdbx::RW;
+use std::ops::Range;
+
+impl<'env, Tx: KvTxMut> Erigon<'env, RW, Tx> {
+    /// Writes the BlockTransactionLookup entry of every tx in the canonical
+    /// blocks in `blocks`, overwriting any already there. Blocks without a
+    /// body, e.g. in a pruned datadir, are skipped. Returns the number of txs
+    /// written.
+    pub fn write_tx_lookups(&self, blocks: Range<u64>) -> Result<usize> {
+        let mut written = 0;
+        for num in blocks {
+            let hash = match self.read_canonical_hash(num)? {
+                Some(hash) => hash,
+                None => continue,
+            };
+            let txs = match self.read_transactions(HeaderKey(num.into(), hash))? {
+                Some(txs) => txs,
+                None => continue,
+            };
+            for tx in txs {
+                self.write_transaction_block_number(tx.tx_hash(), num.into())?;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl ErigonDb<RW> {
+    /// Rebuilds the BlockTransactionLookup table for the canonical blocks in
+    /// `blocks`, committing after every `batch_size` blocks and ticking
+    /// `progress` with the number of blocks done so far. Returns the number
+    /// of txs written.
+    ///
+    /// Lookups of txs in blocks that are no longer canonical are left in
+    /// place, and the TxLookup stage progress is not changed.
+    pub fn rebuild_tx_lookup<P>(
+        &self,
+        blocks: Range<u64>,
+        batch_size: u64,
+        mut progress: P,
+    ) -> Result<usize>
+    where
+        P: Progress,
+    {
+        let batch_size = batch_size.max(1);
+        let total = blocks.end.saturating_sub(blocks.start);
+        let mut ticker = Ticker::new(&mut progress, "rebuild_tx_lookup", Some(total));
+        let mut written = 0;
+        let mut start = blocks.start;
+        while start < blocks.end {
+            let end = start.saturating_add(batch_size).min(blocks.end);
+            written += self.update(|tx| tx.write_tx_lookups(start..end))?;
+            ticker.tick(end - blocks.start, None);
+            start = end;
+        }
+        ticker.finish();
+        Ok(written)
+    }
+}
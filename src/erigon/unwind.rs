@@ -0,0 +1,143 @@
+use crate::{
+    erigon::{bitmap, models::*, tables::*, utils::consts::ADDRESS_LENGTH, Erigon},
+    kv::traits::{KvCursor, KvTx, KvTxMut, TableDecode, TableEncode},
+    Result,
+};
+use ethereum_types::{Address, H256};
+use mdbx::RW;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::RangeBounds,
+};
+
+/// The sync stages whose data [`Erigon::unwind_to`] unwinds: Execution, which
+/// writes PlainState and the changesets, and the history index stages.
+pub const UNWOUND_STAGES: [&str; 3] = ["Execution", "AccountHistoryIndex", "StorageHistoryIndex"];
+
+impl<'env, Tx: KvTxMut> Erigon<'env, RW, Tx> {
+    /// Rewinds the state to what it was after block `block` was executed, the
+    /// way Erigon unwinds its Execution and history stages:
+    /// - accounts and storage changed after `block` are restored in
+    ///   PlainState from their changesets
+    /// - the changesets of the later blocks are deleted
+    /// - the later blocks are removed from the history bitmaps
+    /// - the stages whose data this unwinds, [`UNWOUND_STAGES`], are moved
+    ///   back to `block` if they had progressed past it
+    ///
+    /// The block tables, hashed state and trie are left untouched, and so is
+    /// the progress of the stages that build them.
+    pub fn unwind_to(&self, block: impl Into<BlockNumber>) -> Result<()> {
+        let block = block.into();
+        let start = BlockNumber(block.0.saturating_add(1));
+
+        // The first changeset entry after `block` for each account or slot
+        // holds its value as of `block`. An empty account value means the
+        // account didn't exist yet.
+        let mut accounts = BTreeMap::new();
+        let mut account_cs_keys = BTreeSet::new();
+        let mut cur = self.0.cursor_raw::<AccountChangeSet>()?;
+        let mut kv = cur.seek(start.encode().as_ref())?;
+        while let Some((k, v)) = kv {
+            let AccountCSVal(adr, acct) = AccountCSVal::decode(&v)?;
+            let acct = (v.len() > ADDRESS_LENGTH).then_some(acct);
+            accounts.entry(adr).or_insert(acct);
+            account_cs_keys.insert(k.into_owned());
+            kv = cur.next()?;
+        }
+
+        let mut storage = BTreeMap::new();
+        let mut storage_cs_keys = BTreeSet::new();
+        let mut cur = self.0.cursor_raw::<StorageChangeSet>()?;
+        let mut kv = cur.seek(StorageCSKey(start, Default::default()).encode().as_ref())?;
+        while let Some((k, v)) = kv {
+            let StorageCSKey(_, StorageKey(adr, inc)) = StorageCSKey::decode(&k)?;
+            let StorageCSVal(slot, val) = StorageCSVal::decode(&v)?;
+            storage.entry((adr, inc, slot)).or_insert(val);
+            storage_cs_keys.insert(k.into_owned());
+            kv = cur.next()?;
+        }
+        drop(cur);
+
+        for (&adr, acct) in &accounts {
+            // PlainState is dupsorted, so the old value must be removed first
+            self.delete::<PlainState>(adr)?;
+            if let Some(acct) = acct {
+                let acct = self.recover_codehash(adr, acct.clone())?;
+                self.write_account(adr, acct)?;
+            }
+            self.retain_account_history(adr, ..=block.0)?;
+        }
+        let mut slots = BTreeSet::new();
+        for (&(adr, inc, slot), val) in &storage {
+            let key = StorageKey(adr, inc);
+            self.delete_dup::<Storage>(key, slot)?;
+            if !val.is_zero() {
+                self.write::<Storage>(key, (slot, *val))?;
+            }
+            slots.insert((adr, slot));
+        }
+        for (adr, slot) in slots {
+            self.retain_storage_history(adr, slot, ..=block.0)?;
+        }
+
+        for k in account_cs_keys {
+            self.0.delete_raw::<AccountChangeSet>(&k, None)?;
+        }
+        for k in storage_cs_keys {
+            self.0.delete_raw::<StorageChangeSet>(&k, None)?;
+        }
+
+        for stage in UNWOUND_STAGES {
+            if matches!(self.read_stage_progress(stage)?, Some(progress) if progress > block) {
+                self.write_stage_progress(stage, block)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Removes the blocks outside of `keep` from the history bitmap of `adr`,
+    // rewriting its shards.
+    pub(super) fn retain_account_history(
+        &self,
+        adr: Address,
+        keep: impl RangeBounds<u64>,
+    ) -> Result<()> {
+        let shards = self
+            .cursor::<AccountHistory>()?
+            .walk(AccountHistKey(adr, 0.into()))?
+            .take_while(|res| !matches!(res, Ok((AccountHistKey(k, _), _)) if *k != adr))
+            .map(|res| res.map(|(AccountHistKey(_, n), map)| (n.0, map)))
+            .collect::<Result<Vec<_>>>()?;
+        for (n, _) in &shards {
+            self.delete::<AccountHistory>(AccountHistKey(adr, (*n).into()))?;
+        }
+        for (n, map) in bitmap::retain_in_shards(shards, keep) {
+            self.write::<AccountHistory>(AccountHistKey(adr, n.into()), map)?;
+        }
+        Ok(())
+    }
+
+    // Like `retain_account_history`, for a storage slot.
+    pub(super) fn retain_storage_history(
+        &self,
+        adr: Address,
+        slot: H256,
+        keep: impl RangeBounds<u64>,
+    ) -> Result<()> {
+        let shards = self
+            .cursor::<StorageHistory>()?
+            .walk(StorageHistKey(adr, slot, 0.into()))?
+            .take_while(
+                |res| !matches!(res, Ok((StorageHistKey(k, s, _), _)) if *k != adr || *s != slot),
+            )
+            .map(|res| res.map(|(StorageHistKey(_, _, n), map)| (n.0, map)))
+            .collect::<Result<Vec<_>>>()?;
+        for (n, _) in &shards {
+            self.delete::<StorageHistory>(StorageHistKey(adr, slot, (*n).into()))?;
+        }
+        for (n, map) in bitmap::retain_in_shards(shards, keep) {
+            self.write::<StorageHistory>(StorageHistKey(adr, slot, n.into()), map)?;
+        }
+        Ok(())
+    }
+}
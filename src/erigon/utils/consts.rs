@@ -5,7 +5,22 @@ pub const ADDRESS_LENGTH: usize = Address::len_bytes();
 pub const U64_LENGTH: usize = std::mem::size_of::<u64>();
 pub const BLOOM_BYTE_LENGTH: usize = 256;
 
+// wei per gwei, the unit of withdrawal amounts
+pub const GWEI: u64 = 1_000_000_000;
+
 // keccak256("")
 pub const EMPTY_HASH: H256 = H256(hex_literal::hex!(
     "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
 ));
+
+// keys in the DbInfo table
+pub const DB_SCHEMA_VERSION_KEY: &[u8] = b"dbVersion";
+pub const PLAIN_STATE_VERSION_KEY: &[u8] = b"PlainStateVersion";
+// the prune distance of each kind of data, and whether it is a distance from
+// the head ("older") or a fixed block ("before")
+pub const PRUNE_HISTORY_KEY: &[u8] = b"pruneHistory";
+pub const PRUNE_RECEIPTS_KEY: &[u8] = b"pruneReceipts";
+pub const PRUNE_TX_INDEX_KEY: &[u8] = b"pruneTxIndex";
+pub const PRUNE_CALL_TRACES_KEY: &[u8] = b"pruneCallTraces";
+pub const PRUNE_TYPE_SUFFIX: &[u8] = b"Type";
+pub const PRUNE_TYPE_BEFORE: &[u8] = b"before";
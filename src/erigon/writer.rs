@@ -0,0 +1,115 @@
+use crate::{
+    erigon::{models::*, tables::*, Erigon},
+    error::ensure,
+    kv::traits::KvTxMut,
+    Result,
+};
+use ethereum_types::{Address, H256};
+use mdbx::RW;
+
+/// A block with everything needed to write it to the db.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+    /// The signer of each transaction.
+    pub senders: Vec<Address>,
+    pub uncles: Vec<BlockHeader>,
+    // None before Shanghai
+    pub withdrawals: Option<Vec<Withdrawal>>,
+}
+
+/// Writes blocks to the tables filled by Erigon's Headers, Bodies, Senders and
+/// TxLookup stages, and marks them canonical.
+///
+/// Everything is written through the borrowed transaction, so a batch of
+/// blocks lands atomically when it is committed. Erigon must not be running
+/// against the datadir while it is written to.
+pub struct BlockWriter<'a, 'env, Tx> {
+    db: &'a Erigon<'env, RW, Tx>,
+}
+
+impl<'a, 'env, Tx: KvTxMut> BlockWriter<'a, 'env, Tx> {
+    pub fn new(db: &'a Erigon<'env, RW, Tx>) -> Self {
+        Self { db }
+    }
+
+    /// Writes `block` as the canonical block at its height, returning its key.
+    /// The total difficulty is accumulated from the parent's, if the parent
+    /// has one.
+    pub fn write_block(&self, block: Block) -> Result<HeaderKey> {
+        ensure!(
+            block.senders.len() == block.transactions.len(),
+            "Block has {} txs but {} senders",
+            block.transactions.len(),
+            block.senders.len(),
+        );
+        let hash = block.header.hash();
+        let num = BlockNumber(block.header.number.as_u64());
+        let key = HeaderKey(num, hash);
+
+        let parent_td = match num.0.checked_sub(1) {
+            Some(parent) => self
+                .db
+                .read_total_difficulty(HeaderKey(parent.into(), block.header.parent_hash))?,
+            None => None,
+        };
+        let td = parent_td.unwrap_or_default().0 + block.header.difficulty;
+        self.db
+            .write::<HeadersTotalDifficulty>(key, TotalDifficulty(td))?;
+        self.db.write_header(key, block.header)?;
+        self.db.write_header_number(hash, num)?;
+        self.db.write::<CanonicalHeader>(num, hash)?;
+
+        // Erigon reserves an id for a system tx on either side of the block's
+        // txs, which read_body_for_storage strips back off
+        let tx_amount = block.transactions.len() as u32 + 2;
+        let base_tx_id = self.next_tx_ids(tx_amount.into())?;
+        for (i, tx) in block.transactions.into_iter().enumerate() {
+            self.db
+                .write_transaction_block_number(tx.tx_hash(), num.0.into())?;
+            self.db
+                .write::<BlockTransaction>(TxIndex(base_tx_id + 1 + i as u64), tx)?;
+        }
+        let body = BodyForStorage {
+            base_tx_id,
+            tx_amount,
+            uncles: block.uncles,
+            withdrawals: block.withdrawals,
+        };
+        self.db.write_body_for_storage(key, body)?;
+        self.db.write_senders(key, block.senders)?;
+        Ok(key)
+    }
+
+    /// Writes each of `blocks` in order, then makes the last one the head of
+    /// the chain. Returns the key of the new head, if any blocks were written.
+    pub fn write_chain(
+        &self,
+        blocks: impl IntoIterator<Item = Block>,
+    ) -> Result<Option<HeaderKey>> {
+        let mut head = None;
+        for block in blocks {
+            head = Some(self.write_block(block)?);
+        }
+        if let Some(HeaderKey(_, hash)) = head {
+            self.set_head(hash)?;
+        }
+        Ok(head)
+    }
+
+    /// Points the head header and head block at the block with `hash`.
+    pub fn set_head(&self, hash: H256) -> Result<()> {
+        self.db.write_head_header_hash(hash)?;
+        self.db.write_head_block_hash(hash)
+    }
+
+    // Allocates `n` consecutive ids in the BlockTransaction table, returning
+    // the first.
+    pub(crate) fn next_tx_ids(&self, n: u64) -> Result<u64> {
+        let TxIndex(next) = self.db.read::<Sequence>(TxSequenceKey)?.unwrap_or_default();
+        self.db
+            .write::<Sequence>(TxSequenceKey, TxIndex(next + n))?;
+        Ok(next)
+    }
+}
@@ -0,0 +1,147 @@
+//! The errors this crate raises.
+//!
+//! Fallible functions return [`Result`], whose [`Error`] can be matched on to
+//! tell, e.g., a missing table from a pruned block:
+//!
+//! ```ignore
+//! match db.read_raw("Headers", &key) {
+//!     Err(Error::TableNotFound(_)) => ...,
+//!     ...
+//! }
+//! ```
+//!
+//! [`Error`] implements [`std::error::Error`], so it converts into an
+//! [`eyre::Report`] with `?` for applications that use eyre.
+
+/// The result of a fallible operation of this crate.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// An error raised by this crate.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The named table does not exist in the db.
+    #[error("Table {0} not found")]
+    TableNotFound(String),
+    /// A key or value in `table` could not be decoded. `key` is the raw key,
+    /// when it is known.
+    #[error("Failed to decode {table} entry{}", fmt_key(.key))]
+    Decode {
+        table: &'static str,
+        key: Option<Vec<u8>>,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// A value the db should contain, e.g. the head header hash, is missing.
+    #[error("Missing {what}")]
+    MissingValue { what: &'static str },
+    /// The data of `needed_block` in `table` was pruned by Erigon, which
+    /// keeps it only from block `prune_horizon` on.
+    #[error("Block {needed_block} was pruned from {table}, which starts at block {prune_horizon}")]
+    Pruned {
+        table: &'static str,
+        needed_block: u64,
+        prune_horizon: u64,
+    },
+    /// The db was written by a version of Erigon this crate can't read.
+    #[error("Incompatible db version: {0}")]
+    Incompatible(String),
+    #[error(transparent)]
+    Mdbx(#[from] mdbx::Error),
+    /// Any other failure, e.g. a malformed snapshot file or an io error.
+    #[error("{0:#}")]
+    Other(eyre::Report),
+}
+
+impl From<eyre::Report> for Error {
+    fn from(report: eyre::Report) -> Self {
+        // an `Error` converted to a report on the way up comes back out as
+        // itself rather than as `Other`
+        report.downcast().unwrap_or_else(Self::Other)
+    }
+}
+
+// Converts errors of other crates into `Error::Other`, so they can be
+// propagated with `?`.
+macro_rules! impl_from_other {
+    ($($(#[$meta:meta])* $ty:ty),* $(,)?) => {
+        $(
+            $(#[$meta])*
+            impl From<$ty> for Error {
+                fn from(e: $ty) -> Self {
+                    Self::Other(e.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_other!(
+    std::io::Error,
+    std::array::TryFromSliceError,
+    std::num::ParseIntError,
+    std::string::FromUtf8Error,
+    fastrlp::DecodeError,
+    serde_json::Error,
+    serde_cbor::Error,
+    #[cfg(feature = "secp256k1")]
+    secp256k1::Error,
+    #[cfg(feature = "async")]
+    tokio::task::JoinError,
+    #[cfg(feature = "remote")]
+    tonic::Status,
+    #[cfg(feature = "remote")]
+    tonic::transport::Error,
+);
+
+// Like eyre's `bail!`, but converts the report into the error type of the
+// function, so it can be used in functions returning `Result`.
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return ::std::result::Result::Err(::eyre::eyre!($($arg)*).into())
+    };
+}
+pub(crate) use bail;
+
+// Like eyre's `ensure!`, with the conversion of `bail!`.
+macro_rules! ensure {
+    ($cond:expr, $($arg:tt)*) => {
+        if !$cond {
+            $crate::error::bail!($($arg)*);
+        }
+    };
+}
+pub(crate) use ensure;
+
+fn fmt_key(key: &Option<Vec<u8>>) -> String {
+    match key {
+        Some(key) => format!(
+            " at key 0x{}",
+            key.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        ),
+        None => String::new(),
+    }
+}
+
+// Raises an mdbx error as an `Error::Mdbx`.
+pub(crate) fn mdbx_err(e: mdbx::Error) -> Error {
+    Error::Mdbx(e)
+}
+
+// Raises a failure to open table `name`, which mdbx reports as NOTFOUND if
+// the table doesn't exist.
+pub(crate) fn open_err(name: &str, e: mdbx::Error) -> Error {
+    match e {
+        mdbx::Error::NotFound => Error::TableNotFound(name.to_string()),
+        e => Error::Mdbx(e),
+    }
+}
+
+// Raises a failure to decode an entry of `table`.
+pub(crate) fn decode_err(table: &'static str, key: Option<&[u8]>, e: Error) -> Error {
+    Error::Decode {
+        table,
+        key: key.map(<[u8]>::to_vec),
+        source: e.into(),
+    }
+}
@@ -0,0 +1,144 @@
+use crate::Result;
+use mdbx::{Geometry, NoWriteMap, PageSize, SyncMode};
+use std::{marker::PhantomData, ops::Range, path::Path};
+
+use crate::{
+    error::mdbx_err,
+    kv::{traits::Mode, EnvFlags, MdbxEnv},
+};
+
+/// A builder for an [`MdbxEnv`], for callers that need control over the size
+/// and durability of the environment, e.g. to create a standalone database
+/// with this crate's table layout.
+///
+/// Options that aren't set are left to mdbx's defaults.
+#[derive(Debug, Clone)]
+pub struct MdbxEnvBuilder<M> {
+    max_dbs: usize,
+    max_readers: Option<u64>,
+    flags: EnvFlags,
+    sync_mode: SyncMode,
+    map_size: Option<Range<usize>>,
+    growth_step: Option<isize>,
+    shrink_threshold: Option<isize>,
+    page_size: Option<usize>,
+    _mode: PhantomData<M>,
+}
+
+impl<M: Mode> Default for MdbxEnvBuilder<M> {
+    fn default() -> Self {
+        Self {
+            max_dbs: 0,
+            max_readers: None,
+            flags: EnvFlags::default(),
+            sync_mode: SyncMode::Durable,
+            map_size: None,
+            growth_step: None,
+            shrink_threshold: None,
+            page_size: None,
+            _mode: PhantomData,
+        }
+    }
+}
+
+impl<M: Mode> MdbxEnvBuilder<M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum number of named tables the env can open.
+    pub fn max_dbs(mut self, max_dbs: usize) -> Self {
+        self.max_dbs = max_dbs;
+        self
+    }
+
+    /// Sets the maximum number of concurrent read transactions.
+    pub fn max_readers(mut self, max_readers: u64) -> Self {
+        self.max_readers = Some(max_readers);
+        self
+    }
+
+    pub fn flags(mut self, flags: EnvFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Sets how writes are flushed to disk on commit. Only applies to
+    /// read-write envs. Anything other than `SyncMode::Durable` trades the
+    /// last few transactions (or, with `UtterlyNoSync`, the whole db) on a
+    /// system crash for write throughput.
+    pub fn sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    /// Sets the lower and upper bounds, in bytes, of the size of the data file.
+    pub fn map_size(mut self, size: Range<usize>) -> Self {
+        self.map_size = Some(size);
+        self
+    }
+
+    /// Sets the number of bytes the data file grows by when it fills up.
+    pub fn growth_step(mut self, step: isize) -> Self {
+        self.growth_step = Some(step);
+        self
+    }
+
+    /// Sets the amount of free space, in bytes, at the end of the data file
+    /// that triggers it to shrink.
+    pub fn shrink_threshold(mut self, threshold: isize) -> Self {
+        self.shrink_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets the page size of a new db. Must be a power of two between 256 and
+    /// 65536. Ignored when opening an existing db.
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Opens the env at `path`. Note that even when opening an environment in
+    /// read-only mode, mdbx will still modify the LCK-file, unless the
+    /// filesystem is read-only.
+    pub fn open(self, path: &Path) -> Result<MdbxEnv<M>> {
+        let mode = if M::is_writeable() {
+            mdbx::Mode::ReadWrite {
+                sync_mode: self.sync_mode,
+            }
+        } else {
+            mdbx::Mode::ReadOnly
+        };
+
+        let mut builder = mdbx::Environment::<NoWriteMap>::new();
+        builder
+            .set_max_dbs(self.max_dbs)
+            .set_flags(self.flags.with_mode(mode));
+        if let Some(max_readers) = self.max_readers {
+            builder.set_max_readers(max_readers);
+        }
+        let geometry_set = self.map_size.is_some()
+            || self.growth_step.is_some()
+            || self.shrink_threshold.is_some()
+            || self.page_size.is_some();
+        if geometry_set {
+            builder.set_geometry(Geometry {
+                size: self.map_size,
+                growth_step: self.growth_step,
+                shrink_threshold: self.shrink_threshold,
+                page_size: self.page_size.map(PageSize::Set),
+            });
+        }
+
+        let data_path = if self.flags.no_sub_dir {
+            path.to_path_buf()
+        } else {
+            path.join("mdbx.dat")
+        };
+        Ok(MdbxEnv {
+            inner: builder.open(path).map_err(mdbx_err)?,
+            data_path,
+            _mode: PhantomData,
+        })
+    }
+}
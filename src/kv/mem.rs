@@ -0,0 +1,293 @@
+//! An in-memory KV backend for tests.
+//!
+//! [`MemDb`] stores each table as an ordered set of (key, value) pairs, which
+//! gives the same iteration order as mdbx, including the ordering of
+//! duplicate values in dupsorted tables. Transactions work on a snapshot of
+//! the db taken when they begin. Read-write transactions copy the db on their
+//! first write and replace it on commit.
+use crate::Result;
+use mdbx::{DatabaseFlags, RO, RW};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    marker::PhantomData,
+    ops::Bound::{Excluded, Included, Unbounded},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+};
+
+use crate::kv::traits::{DbFlags, DefaultFlags, KvCursor, KvTx, KvTxMut, Mode, RawPair, Table};
+
+type Entries = BTreeSet<(Vec<u8>, Vec<u8>)>;
+type Tables = BTreeMap<&'static str, Entries>;
+
+/// An in-memory database implementing the KV traits.
+#[derive(Debug, Default)]
+pub struct MemDb {
+    tables: RwLock<Arc<Tables>>,
+    writer: Mutex<()>,
+}
+
+impl MemDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn snapshot(&self) -> Arc<Tables> {
+        self.tables.read().unwrap().clone()
+    }
+
+    /// Begins a read-only transaction.
+    pub fn begin(&self) -> Result<MemTx<'_, RO>> {
+        Ok(MemTx::new(self, self.snapshot(), None))
+    }
+
+    /// Begins a read-write transaction. Blocks if another read-write
+    /// transaction is open.
+    pub fn begin_rw(&self) -> Result<MemTx<'_, RW>> {
+        let guard = self.writer.lock().unwrap();
+        Ok(MemTx::new(self, self.snapshot(), Some(guard)))
+    }
+}
+
+/// A transaction against a [`MemDb`].
+#[derive(Debug)]
+pub struct MemTx<'db, K> {
+    db: &'db MemDb,
+    tables: RefCell<Arc<Tables>>,
+    _writer: Option<MutexGuard<'db, ()>>,
+    _mode: PhantomData<K>,
+}
+
+impl<'db, K> MemTx<'db, K> {
+    fn new(db: &'db MemDb, tables: Arc<Tables>, writer: Option<MutexGuard<'db, ()>>) -> Self {
+        Self {
+            db,
+            tables: RefCell::new(tables),
+            _writer: writer,
+            _mode: PhantomData,
+        }
+    }
+
+    // Removes every entry under `key`, returning whether there were any. The
+    // entries of a key are contiguous, so only that range is visited.
+    fn remove_key(entries: &mut Entries, key: &[u8]) -> bool {
+        let found = entries
+            .range((key.to_vec(), vec![])..)
+            .take_while(|(k, _)| k == key)
+            .cloned()
+            .collect::<Vec<_>>();
+        for kv in &found {
+            entries.remove(kv);
+        }
+        !found.is_empty()
+    }
+
+    // Runs `f` against the entries of the named table, if it has any.
+    fn with_table<R>(&self, name: &str, f: impl FnOnce(&Entries) -> R) -> Option<R> {
+        self.tables.borrow().get(name).map(f)
+    }
+}
+
+impl<'db, K: Mode> KvTx for MemTx<'db, K> {
+    type Cursor<'tx>
+        = MemCursor<'tx, 'db, K>
+    where
+        Self: 'tx;
+
+    fn get_raw<'tx, T>(&'tx self, key: &[u8]) -> Result<Option<Cow<'tx, [u8]>>>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        self.get_named(T::Name::NAME, key)
+    }
+
+    fn cursor_raw<'tx, T>(&'tx self) -> Result<Self::Cursor<'tx>>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        self.cursor_named(T::Name::NAME)
+    }
+
+    fn get_named<'tx>(&'tx self, name: &str, key: &[u8]) -> Result<Option<Cow<'tx, [u8]>>> {
+        Ok(self
+            .with_table(name, |entries| {
+                entries
+                    .range((key.to_vec(), vec![])..)
+                    .next()
+                    .filter(|(k, _)| k == key)
+                    .map(|(_, v)| Cow::Owned(v.clone()))
+            })
+            .flatten())
+    }
+
+    fn cursor_named<'tx>(&'tx self, name: &str) -> Result<Self::Cursor<'tx>> {
+        Ok(MemCursor {
+            tx: self,
+            name: name.to_string(),
+            pos: None,
+        })
+    }
+}
+
+impl<'db> KvTxMut for MemTx<'db, RW> {
+    fn put_raw<'tx, T>(&'tx self, key: &[u8], val: &[u8]) -> Result<()>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let dupsort = <T::Flags as DbFlags>::FLAGS.contains(DatabaseFlags::DUP_SORT);
+        let mut tables = self.tables.borrow_mut();
+        let entries = Arc::make_mut(&mut tables).entry(T::Name::NAME).or_default();
+        if !dupsort {
+            Self::remove_key(entries, key);
+        }
+        entries.insert((key.to_vec(), val.to_vec()));
+        Ok(())
+    }
+
+    fn delete_raw<'tx, T>(&'tx self, key: &[u8], val: Option<&[u8]>) -> Result<bool>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let mut tables = self.tables.borrow_mut();
+        let entries = match Arc::make_mut(&mut tables).get_mut(T::Name::NAME) {
+            Some(entries) => entries,
+            None => return Ok(false),
+        };
+        Ok(match val {
+            Some(val) => entries.remove(&(key.to_vec(), val.to_vec())),
+            None => Self::remove_key(entries, key),
+        })
+    }
+
+    fn commit(self) -> Result<bool> {
+        *self.db.tables.write().unwrap() = self.tables.into_inner();
+        Ok(true)
+    }
+}
+
+/// A cursor into a table of a [`MemTx`]. Values are copied out of the table,
+/// so every returned pair is owned.
+#[derive(Debug)]
+pub struct MemCursor<'tx, 'db, K> {
+    tx: &'tx MemTx<'db, K>,
+    name: String,
+    pos: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<'tx, 'db, K> MemCursor<'tx, 'db, K> {
+    // Moves the cursor to the entry chosen by `f`. If `f` finds nothing, the
+    // cursor is left where it was.
+    fn step<F>(&mut self, f: F) -> Result<Option<RawPair<'tx>>>
+    where
+        F: FnOnce(&Entries, Option<&(Vec<u8>, Vec<u8>)>) -> Option<(Vec<u8>, Vec<u8>)>,
+    {
+        let pos = self.pos.as_ref();
+        let found = self.tx.with_table(&self.name, |entries| f(entries, pos));
+        Ok(match found.flatten() {
+            Some(kv) => {
+                self.pos = Some(kv.clone());
+                Some((Cow::Owned(kv.0), Cow::Owned(kv.1)))
+            }
+            None => None,
+        })
+    }
+}
+
+impl<'tx, 'db, K> KvCursor<'tx> for MemCursor<'tx, 'db, K> {
+    fn first(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.step(|entries, _| entries.iter().next().cloned())
+    }
+    fn last(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.step(|entries, _| entries.iter().next_back().cloned())
+    }
+    fn current(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.step(|_, pos| pos.cloned())
+    }
+    fn next(&mut self) -> Result<Option<RawPair<'tx>>> {
+        // like mdbx, an unpositioned cursor moves to the first entry
+        self.step(|entries, pos| match pos {
+            Some(pos) => entries.range((Excluded(pos), Unbounded)).next().cloned(),
+            None => entries.iter().next().cloned(),
+        })
+    }
+    fn prev(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.step(|entries, pos| match pos {
+            Some(pos) => entries
+                .range((Unbounded, Excluded(pos)))
+                .next_back()
+                .cloned(),
+            None => entries.iter().next_back().cloned(),
+        })
+    }
+    fn next_dup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.step(|entries, pos| {
+            let pos = pos?;
+            entries
+                .range((Excluded(pos), Unbounded))
+                .next()
+                .filter(|(k, _)| *k == pos.0)
+                .cloned()
+        })
+    }
+    fn prev_dup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.step(|entries, pos| {
+            let pos = pos?;
+            entries
+                .range((Unbounded, Excluded(pos)))
+                .next_back()
+                .filter(|(k, _)| *k == pos.0)
+                .cloned()
+        })
+    }
+    fn last_dup(&mut self) -> Result<Option<Cow<'tx, [u8]>>> {
+        let kv = self.step(|entries, pos| {
+            let pos = pos?;
+            entries
+                .range((Included(&(pos.0.clone(), vec![])), Unbounded))
+                .take_while(|(k, _)| *k == pos.0)
+                .last()
+                .cloned()
+        })?;
+        Ok(kv.map(|(_, v)| v))
+    }
+    fn next_nodup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.step(|entries, pos| match pos {
+            Some(pos) => entries
+                .range((Excluded(pos), Unbounded))
+                .find(|(k, _)| *k != pos.0)
+                .cloned(),
+            None => entries.iter().next().cloned(),
+        })
+    }
+    fn seek(&mut self, key: &[u8]) -> Result<Option<RawPair<'tx>>> {
+        self.step(|entries, _| entries.range((key.to_vec(), vec![])..).next().cloned())
+    }
+    fn seek_exact(&mut self, key: &[u8]) -> Result<Option<RawPair<'tx>>> {
+        self.step(|entries, _| {
+            entries
+                .range((key.to_vec(), vec![])..)
+                .next()
+                .filter(|(k, _)| k == key)
+                .cloned()
+        })
+    }
+    fn seek_both_range(&mut self, key: &[u8], subkey: &[u8]) -> Result<Option<Cow<'tx, [u8]>>> {
+        let start = (key.to_vec(), subkey.to_vec());
+        let kv = self.step(|entries, _| {
+            entries
+                .range((Included(&start), Unbounded))
+                .next()
+                .filter(|(k, _)| k == key)
+                .cloned()
+        })?;
+        Ok(kv.map(|(_, v)| v))
+    }
+    fn clone_position(&self) -> Result<Self> {
+        Ok(MemCursor {
+            tx: self.tx,
+            name: self.name.clone(),
+            pos: self.pos.clone(),
+        })
+    }
+}
@@ -0,0 +1,72 @@
+//! Counters and timings of db access, reported through the [`metrics`] facade
+//! when the `metrics` feature is enabled, e.g. to be exported to Prometheus by
+//! a service embedding this crate. Without the feature, recording is a no-op.
+//!
+//! Every metric is labeled with the name of the table it concerns.
+//!
+//! [`metrics`]: https://docs.rs/metrics
+
+/// Counter of values read by key.
+pub const READS: &str = "erigon_db_reads_total";
+/// Histogram of the time, in seconds, taken to read and decode a value by key.
+pub const READ_SECONDS: &str = "erigon_db_read_seconds";
+/// Counter of values written or deleted.
+pub const WRITES: &str = "erigon_db_writes_total";
+/// Counter of operations on typed cursors.
+pub const CURSOR_OPS: &str = "erigon_db_cursor_ops_total";
+/// Counter of keys or values that failed to decode.
+pub const DECODE_FAILURES: &str = "erigon_db_decode_failures_total";
+
+/// Registers a description of each metric with the installed recorder.
+#[cfg(feature = "metrics")]
+pub fn describe() {
+    ::metrics::describe_counter!(READS, "Values read by key");
+    ::metrics::describe_histogram!(
+        READ_SECONDS,
+        ::metrics::Unit::Seconds,
+        "Time taken to read and decode a value by key"
+    );
+    ::metrics::describe_counter!(WRITES, "Values written or deleted");
+    ::metrics::describe_counter!(CURSOR_OPS, "Operations on typed cursors");
+    ::metrics::describe_counter!(DECODE_FAILURES, "Keys or values that failed to decode");
+}
+
+// Runs the read `f` against `table`, counting it and recording its latency.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+#[inline]
+pub(crate) fn timed_read<R>(table: &'static str, f: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "metrics")]
+    let start = std::time::Instant::now();
+    let res = f();
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::increment_counter!(READS, "table" => table);
+        ::metrics::histogram!(READ_SECONDS, start.elapsed().as_secs_f64(), "table" => table);
+    }
+    res
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+#[inline]
+pub(crate) fn record_write(table: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::increment_counter!(WRITES, "table" => table);
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+#[inline]
+pub(crate) fn record_cursor_op(table: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::increment_counter!(CURSOR_OPS, "table" => table);
+}
+
+// Passes `res` through, counting it if it's a decode failure.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+#[inline]
+pub(crate) fn check_decode<T>(table: &'static str, res: crate::Result<T>) -> crate::Result<T> {
+    #[cfg(feature = "metrics")]
+    if res.is_err() {
+        ::metrics::increment_counter!(DECODE_FAILURES, "table" => table);
+    }
+    res
+}
@@ -1,24 +1,36 @@
-use eyre::Result;
-use mdbx::{DatabaseFlags, EnvironmentKind, NoWriteMap, TransactionKind, WriteFlags, RO, RW};
-use std::{borrow::Cow, path::Path};
+use crate::Result;
+use mdbx::{DatabaseFlags, NoWriteMap, TransactionKind, WriteFlags, RO, RW};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::c_void,
+    fs::File,
+    ops::{ControlFlow, Range},
+    path::{Path, PathBuf},
+    ptr,
+    sync::RwLock,
+};
 
+mod builder;
+pub mod mem;
+pub mod metrics;
+mod readahead;
+#[cfg(feature = "remote")]
+pub mod remote;
 pub mod tables;
+pub(crate) mod trace;
 pub mod traits;
 
-use tables::TableHandle;
-use traits::{DbFlags, DbName, DupSort, Mode, Table, TableDecode, TableEncode};
-
-fn open_env<E: EnvironmentKind>(
-    path: &Path,
-    num_tables: usize,
-    flags: mdbx::EnvironmentFlags,
-) -> Result<mdbx::Environment<E>> {
-    mdbx::Environment::new()
-        .set_max_dbs(num_tables)
-        .set_flags(flags)
-        .open(path)
-        .map_err(From::from)
-}
+use crate::error::{decode_err, mdbx_err, open_err};
+pub use builder::MdbxEnvBuilder;
+pub use readahead::Readahead;
+use tables::{NoFlags, TableHandle};
+use trace::db_span;
+use traits::{
+    DbFlags, DbName, DefaultFlags, DupSort, KvCursor, KvTx, KvTxMut, Mode, RawPair, Table,
+    TableDecode, TableDecodeBorrowed, TableEncode,
+};
 
 /// A wrapper around [`mdbx::Environment`].
 ///
@@ -36,12 +48,69 @@ fn open_env<E: EnvironmentKind>(
 #[derive(Debug)]
 pub struct MdbxEnv<M> {
     pub inner: mdbx::Environment<NoWriteMap>,
+    // the path of the data file, mdbx.dat
+    data_path: PathBuf,
     _mode: std::marker::PhantomData<M>,
 }
 impl<M> MdbxEnv<M> {
     pub fn inner(&self) -> &mdbx::Environment<NoWriteMap> {
         &self.inner
     }
+
+    /// Clears the reader slots left behind by processes that exited without
+    /// closing their read transactions, returning the number cleared. Stale
+    /// slots hold back page reclamation and can make new readers fail with
+    /// MDBX_BAD_RSLOT.
+    pub fn reader_check(&self) -> Result<usize> {
+        let mut dead = 0;
+        // safety: the env pointer is valid for as long as `self.inner` is
+        let rc = unsafe { mdbx_sys::mdbx_reader_check(self.inner.env(), &mut dead) };
+        // MDBX_RESULT_TRUE means dead readers were found and cleared
+        if rc != 0 && rc != mdbx_sys::MDBX_RESULT_TRUE {
+            return Err(mdbx_err(mdbx::Error::from_err_code(rc)));
+        }
+        Ok(dead as usize)
+    }
+
+    /// Resizes this process's memory map to the geometry currently recorded
+    /// in the db. Another process growing the db past the map makes new
+    /// transactions fail with MDBX_UNABLE_EXTEND_MAPSIZE until the map is
+    /// resized, which mdbx otherwise only does once no transaction of this
+    /// process is open.
+    pub fn sync_geometry(&self) -> Result<()> {
+        // safety: the env pointer is valid for as long as `self.inner` is,
+        // and -1 keeps each parameter at the db's current value
+        mdbx_rc(unsafe {
+            mdbx_sys::mdbx_env_set_geometry(self.inner.env(), -1, -1, -1, -1, -1, -1)
+        })
+    }
+
+    /// Asks the kernel to read the bytes in `range` of the data file into the
+    /// page cache ahead of use, with `madvise(MADV_WILLNEED)`.
+    ///
+    /// mdbx's readahead setting applies to the whole environment, so an env
+    /// opened with `no_rdahead` (as Erigon's is) reads one page per fault.
+    /// Prefetching the file, or the part of it a table lives in, before a scan
+    /// avoids paying for each of those faults on a cold cache. A no-op on
+    /// non-unix platforms.
+    pub fn prefetch_range(&self, range: Range<u64>) -> Result<()> {
+        let file = File::open(&self.data_path)?;
+        let end = range.end.min(file.metadata()?.len());
+        if range.start >= end {
+            return Ok(());
+        }
+        // safety: the mapping is read-only and dropped without being read
+        let mmap = unsafe {
+            memmap2::MmapOptions::new()
+                .offset(range.start)
+                .len((end - range.start) as usize)
+                .map(&file)?
+        };
+        #[cfg(unix)]
+        mmap.advise(memmap2::Advice::WillNeed)?;
+        drop(mmap);
+        Ok(())
+    }
 }
 
 impl<M: Mode> MdbxEnv<M> {
@@ -49,36 +118,99 @@ impl<M: Mode> MdbxEnv<M> {
     /// read-only mode, mdbx will still modify the LCK-file, unless the filesystem
     /// is read-only.
     pub fn open(path: &Path, num_tables: usize, flags: EnvFlags) -> Result<Self> {
-        let mode = if M::is_writeable() {
-            mdbx::Mode::ReadWrite {
-                sync_mode: mdbx::SyncMode::Durable,
-            }
-        } else {
-            mdbx::Mode::ReadOnly
-        };
-        Ok(Self {
-            inner: open_env(path, num_tables, flags.with_mode(mode))?,
-            _mode: std::marker::PhantomData,
+        Self::builder().max_dbs(num_tables).flags(flags).open(path)
+    }
+
+    /// Returns a builder for an env with more control over its configuration
+    /// than [`MdbxEnv::open`].
+    pub fn builder() -> MdbxEnvBuilder<M> {
+        MdbxEnvBuilder::new()
+    }
+
+    /// Returns mdbx's statistics for the environment as a whole.
+    pub fn stat(&self) -> Result<TableStat> {
+        Ok(self.inner.stat()?.into())
+    }
+
+    /// Returns information about the environment's memory map, last committed
+    /// transaction, and reader slots.
+    pub fn info(&self) -> Result<EnvInfo> {
+        let info = self.inner.info().map_err(mdbx_err)?;
+        Ok(EnvInfo {
+            map_size: info.map_size(),
+            last_pgno: info.last_pgno(),
+            last_txnid: info.last_txnid(),
+            max_readers: info.max_readers(),
+            num_readers: info.num_readers(),
+            page_size: self.inner.stat().map_err(mdbx_err)?.page_size(),
         })
     }
 
     /// Create a read-only mdbx transaction.
     pub fn begin_ro(&self) -> Result<MdbxTx<'_, RO>> {
-        Ok(MdbxTx::new(self.inner.begin_ro_txn()?))
+        Ok(MdbxTx::new(self.inner.begin_ro_txn().map_err(mdbx_err)?))
     }
 }
 
 impl MdbxEnv<RO> {
     /// Create a read-only mdbx transaction.
     pub fn begin(&self) -> Result<MdbxTx<'_, RO>> {
-        Ok(MdbxTx::new(self.inner.begin_ro_txn()?))
+        Ok(MdbxTx::new(self.inner.begin_ro_txn().map_err(mdbx_err)?))
     }
 }
 
 impl MdbxEnv<RW> {
     /// Create a read-write mdbx transaction. Blocks if another rw transaction is open.
     pub fn begin_rw(&self) -> Result<MdbxTx<'_, RW>> {
-        Ok(MdbxTx::new(self.inner.begin_rw_txn()?))
+        Ok(MdbxTx::new(self.inner.begin_rw_txn().map_err(mdbx_err)?))
+    }
+}
+
+impl MdbxEnv<RW> {
+    /// Raises the upper bound on the size of the data file to `size_upper`
+    /// bytes, leaving the rest of the geometry as it is. Must not be called
+    /// while this process has a read-write transaction open.
+    pub fn set_max_map_size(&self, size_upper: usize) -> Result<()> {
+        // safety: the env pointer is valid for as long as `self.inner` is,
+        // and -1 leaves the other parameters unchanged
+        let rc = unsafe {
+            mdbx_sys::mdbx_env_set_geometry(
+                self.inner.env(),
+                -1,
+                -1,
+                size_upper as isize,
+                -1,
+                -1,
+                -1,
+            )
+        };
+        if rc != 0 {
+            return Err(mdbx_err(mdbx::Error::from_err_code(rc)));
+        }
+        Ok(())
+    }
+}
+
+/// Information about an open environment, as reported by mdbx.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct EnvInfo {
+    /// Size of the memory map in bytes.
+    pub map_size: usize,
+    /// Number of the last page used in the storage file.
+    pub last_pgno: usize,
+    /// Id of the last committed transaction.
+    pub last_txnid: usize,
+    /// Maximum number of reader slots in the environment.
+    pub max_readers: usize,
+    /// Number of reader slots currently in use. A reader slot held open for a
+    /// long time prevents mdbx from reclaiming pages freed after it began.
+    pub num_readers: usize,
+    pub page_size: u32,
+}
+impl EnvInfo {
+    /// The number of bytes of the storage file currently in use.
+    pub fn used_bytes(&self) -> usize {
+        (self.last_pgno + 1) * self.page_size as usize
     }
 }
 
@@ -108,6 +240,16 @@ pub struct EnvFlags {
     pub liforeclaim: bool,
 }
 impl EnvFlags {
+    /// Returns the flags with mdbx's readahead enabled or disabled. Readahead
+    /// speeds up long sequential scans, but wastes IO on random reads once
+    /// the db is larger than RAM.
+    pub fn readahead(self, enabled: bool) -> Self {
+        Self {
+            no_rdahead: !enabled,
+            ..self
+        }
+    }
+
     /// Creates an [`mdbx::EnvironmentFlags`] struct with the requested mode.
     pub fn with_mode(self, mode: mdbx::Mode) -> mdbx::EnvironmentFlags {
         mdbx::EnvironmentFlags {
@@ -123,10 +265,20 @@ impl EnvFlags {
     }
 }
 
-/// A wrapper around [`mdbx::Transaction`].
+/// A read-only transaction for composing many reads, not to be confused with
+/// the frozen block segments Erigon calls snapshots (see
+/// [`crate::snapshots`]). Like any mdbx transaction, every read sees the db as
+/// it was when the transaction began, and each table is opened at most once.
+pub type Snapshot<'env> = MdbxTx<'env, RO>;
+
+/// A wrapper around [`mdbx::Transaction`]. Each table is opened at most once
+/// per transaction; its dbi is cached for later reads and writes.
 #[derive(Debug)]
 pub struct MdbxTx<'env, K: TransactionKind> {
     pub inner: mdbx::Transaction<'env, K, NoWriteMap>,
+    // The dbi of each table opened so far, by table name. A dbi is a plain
+    // index that stays valid until the transaction ends.
+    dbis: RwLock<HashMap<&'static str, mdbx_sys::MDBX_dbi>>,
 }
 impl<'env, M> MdbxTx<'env, M>
 where
@@ -139,14 +291,34 @@ where
             flags |= DatabaseFlags::CREATE;
         }
         Ok(TableHandle::new(
-            self.inner.open_db_with_flags(Some(Db::NAME), flags)?,
+            self.inner
+                .open_db_with_flags(Some(Db::NAME), flags)
+                .map_err(|e| open_err(Db::NAME, e))?,
         ))
     }
+
+    // Returns the dbi of table `Db`, opening the table on first use.
+    fn dbi<Db: DbName, Flags: DbFlags>(&self) -> Result<mdbx_sys::MDBX_dbi> {
+        if let Some(&dbi) = self.dbis.read().unwrap().get(Db::NAME) {
+            return Ok(dbi);
+        }
+        let dbi = self.open_db::<Db, Flags>()?.dbi();
+        self.dbis.write().unwrap().insert(Db::NAME, dbi);
+        Ok(dbi)
+    }
+
+    /// Returns the number of tables opened by the transaction so far.
+    pub fn open_tables(&self) -> usize {
+        self.dbis.read().unwrap().len()
+    }
 }
 
 impl<'env, K: TransactionKind> MdbxTx<'env, K> {
     pub fn new(inner: mdbx::Transaction<'env, K, NoWriteMap>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            dbis: Default::default(),
+        }
     }
 
     pub fn get<'tx, T, F>(
@@ -159,7 +331,8 @@ impl<'env, K: TransactionKind> MdbxTx<'env, K> {
         F: DbFlags,
     {
         self.inner
-            .get(db.as_ref(), key.encode().as_ref())?
+            .get(db.as_ref(), key.encode().as_ref())
+            .map_err(mdbx_err)?
             .map(decode_one::<T>)
             .transpose()
     }
@@ -172,7 +345,106 @@ impl<'env, K: TransactionKind> MdbxTx<'env, K> {
         T: Table<'tx>,
         F: DbFlags,
     {
-        Ok(MdbxCursor::new(self.inner.cursor(db.as_ref())?))
+        Ok(MdbxCursor::new(self.inner.cursor(&db).map_err(mdbx_err)?))
+    }
+}
+
+impl<'env, K: TransactionKind> MdbxTx<'env, K> {
+    /// Returns the names of all of the named tables in the environment.
+    pub fn table_names(&self) -> Result<Vec<String>> {
+        // the main (unnamed) database stores one key for each named table
+        let main = self.inner.open_db(None).map_err(mdbx_err)?;
+        let mut cur = self.inner.cursor(&main).map_err(mdbx_err)?;
+        let mut names = Vec::new();
+        let mut kv = cur
+            .first::<Cow<'_, [u8]>, Cow<'_, [u8]>>()
+            .map_err(mdbx_err)?;
+        while let Some((name, _)) = kv {
+            names.push(String::from_utf8_lossy(&name).into_owned());
+            kv = cur.next().map_err(mdbx_err)?;
+        }
+        Ok(names)
+    }
+
+    /// Returns mdbx's statistics for the named table. The table is opened
+    /// with whatever flags it was created with.
+    pub fn table_stat(&self, name: &str) -> Result<TableStat> {
+        let db = self
+            .inner
+            .open_db_with_flags(Some(name), DatabaseFlags::ACCEDE)
+            .map_err(|e| open_err(name, e))?;
+        Ok(self.inner.db_stat(&db).map_err(mdbx_err)?.into())
+    }
+
+    /// Returns up to `limit` raw (key, value) pairs from the named table,
+    /// skipping the first `offset` entries. The table is opened with whatever
+    /// flags it was created with.
+    pub fn dump_named(
+        &self,
+        name: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let db = self
+            .inner
+            .open_db_with_flags(Some(name), DatabaseFlags::ACCEDE)
+            .map_err(|e| open_err(name, e))?;
+        self.dump_raw(TableHandle::<(), NoFlags>::new(db), limit, offset)
+    }
+
+    /// Returns up to `limit` raw (key, value) pairs from the table, skipping
+    /// the first `offset` entries.
+    pub fn dump_raw<Db, F>(
+        &self,
+        db: TableHandle<'_, Db, F>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>>
+    where
+        F: DbFlags,
+    {
+        let mut cur = self.inner.cursor(&db).map_err(mdbx_err)?;
+        cur.iter_start::<Cow<'_, [u8]>, Cow<'_, [u8]>>()
+            .skip(offset)
+            .take(limit)
+            .map(|res| {
+                let (k, v) = res.map_err(mdbx_err)?;
+                Ok((k.into_owned(), v.into_owned()))
+            })
+            .collect()
+    }
+}
+
+/// Statistics for a single table, as reported by mdbx.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TableStat {
+    pub entries: usize,
+    pub depth: u32,
+    pub page_size: u32,
+    pub branch_pages: usize,
+    pub leaf_pages: usize,
+    pub overflow_pages: usize,
+}
+impl TableStat {
+    /// The total number of pages used by the table.
+    pub fn pages(&self) -> usize {
+        self.branch_pages + self.leaf_pages + self.overflow_pages
+    }
+    /// The total size of the table's pages in bytes.
+    pub fn size(&self) -> usize {
+        self.pages() * self.page_size as usize
+    }
+}
+impl From<mdbx::Stat> for TableStat {
+    fn from(stat: mdbx::Stat) -> Self {
+        Self {
+            entries: stat.entries(),
+            depth: stat.depth(),
+            page_size: stat.page_size(),
+            branch_pages: stat.branch_pages(),
+            leaf_pages: stat.leaf_pages(),
+            overflow_pages: stat.overflow_pages(),
+        }
     }
 }
 
@@ -189,30 +461,212 @@ impl<'env> MdbxTx<'env, RW> {
     {
         self.inner
             .put(db.as_ref(), key.encode(), val.encode(), WriteFlags::UPSERT)
-            .map_err(From::from)
+            .map_err(mdbx_err)
+    }
+
+    /// Creates the named table with `flags` if it does not exist already.
+    pub fn create_table(&self, name: &str, flags: DatabaseFlags) -> Result<()> {
+        self.inner
+            .create_db(Some(name), flags | DatabaseFlags::CREATE)
+            .map_err(mdbx_err)?;
+        Ok(())
     }
 
     /// Commit the transaction. The Drop impl for mdbx::Transaction will take care
     /// of this, but use this method explicitly if you wish to handle any errors.
     pub fn commit(self) -> Result<bool> {
-        self.inner.commit().map_err(From::from)
+        self.inner.commit().map_err(mdbx_err)
     }
 }
 
-/// A wrapper around [`mdbx::Cursor`].
+impl<'env, K: Mode> KvTx for MdbxTx<'env, K> {
+    type Cursor<'tx>
+        = mdbx::Cursor<'tx, K>
+    where
+        Self: 'tx;
+
+    fn get_raw<'tx, T>(&'tx self, key: &[u8]) -> Result<Option<Cow<'tx, [u8]>>>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let dbi = self.dbi::<T::Name, T::Flags>()?;
+        let txn = self.inner.txn();
+        let key = mdbx_val(key);
+        let mut data = mdbx_val(&[]);
+        // safety: the dbi belongs to this transaction, and decode_val only
+        // borrows the value for 'tx if the transaction hasn't dirtied its page
+        unsafe {
+            match mdbx_sys::mdbx_get(txn, dbi, &key, &mut data) {
+                mdbx_sys::MDBX_NOTFOUND => Ok(None),
+                rc => {
+                    mdbx_rc(rc)?;
+                    <Cow<'tx, [u8]> as mdbx::TableObject>::decode_val::<K>(txn, &data)
+                        .map(Some)
+                        .map_err(mdbx_err)
+                }
+            }
+        }
+    }
+
+    fn cursor_raw<'tx, T>(&'tx self) -> Result<Self::Cursor<'tx>>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let dbi = self.dbi::<T::Name, T::Flags>()?;
+        // mdbx::Cursor can only be opened through a database handle, so the
+        // cursor is opened on the main db, which needs no lookup by name, and
+        // then bound to the table
+        let main = self.inner.open_db(None).map_err(mdbx_err)?;
+        let cur = self.inner.cursor(&main).map_err(mdbx_err)?;
+        // safety: both the cursor and the dbi belong to this transaction
+        mdbx_rc(unsafe { mdbx_sys::mdbx_cursor_bind(self.inner.txn(), cur.cursor(), dbi) })?;
+        Ok(cur)
+    }
+
+    // Named tables are opened with whatever flags they were created with.
+    fn get_named<'tx>(&'tx self, name: &str, key: &[u8]) -> Result<Option<Cow<'tx, [u8]>>> {
+        let db = self
+            .inner
+            .open_db_with_flags(Some(name), DatabaseFlags::ACCEDE)
+            .map_err(|e| open_err(name, e))?;
+        self.inner.get(&db, key).map_err(mdbx_err)
+    }
+
+    fn cursor_named<'tx>(&'tx self, name: &str) -> Result<Self::Cursor<'tx>> {
+        let db = self
+            .inner
+            .open_db_with_flags(Some(name), DatabaseFlags::ACCEDE)
+            .map_err(|e| open_err(name, e))?;
+        self.inner.cursor(&db).map_err(mdbx_err)
+    }
+}
+
+impl<'env> KvTxMut for MdbxTx<'env, RW> {
+    fn put_raw<'tx, T>(&'tx self, key: &[u8], val: &[u8]) -> Result<()>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let dbi = self.dbi::<T::Name, T::Flags>()?;
+        let key = mdbx_val(key);
+        let mut data = mdbx_val(val);
+        // safety: the dbi belongs to this transaction
+        mdbx_rc(unsafe {
+            mdbx_sys::mdbx_put(
+                self.inner.txn(),
+                dbi,
+                &key,
+                &mut data,
+                WriteFlags::UPSERT.bits(),
+            )
+        })
+    }
+
+    fn delete_raw<'tx, T>(&'tx self, key: &[u8], val: Option<&[u8]>) -> Result<bool>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let dbi = self.dbi::<T::Name, T::Flags>()?;
+        let key = mdbx_val(key);
+        let val = val.map(mdbx_val);
+        let val = val.as_ref().map_or(ptr::null(), |val| val as *const _);
+        // safety: the dbi belongs to this transaction
+        match unsafe { mdbx_sys::mdbx_del(self.inner.txn(), dbi, &key, val) } {
+            mdbx_sys::MDBX_NOTFOUND => Ok(false),
+            rc => mdbx_rc(rc).map(|_| true),
+        }
+    }
+
+    fn commit(self) -> Result<bool> {
+        MdbxTx::commit(self)
+    }
+}
+
+// Points an mdbx value at `data`, for passing keys and values through ffi.
+fn mdbx_val(data: &[u8]) -> mdbx_sys::MDBX_val {
+    mdbx_sys::MDBX_val {
+        iov_len: data.len(),
+        iov_base: data.as_ptr() as *mut c_void,
+    }
+}
+
+// Converts an mdbx return code to a result.
+fn mdbx_rc(rc: i32) -> Result<()> {
+    match rc {
+        mdbx_sys::MDBX_SUCCESS => Ok(()),
+        rc => Err(mdbx_err(mdbx::Error::from_err_code(rc))),
+    }
+}
+
+impl<'tx, K: TransactionKind> KvCursor<'tx> for mdbx::Cursor<'tx, K> {
+    fn first(&mut self) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::first(self).map_err(mdbx_err)
+    }
+    fn last(&mut self) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::last(self).map_err(mdbx_err)
+    }
+    fn current(&mut self) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::get_current(self).map_err(mdbx_err)
+    }
+    fn next(&mut self) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::next(self).map_err(mdbx_err)
+    }
+    fn prev(&mut self) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::prev(self).map_err(mdbx_err)
+    }
+    fn next_dup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::next_dup(self).map_err(mdbx_err)
+    }
+    fn prev_dup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::prev_dup(self).map_err(mdbx_err)
+    }
+    fn last_dup(&mut self) -> Result<Option<Cow<'tx, [u8]>>> {
+        mdbx::Cursor::last_dup(self).map_err(mdbx_err)
+    }
+    fn get_multiple(&mut self) -> Result<Option<Cow<'tx, [u8]>>> {
+        mdbx::Cursor::get_multiple(self).map_err(mdbx_err)
+    }
+    fn next_multiple(&mut self) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::next_multiple(self).map_err(mdbx_err)
+    }
+    fn next_nodup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::next_nodup(self).map_err(mdbx_err)
+    }
+    fn seek(&mut self, key: &[u8]) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::set_range(self, key).map_err(mdbx_err)
+    }
+    fn seek_exact(&mut self, key: &[u8]) -> Result<Option<RawPair<'tx>>> {
+        mdbx::Cursor::set_key(self, key).map_err(mdbx_err)
+    }
+    fn seek_both_range(&mut self, key: &[u8], subkey: &[u8]) -> Result<Option<Cow<'tx, [u8]>>> {
+        mdbx::Cursor::get_both_range(self, key, subkey).map_err(mdbx_err)
+    }
+    // mdbx::Cursor's Clone copies the position with mdbx_cursor_copy
+    fn clone_position(&self) -> Result<Self> {
+        Ok(self.clone())
+    }
+    fn dup_count(&mut self) -> Result<u64> {
+        if KvCursor::current(self)?.is_none() {
+            return Ok(0);
+        }
+        let mut count = 0;
+        // safety: the cursor pointer is valid for as long as `self` is
+        mdbx_rc(unsafe { mdbx_sys::mdbx_cursor_count(self.cursor(), &mut count) })?;
+        Ok(count as u64)
+    }
+}
+
+/// A typed cursor into table `T`, wrapping a backend's raw [`KvCursor`].
 #[derive(Debug)]
-pub struct MdbxCursor<'tx, K, T>
-where
-    K: TransactionKind,
-{
-    pub inner: mdbx::Cursor<'tx, K>,
-    _dbi: std::marker::PhantomData<T>,
+pub struct TableCursor<'tx, C, T> {
+    pub inner: C,
+    _dbi: std::marker::PhantomData<(&'tx (), T)>,
 }
-impl<'tx, K, T> MdbxCursor<'tx, K, T>
-where
-    K: TransactionKind,
-{
-    pub fn new(inner: mdbx::Cursor<'tx, K>) -> Self {
+
+/// A typed cursor into an mdbx table.
+pub type MdbxCursor<'tx, K, T> = TableCursor<'tx, mdbx::Cursor<'tx, K>, T>;
+
+impl<'tx, C, T> TableCursor<'tx, C, T> {
+    pub fn new(inner: C) -> Self {
         Self {
             inner,
             _dbi: std::marker::PhantomData,
@@ -220,18 +674,73 @@ where
     }
 }
 
-impl<'tx, K, T> MdbxCursor<'tx, K, T>
+impl<'tx, C, T> TableCursor<'tx, C, T>
 where
-    K: TransactionKind,
+    C: KvCursor<'tx>,
+{
+    /// Returns a new cursor at the same position as this one, e.g. to
+    /// remember a position during a multi-pass walk, or to fork a nested
+    /// walk from it, without seeking again from the start.
+    pub fn clone_position(&self) -> Result<Self> {
+        self.inner.clone_position().map(Self::new)
+    }
+
+    /// Returns the number of duplicates at the cursor's key, without moving
+    /// it, e.g. the slots of an account after seeking to its StorageKey. 0 if
+    /// the cursor isn't on a key.
+    pub fn dup_count(&mut self) -> Result<u64> {
+        self.inner.dup_count()
+    }
+
+    /// Returns the cursor with readahead of `window` bytes, for a long
+    /// sequential walk in an env opened without readahead. See [`Readahead`].
+    pub fn readahead(self, window: usize) -> TableCursor<'tx, Readahead<C>, T> {
+        TableCursor::new(Readahead::new(self.inner, window))
+    }
+}
+
+impl<'tx, C, T> TableCursor<'tx, C, T>
+where
+    C: KvCursor<'tx>,
     T: Table<'tx>,
 {
-    /// Returns the (key, value) pair at the first key >= `key`
+    // The raw cursor, for an operation that's counted in the metrics.
+    fn raw(&mut self) -> &mut C {
+        metrics::record_cursor_op(<T::Name as DbName>::NAME);
+        &mut self.inner
+    }
+
+    /// Returns the (key, value) pair at the first key >= `key`. Same as
+    /// [`TableCursor::seek_range`].
     pub fn seek(&mut self, key: T::SeekKey) -> Result<Option<(T::Key, T::Value)>>
     where
         T::Key: TableDecode,
     {
-        self.inner
-            .set_range(key.encode().as_ref())?
+        self.seek_range(key)
+    }
+
+    /// Returns the (key, value) pair at the first key >= `key`, or `None` if
+    /// every key in the table is smaller (mdbx's SET_RANGE).
+    pub fn seek_range(&mut self, key: T::SeekKey) -> Result<Option<(T::Key, T::Value)>>
+    where
+        T::Key: TableDecode,
+    {
+        let key = key.encode();
+        db_span!("seek_range", <T::Name as DbName>::NAME, key.as_ref());
+        self.raw().seek(key.as_ref())?.map(decode::<T>).transpose()
+    }
+
+    /// Returns the (key, value) pair at exactly `key`, or `None` if the table
+    /// doesn't contain `key` (mdbx's SET_KEY). For dupsorted tables, the
+    /// first duplicate is returned.
+    pub fn seek_exact(&mut self, key: T::Key) -> Result<Option<(T::Key, T::Value)>>
+    where
+        T::Key: TableDecode,
+    {
+        let key = key.encode();
+        db_span!("seek_exact", <T::Name as DbName>::NAME, key.as_ref());
+        self.raw()
+            .seek_exact(key.as_ref())?
             .map(decode::<T>)
             .transpose()
     }
@@ -241,12 +750,12 @@ where
     where
         T::Key: TableDecode,
     {
-        self.inner.first()?.map(decode::<T>).transpose()
+        self.raw().first()?.map(decode::<T>).transpose()
     }
 
     /// Returns the first value in the table without attempting to decode the returned key.
     pub fn first_val(&mut self) -> Result<Option<T::Value>> {
-        self.inner.first()?.map(decode_val::<T>).transpose()
+        self.raw().first()?.map(decode_val::<T>).transpose()
     }
 
     #[allow(clippy::should_implement_trait)]
@@ -254,7 +763,60 @@ where
     where
         T::Key: TableDecode,
     {
-        self.inner.next()?.map(decode::<T>).transpose()
+        self.raw().next()?.map(decode::<T>).transpose()
+    }
+
+    /// Returns the last key/value pair in the table
+    pub fn last(&mut self) -> Result<Option<(T::Key, T::Value)>>
+    where
+        T::Key: TableDecode,
+    {
+        self.raw().last()?.map(decode::<T>).transpose()
+    }
+
+    /// Returns the key/value pair before the current cursor position. For
+    /// dupsorted tables, this steps through the duplicates at a key in
+    /// descending order before moving to the previous key.
+    pub fn prev(&mut self) -> Result<Option<(T::Key, T::Value)>>
+    where
+        T::Key: TableDecode,
+    {
+        self.raw().prev()?.map(decode::<T>).transpose()
+    }
+
+    /// Returns an owned iterator over (key, value) pairs in descending order,
+    /// beginning at the last key <= `start`, or at the end of the table if
+    /// `start` is `None`. If the table is dupsorted, every duplicate at
+    /// `start` is included, last to first.
+    pub fn walk_back(
+        mut self,
+        start: Option<T::Key>,
+    ) -> Result<impl Iterator<Item = Result<(<T as Table<'tx>>::Key, <T as Table<'tx>>::Value)>>>
+    where
+        T::Key: TableDecode,
+    {
+        let first = match start {
+            Some(start) => {
+                let start = start.encode();
+                db_span!("walk_back", <T::Name as DbName>::NAME, start.as_ref());
+                match self.raw().seek(start.as_ref())? {
+                    // step off the last duplicate at `start` and back again
+                    Some((k, _)) if k[..] == *start.as_ref() => match self.raw().next_nodup()? {
+                        Some(_) => self.raw().prev()?,
+                        None => self.raw().last()?,
+                    },
+                    Some(_) => self.raw().prev()?,
+                    None => self.raw().last()?,
+                }
+            }
+            None => self.raw().last()?,
+        };
+        let mut next = first.map(Ok);
+        Ok(std::iter::from_fn(move || {
+            let kv = next.take()?;
+            next = self.raw().prev().transpose();
+            Some(kv.and_then(decode::<T>))
+        }))
     }
 
     /// Returns an owned iterator over (key, value) pairs beginning at start_key.
@@ -265,14 +827,117 @@ where
     where
         T::Key: TableDecode,
     {
-        let first = self
-            .inner
-            .set_range(start_key.encode().as_ref())?
-            .map(decode::<T>);
+        let start_key = start_key.encode();
+        db_span!("walk", <T::Name as DbName>::NAME, start_key.as_ref());
+        let first = self.raw().seek(start_key.as_ref())?.map(decode::<T>);
 
         Ok(Walker { cur: self, first })
     }
 
+    /// Passes the (key, value) pairs beginning at `start_key` to `f`, in
+    /// order, until it returns [`ControlFlow::Break`]. Returns the value `f`
+    /// broke with, or `None` if the end of the table was reached first.
+    pub fn walk_while<B, F>(&mut self, start_key: T::Key, mut f: F) -> Result<Option<B>>
+    where
+        T::Key: TableDecode,
+        F: FnMut(&T::Key, &T::Value) -> ControlFlow<B>,
+    {
+        let start_key = start_key.encode();
+        db_span!("walk_while", <T::Name as DbName>::NAME, start_key.as_ref());
+        let mut kv = self.raw().seek(start_key.as_ref())?;
+        while let Some(raw) = kv {
+            let (k, v) = decode::<T>(raw)?;
+            if let ControlFlow::Break(b) = f(&k, &v) {
+                return Ok(Some(b));
+            }
+            kv = self.raw().next()?;
+        }
+        Ok(None)
+    }
+
+    /// Returns an owned iterator over the (key, value) pairs beginning at
+    /// `start_key`, mapped through `f`. The iterator ends at the first pair
+    /// for which `f` returns [`ControlFlow::Break`], without reading further.
+    pub fn walk_map<U, F>(
+        mut self,
+        start_key: T::Key,
+        mut f: F,
+    ) -> Result<impl Iterator<Item = Result<U>>>
+    where
+        T::Key: TableDecode,
+        F: FnMut(T::Key, T::Value) -> ControlFlow<(), U>,
+    {
+        let start_key = start_key.encode();
+        db_span!("walk_map", <T::Name as DbName>::NAME, start_key.as_ref());
+        let mut next = self.raw().seek(start_key.as_ref()).transpose();
+        Ok(std::iter::from_fn(move || {
+            let (k, v) = match next.take()?.and_then(decode::<T>) {
+                Ok(kv) => kv,
+                Err(e) => return Some(Err(e)),
+            };
+            match f(k, v) {
+                ControlFlow::Continue(u) => {
+                    next = self.raw().next().transpose();
+                    Some(Ok(u))
+                }
+                ControlFlow::Break(()) => None,
+            }
+        }))
+    }
+
+    /// Like [`TableCursor::walk`], but decodes each value as `V`, which may
+    /// borrow from the db rather than copy out of it, e.g. `Cow<[u8]>` for
+    /// the raw value.
+    pub fn walk_borrowed<V>(
+        mut self,
+        start_key: T::Key,
+    ) -> Result<impl Iterator<Item = Result<(<T as Table<'tx>>::Key, V)>>>
+    where
+        T::Key: TableDecode,
+        V: TableDecodeBorrowed<'tx>,
+    {
+        let start_key = start_key.encode();
+        db_span!("walk", <T::Name as DbName>::NAME, start_key.as_ref());
+        let mut next = self.raw().seek(start_key.as_ref()).transpose();
+        Ok(std::iter::from_fn(move || {
+            let kv = next.take()?;
+            next = self.raw().next().transpose();
+            let table = <T::Name as DbName>::NAME;
+            let res = kv.and_then(|(k, v)| {
+                let kv = (|| Ok((TableDecode::decode(&k)?, V::decode_borrowed(v)?)))();
+                kv.map_err(|e| decode_err(table, Some(&k), e))
+            });
+            Some(metrics::check_decode(table, res))
+        }))
+    }
+
+    /// Returns an owned iterator over the (key, value) pairs with keys in
+    /// `range`. Keys are compared by their encodings, so the iterator stops
+    /// at the first key >= `range.end` rather than walking to the end of the
+    /// table.
+    pub fn walk_range(
+        mut self,
+        range: Range<T::Key>,
+    ) -> Result<impl Iterator<Item = Result<(<T as Table<'tx>>::Key, <T as Table<'tx>>::Value)>>>
+    where
+        T::Key: TableDecode,
+    {
+        let (start, end) = (range.start.encode(), range.end.encode());
+        db_span!("walk_range", <T::Name as DbName>::NAME, start.as_ref());
+        let mut next = self.raw().seek(start.as_ref()).transpose();
+        Ok(std::iter::from_fn(move || {
+            let (k, v) = match next.take()? {
+                Ok(kv) => kv,
+                Err(e) => return Some(Err(e)),
+            };
+            if k[..] >= *end.as_ref() {
+                return None;
+            }
+            next = self.raw().next().transpose();
+            Some(decode::<T>((k, v)))
+        }))
+    }
+
     /// Returns an iterator over (key, value) pairs beginning at start_key. If the table
     /// is dupsorted (contains duplicate items for each key), all of the duplicates
     /// at a given key will be returned before moving on to the next key.
@@ -283,9 +948,14 @@ where
     where
         T::Key: TableDecode,
     {
-        self.inner
-            .iter_from(start_key.encode().as_ref())
-            .map(|res| decode::<T>(res?))
+        let mut start = Some(start_key.encode());
+        std::iter::from_fn(move || {
+            let kv = match start.take() {
+                Some(key) => self.raw().seek(key.as_ref()),
+                None => self.raw().next(),
+            };
+            kv.transpose().map(|res| decode::<T>(res?))
+        })
     }
 
     /// Returns an iterator over values beginning at start_key, without attempting
@@ -296,15 +966,20 @@ where
         &mut self,
         start_key: T::Key,
     ) -> impl Iterator<Item = Result<<T as Table<'tx>>::Value>> + '_ {
-        self.inner
-            .iter_from(start_key.encode().as_ref())
-            .map(|res| decode_val::<T>(res?))
+        let mut start = Some(start_key.encode());
+        std::iter::from_fn(move || {
+            let kv = match start.take() {
+                Some(key) => self.raw().seek(key.as_ref()),
+                None => self.raw().next(),
+            };
+            kv.transpose().map(|res| decode_val::<T>(res?))
+        })
     }
 }
 
-impl<'tx, K, T> MdbxCursor<'tx, K, T>
+impl<'tx, C, T> TableCursor<'tx, C, T>
 where
-    K: TransactionKind,
+    C: KvCursor<'tx>,
     T: DupSort<'tx>,
 {
     /// Finds the given key in the table, then the first duplicate entry at that
@@ -312,13 +987,33 @@ where
     /// returned includes the subkey prefix, meaning you likely want to decode
     /// it into `(subkey, value_at_subkey)`.
     ///
-    /// If you want to find an exact subkey in the dupsort "sub table", you need
-    /// to check that the returned value begins with your subkey. If it does not,
-    /// then the cursor seeked past the requested subkey without a match, meaning
-    /// the table does not contain a value that begins with the provided subkey.
+    /// If you want to find an exact subkey in the dupsort "sub table", use
+    /// [`TableCursor::seek_both_exact`]. Same as [`TableCursor::seek_both_range`].
     pub fn seek_dup(&mut self, key: T::Key, subkey: T::Subkey) -> Result<Option<T::Value>> {
-        self.inner
-            .get_both_range(key.encode().as_ref(), subkey.encode().as_ref())?
+        self.seek_both_range(key, subkey)
+    }
+
+    /// Returns the first duplicate value at `key` that is >= `subkey`, or
+    /// `None` if `key` isn't in the table or every duplicate at it is smaller
+    /// (mdbx's GET_BOTH_RANGE). The value returned includes the subkey prefix,
+    /// which may not match `subkey`.
+    pub fn seek_both_range(&mut self, key: T::Key, subkey: T::Subkey) -> Result<Option<T::Value>> {
+        let key = key.encode();
+        db_span!("seek_both_range", <T::Name as DbName>::NAME, key.as_ref());
+        self.raw()
+            .seek_both_range(key.as_ref(), subkey.encode().as_ref())?
+            .map(decode_one::<T>)
+            .transpose()
+    }
+
+    /// Returns the duplicate value at `key` that begins with `subkey`, or
+    /// `None` if there is no such value.
+    pub fn seek_both_exact(&mut self, key: T::Key, subkey: T::Subkey) -> Result<Option<T::Value>> {
+        let (key, subkey) = (key.encode(), subkey.encode());
+        db_span!("seek_both_exact", <T::Name as DbName>::NAME, key.as_ref());
+        self.raw()
+            .seek_both_range(key.as_ref(), subkey.as_ref())?
+            .filter(|val| val.starts_with(subkey.as_ref()))
             .map(decode_one::<T>)
             .transpose()
     }
@@ -330,7 +1025,7 @@ where
     where
         T::Key: TableDecode,
     {
-        self.inner.next_dup()?.map(decode::<T>).transpose()
+        self.raw().next_dup()?.map(decode::<T>).transpose()
     }
 
     /// Returns the next duplicate value at the current key, without attempting
@@ -338,7 +1033,117 @@ where
     /// subkey prefix, meaning you likely want to decode it into
     /// `(subkey, value_at_subkey)`.
     pub fn next_dup_val(&mut self) -> Result<Option<T::Value>> {
-        self.inner.next_dup()?.map(decode_val::<T>).transpose()
+        self.raw().next_dup()?.map(decode_val::<T>).transpose()
+    }
+
+    /// Returns the current key and the previous duplicate value at that key.
+    /// Note that the value returned includes the subkey prefix, meaning you
+    /// likely want to decode it into `(subkey, value_at_subkey)`.
+    pub fn prev_dup(&mut self) -> Result<Option<(T::Key, T::Value)>>
+    where
+        T::Key: TableDecode,
+    {
+        self.raw().prev_dup()?.map(decode::<T>).transpose()
+    }
+
+    /// Moves to the last duplicate value at the current key and returns it.
+    /// Note that the value returned includes the subkey prefix, meaning you
+    /// likely want to decode it into `(subkey, value_at_subkey)`.
+    pub fn last_dup(&mut self) -> Result<Option<T::Value>> {
+        self.raw().last_dup()?.map(decode_one::<T>).transpose()
+    }
+
+    /// Returns an owned iterator over the duplicate values for the given key,
+    /// from last to first. Note that the values returned include the subkey
+    /// prefix, meaning you likely want to decode them into
+    /// `(subkey, value_at_subkey)`.
+    pub fn walk_dup_back(
+        mut self,
+        key: T::Key,
+    ) -> Result<impl Iterator<Item = Result<<T as Table<'tx>>::Value>>> {
+        let key = key.encode();
+        db_span!("walk_dup_back", <T::Name as DbName>::NAME, key.as_ref());
+        let first = match self.raw().seek_exact(key.as_ref())? {
+            Some(_) => self.raw().last_dup()?,
+            None => None,
+        };
+        let mut next = first.map(Ok);
+        Ok(std::iter::from_fn(move || {
+            let val = next.take()?;
+            next = self
+                .raw()
+                .prev_dup()
+                .transpose()
+                .map(|res| res.map(|(_, v)| v));
+            Some(val.and_then(decode_one::<T>))
+        }))
+    }
+
+    /// Like [`TableCursor::walk_dup`] over every duplicate at `key`, but for
+    /// DUPFIXED tables the values are fetched a page at a time with
+    /// GET_MULTIPLE/NEXT_MULTIPLE rather than one per cursor operation. Other
+    /// tables fall back to reading one value at a time.
+    pub fn walk_dup_fast(
+        mut self,
+        key: T::Key,
+    ) -> Result<impl Iterator<Item = Result<<T as Table<'tx>>::Value>>>
+    where
+        T: DefaultFlags,
+    {
+        let key = key.encode();
+        db_span!("walk_dup_fast", <T::Name as DbName>::NAME, key.as_ref());
+        let size = self.raw().seek_exact(key.as_ref())?.map(|(_, v)| v.len());
+        let dupfixed = <T::Flags as DbFlags>::FLAGS.contains(DatabaseFlags::DUP_FIXED)
+            && size.map_or(false, |size| size > 0);
+        let mut page = match size {
+            Some(_) => self.next_dup_page(key.as_ref(), dupfixed, true)?,
+            None => None,
+        };
+        let mut pos = 0;
+        Ok(std::iter::from_fn(move || {
+            while page.as_ref()?.len() <= pos {
+                page = match self.next_dup_page(key.as_ref(), dupfixed, false) {
+                    Ok(page) => page,
+                    Err(e) => {
+                        page = None;
+                        return Some(Err(e));
+                    }
+                };
+                pos = 0;
+            }
+            let buf = page.as_ref()?;
+            let len = if dupfixed { size? } else { buf.len() };
+            let val = buf.get(pos..pos + len);
+            pos += len;
+            let table = <T::Name as DbName>::NAME;
+            let res = match val {
+                Some(val) => TableDecode::decode(val),
+                None => Err(eyre::eyre!("Truncated DUPFIXED page").into()),
+            };
+            let res = res.map_err(|e| decode_err(table, Some(key.as_ref()), e));
+            Some(metrics::check_decode(table, res))
+        }))
+    }
+
+    // Reads the next batch of duplicates at `key`: a page of values for
+    // DUPFIXED tables, otherwise a single value.
+    fn next_dup_page(
+        &mut self,
+        key: &[u8],
+        dupfixed: bool,
+        first: bool,
+    ) -> Result<Option<Vec<u8>>> {
+        let page = match (dupfixed, first) {
+            (true, true) => self.raw().get_multiple()?,
+            (true, false) => self
+                .raw()
+                .next_multiple()?
+                .filter(|(k, _)| k[..] == *key)
+                .map(|(_, v)| v),
+            (false, true) => self.raw().current()?.map(|(_, v)| v),
+            (false, false) => self.raw().next_dup()?.map(|(_, v)| v),
+        };
+        Ok(page.map(Cow::into_owned))
     }
 
     /// Returns an owned iterator over duplicate values for the given key. Note
@@ -349,15 +1154,48 @@ where
         key: T::Key,
         subkey: T::Subkey,
     ) -> Result<impl Iterator<Item = Result<<T as Table<'tx>>::Value>>> {
+        let key = key.encode();
+        db_span!("walk_dup", <T::Name as DbName>::NAME, key.as_ref());
         let first = self
-            .inner
-            .get_both_range(key.encode().as_ref(), subkey.encode().as_ref())?
+            .raw()
+            .seek_both_range(key.as_ref(), subkey.encode().as_ref())?
             .map(decode_one::<T>);
 
         Ok(DupWalker { cur: self, first })
     }
 }
 
+impl<'tx, T> MdbxCursor<'tx, RW, T>
+where
+    T: Table<'tx>,
+{
+    /// Deletes the key/value pair at the current cursor position. For dupsorted
+    /// tables, only the current duplicate is removed.
+    pub fn delete_current(&mut self) -> Result<()> {
+        self.inner.del(WriteFlags::empty()).map_err(mdbx_err)
+    }
+}
+
+impl<'tx, T> MdbxCursor<'tx, RW, T>
+where
+    T: DupSort<'tx>,
+{
+    /// Deletes the single duplicate entry at `key` whose value begins with
+    /// `subkey`, leaving the rest of the duplicates at `key` untouched. Returns
+    /// `true` if a matching entry was found and deleted.
+    pub fn delete_dup(&mut self, key: T::Key, subkey: T::Subkey) -> Result<bool> {
+        let subkey = subkey.encode();
+        let found = self
+            .inner
+            .get_both_range::<Cow<'_, [u8]>>(key.encode().as_ref(), subkey.as_ref())?
+            .map_or(false, |val| val.starts_with(subkey.as_ref()));
+        if found {
+            self.delete_current()?;
+        }
+        Ok(found)
+    }
+}
+
 // Helper functions, primarily for type inference. These save us from needing
 // to specify the TableObject type we expect from every mdbx function call.
 pub fn decode<'tx, T>(kv: (Cow<'tx, [u8]>, Cow<'tx, [u8]>)) -> Result<(T::Key, T::Value)>
@@ -365,21 +1203,27 @@ where
     T: Table<'tx>,
     T::Key: TableDecode,
 {
-    Ok((TableDecode::decode(&kv.0)?, TableDecode::decode(&kv.1)?))
+    let table = <T::Name as DbName>::NAME;
+    let res = (|| Ok((TableDecode::decode(&kv.0)?, TableDecode::decode(&kv.1)?)))();
+    metrics::check_decode(table, res.map_err(|e| decode_err(table, Some(&kv.0), e)))
 }
 // Decodes only the value, ignoring the returned key.
 pub fn decode_val<'tx, T>(kv: (Cow<'tx, [u8]>, Cow<'tx, [u8]>)) -> Result<T::Value>
 where
     T: Table<'tx>,
 {
-    TableDecode::decode(&kv.1)
+    let table = <T::Name as DbName>::NAME;
+    let res = TableDecode::decode(&kv.1).map_err(|e| decode_err(table, Some(&kv.0), e));
+    metrics::check_decode(table, res)
 }
 // Decodes a single value.
 pub fn decode_one<'tx, T>(val: Cow<'tx, [u8]>) -> Result<T::Value>
 where
     T: Table<'tx>,
 {
-    TableDecode::decode(&val)
+    let table = <T::Name as DbName>::NAME;
+    let res = TableDecode::decode(&val).map_err(|e| decode_err(table, None, e));
+    metrics::check_decode(table, res)
 }
 
 /// An internal struct for turning a cursor to a dupsorted table into an iterator
@@ -387,18 +1231,17 @@ where
 ///
 /// See [Akula](https://github.com/akula-bft/akula/blob/1800ac77b979d410bea5ff3bcd2617cb302d66fe/src/kv/mdbx.rs#L432)
 /// for a much more interesting approach using generators.
-struct DupWalker<'tx, K, T>
+struct DupWalker<'tx, C, T>
 where
-    K: TransactionKind,
     T: Table<'tx>,
 {
-    pub cur: MdbxCursor<'tx, K, T>,
+    pub cur: TableCursor<'tx, C, T>,
     pub first: Option<Result<T::Value>>,
 }
 
-impl<'tx, K, T> std::iter::Iterator for DupWalker<'tx, K, T>
+impl<'tx, C, T> std::iter::Iterator for DupWalker<'tx, C, T>
 where
-    K: TransactionKind,
+    C: KvCursor<'tx>,
     T: DupSort<'tx>,
 {
     type Item = Result<T::Value>;
@@ -416,18 +1259,17 @@ where
 ///
 /// See [Akula](https://github.com/akula-bft/akula/blob/1800ac77b979d410bea5ff3bcd2617cb302d66fe/src/kv/mdbx.rs#L319)
 /// for a much more interesting approach using generators.
-struct Walker<'tx, K, T>
+struct Walker<'tx, C, T>
 where
-    K: TransactionKind,
     T: Table<'tx>,
 {
-    pub cur: MdbxCursor<'tx, K, T>,
+    pub cur: TableCursor<'tx, C, T>,
     pub first: Option<Result<(T::Key, T::Value)>>,
 }
 
-impl<'tx, K, T> std::iter::Iterator for Walker<'tx, K, T>
+impl<'tx, C, T> std::iter::Iterator for Walker<'tx, C, T>
 where
-    K: TransactionKind,
+    C: KvCursor<'tx>,
     T: Table<'tx>,
     T::Key: TableDecode,
 {
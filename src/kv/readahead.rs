@@ -0,0 +1,160 @@
+//! Readahead for a single cursor.
+//!
+//! mdbx's readahead setting applies to the whole environment, and Erigon's
+//! env is opened with it disabled, which suits point lookups. [`Readahead`]
+//! wraps a cursor to read ahead only for the walk it does: whenever the
+//! cursor moves past the pages it last advised, it asks the kernel to load
+//! the next `window` bytes of the map with `madvise(MADV_WILLNEED)`.
+//!
+//! ```ignore
+//! let cur = db.cursor::<CanonicalHeader>()?.readahead(4 << 20);
+//! for read in cur.walk(BlockNumber(0))? { ... }
+//! ```
+use crate::{
+    kv::traits::{KvCursor, RawPair},
+    Result,
+};
+use std::borrow::Cow;
+
+/// A cursor that reads ahead of the values it returns. See the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct Readahead<C> {
+    inner: C,
+    window: usize,
+    // the range of addresses last advised
+    advised: (usize, usize),
+}
+
+impl<C> Readahead<C> {
+    /// Wraps `inner`, reading `window` bytes ahead of it.
+    pub fn new(inner: C, window: usize) -> Self {
+        Self {
+            inner,
+            window,
+            advised: (0, 0),
+        }
+    }
+
+    /// The wrapped cursor.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    // Advises the window following `v`, if `v` is outside the last window.
+    // Only values borrowed from the map are considered: an owned value was
+    // copied out of it, and says nothing about where the cursor is.
+    fn advise(&mut self, v: &[u8]) {
+        let addr = v.as_ptr() as usize;
+        if self.window == 0 || (self.advised.0..self.advised.1).contains(&addr) {
+            return;
+        }
+        let start = addr & !(page_size() - 1);
+        self.advised = (start, start.saturating_add(self.window));
+        will_need(start, self.window);
+    }
+
+    fn pair<'tx>(&mut self, res: Result<Option<RawPair<'tx>>>) -> Result<Option<RawPair<'tx>>> {
+        if let Ok(Some((_, Cow::Borrowed(v)))) = &res {
+            self.advise(v);
+        }
+        res
+    }
+
+    fn value<'tx>(
+        &mut self,
+        res: Result<Option<Cow<'tx, [u8]>>>,
+    ) -> Result<Option<Cow<'tx, [u8]>>> {
+        if let Ok(Some(Cow::Borrowed(v))) = &res {
+            self.advise(v);
+        }
+        res
+    }
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    // safety: sysconf has no preconditions
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+#[cfg(not(unix))]
+fn page_size() -> usize {
+    4096
+}
+
+#[cfg(unix)]
+fn will_need(start: usize, len: usize) {
+    // safety: madvise doesn't access the range, and fails with ENOMEM rather
+    // than faulting if part of it isn't mapped. The advice is only a hint, so
+    // its result is ignored.
+    unsafe {
+        libc::madvise(start as *mut libc::c_void, len, libc::MADV_WILLNEED);
+    }
+}
+
+#[cfg(not(unix))]
+fn will_need(_start: usize, _len: usize) {}
+
+impl<'tx, C: KvCursor<'tx>> KvCursor<'tx> for Readahead<C> {
+    fn first(&mut self) -> Result<Option<RawPair<'tx>>> {
+        let res = self.inner.first();
+        self.pair(res)
+    }
+    fn last(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.inner.last()
+    }
+    fn current(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.inner.current()
+    }
+    fn next(&mut self) -> Result<Option<RawPair<'tx>>> {
+        let res = self.inner.next();
+        self.pair(res)
+    }
+    fn prev(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.inner.prev()
+    }
+    fn next_dup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        let res = self.inner.next_dup();
+        self.pair(res)
+    }
+    fn prev_dup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.inner.prev_dup()
+    }
+    fn last_dup(&mut self) -> Result<Option<Cow<'tx, [u8]>>> {
+        self.inner.last_dup()
+    }
+    fn get_multiple(&mut self) -> Result<Option<Cow<'tx, [u8]>>> {
+        let res = self.inner.get_multiple();
+        self.value(res)
+    }
+    fn next_multiple(&mut self) -> Result<Option<RawPair<'tx>>> {
+        let res = self.inner.next_multiple();
+        self.pair(res)
+    }
+    fn next_nodup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        let res = self.inner.next_nodup();
+        self.pair(res)
+    }
+    fn seek(&mut self, key: &[u8]) -> Result<Option<RawPair<'tx>>> {
+        let res = self.inner.seek(key);
+        self.pair(res)
+    }
+    fn seek_exact(&mut self, key: &[u8]) -> Result<Option<RawPair<'tx>>> {
+        let res = self.inner.seek_exact(key);
+        self.pair(res)
+    }
+    fn seek_both_range(&mut self, key: &[u8], subkey: &[u8]) -> Result<Option<Cow<'tx, [u8]>>> {
+        let res = self.inner.seek_both_range(key, subkey);
+        self.value(res)
+    }
+    fn clone_position(&self) -> Result<Self> {
+        Ok(Self::new(self.inner.clone_position()?, self.window))
+    }
+    fn dup_count(&mut self) -> Result<u64> {
+        self.inner.dup_count()
+    }
+}
@@ -0,0 +1,314 @@
+//! A client for Erigon's remote KV gRPC service.
+//!
+//! Erigon (and its rpcdaemon) can expose the chaindata database over gRPC with
+//! `--private.api.addr`. [`RemoteKv`] connects to that endpoint and provides
+//! read-only transactions implementing [`KvTx`], so an
+//! [`Erigon`](crate::Erigon) reader works against a remote node exactly as it
+//! does against a local mdbx file:
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> {
+//! use erigon_db::{kv::remote::RemoteKv, Erigon};
+//! use mdbx::RO;
+//!
+//! let kv = RemoteKv::connect("http://localhost:9090")?;
+//! let db = Erigon::<RO, _>::new(kv.begin()?);
+//! let head = db.read_head_block_number()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The service is async, while the rest of this crate is not, so each
+//! [`RemoteKv`] owns a single-threaded tokio runtime that drives its requests.
+use eyre::eyre;
+use std::{borrow::Cow, cell::RefCell, collections::HashMap};
+use tokio::{runtime::Runtime, sync::mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{codec::Streaming, transport::Endpoint};
+
+use crate::{
+    kv::{
+        tables::find_tables,
+        traits::{DbFlags, DefaultFlags, KvCursor, KvTx, RawPair, Table},
+    },
+    Error, Result,
+};
+use mdbx::DatabaseFlags;
+
+pub mod proto;
+use proto::{Cursor, KvClient, Op, Pair};
+
+/// A connection to a remote KV service.
+#[derive(Debug)]
+pub struct RemoteKv {
+    rt: Runtime,
+    client: KvClient,
+}
+
+impl RemoteKv {
+    /// Connects to the KV service at `addr`, e.g. `http://localhost:9090`.
+    pub fn connect(addr: impl Into<String>) -> Result<Self> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let endpoint = Endpoint::from_shared(addr.into())?;
+        let channel = rt.block_on(endpoint.connect())?;
+        Ok(Self {
+            rt,
+            client: KvClient::new(channel),
+        })
+    }
+
+    /// Begins a read-only transaction. The remote service does not support
+    /// writes.
+    pub fn begin(&self) -> Result<RemoteTx<'_>> {
+        let (sender, rx) = mpsc::channel(1);
+        let mut client = self.client.clone();
+        let mut responses = self
+            .rt
+            .block_on(client.tx(ReceiverStream::new(rx)))?
+            .into_inner();
+        let first = self
+            .rt
+            .block_on(responses.message())?
+            .ok_or_else(|| eyre!("Remote KV closed the stream before sending a tx id"))?;
+        Ok(RemoteTx {
+            kv: self,
+            conn: RefCell::new(Conn { sender, responses }),
+            tx_id: first.tx_id,
+            view_id: first.view_id,
+            cursors: Default::default(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Conn {
+    sender: mpsc::Sender<Cursor>,
+    responses: Streaming<Pair>,
+}
+
+/// A read-only transaction against a remote KV service. Dropping the
+/// transaction closes the underlying stream, which ends the remote tx.
+#[derive(Debug)]
+pub struct RemoteTx<'kv> {
+    kv: &'kv RemoteKv,
+    conn: RefCell<Conn>,
+    tx_id: u64,
+    view_id: u64,
+    // a cursor per table name, opened by the first point read of the table and
+    // reused by the rest, so a read is one round trip instead of three
+    cursors: RefCell<HashMap<String, u32>>,
+}
+
+impl<'kv> RemoteTx<'kv> {
+    /// The id of the transaction on the remote node.
+    pub fn tx_id(&self) -> u64 {
+        self.tx_id
+    }
+
+    /// The id of the state view on the remote node, which changes every time
+    /// the node commits new state.
+    pub fn view_id(&self) -> u64 {
+        self.view_id
+    }
+
+    // Sends a single request and waits for its response.
+    fn send(&self, req: Cursor) -> Result<Pair> {
+        let mut conn = self.conn.borrow_mut();
+        let Conn { sender, responses } = &mut *conn;
+        self.kv.rt.block_on(async {
+            sender
+                .send(req)
+                .await
+                .map_err(|_| eyre!("Remote KV stream closed"))?;
+            let pair = responses
+                .message()
+                .await?
+                .ok_or_else(|| eyre!("Remote KV stream closed"))?;
+            Ok::<_, Error>(pair)
+        })
+    }
+
+    fn call(&self, op: Op, cursor: u32, k: &[u8], v: &[u8]) -> Result<Pair> {
+        self.send(Cursor {
+            op: op as i32,
+            cursor,
+            k: k.to_vec(),
+            v: v.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    fn open(&self, name: &str, dupsort: bool) -> Result<u32> {
+        let op = if dupsort { Op::OpenDupSort } else { Op::Open };
+        let pair = self.send(Cursor {
+            op: op as i32,
+            bucket_name: name.to_string(),
+            ..Default::default()
+        })?;
+        Ok(pair.cursor_id)
+    }
+
+    // Reads the value at `key` with the cached cursor of the table, opening it
+    // on the first read.
+    fn get_cached(&self, name: &str, dupsort: bool, key: &[u8]) -> Result<Option<Cow<'_, [u8]>>> {
+        let cached = self.cursors.borrow().get(name).copied();
+        let id = match cached {
+            Some(id) => id,
+            None => {
+                let id = self.open(name, dupsort)?;
+                self.cursors.borrow_mut().insert(name.to_string(), id);
+                id
+            }
+        };
+        let pair = self.call(Op::SeekExact, id, key, &[])?;
+        Ok((!pair.k.is_empty()).then_some(Cow::Owned(pair.v)))
+    }
+}
+
+impl<'kv> Drop for RemoteTx<'kv> {
+    fn drop(&mut self) {
+        // as for RemoteCursor, a failed close is cleaned up with the tx
+        for (_, id) in self.cursors.get_mut().drain() {
+            let _ = self.call(Op::Close, id, &[], &[]);
+        }
+    }
+}
+
+impl<'kv> KvTx for RemoteTx<'kv> {
+    type Cursor<'tx>
+        = RemoteCursor<'tx, 'kv>
+    where
+        Self: 'tx;
+
+    fn get_raw<'tx, T>(&'tx self, key: &[u8]) -> Result<Option<Cow<'tx, [u8]>>>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let dupsort = <T::Flags as DbFlags>::FLAGS.contains(DatabaseFlags::DUP_SORT);
+        self.get_cached(T::Name::NAME, dupsort, key)
+    }
+
+    fn cursor_raw<'tx, T>(&'tx self) -> Result<Self::Cursor<'tx>>
+    where
+        T: Table<'tx> + DefaultFlags,
+    {
+        let dupsort = <T::Flags as DbFlags>::FLAGS.contains(DatabaseFlags::DUP_SORT);
+        let id = self.open(T::Name::NAME, dupsort)?;
+        Ok(RemoteCursor {
+            tx: self,
+            id,
+            name: T::Name::NAME.to_string(),
+            dupsort,
+        })
+    }
+
+    fn get_named<'tx>(&'tx self, name: &str, key: &[u8]) -> Result<Option<Cow<'tx, [u8]>>> {
+        let dupsort = find_tables(name).iter().any(|t| t.is_dupsort());
+        self.get_cached(name, dupsort, key)
+    }
+
+    // The remote service needs to know whether a table is dupsorted, so
+    // tables missing from the registry are opened as plain tables.
+    fn cursor_named<'tx>(&'tx self, name: &str) -> Result<Self::Cursor<'tx>> {
+        let dupsort = find_tables(name).iter().any(|t| t.is_dupsort());
+        let id = self.open(name, dupsort)?;
+        Ok(RemoteCursor {
+            tx: self,
+            id,
+            name: name.to_string(),
+            dupsort,
+        })
+    }
+}
+
+/// A cursor over a table in a [`RemoteTx`]. Each call is a round trip to the
+/// remote node.
+#[derive(Debug)]
+pub struct RemoteCursor<'tx, 'kv> {
+    tx: &'tx RemoteTx<'kv>,
+    id: u32,
+    // kept to open a copy of the cursor in clone_position
+    name: String,
+    dupsort: bool,
+}
+
+impl<'tx, 'kv> RemoteCursor<'tx, 'kv> {
+    // The remote service signals "not found" with an empty key.
+    fn call(&mut self, op: Op, k: &[u8], v: &[u8]) -> Result<Option<RawPair<'tx>>> {
+        let pair = self.tx.call(op, self.id, k, v)?;
+        Ok((!pair.k.is_empty()).then(|| (Cow::Owned(pair.k), Cow::Owned(pair.v))))
+    }
+}
+
+impl<'tx, 'kv> Drop for RemoteCursor<'tx, 'kv> {
+    fn drop(&mut self) {
+        // nothing useful can be done if the close fails, and the cursor will
+        // be cleaned up with the rest of the tx anyway
+        let _ = self.tx.call(Op::Close, self.id, &[], &[]);
+    }
+}
+
+impl<'tx, 'kv> KvCursor<'tx> for RemoteCursor<'tx, 'kv> {
+    fn first(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.call(Op::First, &[], &[])
+    }
+    fn last(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.call(Op::Last, &[], &[])
+    }
+    fn current(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.call(Op::Current, &[], &[])
+    }
+    fn next(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.call(Op::Next, &[], &[])
+    }
+    fn prev(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.call(Op::Prev, &[], &[])
+    }
+    fn next_dup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.call(Op::NextDup, &[], &[])
+    }
+    fn prev_dup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.call(Op::PrevDup, &[], &[])
+    }
+    fn last_dup(&mut self) -> Result<Option<Cow<'tx, [u8]>>> {
+        // like SEEK_BOTH, LAST_DUP responds with only a value
+        let pair = self.tx.call(Op::LastDup, self.id, &[], &[])?;
+        Ok((!pair.v.is_empty()).then_some(Cow::Owned(pair.v)))
+    }
+    fn next_nodup(&mut self) -> Result<Option<RawPair<'tx>>> {
+        self.call(Op::NextNoDup, &[], &[])
+    }
+    fn seek(&mut self, key: &[u8]) -> Result<Option<RawPair<'tx>>> {
+        self.call(Op::Seek, key, &[])
+    }
+    fn seek_exact(&mut self, key: &[u8]) -> Result<Option<RawPair<'tx>>> {
+        self.call(Op::SeekExact, key, &[])
+    }
+    fn seek_both_range(&mut self, key: &[u8], subkey: &[u8]) -> Result<Option<Cow<'tx, [u8]>>> {
+        // SEEK_BOTH responds with only a value, which is empty if not found
+        let pair = self.tx.call(Op::SeekBoth, self.id, key, subkey)?;
+        Ok((!pair.v.is_empty()).then_some(Cow::Owned(pair.v)))
+    }
+    // The service can't copy a cursor, so this opens a new one and seeks it
+    // to the current (key, value) pair.
+    fn clone_position(&self) -> Result<Self> {
+        let cur = self.tx.call(Op::Current, self.id, &[], &[])?;
+        let mut clone = RemoteCursor {
+            tx: self.tx,
+            id: self.tx.open(&self.name, self.dupsort)?,
+            name: self.name.clone(),
+            dupsort: self.dupsort,
+        };
+        if !cur.k.is_empty() {
+            let op = if self.dupsort {
+                Op::SeekBothExact
+            } else {
+                Op::SeekExact
+            };
+            clone.call(op, &cur.k, &cur.v)?;
+        }
+        Ok(clone)
+    }
+}
@@ -0,0 +1,100 @@
+//! Message types and client for Erigon's remote KV service, as defined in
+//! [kv.proto](https://github.com/ledgerwatch/interfaces/blob/master/remote/kv.proto).
+//!
+//! These are written out by hand rather than generated so that building the
+//! crate does not require protoc. Only the `Tx` rpc is implemented.
+use tonic::{
+    codec::{ProstCodec, Streaming},
+    codegen::http::uri::PathAndQuery,
+    transport::Channel,
+    IntoStreamingRequest, Response, Status,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Op {
+    First = 0,
+    FirstDup = 1,
+    Seek = 2,
+    SeekBoth = 3,
+    Current = 4,
+    Last = 6,
+    LastDup = 7,
+    Next = 8,
+    NextDup = 9,
+    NextNoDup = 11,
+    Prev = 12,
+    PrevDup = 13,
+    PrevNoDup = 14,
+    SeekExact = 15,
+    SeekBothExact = 16,
+    Open = 30,
+    Close = 31,
+    OpenDupSort = 32,
+    Count = 33,
+}
+
+/// A request to operate on a cursor within the transaction.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Cursor {
+    #[prost(enumeration = "Op", tag = "1")]
+    pub op: i32,
+    #[prost(string, tag = "2")]
+    pub bucket_name: String,
+    #[prost(uint32, tag = "3")]
+    pub cursor: u32,
+    #[prost(bytes = "vec", tag = "4")]
+    pub k: Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    pub v: Vec<u8>,
+}
+
+/// The server's response to a [`Cursor`] request.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Pair {
+    #[prost(bytes = "vec", tag = "1")]
+    pub k: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub v: Vec<u8>,
+    #[prost(uint32, tag = "3")]
+    pub cursor_id: u32,
+    #[prost(uint64, tag = "4")]
+    pub view_id: u64,
+    #[prost(uint64, tag = "5")]
+    pub tx_id: u64,
+}
+
+/// A client for the `remote.KV` service.
+#[derive(Debug, Clone)]
+pub struct KvClient {
+    inner: tonic::client::Grpc<Channel>,
+}
+
+impl KvClient {
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            inner: tonic::client::Grpc::new(channel),
+        }
+    }
+
+    /// Opens a read-only transaction. The first message on the returned stream
+    /// carries the transaction id; every subsequent [`Cursor`] sent on the
+    /// request stream is answered by exactly one [`Pair`].
+    pub async fn tx(
+        &mut self,
+        request: impl IntoStreamingRequest<Message = Cursor>,
+    ) -> Result<Response<Streaming<Pair>>, Status> {
+        self.inner
+            .ready()
+            .await
+            .map_err(|e| Status::unknown(format!("Service was not ready: {}", e)))?;
+        let path = PathAndQuery::from_static("/remote.KV/Tx");
+        self.inner
+            .streaming(
+                request.into_streaming_request(),
+                path,
+                ProstCodec::default(),
+            )
+            .await
+    }
+}
@@ -1,16 +1,20 @@
 use arrayvec::ArrayVec;
 use derive_more::{Deref, DerefMut};
 use ethereum_types::{Address, H256, U256};
-use eyre::{eyre, Result};
+use eyre::eyre;
 use mdbx::DatabaseFlags;
-use roaring::RoaringTreemap;
+use roaring::{RoaringBitmap, RoaringTreemap};
 use std::{
+    any::TypeId,
     convert::AsRef,
     fmt::{Debug, Display},
     ops::Deref,
 };
 
-use crate::kv::traits::*;
+use crate::{error::bail, kv::traits::*, Error, Result};
+
+// The registry of tables is declared alongside the tables themselves.
+pub use crate::erigon::tables::{all_tables, find_tables};
 
 const KECCAK_LENGTH: usize = 32;
 const ADDRESS_LENGTH: usize = 20;
@@ -42,6 +46,42 @@ impl<'tx, Dbi, Flags: DbFlags> AsRef<mdbx::Database<'tx>> for TableHandle<'tx, D
     }
 }
 
+/// Runtime metadata for a table type, for tooling that needs to enumerate
+/// the tables this crate can decode. See [`all_tables`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TableInfo {
+    /// The name of the table type, e.g. `Storage`.
+    pub name: &'static str,
+    /// The name of the mdbx database the table is stored in, e.g. `PlainState`.
+    /// Several table types may share a database.
+    pub db_name: &'static str,
+    pub flags: DatabaseFlags,
+    pub key_type: &'static str,
+    pub value_type: &'static str,
+    pub type_id: TypeId,
+}
+
+impl TableInfo {
+    pub fn of<T>() -> Self
+    where
+        T: Table<'static> + DefaultFlags,
+    {
+        let name = std::any::type_name::<T>();
+        Self {
+            name: name.rsplit("::").next().unwrap_or(name),
+            db_name: <T::Name as DbName>::NAME,
+            flags: <T::Flags as DbFlags>::FLAGS,
+            key_type: std::any::type_name::<T::Key>(),
+            value_type: std::any::type_name::<T::Value>(),
+            type_id: TypeId::of::<T>(),
+        }
+    }
+
+    pub fn is_dupsort(&self) -> bool {
+        self.flags.contains(DatabaseFlags::DUP_SORT)
+    }
+}
+
 pub struct NoFlags;
 impl DbFlags for NoFlags {
     const FLAGS: DatabaseFlags = DatabaseFlags::empty();
@@ -50,6 +90,14 @@ pub struct DupSortFlags;
 impl DbFlags for DupSortFlags {
     const FLAGS: DatabaseFlags = DatabaseFlags::DUP_SORT;
 }
+/// Flags for a dupsorted table whose values all have the same size, which
+/// lets mdbx pack them into pages that can be read in bulk.
+pub struct DupFixedFlags;
+impl DbFlags for DupFixedFlags {
+    const FLAGS: DatabaseFlags = DatabaseFlags::from_bits_truncate(
+        DatabaseFlags::DUP_SORT.bits() | DatabaseFlags::DUP_FIXED.bits(),
+    );
+}
 #[macro_export]
 macro_rules! table_without_flags {
     ($name:ident => $key:ty => $value:ty, seek_key = $seek_key:ty, rename = $rename:ident) => {
@@ -99,15 +147,21 @@ macro_rules! table {
 }
 #[macro_export]
 macro_rules! dupsort_table {
-    ($name:ident => $key:ty => $value:ty, subkey = $subkey:ty, rename = $rename:ident) => {
+    ($name:ident => $key:ty => $value:ty, subkey = $subkey:ty, rename = $rename:ident, flags = $flags:ty) => {
         $crate::table_without_flags!($name => $key => $value, rename = $rename);
         impl $crate::kv::traits::DefaultFlags for $name {
-            type Flags = $crate::kv::tables::DupSortFlags;
+            type Flags = $flags;
         }
         impl crate::kv::traits::DupSort<'_> for $name {
             type Subkey = $subkey;
         }
     };
+    ($name:ident => $key:ty => $value:ty, subkey = $subkey:ty, rename = $rename:ident) => {
+        $crate::dupsort_table!($name => $key => $value, subkey = $subkey, rename = $rename, flags = $crate::kv::tables::DupSortFlags);
+    };
+    ($name:ident => $key:ty => $value:ty, subkey = $subkey:ty, dupfixed) => {
+        $crate::dupsort_table!($name => $key => $value, subkey = $subkey, rename = $name, flags = $crate::kv::tables::DupFixedFlags);
+    };
     ($name:ident => $key:ty => $value:ty, subkey = $subkey:ty) => {
         $crate::dupsort_table!($name => $key => $value, subkey = $subkey, rename = $name);
     }
@@ -199,6 +253,12 @@ impl<const EXPECTED: usize> Display for InvalidLength<EXPECTED> {
 
 impl<const EXPECTED: usize> std::error::Error for InvalidLength<EXPECTED> {}
 
+impl<const EXPECTED: usize> From<InvalidLength<EXPECTED>> for Error {
+    fn from(e: InvalidLength<EXPECTED>) -> Self {
+        Self::Other(e.into())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TooShort<const MINIMUM: usize> {
     pub got: usize,
@@ -212,6 +272,12 @@ impl<const MINIMUM: usize> Display for TooShort<MINIMUM> {
 
 impl<const MINIMUM: usize> std::error::Error for TooShort<MINIMUM> {}
 
+impl<const MINIMUM: usize> From<TooShort<MINIMUM>> for Error {
+    fn from(e: TooShort<MINIMUM>) -> Self {
+        Self::Other(e.into())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TooLong<const MAXIMUM: usize> {
     pub got: usize,
@@ -224,6 +290,12 @@ impl<const MAXIMUM: usize> Display for TooLong<MAXIMUM> {
 
 impl<const MAXIMUM: usize> std::error::Error for TooLong<MAXIMUM> {}
 
+impl<const MAXIMUM: usize> From<TooLong<MAXIMUM>> for Error {
+    fn from(e: TooLong<MAXIMUM>) -> Self {
+        Self::Other(e.into())
+    }
+}
+
 impl TableEncode for H256 {
     type Encoded = [u8; KECCAK_LENGTH];
     fn encode(self) -> Self::Encoded {
@@ -235,7 +307,7 @@ impl TableDecode for H256 {
     fn decode(b: &[u8]) -> Result<Self> {
         match b.len() {
             KECCAK_LENGTH => Ok(H256::from_slice(&*b)),
-            _ => Err(eyre!("bad")),
+            _ => Err(eyre!("bad").into()),
         }
     }
 }
@@ -326,7 +398,7 @@ where
 {
     fn decode(v: &[u8]) -> Result<Self> {
         if v.len() != A_LEN + B_LEN {
-            eyre::bail!("Invalid len: {} != {} + {}", v.len(), A_LEN, B_LEN);
+            bail!("Invalid len: {} != {} + {}", v.len(), A_LEN, B_LEN);
         }
         Ok((
             A::decode(&v[..A_LEN]).unwrap(),
@@ -349,6 +421,20 @@ impl TableDecode for RoaringTreemap {
     }
 }
 
+impl TableEncode for RoaringBitmap {
+    type Encoded = Vec<u8>;
+    fn encode(self) -> Self::Encoded {
+        let mut buf = Vec::with_capacity(self.serialized_size());
+        self.serialize_into(&mut buf).unwrap();
+        buf
+    }
+}
+impl TableDecode for RoaringBitmap {
+    fn decode(b: &[u8]) -> Result<Self> {
+        Ok(RoaringBitmap::deserialize_from(b)?)
+    }
+}
+
 impl TableEncode for bytes::Bytes {
     type Encoded = Self;
 
@@ -379,3 +465,47 @@ impl TableDecode for u32 {
         }
     }
 }
+
+// The alloy-primitives types are stored exactly as their ethereum-types
+// counterparts, so a table may be declared with either.
+#[cfg(feature = "alloy")]
+mod alloy_types {
+    use super::*;
+    use crate::erigon::models::{FromAlloy, ToAlloy};
+
+    impl TableEncode for alloy_primitives::Address {
+        type Encoded = [u8; ADDRESS_LENGTH];
+        fn encode(self) -> Self::Encoded {
+            self.0 .0
+        }
+    }
+    impl TableDecode for alloy_primitives::Address {
+        fn decode(b: &[u8]) -> Result<Self> {
+            Address::decode(b).map(|adr| adr.to_alloy())
+        }
+    }
+
+    impl TableEncode for alloy_primitives::B256 {
+        type Encoded = [u8; KECCAK_LENGTH];
+        fn encode(self) -> Self::Encoded {
+            self.0
+        }
+    }
+    impl TableDecode for alloy_primitives::B256 {
+        fn decode(b: &[u8]) -> Result<Self> {
+            H256::decode(b).map(|hash| hash.to_alloy())
+        }
+    }
+
+    impl TableEncode for alloy_primitives::U256 {
+        type Encoded = VariableVec<KECCAK_LENGTH>;
+        fn encode(self) -> Self::Encoded {
+            U256::from_alloy(self).encode()
+        }
+    }
+    impl TableDecode for alloy_primitives::U256 {
+        fn decode(b: &[u8]) -> Result<Self> {
+            U256::decode(b).map(|n| n.to_alloy())
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Spans around db operations, emitted through [`tracing`] at the TRACE level
+//! when the `tracing` feature is enabled. Each span is named for the
+//! operation and records the table and a hex prefix of the encoded key.
+//!
+//! [`tracing`]: https://docs.rs/tracing
+
+// Keys are truncated to this many bytes in spans.
+#[cfg(feature = "tracing")]
+const KEY_PREFIX_LEN: usize = 16;
+
+// Displays the first few bytes of a key as hex.
+#[cfg(feature = "tracing")]
+pub(crate) struct KeyPrefix<'a>(pub &'a [u8]);
+
+#[cfg(feature = "tracing")]
+impl std::fmt::Display for KeyPrefix<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let len = self.0.len().min(KEY_PREFIX_LEN);
+        for b in &self.0[..len] {
+            write!(f, "{:02x}", b)?;
+        }
+        if self.0.len() > len {
+            write!(f, "..")?;
+        }
+        Ok(())
+    }
+}
+
+// Enters a span named `$op` for the rest of the enclosing scope, recording the
+// table and, if given, a prefix of the encoded key. Expands to nothing without
+// the `tracing` feature.
+macro_rules! db_span {
+    ($op:literal, $table:expr) => {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::trace_span!($op, table = $table).entered();
+    };
+    ($op:literal, $table:expr, $key:expr) => {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::trace_span!(
+            $op,
+            table = $table,
+            key = %$crate::kv::trace::KeyPrefix($key)
+        )
+        .entered();
+    };
+}
+pub(crate) use db_span;
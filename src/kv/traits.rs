@@ -1,4 +1,5 @@
-use std::fmt::Debug;
+use crate::Result;
+use std::{borrow::Cow, fmt::Debug};
 
 pub trait TableEncode: Send + Sync + Sized {
     type Encoded: AsRef<[u8]> + Send + Sync;
@@ -6,7 +7,30 @@ pub trait TableEncode: Send + Sync + Sized {
 }
 
 pub trait TableDecode: Send + Sync + Sized {
-    fn decode(b: &[u8]) -> eyre::Result<Self>;
+    fn decode(b: &[u8]) -> Result<Self>;
+}
+
+/// Decodes a value that may borrow from the bytes it was read from. In
+/// read-only transactions, mdbx hands out values straight from the memory
+/// map, so decoding into a borrowed type skips a copy per value in hot loops.
+/// Values that mdbx has already copied, e.g. dirty pages in a read-write
+/// transaction, are decoded from the owned copy instead.
+///
+/// Every [`TableDecode`] type decodes this way by copying, as before.
+pub trait TableDecodeBorrowed<'v>: Sized {
+    fn decode_borrowed(b: Cow<'v, [u8]>) -> Result<Self>;
+}
+
+impl<'v> TableDecodeBorrowed<'v> for Cow<'v, [u8]> {
+    fn decode_borrowed(b: Cow<'v, [u8]>) -> Result<Self> {
+        Ok(b)
+    }
+}
+
+impl<'v, T: TableDecode> TableDecodeBorrowed<'v> for T {
+    fn decode_borrowed(b: Cow<'v, [u8]>) -> Result<Self> {
+        T::decode(&b)
+    }
 }
 
 pub trait TableObject: TableEncode + TableDecode {}
@@ -48,3 +72,131 @@ impl<'env> Mode for mdbx::RW {
         true
     }
 }
+
+/// An undecoded (key, value) pair read from a table.
+pub type RawPair<'tx> = (Cow<'tx, [u8]>, Cow<'tx, [u8]>);
+
+/// The positioning operations a KV backend's cursors must support, in terms of
+/// raw bytes. [`TableCursor`](crate::kv::TableCursor) wraps a `KvCursor` to
+/// provide typed access. Every method returns `None` when the cursor moves
+/// past the end of the table.
+pub trait KvCursor<'tx> {
+    fn first(&mut self) -> Result<Option<RawPair<'tx>>>;
+    fn last(&mut self) -> Result<Option<RawPair<'tx>>>;
+    fn current(&mut self) -> Result<Option<RawPair<'tx>>>;
+    fn next(&mut self) -> Result<Option<RawPair<'tx>>>;
+    fn prev(&mut self) -> Result<Option<RawPair<'tx>>>;
+    /// Moves to the next duplicate value at the current key. Only meaningful
+    /// for dupsorted tables.
+    fn next_dup(&mut self) -> Result<Option<RawPair<'tx>>>;
+    /// Moves to the previous duplicate value at the current key. Only
+    /// meaningful for dupsorted tables.
+    fn prev_dup(&mut self) -> Result<Option<RawPair<'tx>>>;
+    /// Moves to the last duplicate value at the current key, returning the
+    /// value. Only meaningful for dupsorted tables.
+    fn last_dup(&mut self) -> Result<Option<Cow<'tx, [u8]>>>;
+    /// Returns the value at the current position followed by the rest of the
+    /// duplicates at the current key, concatenated, leaving the cursor on the
+    /// last one returned. For DUPFIXED tables, mdbx returns up to a page of
+    /// values per call; the default implementation reads them one at a time.
+    fn get_multiple(&mut self) -> Result<Option<Cow<'tx, [u8]>>> {
+        let mut page = match self.current()? {
+            Some((_, v)) => v.into_owned(),
+            None => return Ok(None),
+        };
+        while let Some((_, v)) = self.next_dup()? {
+            page.extend_from_slice(&v);
+        }
+        Ok(Some(page.into()))
+    }
+    /// Like [`KvCursor::get_multiple`], starting from the duplicate after the
+    /// current position.
+    fn next_multiple(&mut self) -> Result<Option<RawPair<'tx>>> {
+        let (k, v) = match self.next_dup()? {
+            Some(kv) => kv,
+            None => return Ok(None),
+        };
+        let mut page = v.into_owned();
+        while let Some((_, v)) = self.next_dup()? {
+            page.extend_from_slice(&v);
+        }
+        Ok(Some((k, page.into())))
+    }
+    /// Moves to the first value at the next key, skipping any remaining
+    /// duplicates at the current key.
+    fn next_nodup(&mut self) -> Result<Option<RawPair<'tx>>>;
+    /// Moves to the first key >= `key`.
+    fn seek(&mut self, key: &[u8]) -> Result<Option<RawPair<'tx>>>;
+    /// Moves to exactly `key`.
+    fn seek_exact(&mut self, key: &[u8]) -> Result<Option<RawPair<'tx>>>;
+    /// Moves to `key`, then to the first duplicate value >= `subkey` at that
+    /// key, returning the value.
+    fn seek_both_range(&mut self, key: &[u8], subkey: &[u8]) -> Result<Option<Cow<'tx, [u8]>>>;
+    /// Returns a new cursor into the same table, at the same position. The
+    /// two cursors move independently afterwards.
+    fn clone_position(&self) -> Result<Self>
+    where
+        Self: Sized;
+    /// Returns the number of duplicates at the current key, 1 for tables that
+    /// aren't dupsorted, or 0 if the cursor isn't on a key. The cursor
+    /// doesn't move. mdbx counts them from its page headers; the default
+    /// implementation walks them with a clone of the cursor.
+    fn dup_count(&mut self) -> Result<u64>
+    where
+        Self: Sized,
+    {
+        let key = match self.current()? {
+            Some((k, _)) => k.into_owned(),
+            None => return Ok(0),
+        };
+        let mut probe = self.clone_position()?;
+        let mut count = u64::from(probe.seek_exact(&key)?.is_some());
+        while probe.next_dup()?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// A transaction against a KV backend. [`Erigon`](crate::Erigon) is generic
+/// over this trait, so the same accessors work against any backend.
+pub trait KvTx {
+    type Cursor<'tx>: KvCursor<'tx>
+    where
+        Self: 'tx;
+
+    /// Returns the raw value stored at `key` in table `T`.
+    fn get_raw<'tx, T>(&'tx self, key: &[u8]) -> Result<Option<Cow<'tx, [u8]>>>
+    where
+        T: Table<'tx> + DefaultFlags;
+
+    /// Opens a cursor into table `T`.
+    fn cursor_raw<'tx, T>(&'tx self) -> Result<Self::Cursor<'tx>>
+    where
+        T: Table<'tx> + DefaultFlags;
+
+    /// Returns the raw value stored at `key` in the table named `name`, for
+    /// tables without a [`Table`] definition.
+    fn get_named<'tx>(&'tx self, name: &str, key: &[u8]) -> Result<Option<Cow<'tx, [u8]>>>;
+
+    /// Opens a cursor into the table named `name`, for tables without a
+    /// [`Table`] definition.
+    fn cursor_named<'tx>(&'tx self, name: &str) -> Result<Self::Cursor<'tx>>;
+}
+
+/// A read-write transaction against a KV backend.
+pub trait KvTxMut: KvTx {
+    /// Writes `val` at `key` in table `T`. For dupsorted tables, `val` is
+    /// added to the values stored at `key`.
+    fn put_raw<'tx, T>(&'tx self, key: &[u8], val: &[u8]) -> Result<()>
+    where
+        T: Table<'tx> + DefaultFlags;
+
+    /// Deletes `key` from table `T`. If `val` is provided, only that exact
+    /// duplicate is deleted. Returns `true` if anything was deleted.
+    fn delete_raw<'tx, T>(&'tx self, key: &[u8], val: Option<&[u8]>) -> Result<bool>
+    where
+        T: Table<'tx> + DefaultFlags;
+
+    fn commit(self) -> Result<bool>;
+}
@@ -1,13 +1,29 @@
 #![doc = include_str!("../README.md")]
 #![doc = include_str!("../doc/mdbx.md")]
 pub mod erigon;
+pub mod error;
 pub mod kv;
+#[cfg(feature = "async")]
+pub mod r#async;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod snapshots;
 pub use erigon::*;
+pub use error::{Error, Result};
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{erigon::Erigon, kv::MdbxEnv};
+    use crate::{
+        erigon::{
+            models::{transaction::*, *},
+            progress::{NoProgress, Tick},
+            tables::*,
+            writer::Block,
+            Erigon,
+        },
+        kv::{mem::MemDb, MdbxEnv},
+    };
     use ethereum_types::*;
     use once_cell::sync::Lazy;
     use std::{path::Path, sync::Arc};
@@ -24,7 +40,7 @@ mod tests {
     });
 
     #[test]
-    fn test_mem_db() -> eyre::Result<()> {
+    fn test_mem_db() -> Result<()> {
         let env = ENV.clone();
         let db = Erigon::begin_rw(&env.inner)?;
         let hash = H256::from_low_u64_be(u64::MAX);
@@ -34,6 +50,3609 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_env_builder() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let env = erigon::env_builder::<mdbx::RW>()
+            .map_size(0..1 << 30)
+            .page_size(8192)
+            .sync_mode(mdbx::SyncMode::SafeNoSync)
+            .open(dir.path())?;
+        let db = Erigon::begin_rw(&env)?;
+        db.write_head_header_hash(H256::repeat_byte(1))?;
+        db.commit()?;
+        assert_eq!(env.info()?.page_size, 8192);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_batched() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let env = erigon::env_open::<mdbx::RW>(dir.path())?;
+        let mut reports = vec![];
+        let n = Erigon::update_batched(
+            &env,
+            3,
+            (0..10).map(Address::from_low_u64_be),
+            |tx, chunk| {
+                for adr in chunk {
+                    tx.write_account(*adr, Account::new().nonce(1))?;
+                }
+                Ok(())
+            },
+            |done| reports.push(done),
+        )?;
+        assert_eq!(n, 10);
+        assert_eq!(reports, vec![3, 6, 9, 10]);
+        let db = Erigon::begin_rw(&env)?;
+        assert!(db.read_account(Address::from_low_u64_be(9))?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_database() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let env = Erigon::init_database(&dir.path().join("chaindata"))?;
+        let db = Erigon::begin_rw(&env)?;
+        let names = db.0.table_names()?;
+        assert!(names.iter().any(|n| n == "PlainState"));
+        assert!(names.iter().any(|n| n == "AccountChangeSet"));
+        // accounts and storage can both be written to the dupsorted PlainState
+        let adr = Address::from_low_u64_be(1);
+        db.write_account(adr, Account::new().nonce(1))?;
+        db.write::<Storage>(StorageKey(adr, 1.into()), (H256::zero(), 1.into()))?;
+        assert_eq!(db.read_account(adr)?.unwrap().nonce, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seek_exact_and_range() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        for num in [2, 4] {
+            db.write::<CanonicalHeader>(num.into(), H256::from_low_u64_be(num))?;
+        }
+        let mut cur = db.cursor::<CanonicalHeader>()?;
+        assert_eq!(cur.seek_exact(2.into())?.map(|(k, _)| k.0), Some(2));
+        assert!(cur.seek_exact(3.into())?.is_none());
+        assert_eq!(cur.seek_range(3.into())?.map(|(k, _)| k.0), Some(4));
+        assert!(cur.seek_range(5.into())?.is_none());
+
+        let key = StorageKey(Address::from_low_u64_be(1), 1.into());
+        let (slot_a, slot_b, slot_c) = (
+            H256::from_low_u64_be(1),
+            H256::from_low_u64_be(2),
+            H256::from_low_u64_be(3),
+        );
+        db.write::<Storage>(key, (slot_a, 10.into()))?;
+        db.write::<Storage>(key, (slot_c, 30.into()))?;
+        let mut cur = db.cursor::<Storage>()?;
+        assert_eq!(cur.seek_both_exact(key, slot_a)?, Some((slot_a, 10.into())));
+        assert_eq!(cur.seek_both_exact(key, slot_b)?, None);
+        assert_eq!(cur.seek_both_range(key, slot_b)?, Some((slot_c, 30.into())));
+        assert_eq!(cur.seek_both_range(key, H256::repeat_byte(0xff))?, None);
+        assert_eq!(db.read_storage(key.0, 1, slot_b)?, None);
+        assert_eq!(db.read_storage(key.0, 1, slot_c)?, Some(30.into()));
+        assert_eq!(
+            db.read_storage_many(key.0, 1, &[slot_c, slot_b, slot_a, slot_c])?,
+            vec![Some(30.into()), None, Some(10.into()), Some(30.into())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() -> Result<()> {
+        use crate::kv::traits::KvTxMut;
+
+        let env = ENV.clone();
+        let db = Erigon::begin_rw(&env.inner)?;
+        let err = db.read_raw("NoSuchTable", &[]).unwrap_err();
+        assert!(matches!(err, Error::TableNotFound(name) if name == "NoSuchTable"));
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let err = db.read_head_block_number().unwrap_err();
+        assert!(matches!(err, Error::MissingValue { .. }));
+        db.0.put_raw::<CanonicalHeader>(&1u64.to_be_bytes(), &[0xff])?;
+        let err = db.cursor::<CanonicalHeader>()?.first().unwrap_err();
+        match err {
+            Error::Decode { table, key, .. } => {
+                assert_eq!(table, "CanonicalHeader");
+                assert_eq!(key.as_deref(), Some(&1u64.to_be_bytes()[..]));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        // an error passed through eyre comes back out as itself
+        let report = eyre::Report::from(Error::MissingValue { what: "test" });
+        assert!(matches!(Error::from(report), Error::MissingValue { .. }));
+        let report = eyre::eyre!("other");
+        assert_eq!(Error::from(report).to_string(), "other");
+        Ok(())
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics() -> Result<()> {
+        use crate::kv::{
+            metrics::*,
+            traits::{KvTxMut, TableEncode},
+        };
+        use ::metrics::{
+            Counter, CounterFn, Gauge, Histogram, HistogramFn, Key, KeyName, Label, SharedString,
+            Unit,
+        };
+        use std::{
+            collections::HashMap,
+            sync::{
+                atomic::{AtomicU64, Ordering},
+                Mutex,
+            },
+        };
+
+        // Counts the increments of each counter and the samples of each
+        // histogram.
+        #[derive(Default)]
+        struct Recorder(Mutex<HashMap<Key, Arc<AtomicU64>>>);
+        struct Count(Arc<AtomicU64>);
+        impl CounterFn for Count {
+            fn increment(&self, n: u64) {
+                self.0.fetch_add(n, Ordering::Relaxed);
+            }
+            fn absolute(&self, n: u64) {
+                self.0.fetch_max(n, Ordering::Relaxed);
+            }
+        }
+        impl HistogramFn for Count {
+            fn record(&self, _: f64) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        impl Recorder {
+            fn count(&self, key: &Key) -> Arc<AtomicU64> {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .entry(key.clone())
+                    .or_default()
+                    .clone()
+            }
+            fn get(&self, name: &'static str, table: &'static str) -> u64 {
+                let key = Key::from_parts(name, vec![Label::new("table", table)]);
+                self.count(&key).load(Ordering::Relaxed)
+            }
+        }
+        impl ::metrics::Recorder for Recorder {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn register_counter(&self, key: &Key) -> Counter {
+                Counter::from_arc(Arc::new(Count(self.count(key))))
+            }
+            fn register_gauge(&self, _: &Key) -> Gauge {
+                Gauge::noop()
+            }
+            fn register_histogram(&self, key: &Key) -> Histogram {
+                Histogram::from_arc(Arc::new(Count(self.count(key))))
+            }
+        }
+
+        // the recorder is global, so the test sticks to a table no other test
+        // touches
+        static RECORDER: Lazy<Recorder> = Lazy::new(Recorder::default);
+        ::metrics::set_recorder(&*RECORDER).unwrap();
+        describe();
+        let table = "HashedCodeHash";
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let key = |inc: u64| ContractCodeKey(H256::repeat_byte(1), inc.into());
+        db.write::<HashedCodeHash>(key(1), H256::repeat_byte(2))?;
+        db.write::<HashedCodeHash>(key(2), H256::repeat_byte(3))?;
+        assert!(db.delete::<HashedCodeHash>(key(2))?);
+        assert_eq!(RECORDER.get(WRITES, table), 3);
+
+        assert!(db.read::<HashedCodeHash>(key(1))?.is_some());
+        assert!(db.read::<HashedCodeHash>(key(2))?.is_none());
+        assert_eq!(RECORDER.get(READS, table), 2);
+        assert_eq!(RECORDER.get(READ_SECONDS, table), 2);
+
+        let mut cur = db.cursor::<HashedCodeHash>()?;
+        assert!(cur.first()?.is_some());
+        assert!(cur.next()?.is_none());
+        assert_eq!(RECORDER.get(CURSOR_OPS, table), 2);
+
+        db.0.put_raw::<HashedCodeHash>(key(3).encode().as_ref(), &[0xff])?;
+        assert!(db.read::<HashedCodeHash>(key(3)).is_err());
+        assert_eq!(RECORDER.get(DECODE_FAILURES, table), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_db_version() -> Result<()> {
+        use bytes::Bytes;
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        assert_eq!(db.db_version()?, None);
+        db.check_db_version()?;
+
+        let key = Bytes::from_static(b"dbVersion");
+        let version = |major| DbVersion {
+            major,
+            minor: 1,
+            patch: 2,
+        };
+        let encoded = kv::traits::TableEncode::encode(version(6));
+        db.write::<DbInfo>(key.clone(), Bytes::copy_from_slice(&encoded))?;
+        assert_eq!(db.db_version()?, Some(version(6)));
+        db.check_db_version()?;
+
+        let encoded = kv::traits::TableEncode::encode(version(99));
+        db.write::<DbInfo>(key, Bytes::copy_from_slice(&encoded))?;
+        let err = db.check_db_version().unwrap_err();
+        assert!(matches!(err, Error::Incompatible(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_prune_mode() -> Result<()> {
+        use crate::erigon::PruneDistance;
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        assert!(db.read_prune_mode()?.is_archive());
+
+        let put = |key: &'static [u8], val: &[u8]| {
+            db.write::<DbInfo>(Bytes::from_static(key), Bytes::copy_from_slice(val))
+        };
+        put(b"pruneHistory", &90_000u64.to_be_bytes())?;
+        put(b"pruneHistoryType", b"older")?;
+        put(b"pruneReceipts", &1_000u64.to_be_bytes())?;
+        put(b"pruneReceiptsType", b"before")?;
+        put(b"pruneTxIndex", &u64::MAX.to_be_bytes())?;
+        let mode = db.read_prune_mode()?;
+        assert_eq!(mode.history, PruneDistance::Older(90_000));
+        assert_eq!(mode.receipts, PruneDistance::Before(1_000));
+        assert_eq!(mode.tx_index, PruneDistance::Archive);
+        assert_eq!(mode.history.horizon(100_000), 10_000);
+        assert!(!mode.receipts.keeps(999, 100_000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pruned_reads() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let adr = Address::from_low_u64_be(1);
+        db.write::<DbInfo>(
+            Bytes::from_static(b"pruneHistory"),
+            Bytes::copy_from_slice(&10u64.to_be_bytes()),
+        )?;
+        db.write::<SyncStage>(Bytes::from_static(b"Execution"), BlockNumber(100))?;
+
+        db.account_at(adr, 90)?;
+        db.read_logs(50)?;
+        let err = db.account_at(adr, 50).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Pruned {
+                needed_block: 50,
+                prune_horizon: 90,
+                ..
+            }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        assert!(db.chain().is_err());
+        let mainnet =
+            hex_literal::hex!("d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3");
+        db.write::<CanonicalHeader>(0.into(), H256(mainnet))?;
+        assert_eq!(db.chain()?, Chain::Mainnet);
+        assert_eq!(db.chain()?.chain_id(), Some(1));
+        let other = H256::repeat_byte(1);
+        assert_eq!(Chain::from_genesis_hash(other), Chain::Unknown(other));
+
+        db.write::<ChainConfig>(H256(mainnet), r#"{"chainId":1}"#.into())?;
+        let config = db.read_chain_config(H256(mainnet))?.unwrap();
+        assert_eq!(config["chainId"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_dup() -> Result<()> {
+        let env = ENV.clone();
+        let db = Erigon::begin_rw(&env.inner)?;
+        let adr = Address::from_low_u64_be(0xdead);
+        let key = StorageKey(adr, 1.into());
+        let (slot_a, slot_b) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        db.write::<Storage>(key, (slot_a, 10.into()))?;
+        db.write::<Storage>(key, (slot_b, 20.into()))?;
+
+        assert!(db.delete_dup::<Storage>(key, slot_a)?);
+        assert!(!db.delete_dup::<Storage>(key, slot_a)?);
+        assert_eq!(db.read_storage(adr, 1, slot_a)?, None);
+        assert_eq!(db.read_storage(adr, 1, slot_b)?, Some(20.into()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mem_backend() -> Result<()> {
+        let mem = MemDb::new();
+        let adr = Address::from_low_u64_be(0xbeef);
+        let key = StorageKey(adr, 1.into());
+        let slots = [1, 2, 3].map(H256::from_low_u64_be);
+
+        let db = Erigon::begin_mem_rw(&mem)?;
+        db.write_account(adr, Account::new().nonce(7))?;
+        for (i, slot) in slots.iter().rev().enumerate() {
+            db.write::<Storage>(key, (*slot, (i as u64).into()))?;
+        }
+        assert!(db.delete_dup::<Storage>(key, slots[1])?);
+        // overwriting or deleting a plain key leaves its neighbours alone
+        let (before, after) = (
+            Address::from_low_u64_be(0xbeee),
+            Address::from_low_u64_be(0xbef0),
+        );
+        db.write_account(before, Account::new().nonce(1))?;
+        db.write_account(after, Account::new().nonce(2))?;
+        db.write_account(adr, Account::new().nonce(8))?;
+        db.write_account(adr, Account::new().nonce(7))?;
+        assert!(db.delete::<PlainState>(after)?);
+        assert!(!db.delete::<PlainState>(after)?);
+        db.commit()?;
+
+        let db = Erigon::begin_mem(&mem)?;
+        assert_eq!(db.read_account(adr)?.unwrap().nonce, 7);
+        assert_eq!(db.read_account(before)?.unwrap().nonce, 1);
+        assert_eq!(db.read_account(after)?, None);
+        let storage = db.walk_storage(adr, 1, None)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(storage, vec![(slots[0], 2.into()), (slots[2], 0.into())]);
+
+        // the same entries, read through the untyped escape hatch
+        use crate::kv::traits::{KvCursor, TableEncode};
+        let raw = db.read_raw("PlainState", adr.as_bytes())?;
+        assert_eq!(raw, Some(Account::new().nonce(7).encode().to_vec()));
+        let encoded = key.encode();
+        let mut cur = db.cursor_raw("PlainState")?;
+        assert_eq!(cur.seek_exact(&encoded)?.unwrap().0, &encoded[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kv_backends() -> Result<()> {
+        use crate::kv::traits::{KvTx, KvTxMut, Mode};
+
+        // the same writes and reads, against any backend
+        fn write<Tx: KvTxMut>(db: Erigon<'_, mdbx::RW, Tx>, adr: Address) -> Result<()> {
+            let key = StorageKey(adr, 1.into());
+            db.write_account(adr, Account::new().nonce(3))?;
+            for n in 0..3 {
+                db.write::<CanonicalHeader>(n.into(), H256::from_low_u64_be(n + 1))?;
+            }
+            db.write::<Storage>(key, (H256::zero(), 5.into()))?;
+            db.write::<Storage>(key, (H256::repeat_byte(1), 6.into()))?;
+            assert!(db.delete_dup::<Storage>(key, H256::zero())?);
+            db.commit().map(drop)
+        }
+        type State = (Option<Account>, Vec<(BlockNumber, H256)>, Vec<(H256, U256)>);
+        fn read<K: Mode, Tx: KvTx>(db: &Erigon<'_, K, Tx>, adr: Address) -> Result<State> {
+            let headers = db.cursor::<CanonicalHeader>()?.walk(BlockNumber(1))?;
+            Ok((
+                db.read_account(adr)?,
+                headers.collect::<Result<_>>()?,
+                db.walk_storage(adr, 1, None)?.collect::<Result<_>>()?,
+            ))
+        }
+
+        let adr = Address::from_low_u64_be(1);
+        let dir = tempfile::tempdir()?;
+        let env = Erigon::init_database(dir.path())?;
+        write(Erigon::begin_rw(&env)?, adr)?;
+        let on_mdbx = read(&Erigon::begin_rw(&env)?, adr)?;
+        let mem = MemDb::new();
+        write(Erigon::begin_mem_rw(&mem)?, adr)?;
+        let on_mem = read(&Erigon::begin_mem(&mem)?, adr)?;
+        assert_eq!(on_mdbx, on_mem);
+        assert_eq!(on_mem.0.unwrap().nonce, 3);
+        assert_eq!(
+            on_mem.1,
+            vec![
+                (1.into(), H256::from_low_u64_be(2)),
+                (2.into(), H256::from_low_u64_be(3))
+            ]
+        );
+        assert_eq!(on_mem.2, vec![(H256::repeat_byte(1), 6.into())]);
+        Ok(())
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_remote_kv() -> Result<()> {
+        use crate::kv::{
+            remote::{
+                proto::{Cursor, Op, Pair},
+                RemoteKv,
+            },
+            traits::{KvCursor, KvTx, TableEncode},
+        };
+        use std::{
+            collections::{BTreeMap, HashMap},
+            ops::Bound,
+            pin::Pin,
+            sync::atomic::{AtomicUsize, Ordering},
+            task::{Context, Poll},
+        };
+        use tokio::{net::TcpListener, sync::mpsc};
+        use tokio_stream::{wrappers::ReceiverStream, Stream};
+        use tonic::{
+            codec::{ProstCodec, Streaming},
+            codegen::{http, Body, BoxFuture, Service, StdError},
+            server::{Grpc, NamedService, StreamingService},
+            Request, Response, Status,
+        };
+
+        type Tables = Arc<BTreeMap<String, BTreeMap<Vec<u8>, Vec<u8>>>>;
+        // the number of cursors the service has open
+        static OPEN: AtomicUsize = AtomicUsize::new(0);
+
+        // A KV service over fixed tables, supporting the ops of plain reads.
+        #[derive(Clone)]
+        struct Kv(Tables);
+        impl NamedService for Kv {
+            const NAME: &'static str = "remote.KV";
+        }
+        impl<B> Service<http::Request<B>> for Kv
+        where
+            B: Body + Send + 'static,
+            B::Error: Into<StdError> + Send + 'static,
+        {
+            type Response = http::Response<tonic::body::BoxBody>;
+            type Error = std::convert::Infallible;
+            type Future = BoxFuture<Self::Response, Self::Error>;
+            fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+            fn call(&mut self, req: http::Request<B>) -> Self::Future {
+                let tx = Tx(self.0.clone());
+                Box::pin(
+                    async move { Ok(Grpc::new(ProstCodec::default()).streaming(tx, req).await) },
+                )
+            }
+        }
+
+        struct Tx(Tables);
+        impl StreamingService<Cursor> for Tx {
+            type Response = Pair;
+            type ResponseStream = ReceiverStream<Result<Pair, Status>>;
+            type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+            fn call(&mut self, req: Request<Streaming<Cursor>>) -> Self::Future {
+                let (tables, mut reqs) = (self.0.clone(), req.into_inner());
+                let (sender, rx) = mpsc::channel(1);
+                tokio::spawn(async move {
+                    let first = Pair {
+                        tx_id: 7,
+                        view_id: 3,
+                        ..Default::default()
+                    };
+                    let mut cursors = HashMap::new();
+                    let mut res = Ok(first);
+                    while sender.send(res).await.is_ok() {
+                        match reqs.message().await {
+                            Ok(Some(req)) => res = serve(&tables, &mut cursors, req),
+                            _ => break,
+                        }
+                    }
+                });
+                Box::pin(async move { Ok(Response::new(ReceiverStream::new(rx))) })
+            }
+        }
+
+        // cursors maps the id of each open cursor to its table and position
+        fn serve(
+            tables: &Tables,
+            cursors: &mut HashMap<u32, (String, Option<Vec<u8>>)>,
+            req: Cursor,
+        ) -> Result<Pair, Status> {
+            let id = req.cursor;
+            match Op::from_i32(req.op) {
+                Some(Op::Open) => {
+                    let id = cursors.keys().max().map_or(1, |id| id + 1);
+                    cursors.insert(id, (req.bucket_name, None));
+                    OPEN.fetch_add(1, Ordering::SeqCst);
+                    return Ok(Pair {
+                        cursor_id: id,
+                        ..Default::default()
+                    });
+                }
+                Some(Op::Close) => {
+                    if cursors.remove(&id).is_some() {
+                        OPEN.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    return Ok(Pair::default());
+                }
+                _ => {}
+            }
+            let (name, pos) = cursors
+                .get_mut(&id)
+                .ok_or_else(|| Status::not_found("unknown cursor"))?;
+            let empty = BTreeMap::new();
+            let table = tables.get(name.as_str()).unwrap_or(&empty);
+            let found = match Op::from_i32(req.op) {
+                Some(Op::First) => table.iter().next(),
+                Some(Op::Current) => pos.as_ref().and_then(|k| table.get_key_value(k)),
+                Some(Op::Next) => match pos.take() {
+                    Some(k) => table.range((Bound::Excluded(k), Bound::Unbounded)).next(),
+                    None => table.iter().next(),
+                },
+                Some(Op::Seek) => table.range(req.k..).next(),
+                Some(Op::SeekExact) => table.get_key_value(&req.k),
+                op => return Err(Status::unimplemented(format!("{:?}", op))),
+            };
+            let (k, v) = found
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .unwrap_or_default();
+            *pos = (!k.is_empty()).then(|| k.clone());
+            Ok(Pair {
+                k,
+                v,
+                cursor_id: id,
+                ..Default::default()
+            })
+        }
+
+        struct Incoming(TcpListener);
+        impl Stream for Incoming {
+            type Item = std::io::Result<tokio::net::TcpStream>;
+            fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                self.0
+                    .poll_accept(cx)
+                    .map(|res| Some(res.map(|(stream, _)| stream)))
+            }
+        }
+
+        // write the tables locally, then serve a copy of them
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let hashes = [1, 2, 3].map(H256::from_low_u64_be);
+        for (n, hash) in (0u64..).zip(hashes) {
+            db.write::<CanonicalHeader>(n.into(), hash)?;
+            db.write::<HeaderNumber>(hash, n.into())?;
+        }
+        db.write::<LastHeader>(LastHeaderKey, hashes[2])?;
+        let mut tables = BTreeMap::new();
+        for name in ["CanonicalHeader", "HeaderNumber", "LastHeader"] {
+            let (mut cur, mut table) = (db.cursor_raw(name)?, BTreeMap::new());
+            let mut read = cur.first()?;
+            while let Some((k, v)) = read {
+                table.insert(k.into_owned(), v.into_owned());
+                read = cur.next()?;
+            }
+            tables.insert(name.to_string(), table);
+        }
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let kv = Kv(Arc::new(tables));
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            rt.block_on(async move {
+                let incoming = Incoming(TcpListener::from_std(listener).unwrap());
+                tonic::transport::Server::builder()
+                    .add_service(kv)
+                    .serve_with_incoming(incoming)
+                    .await
+            })
+            .unwrap();
+        });
+
+        let kv = RemoteKv::connect(format!("http://{}", addr))?;
+        let tx = kv.begin()?;
+        assert_eq!((tx.tx_id(), tx.view_id()), (7, 3));
+        let db = Erigon::<mdbx::RO, _>::new(tx);
+        assert_eq!(db.read_head_block_number()?, Some(2.into()));
+        assert_eq!(db.read_canonical_hash(1)?, Some(hashes[1]));
+        assert_eq!(db.read_canonical_hash(3)?, None);
+        // point reads of the three tables share one cursor per table
+        assert_eq!(OPEN.load(Ordering::SeqCst), 3);
+        let walked = db
+            .cursor::<CanonicalHeader>()?
+            .walk(BlockNumber(1))?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(walked, vec![(1.into(), hashes[1]), (2.into(), hashes[2])]);
+        // a clone is a new remote cursor at the same position
+        let mut cur = db.0.cursor_named("CanonicalHeader")?;
+        assert!(cur.seek(BlockNumber(1).encode().as_ref())?.is_some());
+        let mut clone = cur.clone_position()?;
+        assert_eq!(&*clone.next()?.unwrap().1, hashes[2].as_bytes());
+        assert_eq!(&*cur.current()?.unwrap().1, hashes[1].as_bytes());
+        drop((cur, clone));
+        assert_eq!(OPEN.load(Ordering::SeqCst), 3);
+        // dropping the tx closes the cached cursors
+        drop(db);
+        assert_eq!(OPEN.load(Ordering::SeqCst), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_issuance() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        for num in [1, 2, 4, 10] {
+            db.write::<Issuance>(num.into(), U256::from(num * 100))?;
+        }
+        for num in [2, 3] {
+            db.write::<Burnt>(BurntKey(num.into()), U256::from(num))?;
+        }
+        assert_eq!(db.read_issuance(4)?, Some(400.into()));
+        assert_eq!(db.read_burnt(3)?, Some(3.into()));
+
+        let walked = db.walk_issuance(1, 4)?.collect::<Result<Vec<_>>>()?;
+        let expected = [(1, 100, 0), (2, 200, 2), (3, 0, 3), (4, 400, 0)]
+            .map(|(n, i, b)| (BlockNumber(n), U256::from(i), U256::from(b)));
+        assert_eq!(walked, expected);
+        // an open-ended walk stops before the burnt entries
+        let all = db.walk_issuance(0, u64::MAX)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(all.len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_borrowed() -> Result<()> {
+        use std::borrow::Cow;
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let hash = H256::from_low_u64_be(1);
+        db.write::<CanonicalHeader>(1.into(), hash)?;
+        db.write::<CanonicalHeader>(2.into(), H256::from_low_u64_be(2))?;
+        let raw = db.read_borrowed::<CanonicalHeader, Cow<[u8]>>(1.into())?;
+        assert_eq!(raw.as_deref(), Some(hash.as_bytes()));
+        assert_eq!(
+            db.read_borrowed::<CanonicalHeader, H256>(1.into())?,
+            Some(hash)
+        );
+        let lens = db
+            .cursor::<CanonicalHeader>()?
+            .walk_borrowed::<Cow<[u8]>>(0.into())?
+            .map(|read| read.map(|(num, v)| (num.0, v.len())))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(lens, vec![(1, 32), (2, 32)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_read() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let hash = H256::from_low_u64_be;
+        for n in [1, 3, 300] {
+            db.write::<CanonicalHeader>(n.into(), hash(n))?;
+        }
+        let keys = [300, 2, 1, 300, 3].map(BlockNumber);
+        assert_eq!(
+            db.multi_read::<CanonicalHeader, _>(keys)?,
+            vec![
+                Some(hash(300)),
+                None,
+                Some(hash(1)),
+                Some(hash(300)),
+                Some(hash(3))
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = ErigonDb::<mdbx::RW>::open(dir.path())?;
+        let hash = H256::from_low_u64_be(1);
+        db.update(|tx| {
+            tx.write::<CanonicalHeader>(1.into(), hash)?;
+            tx.write::<LastHeader>(LastHeaderKey, hash)
+        })?;
+        let tx = Erigon::begin_snapshot(db.env())?;
+        assert_eq!(tx.read_head_header_hash()?, Some(hash));
+        // the second read of each table reuses its dbi
+        assert_eq!(tx.read_canonical_hash(1)?, Some(hash));
+        assert_eq!(tx.read_canonical_hash(1)?, Some(hash));
+        assert_eq!(tx.read_canonical_hash(2)?, None);
+        let walked = tx.cursor::<CanonicalHeader>()?.walk(0.into())?.count();
+        assert_eq!(walked, 1);
+        assert_eq!(tx.0.open_tables(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_limit() -> Result<()> {
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc,
+        };
+
+        let dir = tempfile::tempdir()?;
+        let env = erigon::env_open::<mdbx::RW>(dir.path())?;
+        let db = ErigonDb::with_max_readers(env, 1);
+        let (entered, wait_entered) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+        let holder = {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                db.view(|_| {
+                    entered.send(()).unwrap();
+                    wait_release.recv().unwrap();
+                    Ok(())
+                })
+            })
+        };
+        wait_entered.recv().unwrap();
+
+        // the only slot is taken, so a second reader waits for it
+        let done = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let (db, done) = (db.clone(), done.clone());
+            std::thread::spawn(move || {
+                db.view(|_| {
+                    done.store(true, Ordering::SeqCst);
+                    Ok(())
+                })
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!done.load(Ordering::SeqCst));
+        release.send(())?;
+        holder.join().unwrap()?;
+        waiter.join().unwrap()?;
+        assert!(done.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dbi_cache() -> Result<()> {
+        fn assert_sync<T: Sync>(_: &T) {}
+
+        let dir = tempfile::tempdir()?;
+        let env = erigon::env_open::<mdbx::RW>(dir.path())?;
+        let db = Erigon::begin_rw(&env)?;
+        assert_sync(&db.0);
+        let hash = H256::from_low_u64_be(1);
+        db.write::<CanonicalHeader>(1.into(), hash)?;
+        assert_eq!(db.0.open_tables(), 1);
+        // reads, cursors and deletes of the same table reuse its dbi
+        assert_eq!(db.read_canonical_hash(1)?, Some(hash));
+        assert_eq!(db.read_canonical_hash(2)?, None);
+        let first = db.cursor::<CanonicalHeader>()?.first()?;
+        assert_eq!(first, Some((1.into(), hash)));
+        assert!(db.delete::<CanonicalHeader>(1.into())?);
+        assert!(!db.delete::<CanonicalHeader>(1.into())?);
+        assert_eq!(db.0.open_tables(), 1);
+        // a cursor bound to a cached dbi reads the right table
+        db.write::<LastHeader>(LastHeaderKey, hash)?;
+        assert_eq!(db.0.open_tables(), 2);
+        assert_eq!(db.cursor::<CanonicalHeader>()?.first()?, None);
+        assert_eq!(db.read_head_header_hash()?, Some(hash));
+        assert_eq!(db.0.open_tables(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_policy() -> Result<()> {
+        use crate::erigon::RetryPolicy;
+
+        let dir = tempfile::tempdir()?;
+        let db = ErigonDb::<mdbx::RW>::open(dir.path())?.with_retry(RetryPolicy::NEVER);
+        assert_eq!(db.env().reader_check()?, 0);
+        db.update(|tx| tx.write::<CanonicalHeader>(1.into(), H256::repeat_byte(1)))?;
+        let hash = db.view(|tx| tx.read_canonical_hash(1))?;
+        assert_eq!(hash, Some(H256::repeat_byte(1)));
+
+        // a begin that fails with a stale reader slot is restarted, up to
+        // max_retries times
+        let db = db.with_retry(RetryPolicy {
+            max_retries: 2,
+            backoff: std::time::Duration::ZERO,
+            remap: true,
+        });
+        let flaky = |fails: &mut u32, err: fn() -> mdbx::Error| -> Result<()> {
+            match fails.checked_sub(1) {
+                Some(left) => {
+                    *fails = left;
+                    Err(Error::Mdbx(err()))
+                }
+                None => Ok(()),
+            }
+        };
+        let mut fails = 2;
+        db.retry(|| flaky(&mut fails, || mdbx::Error::BadRslot))?;
+        assert_eq!(fails, 0);
+        let mut fails = 2;
+        db.retry(|| flaky(&mut fails, || mdbx::Error::UnableExtendMapsize))?;
+        assert_eq!(fails, 0);
+        let mut fails = 3;
+        assert!(db
+            .retry(|| flaky(&mut fails, || mdbx::Error::BadRslot))
+            .is_err());
+        assert_eq!(fails, 0);
+        // other failures aren't retried
+        let mut fails = 2;
+        assert!(db
+            .retry(|| flaky(&mut fails, || mdbx::Error::Corrupted))
+            .is_err());
+        assert_eq!(fails, 1);
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_walk() -> Result<()> {
+        use crate::erigon::utils::keccak256;
+        use mdbx::RW;
+
+        fn collect<K, V>(acc: &mut Vec<(K, V)>, k: K, v: V) -> Result<()> {
+            acc.push((k, v));
+            Ok(())
+        }
+
+        let dir = tempfile::tempdir()?;
+        let db = ErigonDb::<RW>::open(dir.path())?;
+        assert!(db
+            .par_walk::<HeaderNumber, _, _, _>(4, Vec::new, collect)?
+            .is_empty());
+
+        db.update(|tx| {
+            for num in 0..1000u64 {
+                let hash = H256(keccak256(num.to_be_bytes()));
+                tx.write::<CanonicalHeader>(num.into(), hash)?;
+                tx.write::<HeaderNumber>(hash, num.into())?;
+            }
+            Ok(())
+        })?;
+        let blocks = db.view(|tx| {
+            tx.cursor::<CanonicalHeader>()?
+                .walk(BlockNumber(0))?
+                .collect::<Result<Vec<_>>>()
+        })?;
+        let hashes = db.view(|tx| {
+            tx.cursor::<HeaderNumber>()?
+                .walk(H256::zero())?
+                .collect::<Result<Vec<_>>>()
+        })?;
+        // keyed by block number and by hash, from a single shard up to more
+        // shards than there are keys
+        for shards in [1, 3, 8, 2000] {
+            let walked = db.par_walk::<CanonicalHeader, _, _, _>(shards, Vec::new, collect)?;
+            assert!(walked.len() <= shards);
+            assert_eq!(walked.concat(), blocks);
+            let walked = db.par_walk::<HeaderNumber, _, _, _>(shards, Vec::new, collect)?;
+            assert_eq!(walked.concat(), hashes);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_follow() -> Result<()> {
+        use crate::erigon::ChainEvent::{Added, Retracted};
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir()?;
+        let db = ErigonDb::<mdbx::RW>::open(dir.path())?;
+        let extend = |blocks: &[(u64, u64)]| {
+            db.update(|tx| {
+                for &(num, hash) in blocks {
+                    let hash = H256::from_low_u64_be(hash);
+                    tx.write::<CanonicalHeader>(num.into(), hash)?;
+                    tx.write_header_number(hash, num.into())?;
+                    tx.write_head_block_hash(hash)?;
+                }
+                Ok(())
+            })
+        };
+        let key = |num: u64, hash| HeaderKey(num.into(), H256::from_low_u64_be(hash));
+
+        extend(&[(0, 0), (1, 1)])?;
+        let mut follow = db.follow(Duration::from_millis(1))?;
+        extend(&[(2, 2), (3, 3)])?;
+        let mut next = || follow.next().unwrap();
+        assert_eq!(next()?, Added(key(2, 2)));
+        assert_eq!(next()?, Added(key(3, 3)));
+
+        extend(&[(2, 22), (3, 33), (4, 4)])?;
+        assert_eq!(next()?, Retracted(key(3, 3)));
+        assert_eq!(next()?, Retracted(key(2, 2)));
+        assert_eq!(next()?, Added(key(2, 22)));
+        assert_eq!(next()?, Added(key(3, 33)));
+        assert_eq!(next()?, Added(key(4, 4)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_reorg() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let write = |blocks: &[(u64, u64)]| -> Result<()> {
+            for &(num, hash) in blocks {
+                let hash = H256::from_low_u64_be(hash);
+                db.write::<CanonicalHeader>(num.into(), hash)?;
+                db.write_header_number(hash, num.into())?;
+                db.write_head_block_hash(hash)?;
+            }
+            Ok(())
+        };
+        let snapshot = |blocks: &[(u64, u64)]| {
+            blocks
+                .iter()
+                .map(|&(num, hash)| (BlockNumber(num), H256::from_low_u64_be(hash)))
+                .collect::<Vec<_>>()
+        };
+
+        write(&[(0, 0), (1, 1), (2, 2), (3, 3)])?;
+        let seen = snapshot(&[(1, 1), (2, 2), (3, 3)]);
+        let reorg = db.detect_reorg(&seen)?;
+        assert!(!reorg.is_reorg());
+        assert_eq!(reorg.added, 4..4);
+
+        write(&[(2, 22), (3, 33), (4, 4)])?;
+        let reorg = db.detect_reorg(&seen)?;
+        assert_eq!(
+            reorg.ancestor,
+            Some(HeaderKey(1.into(), H256::from_low_u64_be(1)))
+        );
+        assert_eq!(reorg.retracted, 2..4);
+        assert_eq!(reorg.added, 2..5);
+
+        let reorg = db.detect_reorg(&snapshot(&[(3, 3), (2, 2)]))?;
+        assert_eq!(reorg.ancestor, None);
+        assert_eq!(reorg.retracted, 2..4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_headers_at() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let mut hashes = Vec::new();
+        for (num, time) in [(1, 0), (2, 0), (2, 1), (3, 0)] {
+            let header = BlockHeader {
+                number: num.into(),
+                time,
+                ..Default::default()
+            };
+            let hash = header.hash();
+            db.write::<Header>(HeaderKey(num.into(), hash), header)?;
+            if num == 2 {
+                hashes.push(hash);
+            }
+        }
+        hashes.sort();
+        let mut found = db
+            .read_headers_at(2)?
+            .into_iter()
+            .map(|(hash, header)| {
+                assert_eq!(header.number, 2.into());
+                hash
+            })
+            .collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, hashes);
+        assert!(db.read_headers_at(4)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_blocks_matching_bloom() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let (usdc, weth) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let transfer = H256::from_low_u64_be(3);
+        let logs = [
+            vec![],
+            vec![CborLog {
+                address: usdc,
+                topics: vec![transfer],
+                ..Default::default()
+            }],
+            vec![CborLog {
+                address: weth,
+                ..Default::default()
+            }],
+        ];
+        for (num, logs) in logs.iter().enumerate() {
+            let header = BlockHeader {
+                number: num.into(),
+                bloom: logs_bloom(logs),
+                ..Default::default()
+            };
+            let key = HeaderKey((num as u64).into(), header.hash());
+            db.write::<CanonicalHeader>(key.0, key.1)?;
+            db.write::<Header>(key, header)?;
+        }
+
+        let blocks = |addresses: &[Address], topics: &[Vec<H256>]| {
+            db.blocks_matching_bloom(0..3, addresses, topics)
+                .map(|keys| keys.into_iter().map(|key| key.0 .0).collect::<Vec<_>>())
+        };
+        assert_eq!(blocks(&[], &[])?, vec![0, 1, 2]);
+        assert_eq!(blocks(&[usdc, weth], &[])?, vec![1, 2]);
+        assert_eq!(blocks(&[], &[vec![], vec![transfer]])?, vec![1]);
+        assert_eq!(blocks(&[weth], &[vec![transfer]])?, vec![]);
+
+        // a canonical block without its header is skipped rather than failing
+        let hash = db.read_canonical_hash(1)?.unwrap();
+        db.delete::<Header>(HeaderKey(1.into(), hash))?;
+        assert_eq!(blocks(&[], &[])?, vec![0, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_by_timestamp() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        for num in 0..10u64 {
+            let header = BlockHeader {
+                number: num.into(),
+                time: 1000 + num * 12,
+                ..Default::default()
+            };
+            let key = HeaderKey(num.into(), header.hash());
+            db.write::<CanonicalHeader>(key.0, key.1)?;
+            db.write::<Header>(key, header)?;
+        }
+        assert_eq!(db.block_by_timestamp(1000)?, BlockNumber(0));
+        assert_eq!(db.block_by_timestamp(1011)?, BlockNumber(0));
+        assert_eq!(db.block_by_timestamp(1012)?, BlockNumber(1));
+        assert_eq!(db.block_by_timestamp(1050)?, BlockNumber(4));
+        assert_eq!(db.block_by_timestamp(u64::MAX)?, BlockNumber(9));
+        assert!(db.block_by_timestamp(999).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gas_stats() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        for num in 0..4u64 {
+            let header = BlockHeader {
+                number: num.into(),
+                gas_used: num * 10,
+                gas_limit: 100,
+                base_fee: (num >= 2).then(|| (num * 7).into()),
+                ..Default::default()
+            };
+            let key = HeaderKey(num.into(), header.hash());
+            db.write::<CanonicalHeader>(key.0, key.1)?;
+            db.write::<Header>(key, header)?;
+        }
+        let stats = db.gas_stats(1..4)?;
+        assert_eq!(stats.blocks.len(), 3);
+        let used = stats.gas_used.unwrap();
+        assert_eq!((used.min, used.p50, used.max), (10, 20, 30));
+        assert_eq!(stats.base_fee.unwrap().min, 14);
+        assert_eq!(stats.utilization, 0.2);
+
+        let mut csv = Vec::new();
+        stats.write_csv(&mut csv)?;
+        let csv = String::from_utf8(csv)?;
+        assert_eq!(csv.lines().nth(1), Some("1,10,100,"));
+        assert_eq!(csv.lines().nth(3), Some("3,30,100,21"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_appearances() -> Result<()> {
+        use crate::erigon::AppearanceReason::*;
+        use roaring::{RoaringBitmap, RoaringTreemap};
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let (adr, other) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let treemap = |blocks: &[u64]| blocks.iter().copied().collect::<RoaringTreemap>();
+        db.write::<AccountHistory>(AccountHistKey(adr, u64::MAX.into()), treemap(&[1, 5]))?;
+        for slot in 0..2 {
+            let key = StorageHistKey(adr, H256::from_low_u64_be(slot), u64::MAX.into());
+            db.write::<StorageHistory>(key, treemap(&[5 + slot]))?;
+        }
+        let key = StorageHistKey(other, H256::zero(), u64::MAX.into());
+        db.write::<StorageHistory>(key, treemap(&[9]))?;
+        let logs = [3u32, 5].into_iter().collect::<RoaringBitmap>();
+        db.write::<LogAddressIndex>(LogAddressKey(adr, u32::MAX), logs)?;
+
+        let blocks = db.appearances(adr)?.into_iter().collect::<Vec<_>>();
+        assert_eq!(blocks, vec![1, 3, 5, 6]);
+        let reasons = db
+            .appearance_reasons(adr)?
+            .map(|a| (*a.block, a.reasons))
+            .collect::<Vec<_>>();
+        assert_eq!(reasons[2], (5, vec![AccountChange, StorageChange, Log]));
+        assert_eq!(reasons[3], (6, vec![StorageChange]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_accounts() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let adr = Address::from_low_u64_be;
+        for (i, balance, slots) in [(1, 5, 0), (2, 9, 3), (3, 1, 1), (4, 7, 2)] {
+            db.write_account(adr(i), Account::new().balance(balance.into()))?;
+            for slot in 0..slots {
+                let key = StorageKey(adr(i), 1.into());
+                db.write::<Storage>(key, (H256::from_low_u64_be(slot), 1.into()))?;
+            }
+        }
+        let top = db.top_balances(2, NoProgress)?;
+        assert_eq!(top, vec![(adr(2), 9.into()), (adr(4), 7.into())]);
+        let mut ticks = 0;
+        let top = db.top_storage_users(5, |_: &Tick<'_>| ticks += 1)?;
+        assert_eq!(top, vec![(adr(2), 3), (adr(4), 2), (adr(3), 1)]);
+        assert_eq!(ticks, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_storage_footprint() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let adr = Address::from_low_u64_be(1);
+        let key = StorageKey(adr, 1.into());
+        db.write::<Storage>(key, (H256::from_low_u64_be(1), 1.into()))?;
+        db.write::<Storage>(key, (H256::from_low_u64_be(2), 0x100.into()))?;
+        db.write::<Storage>(StorageKey(adr, 2.into()), (H256::zero(), 1.into()))?;
+        assert_eq!(db.storage_slot_count(adr, 1)?, 2);
+        // the key, then each 32-byte slot and its trimmed value
+        assert_eq!(db.storage_size_bytes(adr, 1)?, 28 + 33 + 34);
+        assert_eq!(db.storage_slot_count(adr, 3)?, 0);
+        assert_eq!(db.storage_size_bytes(adr, 3)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_last() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        assert_eq!(db.first::<CanonicalHeader>()?, None);
+        for num in 3..6 {
+            db.write::<CanonicalHeader>(num.into(), H256::from_low_u64_be(num))?;
+        }
+        assert_eq!(
+            db.first::<CanonicalHeader>()?,
+            Some((3.into(), H256::from_low_u64_be(3)))
+        );
+        assert_eq!(
+            db.last::<CanonicalHeader>()?,
+            Some((5.into(), H256::from_low_u64_be(5)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_range() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        for num in 0..10 {
+            db.write::<CanonicalHeader>(num.into(), H256::from_low_u64_be(num))?;
+        }
+        let nums = db
+            .cursor::<CanonicalHeader>()?
+            .walk_range(BlockNumber(3)..BlockNumber(6))?
+            .map(|read| read.map(|(num, _)| num.0))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(nums, vec![3, 4, 5]);
+        let past_end = db
+            .cursor::<CanonicalHeader>()?
+            .walk_range(BlockNumber(8)..BlockNumber(20))?
+            .count();
+        assert_eq!(past_end, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_readahead() -> Result<()> {
+        assert!(!ENV_FLAGS.readahead(true).no_rdahead);
+        assert!(ENV_FLAGS.readahead(false).no_rdahead);
+
+        let dir = tempfile::tempdir()?;
+        {
+            let env = Erigon::init_database(dir.path())?;
+            let db = Erigon::begin_rw(&env)?;
+            for num in 0..1000 {
+                db.write::<CanonicalHeader>(num.into(), H256::from_low_u64_be(num))?;
+            }
+            db.commit()?;
+        }
+
+        let env = erigon::env_open_for_scans::<mdbx::RO>(dir.path())?;
+        env.prefetch_range(0..u64::MAX)?;
+        env.prefetch_range(1 << 40..1 << 41)?;
+        let db = Erigon::begin(&env)?;
+        let plain = db
+            .cursor::<CanonicalHeader>()?
+            .walk(BlockNumber(0))?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(plain.len(), 1000);
+        // a window smaller than a page, one of several pages, and none
+        for window in [1, 64 << 10, 0] {
+            let walked = db
+                .cursor::<CanonicalHeader>()?
+                .readahead(window)
+                .walk(BlockNumber(0))?
+                .collect::<Result<Vec<_>>>()?;
+            assert_eq!(walked, plain);
+        }
+        let mut cur = db.cursor::<CanonicalHeader>()?.readahead(64 << 10);
+        assert_eq!(
+            cur.seek_exact(500.into())?,
+            Some((500.into(), H256::from_low_u64_be(500)))
+        );
+        assert_eq!(cur.clone_position()?.next()?, plain.get(501).cloned());
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_while() -> Result<()> {
+        use std::ops::ControlFlow;
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        for num in 0..10 {
+            db.write::<CanonicalHeader>(num.into(), H256::from_low_u64_be(num * 2))?;
+        }
+        let mut cur = db.cursor::<CanonicalHeader>()?;
+        let found = cur.walk_while(2.into(), |num, hash| {
+            if hash.to_low_u64_be() > 10 {
+                ControlFlow::Break(num.0)
+            } else {
+                ControlFlow::Continue(())
+            }
+        })?;
+        assert_eq!(found, Some(6));
+        assert_eq!(
+            cur.walk_while(0.into(), |_, _| ControlFlow::<()>::Continue(()))?,
+            None
+        );
+
+        let nums = db
+            .cursor::<CanonicalHeader>()?
+            .walk_map(7.into(), |num, _| match num.0 {
+                n if n < 9 => ControlFlow::Continue(n),
+                _ => ControlFlow::Break(()),
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(nums, vec![7, 8]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_back() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        for num in [1, 3, 5] {
+            db.write::<CanonicalHeader>(num.into(), H256::from_low_u64_be(num))?;
+        }
+        let walk_back = |start: Option<u64>| -> Result<Vec<u64>> {
+            db.cursor::<CanonicalHeader>()?
+                .walk_back(start.map(BlockNumber))?
+                .map(|read| read.map(|(num, _)| num.0))
+                .collect()
+        };
+        assert_eq!(walk_back(None)?, vec![5, 3, 1]);
+        assert_eq!(walk_back(Some(3))?, vec![3, 1]);
+        assert_eq!(walk_back(Some(4))?, vec![3, 1]);
+        assert_eq!(walk_back(Some(9))?, vec![5, 3, 1]);
+        assert!(walk_back(Some(0))?.is_empty());
+
+        let key = StorageKey(Address::from_low_u64_be(1), 1.into());
+        for slot in 1..=3 {
+            db.write::<Storage>(key, (H256::from_low_u64_be(slot), slot.into()))?;
+        }
+        let slots = db
+            .cursor::<Storage>()?
+            .walk_dup_back(key)?
+            .map(|read| read.map(|(slot, _)| slot.to_low_u64_be()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(slots, vec![3, 2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_position() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        for num in 0..5 {
+            db.write::<CanonicalHeader>(num.into(), H256::from_low_u64_be(num))?;
+        }
+        let mut cur = db.cursor::<CanonicalHeader>()?;
+        cur.seek(BlockNumber(2))?;
+        let mut fork = cur.clone_position()?;
+        assert_eq!(cur.next()?.map(|(num, _)| num.0), Some(3));
+        assert_eq!(cur.next()?.map(|(num, _)| num.0), Some(4));
+        assert_eq!(fork.next()?.map(|(num, _)| num.0), Some(3));
+        assert_eq!(fork.prev()?.map(|(num, _)| num.0), Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dup_count() -> Result<()> {
+        use crate::kv::traits::KvTxMut;
+
+        fn check<Tx: KvTxMut>(db: &Erigon<'_, mdbx::RW, Tx>) -> Result<()> {
+            let adr = Address::from_low_u64_be(1);
+            let key = StorageKey(adr, 1.into());
+            for slot in 1..4 {
+                db.write::<Storage>(key, (H256::from_low_u64_be(slot), 1.into()))?;
+            }
+            let mut cur = db.cursor::<Storage>()?;
+            assert_eq!(cur.dup_count()?, 0);
+            cur.seek(key)?;
+            cur.next()?;
+            assert_eq!(cur.dup_count()?, 3);
+            // counting doesn't move the cursor off the second slot
+            let (_, (slot, _)) = cur.next()?.unwrap();
+            assert_eq!(slot, H256::from_low_u64_be(3));
+            assert_eq!(db.storage_slot_count(adr, 1)?, 3);
+            Ok(())
+        }
+
+        let mem = MemDb::new();
+        check(&Erigon::begin_mem_rw(&mem)?)?;
+        let dir = tempfile::tempdir()?;
+        let env = erigon::env_open::<mdbx::RW>(dir.path())?;
+        check(&Erigon::begin_rw(&env)?)?;
+        Ok(())
+    }
+
+    crate::dupsort_table!(TestDupFixed => BlockNumber => H256, subkey = H256, dupfixed);
+
+    #[test]
+    fn test_walk_dup_fast() -> Result<()> {
+        use crate::kv::{tables::DupFixedFlags, traits::DbFlags};
+
+        let dir = tempfile::tempdir()?;
+        let env = erigon::env_open::<mdbx::RW>(dir.path())?;
+        let db = Erigon::begin_rw(&env)?;
+        db.0.create_table("TestDupFixed", DupFixedFlags::FLAGS)?;
+        // enough values to span several pages
+        for i in 0..1000 {
+            db.write::<TestDupFixed>(1.into(), H256::from_low_u64_be(i))?;
+        }
+        db.write::<TestDupFixed>(2.into(), H256::zero())?;
+        let vals = db
+            .cursor::<TestDupFixed>()?
+            .walk_dup_fast(1.into())?
+            .map(|read| read.map(|v| v.to_low_u64_be()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(vals, (0..1000).collect::<Vec<_>>());
+        assert_eq!(
+            db.cursor::<TestDupFixed>()?
+                .walk_dup_fast(3.into())?
+                .count(),
+            0
+        );
+
+        // tables that aren't DUPFIXED are read a value at a time
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let key = StorageKey(Address::from_low_u64_be(1), 1.into());
+        for slot in 1..=3 {
+            db.write::<Storage>(key, (H256::from_low_u64_be(slot), slot.into()))?;
+        }
+        let vals = db
+            .cursor::<Storage>()?
+            .walk_dup_fast(key)?
+            .map(|read| read.map(|(_, v)| v.as_u64()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(vals, vec![1, 2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_balance_at() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let adr = Address::from_low_u64_be(0xf00d);
+        // the balance went from 1 to 2 in block 5
+        let before = Account::new().balance(1.into());
+        db.write_account(adr, Account::new().balance(2.into()))?;
+        db.write::<AccountChangeSet>(5.into(), AccountCSVal(adr, before))?;
+        let bitmap: roaring::RoaringTreemap = [5].into_iter().collect();
+        db.write::<AccountHistory>(AccountHistKey(adr, u64::MAX.into()), bitmap)?;
+
+        assert_eq!(db.balance_at(adr, 4)?, 1.into());
+        assert_eq!(db.balance_at(adr, 5)?, 2.into());
+        assert_eq!(db.nonce_at(adr, 5)?, 0);
+        assert!(db.code_at(adr, 5)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_activity() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let adr = Address::from_low_u64_be(0xf00d);
+        let acct = |bal: u64| Account::new().balance(bal.into());
+        db.write_account(adr, acct(3))?;
+        db.write::<AccountChangeSet>(2.into(), AccountCSVal(adr, acct(0)))?;
+        db.write::<AccountChangeSet>(5.into(), AccountCSVal(adr, acct(1)))?;
+        db.write::<AccountChangeSet>(9.into(), AccountCSVal(adr, acct(2)))?;
+        let bitmap: roaring::RoaringTreemap = [2, 5, 9].into_iter().collect();
+        db.write::<AccountHistory>(AccountHistKey(adr, u64::MAX.into()), bitmap)?;
+
+        let activity = db.account_activity(adr, 3..10)?;
+        let timeline = activity
+            .iter()
+            .map(|a| (*a.block, a.balance_before(), a.balance_after()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            timeline,
+            vec![(5, 1.into(), 2.into()), (9, 2.into(), 3.into())]
+        );
+        assert!(activity.iter().all(|a| a.txs.is_empty()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_erc20_transfers() -> Result<()> {
+        use crate::erigon::analytics::erc20::{Transfer, TRANSFER_TOPIC};
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let (token, from, to) = (
+            Address::from_low_u64_be(1),
+            Address::from_low_u64_be(2),
+            Address::from_low_u64_be(3),
+        );
+        let transfer = CborLog {
+            address: token,
+            topics: vec![TRANSFER_TOPIC, from.into(), to.into()],
+            data: H256::from_low_u64_be(500).as_bytes().to_vec().into(),
+        };
+        // an ERC-721 transfer, with the token id as a fourth topic
+        let nft = CborLog {
+            topics: vec![TRANSFER_TOPIC, from.into(), to.into(), H256::zero()],
+            data: Default::default(),
+            ..transfer.clone()
+        };
+        db.write::<TransactionLog>(LogsKey(4.into(), 0), CborLogs(Some(vec![transfer.clone()])))?;
+        db.write::<TransactionLog>(LogsKey(5.into(), 1), CborLogs(Some(vec![nft, transfer])))?;
+        db.write::<TransactionLog>(LogsKey(6.into(), 0), CborLogs(None))?;
+
+        let transfers = db.erc20_transfers(5..7)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            transfers,
+            vec![Transfer {
+                block: 5.into(),
+                tx_index: 1,
+                token,
+                from,
+                to,
+                amount: 500.into(),
+            }]
+        );
+        assert_eq!(db.erc20_transfers(0..10)?.count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mapping_slots() -> Result<()> {
+        use crate::erigon::slots::{array_slot, map_slot};
+
+        // keccak256(0) + 1
+        assert_eq!(
+            array_slot(H256::zero(), 1.into()),
+            H256(hex_literal::hex!(
+                "290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e564"
+            ))
+        );
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let (token, holder) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        db.write_account(token, Account::new().incarnation(1.into()))?;
+        let slot = map_slot(H256::zero(), holder.into());
+        db.write::<Storage>(StorageKey(token, 1.into()), (slot, 500.into()))?;
+        assert_eq!(
+            db.read_mapping(token, 1, H256::zero(), holder.into())?,
+            Some(500.into())
+        );
+        assert_eq!(
+            db.read_mapping_value(token, H256::zero(), holder.into(), 7)?,
+            500.into()
+        );
+        assert_eq!(
+            db.read_mapping_value(token, H256::zero(), token.into(), 7)?,
+            0.into()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_size() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let adr = Address::from_low_u64_be(0xc0de);
+        let hash = H256::from_low_u64_be(1);
+        let code = Bytecode::from(bytes::Bytes::from(vec![0x60; 42]));
+        db.write_account(adr, Account::new().codehash(hash))?;
+        db.write::<Code>(hash, code.clone())?;
+
+        assert_eq!(db.read_code_size(adr)?, 42);
+        assert_eq!(db.read_code_size(Address::zero())?, 0);
+        let all = db.walk_code()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(all, vec![(hash, code)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytecode_analysis() {
+        // PUSH1 0 CALLDATALOAD PUSH1 0xe0 SHR DUP1 PUSH4 sel EQ PUSH2 0x0012
+        // JUMPI STOP JUMPDEST PUSH2 0x5b5b, with JUMPDEST bytes in push data
+        let code = Bytecode::from(bytes::Bytes::from(
+            hex_literal::hex!("60003560e01c8063a9059cbb1461001257005b615b5b").to_vec(),
+        ));
+        let ops = code
+            .iter_opcodes()
+            .map(|ins| ins.opcode)
+            .collect::<Vec<_>>();
+        assert_eq!(ops[..4], [0x60, 0x35, 0x60, 0x1c]);
+        assert_eq!(code.iter_opcodes().last().unwrap().immediate, &[0x5b, 0x5b]);
+        assert_eq!(code.jumpdests(), vec![18]);
+        assert_eq!(code.selectors(), vec![[0xa9, 0x05, 0x9c, 0xbb]]);
+        assert!(!code.is_erc20_like());
+    }
+
+    #[test]
+    fn test_bitmap() -> Result<()> {
+        use crate::erigon::bitmap::{merge_shards, seek_in_bitmap};
+        use roaring::RoaringTreemap;
+
+        let map: RoaringTreemap = [0, 5, 9].into_iter().collect();
+        assert_eq!(seek_in_bitmap(&map, 0), Some(0));
+        assert_eq!(seek_in_bitmap(&map, 1), Some(5));
+        assert_eq!(seek_in_bitmap(&map, 5), Some(5));
+        assert_eq!(seek_in_bitmap(&map, 10), None);
+        assert_eq!(seek_in_bitmap(&RoaringTreemap::new(), 0), None);
+
+        let shards = [[1, 2], [7, 8]].map(|s| Ok(s.into_iter().collect()));
+        let merged = merge_shards(shards)?;
+        assert_eq!(merged.iter().collect::<Vec<_>>(), vec![1, 2, 7, 8]);
+
+        // an address without history reads as unchanged
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem(&mem)?;
+        assert_eq!(db.read_account_hist(Address::zero(), 1)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_table() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = ErigonDb::<mdbx::RW>::open(dir.path())?;
+        db.update(|tx| {
+            for num in 0..100 {
+                tx.write::<CanonicalHeader>(num.into(), H256::from_low_u64_be(num))?;
+            }
+            Ok(())
+        })?;
+        let sample = db.view(|tx| tx.sample_table::<CanonicalHeader>(20))?;
+        assert_eq!((sample.entries, sample.samples), (100, 20));
+        assert_eq!((sample.key_sizes.min, sample.key_sizes.max), (8, 8));
+        assert_eq!(sample.value_sizes.p50, 32);
+        assert_eq!(sample.prefixes, [(0, 100)].into_iter().collect());
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_tables() {
+        use crate::kv::tables::{all_tables, find_tables, TableInfo};
+
+        let tables = all_tables();
+        let storage = tables.iter().find(|t| t.name == "Storage").unwrap();
+        assert_eq!(storage.db_name, "PlainState");
+        assert!(storage.is_dupsort());
+        assert_eq!(*storage, TableInfo::of::<Storage>());
+
+        let plain_state = find_tables("PlainState");
+        assert_eq!(plain_state.len(), 2);
+        assert!(!plain_state[0].is_dupsort());
+        assert!(find_tables("NotATable").is_empty());
+    }
+
+    #[test]
+    fn test_sharded_history() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let adr = Address::from_low_u64_be(0xcafe);
+        db.write_account(adr, Account::new().balance(3.into()))?;
+        for (block, balance) in [(3, 0), (10, 1), (20, 2)] {
+            let acct = Account::new().balance(balance.into());
+            db.write::<AccountChangeSet>(block.into(), AccountCSVal(adr, acct))?;
+        }
+        // the first shard's key is past its last block, so a seek to block 12
+        // lands on it, but the next change is in the last shard
+        let shards = [(15, vec![3, 10]), (u64::MAX, vec![20])];
+        for (key, blocks) in shards {
+            let map: roaring::RoaringTreemap = blocks.into_iter().collect();
+            db.write::<AccountHistory>(AccountHistKey(adr, key.into()), map)?;
+        }
+
+        let balance = |block: u64| -> Result<U256> {
+            Ok(db.account_at(adr, block)?.unwrap_or_default().balance)
+        };
+        assert_eq!(balance(2)?, 0.into());
+        assert_eq!(balance(4)?, 1.into());
+        assert_eq!(balance(12)?, 2.into());
+        assert_eq!(balance(21)?, 3.into());
+        assert_eq!(db.read_account_hist(adr, 21)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_state() -> Result<()> {
+        use crate::erigon::compare::{diff_state, StateMismatch};
+
+        let (mem_a, mem_b) = (MemDb::new(), MemDb::new());
+        let (a, b) = (Erigon::begin_mem_rw(&mem_a)?, Erigon::begin_mem_rw(&mem_b)?);
+        let adr = |n| Address::from_low_u64_be(n);
+        let slot = H256::from_low_u64_be;
+        for db in [&a, &b] {
+            db.write_account(adr(1), Account::new().nonce(1))?;
+            db.write::<Storage>(StorageKey(adr(1), 1.into()), (slot(1), 1.into()))?;
+        }
+        a.write_account(adr(2), Account::new().nonce(2))?;
+        b.write_account(adr(2), Account::new().nonce(3))?;
+        b.write_account(adr(3), Account::new())?;
+        a.write::<Storage>(StorageKey(adr(1), 1.into()), (slot(2), 5.into()))?;
+
+        let diff = diff_state(&a, &b, 10)?;
+        assert_eq!(diff.len(), 3);
+        assert_eq!(
+            diff[0],
+            StateMismatch::Storage {
+                address: adr(1),
+                incarnation: 1.into(),
+                slot: slot(2),
+                a: 5.into(),
+                b: 0.into(),
+            }
+        );
+        assert!(
+            matches!(&diff[1], StateMismatch::Account { b: Some(acct), .. } if acct.nonce == 3)
+        );
+        assert!(matches!(&diff[2], StateMismatch::Account { a: None, .. }));
+        assert_eq!(diff_state(&a, &b, 1)?.len(), 1);
+        assert!(diff_state(&a, &a, 10)?.is_empty());
+        Ok(())
+    }
+
+    // Writes the changesets and history of three changes: address 1's balance
+    // goes 1 -> 2 in block 5 and 2 -> 3 in block 8, address 2 is created with
+    // a balance of 1 in block 7, and slot 3 of address 1 goes 1 -> 2 in block 6.
+    fn history_fixture() -> Result<MemDb> {
+        use crate::kv::traits::{KvTxMut, TableEncode};
+        use roaring::RoaringTreemap;
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let (adr, created) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let acct = |balance: u64| Account::new().balance(balance.into());
+        let (key, slot) = (StorageKey(adr, 0.into()), H256::from_low_u64_be(3));
+        db.write_account(adr, acct(3))?;
+        db.write_account(created, acct(1))?;
+        db.write::<Storage>(key, (slot, 2.into()))?;
+        db.write::<AccountChangeSet>(5.into(), AccountCSVal(adr, acct(1)))?;
+        db.write::<AccountChangeSet>(8.into(), AccountCSVal(adr, acct(2)))?;
+        db.0.put_raw::<AccountChangeSet>(BlockNumber(7).encode().as_ref(), created.as_bytes())?;
+        db.write::<StorageChangeSet>((6, adr, 0).into(), StorageCSVal(slot, 1.into()))?;
+        let map = |blocks: &[u64]| blocks.iter().copied().collect::<RoaringTreemap>();
+        db.write::<AccountHistory>(AccountHistKey(adr, u64::MAX.into()), map(&[5, 8]))?;
+        db.write::<AccountHistory>(AccountHistKey(created, u64::MAX.into()), map(&[7]))?;
+        db.write::<StorageHistory>(StorageHistKey(adr, slot, u64::MAX.into()), map(&[6]))?;
+        db.commit()?;
+        Ok(mem)
+    }
+
+    #[test]
+    fn test_state_diff() -> Result<()> {
+        let mem = history_fixture()?;
+        let db = Erigon::begin_mem(&mem)?;
+        let (adr, created) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let slot = H256::from_low_u64_be(3);
+
+        let diff = db.state_diff(5, 9)?;
+        assert_eq!(diff.accounts.len(), 2);
+        let balances = |d: &AccountDiff| {
+            (
+                d.before.map(|acct| acct.balance),
+                d.after.map(|acct| acct.balance),
+            )
+        };
+        assert_eq!(
+            balances(&diff.accounts[&adr]),
+            (Some(1.into()), Some(3.into()))
+        );
+        assert_eq!(balances(&diff.accounts[&created]), (None, Some(1.into())));
+        assert_eq!(
+            diff.storage[&(adr, 0.into())][&slot],
+            StorageDiff {
+                before: 1.into(),
+                after: 2.into(),
+            }
+        );
+
+        // only the changes in blocks [6, 8) are included
+        let diff = db.state_diff(6, 8)?;
+        assert_eq!(diff.accounts.keys().collect::<Vec<_>>(), vec![&created]);
+        assert_eq!(diff.storage[&(adr, 0.into())].len(), 1);
+        let diff = db.state_diff(6, 7)?;
+        assert!(diff.accounts.is_empty());
+        assert_eq!(diff.storage.len(), 1);
+
+        assert_eq!(db.state_diff(9, 20)?, StateDiff::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_genesis_alloc() -> Result<()> {
+        use crate::kv::traits::{KvTxMut, TableEncode};
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let adr = Address::from_low_u64_be;
+        let (a, c, d, e) = (adr(0xa), adr(0xc), adr(0xd), adr(0xe));
+        let slot = H256::from_low_u64_be;
+        // a's balance goes 1 -> 2 in block 5 and 2 -> 3 in block 8
+        db.write_account(a, Account::new().balance(3.into()))?;
+        db.write::<AccountChangeSet>(5.into(), AccountCSVal(a, Account::new().balance(1.into())))?;
+        db.write::<AccountChangeSet>(8.into(), AccountCSVal(a, Account::new().balance(2.into())))?;
+        // c is a contract in its second incarnation, whose nonce goes 1 -> 2
+        // slot 1 goes 5 -> 7, and slot 2 is set in block 6. Its changeset entry
+        // omits the codehash, and a slot of its first incarnation is left
+        // behind.
+        let (hash, code) = (
+            H256::repeat_byte(0xcc),
+            bytes::Bytes::from_static(&[0x60, 0x00]),
+        );
+        let contract = |nonce| Account::new().nonce(nonce).incarnation(2.into());
+        db.write_account(c, contract(2).codehash(hash))?;
+        db.write::<AccountChangeSet>(6.into(), AccountCSVal(c, contract(1)))?;
+        db.write::<PlainCodeHash>(PlainCodeKey(c, 2.into()), hash)?;
+        db.write::<Code>(hash, Bytecode::from(code.clone()))?;
+        db.write::<Storage>(StorageKey(c, 2.into()), (slot(1), 7.into()))?;
+        db.write::<Storage>(StorageKey(c, 2.into()), (slot(2), 9.into()))?;
+        db.write::<Storage>(StorageKey(c, 1.into()), (slot(3), 1.into()))?;
+        db.write::<StorageChangeSet>((6, c, 2).into(), StorageCSVal(slot(1), 5.into()))?;
+        db.write::<StorageChangeSet>((6, c, 2).into(), StorageCSVal(slot(2), 0.into()))?;
+        // d is created in block 7
+        db.write_account(d, Account::new().balance(1.into()))?;
+        db.0.put_raw::<AccountChangeSet>(BlockNumber(7).encode().as_ref(), d.as_bytes())?;
+        // e exists but is empty until it is sent 4 wei in block 7
+        db.write_account(e, Account::new().balance(4.into()))?;
+        db.write::<AccountChangeSet>(7.into(), AccountCSVal(e, Account::new()))?;
+
+        let alloc = db.generate_genesis_alloc(5)?;
+        assert_eq!(alloc.keys().collect::<Vec<_>>(), vec![&a, &c, &e]);
+        assert_eq!(alloc[&e], GenesisAccount::default());
+        assert_eq!(alloc[&a].balance, 1.into());
+        assert_eq!(
+            alloc[&c],
+            GenesisAccount {
+                balance: 0.into(),
+                nonce: 1.into(),
+                code: Some(code.clone()),
+                storage: [(slot(1), H256::from_low_u64_be(5))].into_iter().collect(),
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(&alloc[&a])?,
+            serde_json::json!({ "balance": "0x1" })
+        );
+        assert_eq!(serde_json::to_value(&alloc[&c])?["code"], "0x6000");
+
+        let alloc = db.generate_genesis_alloc(6)?;
+        assert_eq!(alloc[&a].balance, 2.into());
+        assert_eq!(alloc[&c].nonce, 1.into());
+        assert!(!alloc.contains_key(&d));
+
+        // past the last change, the alloc is the current state
+        let alloc = db.generate_genesis_alloc(9)?;
+        assert_eq!(alloc.len(), 4);
+        assert_eq!(alloc[&e].balance, 4.into());
+        assert_eq!(alloc[&a].balance, 3.into());
+        assert_eq!(alloc[&c].nonce, 2.into());
+        assert_eq!(alloc[&c].storage[&slot(1)], H256::from_low_u64_be(7));
+        assert_eq!(alloc[&c].storage[&slot(2)], H256::from_low_u64_be(9));
+        assert_eq!(alloc[&d].balance, 1.into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwind() -> Result<()> {
+        let mem = history_fixture()?;
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let (adr, created) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let slot = H256::from_low_u64_be(3);
+        db.write_stage_progress("Execution", 10.into())?;
+        db.write_stage_progress("HashState", 10.into())?;
+
+        db.unwind_to(6)?;
+        assert_eq!(db.read_account(adr)?.unwrap().balance, 2.into());
+        assert_eq!(db.read_account(created)?, None);
+        assert_eq!(db.read_storage(adr, 0, slot)?, Some(2.into()));
+        assert_eq!(db.account_at(adr, 6)?.unwrap().balance, 2.into());
+        assert_eq!(db.account_at(adr, 5)?.unwrap().balance, 1.into());
+        assert_eq!(db.read_account_changeset(adr, 8)?, None);
+        assert_eq!(db.read_account_hist(created, 0)?, None);
+        assert_eq!(db.read_stage_progress("Execution")?, Some(6.into()));
+        // hashed state isn't unwound, so neither is the stage that builds it
+        assert_eq!(db.read_stage_progress("HashState")?, Some(10.into()));
+
+        db.unwind_to(5)?;
+        assert_eq!(db.read_storage(adr, 0, slot)?, Some(1.into()));
+        assert_eq!(db.storage_at(adr, 0, slot, 0)?, 1.into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune() -> Result<()> {
+        use roaring::RoaringTreemap;
+
+        let dir = tempfile::tempdir()?;
+        let db = ErigonDb::<mdbx::RW>::open(dir.path())?;
+        let adr = Address::from_low_u64_be(1);
+        let map = |blocks: &[u64]| blocks.iter().copied().collect::<RoaringTreemap>();
+        db.update(|tx| {
+            for block in 1..=5 {
+                let acct = Account::new().nonce(block);
+                tx.write::<AccountChangeSet>(block.into(), AccountCSVal(adr, acct))?;
+            }
+            tx.write::<AccountHistory>(AccountHistKey(adr, 2.into()), map(&[1, 2]))?;
+            tx.write::<AccountHistory>(AccountHistKey(adr, u64::MAX.into()), map(&[3, 4, 5]))
+        })?;
+
+        let mut ticks = 0;
+        let mut reports = db.prune_changesets(4, 2, |_: &Tick<'_>| ticks += 1)?;
+        reports.extend(db.prune_history(4, 1, NoProgress)?);
+        let last = |table| reports.iter().find(|p| p.table == table).copied();
+        assert_eq!(last("AccountChangeSet").unwrap().deleted, 3);
+        assert_eq!(last("AccountHistory").unwrap().deleted, 1);
+        // two batches of account changesets and one of storage changesets
+        assert_eq!(ticks, 3);
+
+        db.view(|tx| {
+            assert_eq!(tx.read_account_changeset(adr, 3)?, None);
+            assert_eq!(tx.read_account_changeset(adr, 4)?.unwrap().nonce, 4);
+            let shards = tx
+                .cursor::<AccountHistory>()?
+                .walk(AccountHistKey(adr, 0.into()))?
+                .collect::<Result<Vec<_>>>()?;
+            assert_eq!(
+                shards,
+                vec![(AccountHistKey(adr, u64::MAX.into()), map(&[4, 5]))]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_progress() -> Result<()> {
+        use crate::{erigon::progress::Progress, kv::traits::TableEncode};
+        use roaring::RoaringTreemap;
+        use std::time::Duration;
+
+        let tick = Tick {
+            op: "scan",
+            done: 25,
+            total: Some(100),
+            key: None,
+            elapsed: Duration::from_secs(10),
+        };
+        assert_eq!(tick.eta(), Some(Duration::from_secs(30)));
+        assert_eq!(Tick { done: 200, ..tick }.eta(), Some(Duration::ZERO));
+        assert_eq!(Tick { done: 0, ..tick }.eta(), None);
+        assert_eq!(
+            Tick {
+                total: None,
+                ..tick
+            }
+            .eta(),
+            None
+        );
+
+        type Ticked = (&'static str, u64, Option<Vec<u8>>);
+        #[derive(Default)]
+        struct Recorder {
+            ticks: Vec<Ticked>,
+            finished: Vec<&'static str>,
+        }
+        impl Progress for &mut Recorder {
+            fn tick(&mut self, tick: &Tick<'_>) {
+                self.ticks
+                    .push((tick.op, tick.done, tick.key.map(<[u8]>::to_vec)));
+            }
+            fn finish(&mut self, op: &'static str) {
+                self.finished.push(op);
+            }
+        }
+
+        let dir = tempfile::tempdir()?;
+        let db = ErigonDb::<mdbx::RW>::open(dir.path())?;
+        let adr = Address::from_low_u64_be(1);
+        let map = |blocks: &[u64]| blocks.iter().copied().collect::<RoaringTreemap>();
+        let last = AccountHistKey(adr, u64::MAX.into());
+        db.update(|tx| {
+            tx.write::<AccountHistory>(AccountHistKey(adr, 2.into()), map(&[1, 2]))?;
+            tx.write::<AccountHistory>(last, map(&[3, 4, 5]))
+        })?;
+
+        // a tick per batch of a shard each, with the key the next batch
+        // resumes from, and a finish per table
+        let mut recorder = Recorder::default();
+        db.prune_history(4, 1, &mut recorder)?;
+        assert_eq!(
+            recorder.ticks,
+            vec![
+                ("AccountHistory", 1, Some(last.encode().to_vec())),
+                ("AccountHistory", 2, None),
+                ("StorageHistory", 0, None),
+            ]
+        );
+        assert_eq!(recorder.finished, vec!["AccountHistory", "StorageHistory"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuild_history() -> Result<()> {
+        use crate::erigon::bitmap::{self, LAST_SHARD};
+        use roaring::RoaringTreemap;
+
+        let many = (0..10_000).map(|n| n * 3).collect::<RoaringTreemap>();
+        let shards = bitmap::split_into_shards(&many, bitmap::CHUNK_LIMIT);
+        assert!(shards.len() > 1);
+        assert_eq!(shards.last().unwrap().0, LAST_SHARD);
+        assert_eq!(shards[0].0, shards[0].1.max().unwrap());
+        assert!(shards
+            .iter()
+            .all(|(_, m)| m.serialized_size() <= bitmap::CHUNK_LIMIT));
+        // each full shard is cut only when the next block wouldn't fit
+        for pair in shards.windows(2) {
+            let mut grown = pair[0].1.clone();
+            grown.insert(pair[1].1.min().unwrap());
+            assert!(grown.serialized_size() > bitmap::CHUNK_LIMIT);
+        }
+        let union = shards
+            .iter()
+            .fold(RoaringTreemap::new(), |acc, (_, m)| acc | m);
+        assert_eq!(union, many);
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let adr = Address::from_low_u64_be(1);
+        let slot = H256::from_low_u64_be(2);
+        for block in [2, 4, 7] {
+            let acct = Account::new().nonce(block);
+            db.write::<AccountChangeSet>(block.into(), AccountCSVal(adr, acct))?;
+            db.write::<StorageChangeSet>(
+                (block, adr, Incarnation(1)).into(),
+                StorageCSVal(slot, 1.into()),
+            )?;
+        }
+        // a stale entry in the rebuilt range is dropped, one outside is kept
+        let stale = [1, 5].into_iter().collect::<RoaringTreemap>();
+        db.write::<AccountHistory>(AccountHistKey(adr, LAST_SHARD.into()), stale)?;
+
+        assert_eq!(db.rebuild_account_history(2..10)?, 1);
+        assert_eq!(db.rebuild_storage_history(0..10)?, 1);
+        let map = db.read::<AccountHistory>(AccountHistKey(adr, LAST_SHARD.into()))?;
+        assert_eq!(
+            map.unwrap().into_iter().collect::<Vec<_>>(),
+            vec![1, 2, 4, 7]
+        );
+        let map = db.read::<StorageHistory>(StorageHistKey(adr, slot, LAST_SHARD.into()))?;
+        assert_eq!(map.unwrap().into_iter().collect::<Vec<_>>(), vec![2, 4, 7]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_consensus_tables() -> Result<()> {
+        use crate::kv::traits::{KvTxMut, Table, TableEncode};
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let hash = H256::repeat_byte(0xab);
+        let key = HeaderKey(30000.into(), hash);
+        assert_eq!(
+            (Epoch::NAME, PendingEpoch::NAME),
+            ("DevEpoch", "DevPendingEpoch")
+        );
+
+        let proof = bytes::Bytes::from_static(&[0xc0, 0x01]);
+        db.write::<Epoch>(key, proof.clone())?;
+        db.write::<PendingEpoch>(key, proof.slice(1..))?;
+        assert_eq!(db.read_epoch(key)?, Some(proof));
+        assert_eq!(
+            db.read_pending_epoch(key)?,
+            Some(bytes::Bytes::from_static(&[0x01]))
+        );
+        assert_eq!(db.read_epoch(HeaderKey(30001.into(), hash))?, None);
+
+        // a snapshot as erigon writes it, with signers encoded as empty structs
+        // and the keys of recents as strings
+        let snap = br#"{"number":30000,"hash":"0xabababababababababababababababababababababababababababababababab","signers":{"0x0000000000000000000000000000000000000002":{},"0x0000000000000000000000000000000000000001":{}},"recents":{"29999":"0x0000000000000000000000000000000000000001","30000":"0x0000000000000000000000000000000000000002"},"votes":[],"tally":{}}"#;
+        db.0.put_raw::<CliqueSeparate>(key.encode().as_ref(), snap)?;
+        let snap = db.read_clique_snapshot(key)?.unwrap();
+        assert_eq!((snap.number, snap.hash), (30000, hash));
+        let (one, two) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        assert_eq!(snap.signer_addresses(), vec![one, two]);
+        assert_eq!(snap.recents.get(&29999), Some(&one));
+        assert!(snap.votes.is_empty() && snap.tally.is_empty());
+        // and it roundtrips through the table encoding
+        db.write::<CliqueSeparate>(key, snap.clone())?;
+        assert_eq!(db.read_clique_snapshot(key)?, Some(snap));
+        Ok(())
+    }
+
+    #[cfg(feature = "polygon")]
+    #[test]
+    fn test_bor_tables() -> Result<()> {
+        use crate::kv::traits::KvTxMut;
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+
+        // the receipt of a state-sync tx, with the StateCommitted(1234, true)
+        // log of the StateReceiver contract inline
+        let receipt = hex_literal::hex!(
+            "f8800180f87cf87a940000000000000000000000000000000000001001f842a05a22"
+            "725590b0a51c923940223f7458512164b1113359a735e86e7f27f44791eea00000"
+            "0000000000000000000000000000000000000000000000000000000004d2a00000"
+            "000000000000000000000000000000000000000000000000000000000001"
+        );
+        db.0.put_raw::<BorReceipts>(&16u64.to_be_bytes(), &receipt)?;
+        let receipt = db.read_bor_receipt(16)?.unwrap();
+        assert!(receipt.succeeded());
+        assert_eq!(receipt.cumulative_gas_used, 0);
+        assert_eq!(receipt.logs.len(), 1);
+        let log = &receipt.logs[0];
+        assert_eq!(log.address, Address::from_low_u64_be(0x1001));
+        assert_eq!(log.topics[1], H256::from_low_u64_be(1234));
+        assert_eq!(&log.data[..], H256::from_low_u64_be(1).as_bytes());
+        assert_eq!(db.read_bor_receipt(17)?, None);
+
+        let hash = H256::repeat_byte(0xab);
+        db.0.put_raw::<BlockBorTransactionLookup>(hash.as_bytes(), &[0x03, 0xe8])?;
+        assert_eq!(
+            db.read_bor_transaction_block_number(hash)?,
+            Some(1000.into())
+        );
+
+        let span = br#"{"span_id":1,"start_block":256,"end_block":6655,"validator_set":{"validators":[{"ID":1,"power":10000,"signer":"0x5973918275c01f50555d44e92c9d9b353cadad54"}],"proposer":null},"selected_producers":[{"ID":1,"power":10000,"signer":"0x5973918275c01f50555d44e92c9d9b353cadad54"}],"bor_chain_id":"137"}"#;
+        db.0.put_raw::<BorSpans>(&1u64.to_be_bytes(), span)?;
+        let span = db.read_span(1)?.unwrap();
+        assert_eq!((span.id, span.start_block, span.end_block), (1, 256, 6655));
+        assert_eq!(span.chain_id, "137");
+        assert_eq!(span.selected_producers.len(), 1);
+        assert_eq!(span.validator_set["validators"][0]["power"], 10000);
+
+        let checkpoint = br#"{"proposer":"0x5973918275c01f50555d44e92c9d9b353cadad54","start_block":0,"end_block":255,"root_hash":"0x2b7bbec9b6e39d8f10ff0d2de7a6fe3e0b2f16b3bce6aba4e4c0d5f8d2f5f6a7","bor_chain_id":"137","timestamp":1590000000}"#;
+        db.0.put_raw::<BorCheckpoints>(&1u64.to_be_bytes(), checkpoint)?;
+        let checkpoint = db.read_checkpoint(1)?.unwrap();
+        assert_eq!(
+            checkpoint.proposer,
+            Address(hex_literal::hex!(
+                "5973918275c01f50555d44e92c9d9b353cadad54"
+            ))
+        );
+        assert_eq!((checkpoint.start_block, checkpoint.end_block), (0, 255));
+        assert_eq!(checkpoint.root_hash.0[..2], [0x2b, 0x7b]);
+        assert_eq!(checkpoint.timestamp, 1_590_000_000);
+        // a malformed value is a decode error of its table
+        db.0.put_raw::<BorCheckpoints>(&2u64.to_be_bytes(), b"{")?;
+        assert!(matches!(
+            db.read_checkpoint(2),
+            Err(Error::Decode {
+                table: "BorCheckpoints",
+                ..
+            })
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "caplin")]
+    #[test]
+    fn test_beacon_tables() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let (root, hash) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        db.write::<CanonicalBlockRoots>(Slot(7), root)?;
+        db.write::<BlockRootToSlot>(root, Slot(7))?;
+        db.write::<BlockRootToBlockNumber>(root, ExecutionBlockNumber(5))?;
+        db.write::<BlockRootToBlockHash>(root, hash)?;
+        assert_eq!(kv::traits::TableEncode::encode(Slot(7)), [0, 0, 0, 7]);
+        assert_eq!(db.read_canonical_block_root(7)?, Some(root));
+        assert_eq!(db.read_beacon_slot(root)?, Some(Slot(7)));
+        assert_eq!(
+            db.read_beacon_execution_block(root)?,
+            Some(HeaderKey(5.into(), hash))
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "otterscan")]
+    #[test]
+    fn test_call_index() -> Result<()> {
+        use roaring::RoaringTreemap;
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let adr = Address::from_low_u64_be(1);
+        let map = |blocks: &[u64]| blocks.iter().copied().collect::<RoaringTreemap>();
+        db.write::<CallFromIndex>(AccountHistKey(adr, 3.into()), map(&[1, 3]))?;
+        db.write::<CallFromIndex>(AccountHistKey(adr, u64::MAX.into()), map(&[8]))?;
+        db.write::<CallToIndex>(AccountHistKey(adr, u64::MAX.into()), map(&[3, 5]))?;
+
+        let blocks = |v: Vec<BlockNumber>| v.into_iter().map(|n| n.0).collect::<Vec<_>>();
+        assert_eq!(blocks(db.search_blocks_before(adr, 8, 10)?), vec![5, 3, 1]);
+        assert_eq!(blocks(db.search_blocks_before(adr, 5, 1)?), vec![3]);
+        assert_eq!(blocks(db.search_blocks_after(adr, 1, 2)?), vec![3, 5]);
+        assert!(db.search_blocks_before(adr, 0, 10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_body_withdrawals() -> Result<()> {
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let pre = BodyForStorage {
+            base_tx_id: 10,
+            tx_amount: 4,
+            uncles: vec![],
+            withdrawals: None,
+        };
+        let post = BodyForStorage {
+            withdrawals: Some(vec![Withdrawal {
+                index: 1,
+                validator: 2,
+                address: Address::from_low_u64_be(3),
+                amount: 4,
+            }]),
+            ..pre.clone()
+        };
+        let (pre_key, post_key) = (
+            HeaderKey(1.into(), H256::zero()),
+            HeaderKey(2.into(), H256::zero()),
+        );
+        db.write_body_for_storage(pre_key, pre.clone())?;
+        db.write_body_for_storage(post_key, post.clone())?;
+        assert_eq!(db.read::<BlockBody>(pre_key)?, Some(pre));
+        assert_eq!(db.read::<BlockBody>(post_key)?, Some(post));
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_rlp() -> eyre::Result<()> {
+        use crate::kv::traits::{TableDecode, TableEncode};
+
+        // mainnet genesis
+        let raw = hex::decode(GENESIS_HEADER)?;
+        let header = BlockHeader::decode(&raw)?;
+        assert_eq!(header.base_fee, None);
+        assert_eq!(header.encode().to_vec(), raw);
+        let hash: H256 =
+            "0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3".parse()?;
+        assert_eq!(header.hash(), hash);
+
+        // each fork's trailing fields survive a round trip
+        let cancun = BlockHeader {
+            base_fee: Some(7.into()),
+            withdrawals_root: Some(H256::from_low_u64_be(1)),
+            blob_gas_used: Some(0x20000),
+            excess_blob_gas: Some(0),
+            parent_beacon_block_root: Some(H256::from_low_u64_be(2)),
+            ..header.clone()
+        };
+        let shanghai = BlockHeader {
+            blob_gas_used: None,
+            excess_blob_gas: None,
+            parent_beacon_block_root: None,
+            ..cancun.clone()
+        };
+        // the fields are appended to the genesis encoding in fork order, and
+        // the hashes were computed with an independent rlp encoder
+        let cases = [
+            (
+                shanghai,
+                "07a00000000000000000000000000000000000000000000000000000000000000001",
+                "0x85d2e68b7e99c261dd97023dd3ecb650686d87b78c6a29a012ce5448ea78ea24",
+            ),
+            (
+                cancun,
+                "07a00000000000000000000000000000000000000000000000000000000000000001\
+                 8302000080a00000000000000000000000000000000000000000000000000000000000000002",
+                "0x049289a6961131b4b17440ad2f9e3ff56f88a6ae7ba4ffdad9b72a71e69ada31",
+            ),
+        ];
+        for (header, tail, hash) in cases {
+            let encoded = header.clone().encode();
+            // both lists are long enough for a two-byte length
+            assert_eq!(encoded[3..], [&raw[3..], &hex::decode(tail)?[..]].concat());
+            assert_eq!(header.hash(), hash.parse::<H256>()?);
+            assert_eq!(BlockHeader::decode(&encoded)?, header);
+        }
+
+        // an AuRa header carries a step and a signature instead of a mix
+        // digest and nonce
+        let mut seal = vec![];
+        fastrlp::Encodable::encode(&7u64, &mut seal);
+        fastrlp::Encodable::encode(&bytes::Bytes::from(vec![0xaa; 65]), &mut seal);
+        let aura = BlockHeader {
+            mix_digest: H256::zero(),
+            nonce: H64::zero(),
+            seal: Some(Rlp(seal.into())),
+            ..header
+        };
+        let raw = aura.clone().encode();
+        assert_eq!(BlockHeader::decode(&raw)?, aura);
+        let mut buf = &raw[..];
+        assert_eq!(
+            BlockHeader::decode_with_format(&mut buf, HeaderFormat::AURA)?,
+            aura
+        );
+        let fields = aura.seal_fields()?.unwrap();
+        assert_eq!(fields[0][..], [7]);
+        assert_eq!(fields[1].len(), 65);
+        Ok(())
+    }
+
+    const GENESIS_HEADER: &str = "f90214a00000000000000000000000000000000000000000000000000000000000000000a01dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347940000000000000000000000000000000000000000a0d7f8974fb5ac78d9ac099b9ad5018bedc2ce0a72dad1827a1709da30580f0544a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421b9010000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000850400000000808213888080a011bbe8db4e347b4e8c937c1c8370e4b5ed33adb3db69cbdb7a38e1e50b1b82faa00000000000000000000000000000000000000000000000000000000000000000880000000000000042";
+
+    #[test]
+    fn test_validate_parent() {
+        let parent = BlockHeader {
+            number: 10.into(),
+            time: 100,
+            gas_limit: 30_000_000,
+            gas_used: 30_000_000,
+            base_fee: Some(800.into()),
+            ..Default::default()
+        };
+        // a full block raises the base fee by 1/8
+        assert_eq!(parent.next_base_fee(), 900.into());
+        let child = BlockHeader {
+            parent_hash: parent.hash(),
+            number: 11.into(),
+            time: 112,
+            gas_limit: 30_000_000,
+            base_fee: Some(900.into()),
+            ..Default::default()
+        };
+        assert!(validate_parent(&child, &parent).is_ok());
+
+        let bad = [
+            BlockHeader {
+                base_fee: Some(800.into()),
+                ..child.clone()
+            },
+            BlockHeader {
+                parent_hash: H256::zero(),
+                ..child.clone()
+            },
+            BlockHeader {
+                gas_limit: 40_000_000,
+                ..child.clone()
+            },
+            BlockHeader {
+                time: 100,
+                ..child.clone()
+            },
+        ];
+        for header in bad {
+            assert!(validate_parent(&header, &parent).is_err());
+        }
+    }
+
+    // A signed-looking tx with the given nonce, for tests that write blocks.
+    fn blob_tx(nonce: u64) -> Transaction {
+        Transaction::Blob(BlobTx {
+            chain_id: 1.into(),
+            nonce,
+            tip: 1.into(),
+            fee_cap: 1.into(),
+            gas: 21000,
+            to: Address::from_low_u64_be(5),
+            value: 0.into(),
+            data: Default::default(),
+            access_list: vec![],
+            blob_fee_cap: 1.into(),
+            blob_versioned_hashes: vec![],
+            v: 1.into(),
+            r: 1.into(),
+            s: 1.into(),
+        })
+    }
+
+    // The sender of every tx in the blocks built by `blocks`.
+    const SENDER: u64 = 6;
+
+    // Blocks with the given headers and txs, all sent by SENDER, and no uncles
+    // or withdrawals.
+    fn blocks(chain: Vec<(BlockHeader, Vec<Transaction>)>) -> Vec<Block> {
+        let sender = Address::from_low_u64_be(SENDER);
+        chain
+            .into_iter()
+            .map(|(header, txs)| Block {
+                header,
+                senders: vec![sender; txs.len()],
+                transactions: txs,
+                uncles: vec![],
+                withdrawals: None,
+            })
+            .collect()
+    }
+
+    // A genesis block and a child holding `txs`, with the roots, gas limit and
+    // timestamps that verify_body and audit check.
+    fn valid_chain(txs: Vec<Transaction>) -> Vec<Block> {
+        use crate::erigon::trie::{compute_tx_root, compute_uncle_hash};
+
+        let genesis = BlockHeader {
+            tx_hash: compute_tx_root(&[]),
+            uncle_hash: compute_uncle_hash(&[]),
+            gas_limit: 30_000_000,
+            ..Default::default()
+        };
+        let child = BlockHeader {
+            parent_hash: genesis.hash(),
+            number: 1.into(),
+            time: 1,
+            tx_hash: compute_tx_root(&txs),
+            ..genesis.clone()
+        };
+        blocks(vec![(genesis, vec![]), (child, txs)])
+    }
+
+    #[test]
+    fn test_rebuild_tx_lookup() -> Result<()> {
+        use crate::erigon::writer::BlockWriter;
+
+        let dir = tempfile::tempdir()?;
+        let db = ErigonDb::<mdbx::RW>::open(dir.path())?;
+        let genesis = BlockHeader::default();
+        let child = BlockHeader {
+            parent_hash: genesis.hash(),
+            number: 1.into(),
+            ..Default::default()
+        };
+        let txs = [blob_tx(0), blob_tx(1)];
+        let hashes = txs.iter().map(Transaction::tx_hash).collect::<Vec<_>>();
+        let blocks = blocks(vec![(genesis, vec![]), (child, txs.to_vec())]);
+        db.update(|tx| {
+            BlockWriter::new(tx).write_chain(blocks)?;
+            for hash in &hashes {
+                tx.delete::<BlockTransactionLookup>(*hash)?;
+            }
+            Ok(())
+        })?;
+
+        let mut reports = vec![];
+        let tick = |t: &Tick<'_>| reports.push((t.done, t.total));
+        assert_eq!(db.rebuild_tx_lookup(0..2, 1, tick)?, 2);
+        assert_eq!(reports, vec![(1, Some(2)), (2, Some(2))]);
+        db.view(|tx| {
+            for hash in &hashes {
+                assert!(tx.read_transaction_by_hash(*hash)?.is_some());
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_block_writer() -> Result<()> {
+        use crate::erigon::writer::BlockWriter;
+
+        let sender = Address::from_low_u64_be(SENDER);
+        let genesis = BlockHeader {
+            difficulty: 2.into(),
+            ..Default::default()
+        };
+        let child = BlockHeader {
+            parent_hash: genesis.hash(),
+            number: 1.into(),
+            difficulty: 3.into(),
+            ..Default::default()
+        };
+        let blocks = blocks(vec![
+            (genesis, vec![blob_tx(0)]),
+            (child, vec![blob_tx(1), blob_tx(2)]),
+        ]);
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let head = BlockWriter::new(&db).write_chain(blocks)?.unwrap();
+        db.commit()?;
+
+        let db = Erigon::begin_mem(&mem)?;
+        assert_eq!(db.read_head_header_hash()?, Some(head.1));
+        assert_eq!(db.read_canonical_hash(1)?, Some(head.1));
+        assert_eq!(db.read_total_difficulty(head)?.unwrap().0, 5.into());
+        assert_eq!(
+            db.read_transactions(head)?.unwrap(),
+            vec![blob_tx(1), blob_tx(2)]
+        );
+        assert_eq!(db.read_senders(head)?.unwrap(), vec![sender; 2]);
+        // the genesis block took ids 0-2, so the child's txs are at 4 and 5
+        assert_eq!(db.read_body_for_storage(head)?.unwrap().base_tx_id, 4);
+        let (key, idx, _) = db.read_transaction_by_hash(blob_tx(2).tx_hash())?.unwrap();
+        assert_eq!((key, idx), (head, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_transactions() -> Result<()> {
+        use crate::erigon::writer::BlockWriter;
+
+        let genesis = BlockHeader::default();
+        let child = BlockHeader {
+            parent_hash: genesis.hash(),
+            number: 1.into(),
+            ..Default::default()
+        };
+        let blocks = blocks(vec![
+            (genesis, vec![blob_tx(0)]),
+            (child, vec![blob_tx(1), blob_tx(2)]),
+        ]);
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        BlockWriter::new(&db).write_chain(blocks)?;
+
+        let streamed = db
+            .stream_transactions(0, 1)?
+            .map(|res| res.map(|(num, idx, tx, _)| (num.0, idx, tx)))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            streamed,
+            vec![(0, 0, blob_tx(0)), (1, 0, blob_tx(1)), (1, 1, blob_tx(2))]
+        );
+
+        // a gap in the block's tx ids is an error, not a skip to the next tx
+        assert!(db.delete::<BlockTransaction>(TxIndex(4))?);
+        let res = db.stream_transactions(1, 1)?.collect::<Result<Vec<_>>>();
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_subset() -> Result<()> {
+        use crate::erigon::{fixtures::extract_subset, writer::BlockWriter};
+
+        let mut parent = H256::zero();
+        let chain = (0..3u64).map(|num| {
+            let header = BlockHeader {
+                parent_hash: parent,
+                number: num.into(),
+                ..Default::default()
+            };
+            parent = header.hash();
+            (header, vec![blob_tx(2 * num), blob_tx(2 * num + 1)])
+        });
+
+        let src_dir = tempfile::tempdir()?;
+        let src_env = erigon::env_open::<mdbx::RW>(src_dir.path())?;
+        let db = Erigon::begin_rw(&src_env)?;
+        BlockWriter::new(&db).write_chain(blocks(chain.collect()))?;
+        db.commit()?;
+
+        let dst_dir = tempfile::tempdir()?;
+        extract_subset(&src_env, dst_dir.path(), 1..3, &[])?;
+        let dst_env = erigon::env_open::<mdbx::RW>(dst_dir.path())?;
+        let dst = Erigon::begin_rw(&dst_env)?;
+        for num in 1..3u64 {
+            let hash = dst.read_canonical_hash(num)?.unwrap();
+            assert_eq!(
+                dst.read_transactions(HeaderKey(num.into(), hash))?.unwrap(),
+                vec![blob_tx(2 * num), blob_tx(2 * num + 1)]
+            );
+        }
+        // the copied blocks hold ids 0-7, so new blocks are written after them
+        assert_eq!(dst.read::<Sequence>(TxSequenceKey)?, Some(TxIndex(8)));
+        Ok(())
+    }
+
+    #[cfg(feature = "rpc")]
+    #[test]
+    fn test_rpc_blocks() -> Result<()> {
+        use crate::erigon::writer::BlockWriter;
+        use crate::rpc::{read_block, resolve, BlockId, BlockTag};
+
+        let sender = Address::from_low_u64_be(SENDER);
+        let genesis = BlockHeader::default();
+        let child = BlockHeader {
+            parent_hash: genesis.hash(),
+            number: 1.into(),
+            ..Default::default()
+        };
+        let genesis_hash = genesis.hash();
+        let blocks = blocks(vec![(genesis, vec![]), (child, vec![blob_tx(0)])]);
+        let dir = tempfile::tempdir()?;
+        let env = erigon::env_open::<mdbx::RW>(dir.path())?;
+        let db = Erigon::begin_rw(&env)?;
+        let head = BlockWriter::new(&db).write_chain(blocks)?.unwrap();
+        db.commit()?;
+        let db = Erigon::<mdbx::RO>::new(env.begin_ro()?);
+
+        let latest = BlockId::Tag(BlockTag::Latest);
+        assert_eq!(resolve(&db, latest)?, Some(head.0));
+        assert_eq!(resolve(&db, BlockId::Number(1.into()))?, Some(head.0));
+        // blocks past the head don't exist yet, rather than being the head
+        assert_eq!(resolve(&db, BlockId::Number(2.into()))?, None);
+        assert!(read_block(&db, BlockId::Number(2.into()), true)?.is_none());
+
+        let block = read_block(&db, latest, true)?.unwrap();
+        let json = serde_json::to_value(&block)?;
+        assert_eq!(json["hash"], serde_json::to_value(head.1)?);
+        assert_eq!(json["number"], "0x1");
+        // header fields come from HeaderJson, with byte strings as hex data
+        assert_eq!(json["extraData"], "0x");
+        assert_eq!(json["parentHash"], serde_json::to_value(genesis_hash)?);
+        assert!(json.get("header").is_none());
+        let tx = &json["transactions"][0];
+        assert_eq!(tx["hash"], serde_json::to_value(blob_tx(0).tx_hash())?);
+        assert_eq!(tx["blockHash"], json["hash"]);
+        assert_eq!(tx["blockNumber"], "0x1");
+        assert_eq!(tx["transactionIndex"], "0x0");
+        assert_eq!(tx["from"], serde_json::to_value(sender)?);
+        assert_eq!(tx["type"], "0x3");
+        assert_eq!(tx["maxFeePerBlobGas"], "0x1");
+        assert_eq!(tx["input"], "0x");
+        // the variant name of the model enum doesn't leak into the output
+        assert!(tx.get("Blob").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_trie_root() {
+        use crate::erigon::{
+            trie::{hex_prefix, trie_root, EMPTY_ROOT},
+            utils::keccak256,
+        };
+
+        assert_eq!(trie_root(Vec::<(H256, Vec<u8>)>::new()), EMPTY_ROOT);
+        assert_eq!(H256(keccak256([0x80])), EMPTY_ROOT);
+        // from ethereum/tests TrieTests/trieanyorder.json, where keys can be
+        // prefixes of each other
+        let root = |pairs: &[(&str, &str)]| {
+            trie_root(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (k.as_bytes(), v.as_bytes().to_vec())),
+            )
+        };
+        let long = "a".repeat(50);
+        assert_eq!(
+            root(&[("A", &long)]),
+            H256(hex_literal::hex!(
+                "d23786fb4a010da3ce639d66d5e904a11dbc02746d1ce25029e53290cabf28ab"
+            ))
+        );
+        assert_eq!(
+            root(&[
+                ("doe", "reindeer"),
+                ("dog", "puppy"),
+                ("dogglesworth", "cat")
+            ]),
+            H256(hex_literal::hex!(
+                "8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"
+            ))
+        );
+        assert_eq!(
+            root(&[
+                ("do", "verb"),
+                ("horse", "stallion"),
+                ("doge", "coin"),
+                ("dog", "puppy")
+            ]),
+            H256(hex_literal::hex!(
+                "5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84"
+            ))
+        );
+        assert_eq!(
+            root(&[("foo", "bar"), ("food", "bass")]),
+            H256(hex_literal::hex!(
+                "17beaa1648bafa633cda809c90c04af50fc8aed3cb40d16efbddee6fdf63c4c3"
+            ))
+        );
+
+        assert_eq!(hex_prefix(&[1, 2, 3, 4, 5], false), vec![0x11, 0x23, 0x45]);
+        assert_eq!(
+            hex_prefix(&[0, 1, 2, 3, 4, 5], false),
+            vec![0x00, 0x01, 0x23, 0x45]
+        );
+        assert_eq!(
+            hex_prefix(&[0, 15, 1, 12, 11, 8], true),
+            vec![0x20, 0x0f, 0x1c, 0xb8]
+        );
+        assert_eq!(
+            hex_prefix(&[15, 1, 12, 11, 8], true),
+            vec![0x3f, 0x1c, 0xb8]
+        );
+    }
+
+    #[test]
+    fn test_trie_state_root() -> Result<()> {
+        use crate::erigon::{
+            trie::{account_leaf, hex_prefix, EMPTY_ROOT},
+            utils::keccak256,
+        };
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        // an account under each of nibbles 1 and 2, and two under 3 that
+        // share the nibble after it as well
+        let key = |first: u8, fill: u8| {
+            let mut key = H256::repeat_byte(fill);
+            key.0[0] = first;
+            key
+        };
+        let (a, b, c, d) = (
+            key(0x11, 0x11),
+            key(0x22, 0x22),
+            key(0x3a, 0x55),
+            key(0x3a, 0x66),
+        );
+        let inc = Incarnation(1);
+        db.write::<HashedAccount>(a, Account::new().nonce(1))?;
+        db.write::<HashedAccount>(b, Account::new().nonce(2))?;
+        db.write::<HashedAccount>(c, Account::new().nonce(3).incarnation(inc))?;
+        db.write::<HashedAccount>(d, Account::new().nonce(4))?;
+        for slot in 1..=3 {
+            let val = (H256::from_low_u64_be(slot), U256::from(slot));
+            db.write::<HashedStorage>(HashedStorageKey(c, inc), val)?;
+        }
+
+        let expected = db.hashed_state_root()?;
+        assert_ne!(expected, EMPTY_ROOT);
+        // with no intermediate hashes, every leaf is read
+        assert_eq!(db.trie_state_root()?, expected);
+        let storage_root = db.hashed_storage_root(c, inc)?;
+        assert_eq!(db.trie_storage_root(c, inc)?, storage_root);
+
+        // the slots share all but their last nibble, so the storage trie is
+        // an extension to the one stored branch
+        let key = TrieStorageKey {
+            hashed_adr: c,
+            incarnation: inc,
+            nibbles: Nibbles(vec![0; 63]),
+        };
+        let node = TrieNode {
+            has_state: 0b1110,
+            ..Default::default()
+        };
+        db.write::<TrieStorage>(key, node)?;
+        assert_eq!(db.trie_storage_root(c, inc)?, storage_root);
+
+        // the root stores the hash of b's leaf, the only node under nibble 2,
+        // and the branch at [3, a] sits below an extension
+        let mut leaf = vec![];
+        let path = hex_prefix(&Nibbles::unpack(b.as_bytes())[1..], true);
+        let val = account_leaf(&Account::new().nonce(2), EMPTY_ROOT);
+        let items = [bytes::Bytes::from(path), bytes::Bytes::from(val)];
+        fastrlp::encode_list::<bytes::Bytes, _>(&items, &mut leaf);
+        let root = |hash| TrieNode {
+            has_state: 0b1110,
+            has_tree: 0b1000,
+            has_hash: 0b0100,
+            root_hash: Some(expected),
+            hashes: vec![hash],
+        };
+        db.write::<TrieAccount>(Nibbles::default(), root(H256(keccak256(leaf))))?;
+        let branch = TrieNode {
+            has_state: 0b0110_0000,
+            ..Default::default()
+        };
+        db.write::<TrieAccount>(Nibbles(vec![3, 0xa]), branch)?;
+        assert_eq!(db.trie_state_root()?, expected);
+        // a stored hash is used in place of the leaves beneath it
+        db.write::<TrieAccount>(Nibbles::default(), root(H256::repeat_byte(1)))?;
+        assert_ne!(db.trie_state_root()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_body() -> Result<()> {
+        use crate::erigon::writer::BlockWriter;
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let blocks = valid_chain(vec![blob_tx(0), blob_tx(1)]);
+        let head = BlockWriter::new(&db).write_chain(blocks)?.unwrap();
+        let genesis = HeaderKey(0.into(), db.read_canonical_hash(0)?.unwrap());
+        db.verify_body(genesis)?;
+        db.verify_body(head)?;
+
+        // a gap in the tx ids is a missing tx, not a root mismatch
+        let mut body = db.read::<BlockBody>(head)?.unwrap();
+        let first = TxIndex(body.base_tx_id);
+        let tx = db.read::<BlockTransaction>(first)?.unwrap();
+        assert!(db.delete::<BlockTransaction>(first)?);
+        assert!(matches!(
+            db.verify_body(head),
+            Err(Error::MissingValue {
+                what: "transactions"
+            })
+        ));
+        db.write::<BlockTransaction>(first, tx)?;
+
+        // a body with a tx missing no longer matches the header
+        body.tx_amount -= 1;
+        db.write::<BlockBody>(head, body)?;
+        assert!(db.verify_body(head).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_reward() -> Result<()> {
+        use crate::erigon::writer::BlockWriter;
+
+        let genesis = BlockHeader {
+            difficulty: 1.into(),
+            ..Default::default()
+        };
+        let uncle = BlockHeader {
+            number: 1.into(),
+            coinbase: Address::from_low_u64_be(2),
+            ..genesis.clone()
+        };
+        let child = |parent: &BlockHeader| BlockHeader {
+            parent_hash: parent.hash(),
+            number: parent.number + 1,
+            coinbase: Address::from_low_u64_be(1),
+            ..parent.clone()
+        };
+        let one = child(&genesis);
+        let two = child(&one);
+        let mut blocks = blocks(vec![(genesis, vec![]), (one, vec![]), (two, vec![])]);
+        blocks[2].uncles = vec![uncle];
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let head = BlockWriter::new(&db).write_chain(blocks)?.unwrap();
+        let genesis = db.read_canonical_hash(0)?.unwrap();
+        db.write::<ChainConfig>(genesis, r#"{"chainId":1,"byzantiumBlock":2}"#.into())?;
+
+        let ether = U256::exp10(18);
+        assert_eq!(db.read_uncles(head)?.len(), 1);
+        let reward = db.block_reward(head)?;
+        assert_eq!(reward.static_reward, ether * 3);
+        assert_eq!(reward.inclusion_reward, ether * 3 / 32);
+        assert_eq!(
+            reward.uncles,
+            vec![(Address::from_low_u64_be(2), ether * 3 * 7 / 8)]
+        );
+        let one = HeaderKey(1.into(), db.read_canonical_hash(1)?.unwrap());
+        assert_eq!(db.block_reward(one)?.total(), ether * 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_withdrawals_for() -> Result<()> {
+        use crate::erigon::writer::BlockWriter;
+
+        let validator = Address::from_low_u64_be(1);
+        let withdrawal = |index, address| Withdrawal {
+            index,
+            validator: index,
+            address,
+            amount: 2,
+        };
+        let genesis = BlockHeader::default();
+        let child = BlockHeader {
+            parent_hash: genesis.hash(),
+            number: 1.into(),
+            ..Default::default()
+        };
+        let mut blocks = blocks(vec![(genesis, vec![]), (child, vec![])]);
+        blocks[1].withdrawals = Some(vec![
+            withdrawal(0, validator),
+            withdrawal(1, Address::zero()),
+        ]);
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let head = BlockWriter::new(&db).write_chain(blocks)?.unwrap();
+        assert_eq!(db.read_withdrawals(head)?.map(|ws| ws.len()), Some(2));
+        let (found, total) = db.withdrawals_for(validator, 0..2)?;
+        assert_eq!(found, vec![(BlockNumber(1), withdrawal(0, validator))]);
+        assert_eq!(total, U256::from(2_000_000_000u64));
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit() -> Result<()> {
+        use crate::erigon::{writer::BlockWriter, AuditCheck, AuditFlags};
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        BlockWriter::new(&db).write_chain(valid_chain(vec![blob_tx(0)]))?;
+        let checks = AuditFlags {
+            senders: false,
+            ..AuditFlags::ALL
+        };
+        let report = db.audit(0..2, checks, NoProgress)?;
+        assert_eq!(report.blocks, 2);
+        assert!(report.is_ok(), "{:?}", report.issues);
+
+        let adr = Address::from_low_u64_be(1);
+        db.write::<AccountChangeSet>(1.into(), AccountCSVal(adr, Account::new()))?;
+        db.delete::<BlockTransactionLookup>(blob_tx(0).tx_hash())?;
+        let found = db
+            .audit(0..3, checks, NoProgress)?
+            .issues
+            .into_iter()
+            .map(|issue| (issue.block.0, issue.check))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (1, AuditCheck::History),
+                (1, AuditCheck::TxLookup),
+                (2, AuditCheck::CanonicalChain),
+            ]
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "secp256k1")]
+    #[test]
+    fn test_verify_senders() -> Result<()> {
+        use crate::erigon::writer::BlockWriter;
+        use secp256k1::{Message, SecretKey, SECP256K1};
+
+        let key = SecretKey::from_slice(&[1; 32])?;
+        let sign = |nonce| -> Result<Transaction> {
+            let mut tx = blob_tx(nonce);
+            let msg = Message::from_slice(tx.hash().as_bytes())?;
+            let (recid, sig) = SECP256K1
+                .sign_ecdsa_recoverable(&msg, &key)
+                .serialize_compact();
+            if let Transaction::Blob(tx) = &mut tx {
+                tx.v = U256::from(recid.to_i32() as u64);
+                tx.r = U256::from_big_endian(&sig[..32]);
+                tx.s = U256::from_big_endian(&sig[32..]);
+            }
+            Ok(tx)
+        };
+        let txs = vec![sign(0)?, sign(1)?];
+        let signer = txs[0].recover_sender()?;
+        let wrong = Address::from_low_u64_be(SENDER);
+        let mut blocks = blocks(vec![(BlockHeader::default(), txs)]);
+        blocks[0].senders[0] = signer;
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        BlockWriter::new(&db).write_chain(blocks)?;
+        let mismatches = db.verify_senders(0..1, 1)?;
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].index, 1);
+        assert_eq!(mismatches[0].stored, Some(wrong));
+        assert_eq!(mismatches[0].recovered, Some(signer));
+        // checking every other tx skips the bad one
+        assert!(db.verify_senders(0..1, 2)?.is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "ethers-types")]
+    #[test]
+    fn test_ethers_roundtrip() {
+        let legacy = Transaction::Legacy(LegacyTx {
+            nonce: 1,
+            gas_price: 2.into(),
+            gas: 21000,
+            to: TxAction::Create,
+            value: 3.into(),
+            data: vec![4].into(),
+            // eip155 on mainnet, parity 0
+            v: VPackChainId(37.into()),
+            r: 5.into(),
+            s: 6.into(),
+        });
+        let dynamic_fee = Transaction::DynamicFee(DynamicFeeTx {
+            chain_id: 1.into(),
+            nonce: 1,
+            tip: 2.into(),
+            fee_cap: 3.into(),
+            gas: 21000,
+            to: TxAction::Call(Address::from_low_u64_be(4)),
+            value: 5.into(),
+            data: Default::default(),
+            access_list: vec![AccessTuple {
+                address: Address::from_low_u64_be(6),
+                slots: vec![H256::from_low_u64_be(7)],
+            }],
+            v: 1.into(),
+            r: 8.into(),
+            s: 9.into(),
+        });
+        let blob = match blob_tx(0) {
+            Transaction::Blob(tx) => Transaction::Blob(BlobTx {
+                blob_versioned_hashes: vec![H256::repeat_byte(1)],
+                ..tx
+            }),
+            _ => unreachable!(),
+        };
+        for msg in [legacy, dynamic_fee, blob] {
+            let signer = Address::from_low_u64_be(0xaa);
+            let tx = ethers::types::Transaction::from(TransactionWithSigner {
+                msg: msg.clone(),
+                signer,
+            });
+            assert_eq!(tx.from, signer);
+            assert_eq!(Transaction::from(&tx), msg);
+        }
+
+        let header = BlockHeader {
+            number: 5.into(),
+            gas_limit: 30_000_000,
+            time: 6,
+            extra: vec![7].into(),
+            base_fee: Some(8.into()),
+            withdrawals_root: Some(H256::from_low_u64_be(9)),
+            blob_gas_used: Some(0x20000),
+            excess_blob_gas: Some(0),
+            parent_beacon_block_root: Some(H256::from_low_u64_be(10)),
+            ..Default::default()
+        };
+        let block = ethers::types::Block::<H256>::from(&header);
+        assert_eq!(block.hash, Some(header.hash()));
+        assert_eq!(BlockHeader::from(&block), header);
+
+        let log = CborLog {
+            address: Address::from_low_u64_be(1),
+            topics: vec![H256::from_low_u64_be(2)],
+            data: vec![3].into(),
+        };
+        assert_eq!(CborLog::from(&ethers::types::Log::from(&log)), log);
+    }
+
+    #[cfg(feature = "alloy")]
+    #[test]
+    fn test_alloy_roundtrip() {
+        use crate::erigon::models::{FromAlloy, ToAlloy};
+
+        let n = U256::MAX - 5;
+        assert_eq!(
+            n.to_alloy(),
+            alloy_primitives::U256::MAX - alloy_primitives::U256::from(5)
+        );
+        assert_eq!(U256::from_alloy(n.to_alloy()), n);
+        let log = CborLog {
+            address: Address::from_low_u64_be(1),
+            topics: vec![H256::from_low_u64_be(2)],
+            data: vec![3].into(),
+        };
+        let alloy = log.to_alloy();
+        assert_eq!(alloy.address.0 .0, log.address.0);
+        assert_eq!(CborLog::from_alloy(alloy), log);
+    }
+
+    #[test]
+    #[cfg(feature = "alloy")]
+    fn test_alloy_tables() -> Result<()> {
+        use crate::erigon::models::ToAlloy;
+        crate::table!(AlloyCanonical => BlockNumber => alloy_primitives::B256, rename = CanonicalHeader);
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let hash = H256::from_low_u64_be(0xabcd);
+        db.write::<CanonicalHeader>(7.into(), hash)?;
+        assert_eq!(db.read::<AlloyCanonical>(7.into())?, Some(hash.to_alloy()));
+        db.write::<AlloyCanonical>(8.into(), hash.to_alloy())?;
+        assert_eq!(db.read::<CanonicalHeader>(8.into())?, Some(hash));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "evm")]
+    fn test_call() -> Result<()> {
+        use crate::erigon::evm::CallRequest;
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        let header = BlockHeader {
+            gas_limit: 30_000_000,
+            base_fee: Some(7.into()),
+            ..Default::default()
+        };
+        let genesis = header.hash();
+        db.write::<CanonicalHeader>(0.into(), genesis)?;
+        db.write_header(HeaderKey(0.into(), genesis), header)?;
+        db.write::<ChainConfig>(genesis, r#"{"chainId":1,"londonBlock":0}"#.into())?;
+
+        // PUSH1 0x2a PUSH1 0 MSTORE PUSH1 0x20 PUSH1 0 RETURN
+        let code = Bytecode::from(bytes::Bytes::from(
+            hex_literal::hex!("602a60005260206000f3").to_vec(),
+        ));
+        let (adr, codehash) = (Address::from_low_u64_be(0xc0de), H256::repeat_byte(1));
+        db.write_account(adr, Account::new().codehash(codehash).incarnation(1.into()))?;
+        db.write::<Code>(codehash, code)?;
+
+        let req = CallRequest {
+            to: Some(adr),
+            ..Default::default()
+        };
+        let res = db.call(0, req)?;
+        assert!(res.success);
+        assert_eq!(U256::from_big_endian(&res.output), 42.into());
+        Ok(())
+    }
+
+    #[cfg(feature = "evm")]
+    const COUNTER: u64 = 5;
+
+    // A tx from SENDER to COUNTER, a contract that increments slot 0.
+    #[cfg(feature = "evm")]
+    fn counter_tx(nonce: u64) -> Transaction {
+        use crate::erigon::models::transaction::{DynamicFeeTx, TxAction};
+        Transaction::DynamicFee(DynamicFeeTx {
+            chain_id: 1.into(),
+            nonce,
+            tip: 0.into(),
+            fee_cap: 1.into(),
+            gas: 100_000,
+            to: TxAction::Call(Address::from_low_u64_be(COUNTER)),
+            value: 0.into(),
+            data: Default::default(),
+            access_list: vec![],
+            v: 0.into(),
+            r: 1.into(),
+            s: 1.into(),
+        })
+    }
+
+    // Writes a genesis block and a child with header `child` holding two
+    // counter txs, with the state before the child.
+    #[cfg(feature = "evm")]
+    fn counter_chain(child: BlockHeader) -> Result<MemDb> {
+        use crate::erigon::writer::BlockWriter;
+
+        let sender = Address::from_low_u64_be(SENDER);
+        let genesis = BlockHeader::default();
+        let child = BlockHeader {
+            parent_hash: genesis.hash(),
+            number: 1.into(),
+            difficulty: 1.into(),
+            gas_limit: 30_000_000,
+            base_fee: Some(1.into()),
+            ..child
+        };
+        let txs = vec![counter_tx(0), counter_tx(1)];
+        let blocks = blocks(vec![(genesis.clone(), vec![]), (child, txs)]);
+
+        let mem = MemDb::new();
+        let db = Erigon::begin_mem_rw(&mem)?;
+        BlockWriter::new(&db).write_chain(blocks)?;
+        db.write::<ChainConfig>(genesis.hash(), r#"{"chainId":1,"londonBlock":0}"#.into())?;
+        // PUSH1 1 PUSH1 0 SLOAD ADD PUSH1 0 SSTORE STOP
+        let code = Bytecode::from(bytes::Bytes::from(
+            hex_literal::hex!("60016000540160005500").to_vec(),
+        ));
+        let codehash = H256::repeat_byte(1);
+        db.write::<Code>(codehash, code)?;
+        let counter = Account::new().codehash(codehash).incarnation(1.into());
+        db.write_account(Address::from_low_u64_be(COUNTER), counter)?;
+        db.write_account(sender, Account::new().balance(U256::exp10(18)))?;
+        db.commit()?;
+        Ok(mem)
+    }
+
+    #[test]
+    #[cfg(feature = "evm")]
+    fn test_trace_transaction() -> Result<()> {
+        use crate::erigon::tracer::{Trace, TracerKind};
+
+        let mem = counter_chain(BlockHeader::default())?;
+        let db = Erigon::begin_mem(&mem)?;
+        let (sender, counter) = (
+            Address::from_low_u64_be(SENDER),
+            Address::from_low_u64_be(COUNTER),
+        );
+
+        // the first tx in the block bumped the counter before the second ran
+        let trace = db.trace_transaction(counter_tx(1).tx_hash(), TracerKind::Prestate)?;
+        let prestate = match trace {
+            Some(Trace::Prestate(prestate)) => prestate,
+            other => panic!("unexpected trace: {:?}", other),
+        };
+        assert_eq!(prestate[&sender].nonce, 1);
+        assert_eq!(
+            prestate[&counter].storage[&H256::zero()],
+            H256::from_low_u64_be(1)
+        );
+
+        let trace = db.trace_transaction(counter_tx(0).tx_hash(), TracerKind::CallTracer)?;
+        let frame = match trace {
+            Some(Trace::Call(frame)) => frame,
+            other => panic!("unexpected trace: {:?}", other),
+        };
+        assert_eq!(
+            (frame.kind, frame.from, frame.to),
+            ("CALL", sender, Some(counter))
+        );
+        assert!(frame.error.is_none() && frame.calls.is_empty());
+        assert!(db
+            .trace_transaction(H256::zero(), TracerKind::CallTracer)?
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "evm")]
+    fn test_verify_block_execution() -> Result<()> {
+        use crate::erigon::trie::{ordered_trie_root, receipt_leaf};
+
+        let mem = counter_chain(BlockHeader::default())?;
+        let receipts = Erigon::begin_mem(&mem)?.execute_block(1)?;
+        assert_eq!(receipts.len(), 2);
+        assert!(receipts
+            .iter()
+            .all(|(r, logs)| r.status == 1 && logs.is_empty()));
+        assert!(receipts[1].0.cumulative_gas_used > receipts[0].0.cumulative_gas_used);
+        assert!(Erigon::begin_mem(&mem)?.verify_block_execution(1).is_err());
+
+        let child = BlockHeader {
+            gas_used: receipts[1].0.cumulative_gas_used,
+            receipts_hash: ordered_trie_root(receipts.iter().map(|(r, l)| receipt_leaf(r, l))),
+            ..Default::default()
+        };
+        let mem = counter_chain(child)?;
+        Erigon::begin_mem(&mem)?.verify_block_execution(1)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_views() -> Result<()> {
+        use crate::erigon::json::{HeaderJson, TransactionJson};
+
+        let header = BlockHeader {
+            number: 16.into(),
+            gas_limit: 30_000_000,
+            extra: vec![0xab, 0x01].into(),
+            base_fee: Some(7.into()),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(HeaderJson::from(&header))?;
+        assert_eq!(json["number"], "0x10");
+        assert_eq!(json["gasLimit"], "0x1c9c380");
+        assert_eq!(json["extraData"], "0xab01");
+        assert_eq!(json["baseFeePerGas"], "0x7");
+        assert_eq!(json["hash"], serde_json::to_value(header.hash())?);
+        assert!(json.get("withdrawalsRoot").is_none());
+
+        let tx = blob_tx(2);
+        let json = serde_json::to_value(TransactionJson::from(&tx))?;
+        assert_eq!(json["type"], "0x3");
+        assert_eq!(json["nonce"], "0x2");
+        assert_eq!(json["input"], "0x");
+        assert_eq!(
+            json["to"],
+            serde_json::to_value(Address::from_low_u64_be(5))?
+        );
+        assert!(json.get("gasPrice").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_blob_tx() -> Result<()> {
+        use crate::kv::traits::TableDecode;
+        use fastrlp::Encodable;
+
+        let tx = BlobTx {
+            chain_id: 1.into(),
+            nonce: 2,
+            tip: 3.into(),
+            fee_cap: 4.into(),
+            gas: 21000,
+            to: Address::from_low_u64_be(5),
+            value: 6.into(),
+            data: Default::default(),
+            access_list: vec![],
+            blob_fee_cap: 7.into(),
+            blob_versioned_hashes: vec![H256::from_low_u64_be(8)],
+            v: 1.into(),
+            r: 9.into(),
+            s: 10.into(),
+        };
+        let typed = |payload: &[u8]| {
+            let mut out = vec![];
+            fastrlp::Header {
+                list: false,
+                payload_length: payload.len() + 1,
+            }
+            .encode(&mut out);
+            out.push(BlobTx::TYPE);
+            out.extend_from_slice(payload);
+            out
+        };
+        let mut canonical = vec![];
+        tx.encode(&mut canonical);
+        // rlp([tx_payload_body, blobs, commitments, proofs]), with no blobs
+        let mut wrapped = vec![];
+        fastrlp::Header {
+            list: true,
+            payload_length: canonical.len() + 3,
+        }
+        .encode(&mut wrapped);
+        wrapped.extend_from_slice(&canonical);
+        wrapped.extend_from_slice(&[fastrlp::EMPTY_LIST_CODE; 3]);
+
+        for raw in [canonical, wrapped] {
+            let decoded = Transaction::decode(&typed(&raw))?;
+            assert_eq!(decoded, Transaction::Blob(tx.clone()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_tx_roundtrip() -> Result<()> {
+        use crate::kv::traits::{TableDecode, TableEncode};
+
+        let access_list = vec![AccessTuple {
+            address: Address::from_low_u64_be(1),
+            slots: vec![H256::from_low_u64_be(2)],
+        }];
+        let txs = [
+            Transaction::Legacy(LegacyTx {
+                nonce: 1,
+                gas_price: 2.into(),
+                gas: 21000,
+                to: TxAction::Call(Address::from_low_u64_be(3)),
+                value: 4.into(),
+                data: vec![5, 6].into(),
+                v: U256::from(37).into(),
+                r: 7.into(),
+                s: 8.into(),
+            }),
+            Transaction::AccessList(AccessListTx {
+                chain_id: 1.into(),
+                nonce: 2,
+                gas_price: 3.into(),
+                gas: 21000,
+                to: TxAction::Create,
+                value: 4.into(),
+                data: vec![5].into(),
+                access_list: access_list.clone(),
+                v: 1.into(),
+                r: 6.into(),
+                s: 7.into(),
+            }),
+            Transaction::DynamicFee(DynamicFeeTx {
+                chain_id: 1.into(),
+                nonce: 3,
+                tip: 2.into(),
+                fee_cap: 3.into(),
+                gas: 21000,
+                to: TxAction::Call(Address::from_low_u64_be(4)),
+                value: 5.into(),
+                data: Default::default(),
+                access_list,
+                v: 0.into(),
+                r: 6.into(),
+                s: 7.into(),
+            }),
+            blob_tx(4),
+        ];
+        for tx in txs {
+            let encoded = TableEncode::encode(tx.clone());
+            assert_eq!(encoded.len(), fastrlp::Encodable::length(&tx));
+            if let Some(ty) = tx.tx_type() {
+                // typed txs are an rlp string of TransactionType || TransactionPayload
+                let head = fastrlp::Header::decode(&mut &encoded[..])?;
+                assert!(!head.list);
+                assert_eq!(encoded[encoded.len() - head.payload_length], ty);
+            }
+            assert_eq!(Transaction::decode(&encoded)?, tx);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_domain_files() {
+        use crate::snapshots::domains::{Domain, DomainFile, FileKind};
+
+        let file = DomainFile::parse(Path::new("snapshots/domain/v1-accounts.0-32.kv")).unwrap();
+        assert_eq!(file.version, 1);
+        assert_eq!(file.domain, Domain::Accounts);
+        assert_eq!(file.kind, FileKind::Values);
+        assert_eq!(file.steps, 0..32);
+        // index files are skipped
+        assert!(DomainFile::parse(Path::new("v1-storage.0-32.kvi")).is_none());
+        assert!(DomainFile::parse(Path::new("v1-headers.0-500.seg")).is_none());
+    }
+
+    #[test]
+    fn test_domain_reads() -> Result<()> {
+        use crate::snapshots::domains::{decode_account, decode_storage, Domain, Domains};
+
+        let fixtures = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/domains"
+        ));
+        let domains = Domains::open(fixtures)?;
+        assert_eq!(domains.frozen_steps(Domain::Accounts), 1);
+        assert_eq!(domains.frozen_steps(Domain::Code), 0);
+        let accounts = domains.reader(Domain::Accounts)?;
+        let (a, b) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xbb));
+
+        let latest = |adr: Address| -> Result<Option<Account>> {
+            match accounts.latest(adr.as_bytes())? {
+                Some(val) => decode_account(&val),
+                None => Ok(None),
+            }
+        };
+        assert_eq!(latest(a)?, Some(Account::new().nonce(2).balance(3.into())));
+        let contract = Account {
+            nonce: 1,
+            incarnation: 1.into(),
+            balance: 0x10.into(),
+            codehash: H256::repeat_byte(0xcc),
+        };
+        assert_eq!(latest(b)?, Some(contract));
+        assert_eq!(latest(Address::repeat_byte(0xdd))?, None);
+
+        // a changed at txnums 5 and 9, and didn't exist before the first
+        assert_eq!(accounts.before(a.as_bytes(), 0)?, Some(vec![]));
+        assert_eq!(accounts.before(a.as_bytes(), 5)?, Some(vec![]));
+        let val = accounts.before(a.as_bytes(), 6)?.unwrap();
+        assert_eq!(
+            decode_account(&val)?,
+            Some(Account::new().nonce(1).balance(3.into()))
+        );
+        assert_eq!(accounts.before(a.as_bytes(), 10)?, None);
+        assert_eq!(accounts.before(b.as_bytes(), 8)?, None);
+
+        let storage = domains.reader(Domain::Storage)?;
+        let key = [a.as_bytes(), H256::from_low_u64_be(2).as_bytes()].concat();
+        assert_eq!(
+            decode_storage(&storage.before(&key, 4)?.unwrap())?,
+            0x100.into()
+        );
+        assert_eq!(decode_storage(&storage.latest(&key)?.unwrap())?, 2.into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress() -> Result<()> {
+        use crate::snapshots::decompress::Decompressor;
+
+        let path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/words.seg"
+        ));
+        let d = Decompressor::open(path)?;
+        let expected: Vec<&[u8]> = vec![
+            b"",
+            b"erigon",
+            b"an erigon snapshot",
+            &[0, 0, 0, 0, 1, 0, 0, 0, 0],
+            b"no patterns here",
+            b"snapshotsnapshot",
+            b"",
+        ];
+        assert_eq!(d.words_count(), expected.len() as u64);
+        assert_eq!(d.empty_words_count(), 2);
+        assert_eq!(d.words().collect::<Result<Vec<_>>>()?, expected);
+
+        // skipping lands on the same offsets as decoding
+        let mut getter = d.getter();
+        getter.skip_word()?;
+        getter.skip_word()?;
+        getter.skip_word()?;
+        assert_eq!(getter.next_word()?, expected[3]);
+        getter.reset(13);
+        assert_eq!(getter.next_word()?, expected[4]);
+
+        // a pattern dictionary out of canonical order, or deeper than a u64
+        // code, is an error rather than endless recursion
+        let dir = tempfile::tempdir()?;
+        for dict in [&[2, 1, b'a', 1, 1, b'b'][..], &[100, 1, b'a'][..]] {
+            let mut seg = [0u64.to_be_bytes(), 0u64.to_be_bytes()].concat();
+            seg.extend((dict.len() as u64).to_be_bytes());
+            seg.extend(dict);
+            seg.extend(0u64.to_be_bytes());
+            let path = dir.path().join("bad.seg");
+            std::fs::write(&path, seg)?;
+            assert!(Decompressor::open(&path).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_lookup() -> Result<()> {
+        use crate::snapshots::index::{murmur3_x64_128, Index};
+
+        assert_eq!(
+            murmur3_x64_128(b"hello", 0),
+            (0xcbd8a7b341bd9b02, 0x5b1e906a48ae1d19)
+        );
+        // a single bucket of six keys, each mapped to 100 + its number
+        let path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/keys.idx"
+        ));
+        let idx = Index::open(path)?;
+        assert_eq!(idx.key_count(), 6);
+        for i in 0..6 {
+            let key = format!("key-{}", i);
+            assert_eq!(idx.lookup(key.as_bytes())?, Some(100 + i));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_snapshots() -> eyre::Result<()> {
+        use crate::snapshots::BlockSnapshots;
+
+        // mainnet blocks 0 and 1, with block 1 given the first mainnet tx
+        let fixtures = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/snapshots"
+        ));
+        let snapshots = BlockSnapshots::open(fixtures)?;
+        assert_eq!(snapshots.frozen_blocks(), 1000);
+
+        let genesis = snapshots.read_header(0)?.unwrap();
+        assert_eq!(
+            genesis.hash(),
+            H256(hex_literal::hex!(
+                "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"
+            ))
+        );
+        let header = snapshots.read_header(1)?.unwrap();
+        assert_eq!(
+            header.hash(),
+            H256(hex_literal::hex!(
+                "88e96d4537bea4d9c05d12549907b32561d3bf31f45aae734cdc119f13406cb6"
+            ))
+        );
+        assert_eq!(header.parent_hash, genesis.hash());
+        assert_eq!(snapshots.read_header(1000)?, None);
+
+        assert_eq!(snapshots.read_body_for_storage(0)?.unwrap().tx_amount, 0);
+        assert_eq!(snapshots.read_transactions(0)?, Some(vec![]));
+        let body = snapshots.read_body_for_storage(1)?.unwrap();
+        assert_eq!((body.base_tx_id, body.tx_amount), (3, 1));
+        let txs = snapshots.read_transactions(1)?.unwrap();
+        assert_eq!(txs.len(), 1);
+        assert_eq!(
+            txs[0].hash(),
+            H256(hex_literal::hex!(
+                "5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060"
+            ))
+        );
+        let sender: Address = "0xa1e4380a3b1f749673e270229993ee55f35663b4".parse()?;
+        assert_eq!(snapshots.read_senders(1)?, Some(vec![sender]));
+
+        // a merged segment is preferred over the narrower one it replaces
+        let dir = tempfile::tempdir()?;
+        for entry in std::fs::read_dir(fixtures)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            std::fs::copy(&path, dir.path().join(name))?;
+            std::fs::copy(&path, dir.path().join(name.replace("000001", "000002")))?;
+        }
+        let snapshots = BlockSnapshots::open(dir.path())?;
+        assert_eq!(snapshots.segments().len(), 1);
+        assert_eq!(snapshots.frozen_blocks(), 2000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tiered_reads() -> Result<()> {
+        use crate::{snapshots::BlockSnapshots, TieredErigon};
+
+        let fixtures = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/snapshots"
+        ));
+        let snapshots = BlockSnapshots::open(fixtures)?;
+        let genesis = snapshots.read_header(0)?.unwrap();
+        let frozen = snapshots.read_header(1)?.unwrap();
+        // a block past the frozen boundary, and a non-canonical sibling of a
+        // frozen block, which only mdbx holds
+        let live = BlockHeader {
+            number: 1000.into(),
+            ..frozen.clone()
+        };
+        let sibling = BlockHeader {
+            extra: bytes::Bytes::from_static(b"sibling"),
+            ..frozen.clone()
+        };
+
+        let dir = tempfile::tempdir()?;
+        {
+            let env = erigon::env_open::<mdbx::RW>(dir.path())?;
+            let db = Erigon::begin_rw(&env)?;
+            db.write::<Header>(HeaderKey(1000.into(), live.hash()), live.clone())?;
+            db.write::<CanonicalHeader>(1000.into(), live.hash())?;
+            db.write::<Header>(HeaderKey(1.into(), sibling.hash()), sibling.clone())?;
+            db.commit()?;
+        }
+        let tiered = TieredErigon::new(erigon::env_open(dir.path())?, snapshots);
+        let tx = tiered.begin()?;
+        assert!(tx.is_frozen(999) && !tx.is_frozen(1000));
+
+        assert_eq!(tx.read_canonical_hash(1)?, Some(frozen.hash()));
+        assert_eq!(tx.read_canonical_hash(1000)?, Some(live.hash()));
+        let key = HeaderKey(1.into(), frozen.hash());
+        assert_eq!(tx.read_header(key)?, Some(frozen));
+        assert_eq!(
+            tx.read_header(HeaderKey(1.into(), sibling.hash()))?,
+            Some(sibling)
+        );
+        assert_eq!(
+            tx.read_header(HeaderKey(1000.into(), live.hash()))?,
+            Some(live)
+        );
+        assert_eq!(tx.read_transactions(key)?.unwrap().len(), 1);
+        assert_eq!(
+            tx.read_senders(HeaderKey(0.into(), genesis.hash()))?,
+            Some(vec![])
+        );
+        // mdbx on its own doesn't have the frozen block
+        assert_eq!(tx.mdbx().read_header(key)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tiered_history() -> Result<()> {
+        use crate::{snapshots::domains::Domains, TieredErigon};
+
+        let fixtures = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/domains"
+        ));
+        let a = Address::repeat_byte(0xaa);
+        let slot = H256::from_low_u64_be(2);
+        let dir = tempfile::tempdir()?;
+        {
+            // the history reads fall through to the tables of mdbx
+            let env = Erigon::init_database(dir.path())?;
+            let db = Erigon::begin_rw(&env)?;
+            // blocks 1 and 2 start at txnums 4 and 9
+            for (block, max) in [(0, 3), (1, 8), (2, 12)] {
+                db.write::<MaxTxNum>(block.into(), max.into())?;
+            }
+            db.write_account(a, Account::new().nonce(2).balance(3.into()))?;
+            db.write::<Storage>(StorageKey(a, 1.into()), (slot, 2.into()))?;
+            db.commit()?;
+        }
+        let tiered = TieredErigon::new(erigon::env_open(dir.path())?, Default::default())
+            .with_domains(&Domains::open(fixtures)?)?;
+        let tx = tiered.begin()?;
+
+        // the files record changes to a at txnums 5 and 9, and mdbx holds the
+        // state after them
+        assert_eq!(tx.account_at(a, 1)?, None);
+        assert_eq!(
+            tx.account_at(a, 2)?,
+            Some(Account::new().nonce(1).balance(3.into()))
+        );
+        assert_eq!(
+            tx.account_at(a, 3)?,
+            Some(Account::new().nonce(2).balance(3.into()))
+        );
+        assert_eq!(tx.storage_at(a, 1, slot, 1)?, 0x100.into());
+        assert_eq!(tx.storage_at(a, 1, slot, 3)?, 2.into());
+        Ok(())
+    }
+
     #[test]
     fn test_live() -> eyre::Result<()> {
         let path = Path::new(env!("ERIGON_CHAINDATA"));
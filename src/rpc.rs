@@ -0,0 +1,455 @@
+//! A read-only JSON-RPC server over an Erigon database.
+//!
+//! Serves a subset of the `eth` namespace directly from the database, without
+//! going through Erigon's rpcdaemon. Historical state is read through the
+//! history and changeset tables, so queries against past blocks have archive
+//! semantics as long as Erigon has not pruned them.
+use crate::{
+    erigon::{
+        json::{HeaderJson, LogJson, TransactionJson},
+        models::*,
+        Erigon, ErigonDb,
+    },
+    error::bail,
+    r#async::AsyncErigon,
+    Error, Result,
+};
+use bytes::Bytes;
+use ethereum_types::{Address, H256, U256, U64};
+use eyre::eyre;
+use jsonrpsee::{
+    core::{async_trait, Error as RpcError, RpcResult},
+    http_server::{HttpServerBuilder, HttpServerHandle},
+    proc_macros::rpc,
+};
+use mdbx::RO;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+/// The maximum number of blocks a single `eth_getLogs` call may scan.
+pub const MAX_LOG_BLOCK_RANGE: u64 = 10_000;
+
+/// A block number or one of the named block tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BlockId {
+    Tag(BlockTag),
+    Number(U64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockTag {
+    Earliest,
+    Latest,
+    Pending,
+}
+
+impl Default for BlockId {
+    fn default() -> Self {
+        Self::Tag(BlockTag::Latest)
+    }
+}
+
+/// A single value or a list of values, as accepted by the `address` and
+/// `topics` fields of a log filter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ValueOrArray<T> {
+    Value(T),
+    Array(Vec<T>),
+}
+
+impl<T: PartialEq> ValueOrArray<T> {
+    fn matches(&self, v: &T) -> bool {
+        match self {
+            Self::Value(x) => x == v,
+            Self::Array(xs) => xs.is_empty() || xs.contains(v),
+        }
+    }
+}
+
+impl<T: Clone> ValueOrArray<T> {
+    // Returns the values to match against. An empty array matches any value,
+    // and is returned as is.
+    fn to_vec(&self) -> Vec<T> {
+        match self {
+            Self::Value(x) => vec![x.clone()],
+            Self::Array(xs) => xs.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFilter {
+    pub from_block: Option<BlockId>,
+    pub to_block: Option<BlockId>,
+    pub block_hash: Option<H256>,
+    pub address: Option<ValueOrArray<Address>>,
+    #[serde(default)]
+    pub topics: Vec<Option<ValueOrArray<H256>>>,
+}
+
+impl LogFilter {
+    fn matches(&self, log: &CborLog) -> bool {
+        if let Some(address) = &self.address {
+            if !address.matches(&log.address) {
+                return false;
+            }
+        }
+        self.topics
+            .iter()
+            .enumerate()
+            .all(|(i, topic)| match topic {
+                Some(topic) => log.topics.get(i).map_or(false, |t| topic.matches(t)),
+                None => true,
+            })
+    }
+}
+
+/// A log as returned by `eth_getLogs`: the [`LogJson`] view of the log plus
+/// the fields locating it in the chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcLog {
+    #[serde(flatten)]
+    pub log: LogJson,
+    pub block_hash: H256,
+    pub block_number: U64,
+    pub transaction_hash: H256,
+    pub transaction_index: U64,
+    pub log_index: U64,
+    pub removed: bool,
+}
+
+/// A transaction as returned by `eth_getTransactionByHash`: the
+/// [`TransactionJson`] view of the transaction plus the fields locating it in
+/// its block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcTransaction {
+    pub block_hash: H256,
+    pub block_number: U64,
+    pub transaction_index: U64,
+    #[serde(flatten)]
+    pub tx: TransactionJson,
+}
+
+impl RpcTransaction {
+    fn new(key: HeaderKey, idx: usize, tx: &Transaction, from: Option<Address>) -> Self {
+        Self {
+            block_hash: key.1,
+            block_number: key.0 .0.into(),
+            transaction_index: U64::from(idx),
+            tx: TransactionJson {
+                from,
+                ..TransactionJson::from(tx)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum BlockTransactions {
+    Hashes(Vec<H256>),
+    Full(Vec<RpcTransaction>),
+}
+
+/// A block as returned by `eth_getBlockByNumber`: the [`HeaderJson`] view of
+/// its header plus the body fields.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBlock {
+    #[serde(flatten)]
+    pub header: HeaderJson,
+    pub total_difficulty: Option<U256>,
+    pub transactions: BlockTransactions,
+    pub uncles: Vec<H256>,
+}
+
+#[rpc(server, namespace = "eth")]
+pub trait EthApi {
+    #[method(name = "blockNumber")]
+    async fn block_number(&self) -> RpcResult<U64>;
+
+    #[method(name = "getBalance")]
+    async fn get_balance(&self, address: Address, block: Option<BlockId>) -> RpcResult<U256>;
+
+    #[method(name = "getTransactionCount")]
+    async fn get_transaction_count(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> RpcResult<U64>;
+
+    #[method(name = "getStorageAt")]
+    async fn get_storage_at(
+        &self,
+        address: Address,
+        slot: U256,
+        block: Option<BlockId>,
+    ) -> RpcResult<H256>;
+
+    #[method(name = "getCode")]
+    async fn get_code(&self, address: Address, block: Option<BlockId>) -> RpcResult<Bytes>;
+
+    #[method(name = "getBlockByNumber")]
+    async fn get_block_by_number(&self, block: BlockId, full: bool) -> RpcResult<Option<RpcBlock>>;
+
+    #[method(name = "getTransactionByHash")]
+    async fn get_transaction_by_hash(&self, hash: H256) -> RpcResult<Option<RpcTransaction>>;
+
+    #[method(name = "getLogs")]
+    async fn get_logs(&self, filter: LogFilter) -> RpcResult<Vec<RpcLog>>;
+}
+
+/// Implements the `eth` namespace over an [`AsyncErigon`] handle.
+#[derive(Debug, Clone)]
+pub struct EthRpc {
+    db: AsyncErigon<RO>,
+}
+
+impl EthRpc {
+    pub fn new(db: impl Into<AsyncErigon<RO>>) -> Self {
+        Self { db: db.into() }
+    }
+
+    async fn view<T, F>(&self, f: F) -> RpcResult<T>
+    where
+        F: FnOnce(&Erigon<'_, RO>) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.db.view(f).await.map_err(internal)
+    }
+}
+
+fn internal(e: Error) -> RpcError {
+    RpcError::Custom(e.to_string())
+}
+
+/// Resolves a block id to a concrete block number, or `None` if the block is
+/// past the head block.
+pub(crate) fn resolve(db: &Erigon<'_, RO>, id: BlockId) -> Result<Option<BlockNumber>> {
+    let head = db.read_head_block_number()?.unwrap_or_default();
+    Ok(match id {
+        BlockId::Tag(BlockTag::Earliest) => Some(BlockNumber(0)),
+        BlockId::Tag(BlockTag::Latest | BlockTag::Pending) => Some(head),
+        BlockId::Number(n) if n.as_u64() > *head => None,
+        BlockId::Number(n) => Some(BlockNumber(n.as_u64())),
+    })
+}
+
+// Like resolve, but errors on blocks past the head, for methods that can't
+// return null.
+fn resolve_state(db: &Erigon<'_, RO>, id: Option<BlockId>) -> Result<BlockNumber> {
+    let id = id.unwrap_or_default();
+    resolve(db, id)?.ok_or_else(|| eyre!("unknown block {:?}", id).into())
+}
+
+/// Returns the account as it was after executing the given block. History is
+/// keyed by the state *before* a block executes, so this reads at `block + 1`.
+fn account_after(db: &Erigon<'_, RO>, adr: Address, id: Option<BlockId>) -> Result<Account> {
+    let head = db.read_head_block_number()?.unwrap_or_default();
+    let num = resolve_state(db, id)?;
+    let acct = if num >= head {
+        db.read_account(adr)?
+    } else {
+        db.account_at(adr, *num + 1)?
+    };
+    Ok(acct.unwrap_or_default())
+}
+
+pub(crate) fn read_block(db: &Erigon<'_, RO>, id: BlockId, full: bool) -> Result<Option<RpcBlock>> {
+    let num = match resolve(db, id)? {
+        Some(num) => num,
+        None => return Ok(None),
+    };
+    let hash = match db.read_canonical_hash(num)? {
+        Some(hash) => hash,
+        None => return Ok(None),
+    };
+    let key = HeaderKey(num, hash);
+    let header = match db.read_header(key)? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let uncles = db
+        .read_body_for_storage(key)?
+        .map(|body| body.uncles.iter().map(BlockHeader::hash).collect())
+        .unwrap_or_default();
+    let txs = db.read_transactions(key)?.unwrap_or_default();
+    let transactions = if full {
+        let senders = db.read_senders(key)?.unwrap_or_default();
+        BlockTransactions::Full(
+            txs.iter()
+                .enumerate()
+                .map(|(idx, tx)| RpcTransaction::new(key, idx, tx, senders.get(idx).copied()))
+                .collect(),
+        )
+    } else {
+        BlockTransactions::Hashes(txs.iter().map(Transaction::tx_hash).collect())
+    };
+    Ok(Some(RpcBlock {
+        header: HeaderJson::from(&header),
+        total_difficulty: db.read_total_difficulty(key)?.map(|td| td.0),
+        transactions,
+        uncles,
+    }))
+}
+
+fn read_logs(db: &Erigon<'_, RO>, filter: &LogFilter) -> Result<Vec<RpcLog>> {
+    let (from, to) = match filter.block_hash {
+        Some(hash) => match db.read_header_number(hash)? {
+            Some(num) => (num, num),
+            None => return Ok(vec![]),
+        },
+        None => {
+            // a range starting past the head has no logs yet, and one ending
+            // past it is cut off at the head
+            let from = match resolve(db, filter.from_block.unwrap_or_default())? {
+                Some(from) => from,
+                None => return Ok(vec![]),
+            };
+            let to = match resolve(db, filter.to_block.unwrap_or_default())? {
+                Some(to) => to,
+                None => db.read_head_block_number()?.unwrap_or_default(),
+            };
+            (from, to)
+        }
+    };
+    if *to >= *from + MAX_LOG_BLOCK_RANGE {
+        bail!(
+            "block range {}..={} exceeds {} blocks",
+            *from,
+            *to,
+            MAX_LOG_BLOCK_RANGE
+        );
+    }
+
+    let addresses = filter
+        .address
+        .as_ref()
+        .map(ValueOrArray::to_vec)
+        .unwrap_or_default();
+    let topics = filter
+        .topics
+        .iter()
+        .map(|topic| topic.as_ref().map(ValueOrArray::to_vec).unwrap_or_default())
+        .collect::<Vec<_>>();
+    let mut out = vec![];
+    for key in db.blocks_matching_bloom(*from..*to + 1, &addresses, &topics)? {
+        let HeaderKey(BlockNumber(num), hash) = key;
+        let logs = db.read_logs(num)?;
+        if logs.is_empty() {
+            continue;
+        }
+        let txs = db.read_transactions(key)?.unwrap_or_default();
+        let mut log_index = 0u64;
+        for (tx_idx, tx_logs) in logs {
+            for log in tx_logs {
+                if filter.matches(&log) {
+                    out.push(RpcLog {
+                        log: LogJson::from(&log),
+                        block_hash: hash,
+                        block_number: num.into(),
+                        transaction_hash: txs
+                            .get(tx_idx as usize)
+                            .map(Transaction::tx_hash)
+                            .unwrap_or_default(),
+                        transaction_index: (tx_idx as u64).into(),
+                        log_index: log_index.into(),
+                        removed: false,
+                    });
+                }
+                log_index += 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[async_trait]
+impl EthApiServer for EthRpc {
+    async fn block_number(&self) -> RpcResult<U64> {
+        self.view(|db| Ok(db.read_head_block_number()?.unwrap_or_default().0.into()))
+            .await
+    }
+
+    async fn get_balance(&self, address: Address, block: Option<BlockId>) -> RpcResult<U256> {
+        self.view(move |db| Ok(account_after(db, address, block)?.balance))
+            .await
+    }
+
+    async fn get_transaction_count(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> RpcResult<U64> {
+        self.view(move |db| Ok(account_after(db, address, block)?.nonce.into()))
+            .await
+    }
+
+    async fn get_storage_at(
+        &self,
+        address: Address,
+        slot: U256,
+        block: Option<BlockId>,
+    ) -> RpcResult<H256> {
+        self.view(move |db| {
+            let mut buf = [0; 32];
+            slot.to_big_endian(&mut buf);
+            let slot = H256(buf);
+
+            let head = db.read_head_block_number()?.unwrap_or_default();
+            let num = resolve_state(db, block)?;
+            let inc = account_after(db, address, block)?.incarnation;
+            let val = if num >= head {
+                db.read_storage(address, inc, slot)?.unwrap_or_default()
+            } else {
+                db.storage_at(address, inc, slot, *num + 1)?
+            };
+            val.to_big_endian(&mut buf);
+            Ok(H256(buf))
+        })
+        .await
+    }
+
+    async fn get_code(&self, address: Address, block: Option<BlockId>) -> RpcResult<Bytes> {
+        self.view(move |db| {
+            let codehash = account_after(db, address, block)?.codehash;
+            Ok(db.read_code(codehash)?.unwrap_or_default().0)
+        })
+        .await
+    }
+
+    async fn get_block_by_number(&self, block: BlockId, full: bool) -> RpcResult<Option<RpcBlock>> {
+        self.view(move |db| read_block(db, block, full)).await
+    }
+
+    async fn get_transaction_by_hash(&self, hash: H256) -> RpcResult<Option<RpcTransaction>> {
+        self.view(move |db| {
+            let (key, idx, tx) = match db.read_transaction_by_hash(hash)? {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            let from = db
+                .read_senders(key)?
+                .and_then(|senders| senders.get(idx as usize).copied());
+            Ok(Some(RpcTransaction::new(key, idx as usize, &tx, from)))
+        })
+        .await
+    }
+
+    async fn get_logs(&self, filter: LogFilter) -> RpcResult<Vec<RpcLog>> {
+        self.view(move |db| read_logs(db, &filter)).await
+    }
+}
+
+/// Starts an HTTP JSON-RPC server on `addr` serving the `eth` namespace.
+/// The server runs until the returned handle is stopped or dropped.
+pub async fn serve(db: ErigonDb<RO>, addr: SocketAddr) -> Result<HttpServerHandle> {
+    let server = HttpServerBuilder::default().build(addr).await?;
+    Ok(server.start(EthRpc::new(db).into_rpc())?)
+}
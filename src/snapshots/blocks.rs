@@ -0,0 +1,246 @@
+//! Reader for Erigon's frozen block segments.
+//!
+//! Erigon moves blocks older than its snapshot boundary out of mdbx and into
+//! `.seg` files in the snapshots directory, named
+//! `v{version}-{from}-{to}-{kind}.seg` with block numbers in thousands. Each
+//! segment has a `.idx` file alongside it that gives the offset of each word.
+//! The words of each kind of segment are:
+//! - `headers`: `header_hash[0] || rlp(header)`
+//! - `bodies`: `rlp(body_for_storage)`, where `base_tx_id` counts the txs of
+//!   all segments
+//! - `transactions`: `tx_hash[0] || sender || rlp(tx)`, with empty words for
+//!   the system txs
+use ethereum_types::{Address, H256};
+use eyre::eyre;
+use std::{
+    collections::BTreeMap,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    erigon::{models::*, strip_system_txs, utils::keccak256},
+    error::bail,
+    kv::traits::TableDecode,
+    snapshots::{decompress::Decompressor, index::Index},
+    Result,
+};
+
+const HEADERS: &str = "headers";
+const BODIES: &str = "bodies";
+const TRANSACTIONS: &str = "transactions";
+
+// tx_hash[0] || sender
+const TX_PREFIX_LENGTH: usize = 1 + Address::len_bytes();
+
+/// A `.seg` file and its index.
+#[derive(Debug)]
+pub struct Segment {
+    pub seg: Decompressor,
+    pub idx: Index,
+}
+
+impl Segment {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            seg: Decompressor::open(path)?,
+            idx: Index::open(&path.with_extension("idx"))?,
+        })
+    }
+
+    /// Returns the word with id `id`, e.g. the header of block `id`.
+    pub fn word(&self, id: u64) -> Result<Vec<u8>> {
+        self.words(id, 1).map(|mut words| words.remove(0))
+    }
+
+    /// Returns the `count` consecutive words starting at id `id`.
+    pub fn words(&self, id: u64, count: u64) -> Result<Vec<Vec<u8>>> {
+        let ordinal = id
+            .checked_sub(self.idx.base_data_id())
+            .ok_or_else(|| eyre!("Id {} precedes segment", id))?;
+        let mut getter = self.seg.getter();
+        getter.reset(self.idx.ordinal_lookup(ordinal)?);
+        (0..count).map(|_| getter.next_word()).collect()
+    }
+}
+
+/// The headers, bodies, and transactions of a range of blocks.
+#[derive(Debug)]
+pub struct BlockSegments {
+    pub blocks: Range<u64>,
+    pub headers: Segment,
+    pub bodies: Segment,
+    pub transactions: Segment,
+}
+
+/// The frozen block segments of an Erigon datadir.
+#[derive(Debug, Default)]
+pub struct BlockSnapshots {
+    segments: Vec<BlockSegments>,
+}
+
+impl BlockSnapshots {
+    /// Opens the segments in `dir`, usually `<datadir>/snapshots`. Only
+    /// segments covering a contiguous range of blocks from genesis, with all
+    /// three kinds of segment and their indices present, are used. Where
+    /// ranges overlap, the widest is used.
+    pub fn open(dir: &Path) -> Result<Self> {
+        let mut found = BTreeMap::<_, BTreeMap<String, PathBuf>>::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if let Some((blocks, kind)) = parse_segment_name(&path) {
+                if path.with_extension("idx").exists() {
+                    found
+                        .entry((blocks.start, blocks.end))
+                        .or_default()
+                        .insert(kind, path);
+                }
+            }
+        }
+
+        // a merged segment and the smaller ones it replaces can both be
+        // present until erigon deletes the latter, so the widest complete
+        // range starting at each boundary is used
+        let complete = |paths: &BTreeMap<String, PathBuf>| {
+            [HEADERS, BODIES, TRANSACTIONS]
+                .iter()
+                .all(|kind| paths.contains_key(*kind))
+        };
+        let mut segments = vec![];
+        let mut end = 0;
+        loop {
+            let widest = found
+                .range((end, end + 1)..=(end, u64::MAX))
+                .rev()
+                .find(|(_, paths)| complete(paths))
+                .map(|(blocks, _)| *blocks);
+            let (from, to) = match widest {
+                Some(blocks) => blocks,
+                None => break,
+            };
+            let paths = &found[&(from, to)];
+            let open = |kind: &str| Segment::open(&paths[kind]);
+            segments.push(BlockSegments {
+                blocks: from..to,
+                headers: open(HEADERS)?,
+                bodies: open(BODIES)?,
+                transactions: open(TRANSACTIONS)?,
+            });
+            end = to;
+        }
+        Ok(Self { segments })
+    }
+
+    /// Returns the number of blocks frozen into segments. Blocks below this
+    /// number may no longer be in mdbx.
+    pub fn frozen_blocks(&self) -> u64 {
+        self.segments.last().map_or(0, |s| s.blocks.end)
+    }
+
+    pub fn segments(&self) -> &[BlockSegments] {
+        &self.segments
+    }
+
+    fn find(&self, num: u64) -> Option<&BlockSegments> {
+        self.segments.iter().find(|s| s.blocks.contains(&num))
+    }
+
+    // Returns the rlp of the header of block `num`, if it is frozen.
+    fn header_rlp(&self, num: u64) -> Result<Option<Vec<u8>>> {
+        self.find(num)
+            .map(|s| {
+                let mut word = s.headers.word(num)?;
+                if word.is_empty() {
+                    bail!("Empty header word for block {}", num);
+                }
+                // skip the first byte of the hash
+                word.remove(0);
+                Ok(word)
+            })
+            .transpose()
+    }
+
+    /// Returns the header of block `num`, if it is frozen.
+    pub fn read_header(&self, num: u64) -> Result<Option<BlockHeader>> {
+        self.header_rlp(num)?
+            .map(|rlp| BlockHeader::decode(&rlp))
+            .transpose()
+    }
+
+    /// Returns the hash of block `num`, if it is frozen, without decoding its
+    /// header.
+    pub fn read_header_hash(&self, num: u64) -> Result<Option<H256>> {
+        Ok(self.header_rlp(num)?.map(|rlp| H256(keccak256(rlp))))
+    }
+
+    /// Returns the body of block `num`, if it is frozen. Like
+    /// [`Erigon::read_body_for_storage`](crate::Erigon::read_body_for_storage),
+    /// the system txs are excluded.
+    pub fn read_body_for_storage(&self, num: u64) -> Result<Option<BodyForStorage>> {
+        self.read_body_with_system_txs(num)?
+            .map(|body| strip_system_txs(body, num))
+            .transpose()
+    }
+
+    fn read_body_with_system_txs(&self, num: u64) -> Result<Option<BodyForStorage>> {
+        self.find(num)
+            .map(|s| BodyForStorage::decode(&s.bodies.word(num)?))
+            .transpose()
+    }
+
+    // Returns the words of the user txs of block `num`.
+    fn tx_words(&self, num: u64) -> Result<Option<Vec<Vec<u8>>>> {
+        let (segments, body) = match (self.find(num), self.read_body_for_storage(num)?) {
+            (Some(s), Some(body)) => (s, body),
+            _ => return Ok(None),
+        };
+        if body.tx_amount == 0 {
+            return Ok(Some(vec![]));
+        }
+        let words = segments
+            .transactions
+            .words(body.base_tx_id, body.tx_amount.into())?;
+        for word in &words {
+            if word.len() <= TX_PREFIX_LENGTH {
+                bail!("Invalid tx word in block {}", num);
+            }
+        }
+        Ok(Some(words))
+    }
+
+    /// Returns the transactions of block `num`, if it is frozen.
+    pub fn read_transactions(&self, num: u64) -> Result<Option<Vec<Transaction>>> {
+        self.tx_words(num)?
+            .map(|words| {
+                words
+                    .iter()
+                    .map(|w| Transaction::decode(&w[TX_PREFIX_LENGTH..]))
+                    .collect()
+            })
+            .transpose()
+    }
+
+    /// Returns the senders of the transactions of block `num`, if it is frozen.
+    pub fn read_senders(&self, num: u64) -> Result<Option<Vec<Address>>> {
+        Ok(self.tx_words(num)?.map(|words| {
+            words
+                .iter()
+                .map(|w| Address::from_slice(&w[1..TX_PREFIX_LENGTH]))
+                .collect()
+        }))
+    }
+}
+
+// Parses `v{version}-{from}-{to}-{kind}.seg`.
+fn parse_segment_name(path: &Path) -> Option<(Range<u64>, String)> {
+    if path.extension()? != "seg" {
+        return None;
+    }
+    let mut parts = path.file_stem()?.to_str()?.splitn(4, '-');
+    parts.next()?.strip_prefix('v')?;
+    let from: u64 = parts.next()?.parse().ok()?;
+    let to: u64 = parts.next()?.parse().ok()?;
+    let kind = parts.next()?.to_string();
+    Some((from * 1000..to * 1000, kind))
+}
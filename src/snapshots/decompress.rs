@@ -0,0 +1,355 @@
+//! Reader for Erigon's compressed word files, used for `.seg` snapshots.
+//!
+//! A compressed file is a sequence of words (byte strings). The layout is:
+//!
+//! ```text
+//! words_count:       u64 (big endian)
+//! empty_words_count: u64
+//! pattern_dict_size: u64
+//! pattern_dict:      [uvarint(depth) || uvarint(len) || pattern]
+//! pos_dict_size:     u64
+//! pos_dict:          [uvarint(depth) || uvarint(pos)]
+//! words:             ...
+//! ```
+//!
+//! Each dictionary lists the depth of its entries in a huffman tree. A word
+//! is encoded as a huffman-coded sequence of positions: first the word length
+//! plus one, then the (relative) position of each pattern in the word followed
+//! by the pattern's code, then a 0 terminator. The bytes of the word not
+//! covered by any pattern follow the codes, starting at the next byte
+//! boundary.
+use crate::{
+    error::{bail, ensure},
+    Result,
+};
+use eyre::eyre;
+use memmap2::Mmap;
+use std::{fs::File, path::Path};
+
+/// A memory-mapped compressed file.
+#[derive(Debug)]
+pub struct Decompressor {
+    mmap: Mmap,
+    words_count: u64,
+    empty_words_count: u64,
+    patterns: Huffman<Vec<u8>>,
+    positions: Huffman<u64>,
+    words_start: usize,
+}
+
+impl Decompressor {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        // safety: snapshot files are immutable once erigon has written them
+        let mmap = unsafe { Mmap::map(&file)? };
+        let data = &mmap[..];
+
+        let mut pos = 0;
+        let words_count = read_u64(data, &mut pos)?;
+        let empty_words_count = read_u64(data, &mut pos)?;
+
+        let size = read_u64(data, &mut pos)? as usize;
+        let mut dict = data
+            .get(pos..pos + size)
+            .ok_or_else(|| eyre!("Truncated pattern dictionary"))?;
+        pos += size;
+        let (mut depths, mut patterns) = (vec![], vec![]);
+        while !dict.is_empty() {
+            depths.push(read_uvarint(&mut dict)?);
+            let len = read_uvarint(&mut dict)? as usize;
+            let pattern = dict.get(..len).ok_or_else(|| eyre!("Truncated pattern"))?;
+            patterns.push(pattern.to_vec());
+            dict = &dict[len..];
+        }
+        let patterns = Huffman::new(&depths, patterns)?;
+
+        let size = read_u64(data, &mut pos)? as usize;
+        let mut dict = data
+            .get(pos..pos + size)
+            .ok_or_else(|| eyre!("Truncated position dictionary"))?;
+        pos += size;
+        let (mut depths, mut positions) = (vec![], vec![]);
+        while !dict.is_empty() {
+            depths.push(read_uvarint(&mut dict)?);
+            positions.push(read_uvarint(&mut dict)?);
+        }
+        let positions = Huffman::new(&depths, positions)?;
+
+        Ok(Self {
+            mmap,
+            words_count,
+            empty_words_count,
+            patterns,
+            positions,
+            words_start: pos,
+        })
+    }
+
+    pub fn words_count(&self) -> u64 {
+        self.words_count
+    }
+
+    pub fn empty_words_count(&self) -> u64 {
+        self.empty_words_count
+    }
+
+    /// Returns an iterator over the words of the file.
+    pub fn words(&self) -> Getter<'_> {
+        self.getter()
+    }
+
+    /// Returns a getter positioned at the first word.
+    pub fn getter(&self) -> Getter<'_> {
+        Getter {
+            d: self,
+            data: &self.mmap[self.words_start..],
+            pos: 0,
+            bit: 0,
+        }
+    }
+}
+
+/// A cursor over the words of a [`Decompressor`]. Offsets are relative to the
+/// start of the words, as stored in the `.idx` files.
+#[derive(Debug)]
+pub struct Getter<'a> {
+    d: &'a Decompressor,
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl<'a> Getter<'a> {
+    /// Moves to the word at byte `offset`.
+    pub fn reset(&mut self, offset: u64) {
+        self.pos = offset as usize;
+        self.bit = 0;
+    }
+
+    pub fn has_next(&self) -> bool {
+        self.pos < self.data.len()
+    }
+
+    /// Decodes the word at the current offset and moves to the next word.
+    pub fn next_word(&mut self) -> Result<Vec<u8>> {
+        let start = self.pos;
+        let word_len = self.next_pos(true)?.checked_sub(1);
+        let word_len = word_len.ok_or_else(|| eyre!("Invalid word length at offset {}", start))?;
+        let word_len = word_len as usize;
+        let mut word = vec![0; word_len];
+        if word_len == 0 {
+            self.align();
+            return Ok(word);
+        }
+
+        // first pass: fill in the patterns
+        let mut at = 0;
+        loop {
+            let pos = self.next_pos(false)?;
+            if pos == 0 {
+                break;
+            }
+            at += pos as usize - 1;
+            let pattern = self.next_pattern()?;
+            word.get_mut(at..at + pattern.len())
+                .ok_or_else(|| eyre!("Pattern overflows word at offset {}", start))?
+                .copy_from_slice(pattern);
+        }
+        self.align();
+        let mut raw = self.pos;
+
+        // second pass: re-read the codes to find the gaps between patterns,
+        // which are filled from the raw bytes following the codes
+        self.reset(start as u64);
+        self.next_pos(true)?;
+        let (mut at, mut uncovered) = (0, 0);
+        loop {
+            let pos = self.next_pos(false)?;
+            if pos == 0 {
+                break;
+            }
+            at += pos as usize - 1;
+            if at > uncovered {
+                let len = at - uncovered;
+                word[uncovered..at].copy_from_slice(self.raw(raw, len)?);
+                raw += len;
+            }
+            uncovered = at + self.next_pattern()?.len();
+        }
+        if word_len > uncovered {
+            let len = word_len - uncovered;
+            word[uncovered..].copy_from_slice(self.raw(raw, len)?);
+            raw += len;
+        }
+        self.reset(raw as u64);
+        Ok(word)
+    }
+
+    /// Moves to the next word without decoding the current one.
+    pub fn skip_word(&mut self) -> Result<()> {
+        let word_len = self.next_pos(true)?.saturating_sub(1) as usize;
+        if word_len == 0 {
+            self.align();
+            return Ok(());
+        }
+        let (mut at, mut uncovered, mut raw) = (0, 0, 0);
+        loop {
+            let pos = self.next_pos(false)?;
+            if pos == 0 {
+                break;
+            }
+            at += pos as usize - 1;
+            raw += at.saturating_sub(uncovered);
+            uncovered = at + self.next_pattern()?.len();
+        }
+        raw += word_len.saturating_sub(uncovered);
+        self.align();
+        self.pos += raw;
+        Ok(())
+    }
+
+    fn raw(&self, from: usize, len: usize) -> Result<&'a [u8]> {
+        self.data
+            .get(from..from + len)
+            .ok_or_else(|| eyre!("Truncated word data at offset {}", from).into())
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.pos += 1;
+            self.bit = 0;
+        }
+    }
+
+    fn next_bit(&mut self) -> Result<bool> {
+        let byte = self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| eyre!("Unexpected end of compressed data"))?;
+        let bit = byte >> self.bit & 1 == 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.pos += 1;
+            self.bit = 0;
+        }
+        Ok(bit)
+    }
+
+    fn next_pos(&mut self, clean: bool) -> Result<u64> {
+        if clean {
+            self.align();
+        }
+        let d = self.d;
+        d.positions.decode(|| self.next_bit()).copied()
+    }
+
+    fn next_pattern(&mut self) -> Result<&'a [u8]> {
+        let d = self.d;
+        d.patterns.decode(|| self.next_bit()).map(Vec::as_slice)
+    }
+}
+
+impl<'a> Iterator for Getter<'a> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.has_next().then(|| self.next_word())
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    Leaf(usize),
+    Branch(usize, usize),
+}
+
+// A huffman tree given by the depth of each of its leaves, in order. Codes are
+// read from the least significant bit of each byte first, and a 1 bit takes
+// the right branch.
+#[derive(Debug)]
+struct Huffman<T> {
+    values: Vec<T>,
+    nodes: Vec<Node>,
+}
+
+impl<T> Huffman<T> {
+    fn new(depths: &[u64], values: Vec<T>) -> Result<Self> {
+        let mut tree = Self {
+            values,
+            nodes: vec![],
+        };
+        if !depths.is_empty() {
+            tree.build(depths, 0, 0)?;
+        }
+        Ok(tree)
+    }
+
+    // Builds the subtree at `depth` whose leftmost leaf is `first`, returning
+    // the number of leaves it holds. The root is always node 0.
+    fn build(&mut self, depths: &[u64], first: usize, depth: u64) -> Result<usize> {
+        ensure!(depth <= 64, "Huffman code deeper than 64 bits");
+        let id = self.nodes.len();
+        // a dictionary can run out of leaves, which decode rejects
+        if first >= depths.len() {
+            self.nodes.push(Node::Leaf(first));
+            return Ok(0);
+        }
+        // leaves must be in canonical order, each no shallower than the last
+        ensure!(
+            depths[first] >= depth,
+            "Huffman depths out of order at entry {}",
+            first
+        );
+        if depths[first] == depth {
+            self.nodes.push(Node::Leaf(first));
+            return Ok(1);
+        }
+        self.nodes.push(Node::Branch(0, 0));
+        let left_id = self.nodes.len();
+        let left = self.build(depths, first, depth + 1)?;
+        let right_id = self.nodes.len();
+        let right = self.build(depths, first + left, depth + 1)?;
+        self.nodes[id] = Node::Branch(left_id, right_id);
+        Ok(left + right)
+    }
+
+    fn decode(&self, mut next_bit: impl FnMut() -> Result<bool>) -> Result<&T> {
+        let mut node = self
+            .nodes
+            .first()
+            .ok_or_else(|| eyre!("Empty huffman dictionary"))?;
+        loop {
+            match *node {
+                Node::Leaf(i) => {
+                    return self
+                        .values
+                        .get(i)
+                        .ok_or_else(|| eyre!("Invalid huffman code").into())
+                }
+                Node::Branch(left, right) => {
+                    node = &self.nodes[if next_bit()? { right } else { left }];
+                }
+            }
+        }
+    }
+}
+
+fn read_u64(data: &[u8], pos: &mut usize) -> Result<u64> {
+    let bytes = data
+        .get(*pos..*pos + 8)
+        .ok_or_else(|| eyre!("Unexpected end of file"))?;
+    *pos += 8;
+    Ok(u64::from_be_bytes(bytes.try_into()?))
+}
+
+fn read_uvarint(buf: &mut &[u8]) -> Result<u64> {
+    let mut val = 0;
+    for (i, byte) in buf.iter().enumerate().take(10) {
+        val |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Ok(val);
+        }
+    }
+    bail!("Invalid uvarint")
+}
@@ -0,0 +1,399 @@
+//! Readers for Erigon 3 domain files.
+//!
+//! Erigon 3 keeps only recent state in mdbx. Older account, storage, and code
+//! state is frozen into files under the snapshots directory named
+//! `v{version}-{domain}.{from_step}-{to_step}.{ext}`, where a step is a fixed
+//! range of [`STEP_SIZE`] txnums:
+//! - `.kv` files hold the latest value of each key as of `to_step`, as
+//!   alternating key and value words.
+//! - `.ef` files hold, for each key, the txnums at which it changed, as a key
+//!   word followed by an Elias-Fano sequence.
+//! - `.v` files hold the value each key had before each of those changes.
+//!
+//! Each file has a recsplit index alongside it (`.kvi`, `.efi`, `.vi`). The
+//! `.kvi` and `.efi` indices are keyed by the key, and the `.vi` index by
+//! `txnum || key`. Keys are the address for accounts and code, and
+//! `address || slot` for storage.
+use ethereum_types::{H256, U256};
+use eyre::eyre;
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    erigon::models::Account,
+    error::{bail, ensure},
+    snapshots::{
+        decompress::{Decompressor, Getter},
+        eliasfano::EliasFano,
+        index::Index,
+        Segment,
+    },
+    Error, Result,
+};
+
+/// The number of txnums in a step.
+pub const STEP_SIZE: u64 = 1_562_500;
+
+/// A kind of state stored in domain files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Domain {
+    Accounts,
+    Storage,
+    Code,
+    Commitment,
+}
+
+impl Domain {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Accounts => "accounts",
+            Self::Storage => "storage",
+            Self::Code => "code",
+            Self::Commitment => "commitment",
+        }
+    }
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Domain {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "accounts" => Self::Accounts,
+            "storage" => Self::Storage,
+            "code" => Self::Code,
+            "commitment" => Self::Commitment,
+            _ => bail!("Unknown domain: {}", s),
+        })
+    }
+}
+
+/// What a domain file holds, determined by its extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FileKind {
+    /// `.kv`: the latest value of each key.
+    Values,
+    /// `.v`: the previous values of each key.
+    History,
+    /// `.ef`: the txnums at which each key changed.
+    InvertedIndex,
+}
+
+impl FileKind {
+    pub fn ext(&self) -> &'static str {
+        match self {
+            Self::Values => "kv",
+            Self::History => "v",
+            Self::InvertedIndex => "ef",
+        }
+    }
+
+    /// The extension of the recsplit index of the file.
+    pub fn index_ext(&self) -> &'static str {
+        match self {
+            Self::Values => "kvi",
+            Self::History => "vi",
+            Self::InvertedIndex => "efi",
+        }
+    }
+
+    fn from_ext(ext: &str) -> Option<Self> {
+        Some(match ext {
+            "kv" => Self::Values,
+            "v" => Self::History,
+            "ef" => Self::InvertedIndex,
+            _ => return None,
+        })
+    }
+}
+
+/// A single domain file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DomainFile {
+    pub path: PathBuf,
+    pub version: u32,
+    pub domain: Domain,
+    pub kind: FileKind,
+    pub steps: Range<u64>,
+}
+
+impl DomainFile {
+    /// Parses a path named `v{version}-{domain}.{from_step}-{to_step}.{ext}`.
+    /// Returns `None` for any other file, including the index files that sit
+    /// alongside the domain files.
+    pub fn parse(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let (name, ext) = name.rsplit_once('.')?;
+        let (name, steps) = name.rsplit_once('.')?;
+        let (version, domain) = name.split_once('-')?;
+        let (from, to) = steps.split_once('-')?;
+        Some(Self {
+            path: path.to_path_buf(),
+            version: version.strip_prefix('v')?.parse().ok()?,
+            domain: domain.parse().ok()?,
+            kind: FileKind::from_ext(ext)?,
+            steps: from.parse().ok()?..to.parse().ok()?,
+        })
+    }
+
+    /// The file name of the file's index.
+    pub fn index_name(&self) -> String {
+        format!(
+            "v{}-{}.{}-{}.{}",
+            self.version,
+            self.domain,
+            self.steps.start,
+            self.steps.end,
+            self.kind.index_ext()
+        )
+    }
+}
+
+/// The set of domain files in an Erigon 3 snapshots directory.
+#[derive(Clone, Debug, Default)]
+pub struct Domains {
+    files: Vec<DomainFile>,
+    // every other file found, by name, which includes the indices
+    others: HashMap<String, PathBuf>,
+}
+
+impl Domains {
+    /// Finds the domain files in `dir` and its immediate subdirectories, which
+    /// is where Erigon 3 puts them (e.g. `snapshots/domain`,
+    /// `snapshots/history`, `snapshots/idx`).
+    pub fn open(dir: &Path) -> Result<Self> {
+        let mut paths = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                for entry in fs::read_dir(&path)? {
+                    paths.push(entry?.path());
+                }
+            } else {
+                paths.push(path);
+            }
+        }
+        let (mut files, mut others) = (vec![], HashMap::new());
+        for path in paths {
+            match DomainFile::parse(&path) {
+                Some(file) => files.push(file),
+                None => {
+                    if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                        others.insert(name.to_string(), path);
+                    }
+                }
+            }
+        }
+        files.sort_by_key(|f| (f.domain, f.kind, f.steps.start, f.steps.end));
+        Ok(Self { files, others })
+    }
+
+    /// Returns true if no domain files were found, i.e. the datadir predates
+    /// Erigon 3 and mdbx holds all of the state history.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Returns the files of the given domain and kind, ordered by step.
+    pub fn files(&self, domain: Domain, kind: FileKind) -> impl Iterator<Item = &DomainFile> {
+        self.files
+            .iter()
+            .filter(move |f| f.domain == domain && f.kind == kind)
+    }
+
+    /// Returns the end of the contiguous range of steps, starting at step 0,
+    /// whose values for `domain` are frozen into files.
+    pub fn frozen_steps(&self, domain: Domain) -> u64 {
+        widest(self.files(domain, FileKind::Values))
+            .last()
+            .map_or(0, |f| f.steps.end)
+    }
+
+    /// Opens the files of `domain` that cover the frozen steps, along with
+    /// their indices.
+    pub fn reader(&self, domain: Domain) -> Result<DomainReader> {
+        let open = |file: &DomainFile| -> Result<Segment> {
+            let name = file.index_name();
+            let idx = self
+                .others
+                .get(&name)
+                .ok_or_else(|| eyre!("Missing index {}", name))?;
+            ensure!(
+                file.version == 1,
+                "Unsupported domain file version {}",
+                file.version
+            );
+            Ok(Segment {
+                seg: Decompressor::open(&file.path)?,
+                idx: Index::open(idx)?,
+            })
+        };
+        let values = widest(self.files(domain, FileKind::Values))
+            .into_iter()
+            .map(|f| Ok((f.steps.clone(), open(f)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut history = vec![];
+        for ef in widest(self.files(domain, FileKind::InvertedIndex)) {
+            let v = self
+                .files(domain, FileKind::History)
+                .find(|v| v.steps == ef.steps)
+                .ok_or_else(|| eyre!("Missing history of {}", ef.path.display()))?;
+            history.push((ef.steps.clone(), open(ef)?, open(v)?));
+        }
+        Ok(DomainReader { values, history })
+    }
+}
+
+// Picks the widest file starting at each step, so that the larger files
+// produced by merges replace the smaller files they were merged from, up to
+// the first gap. `files` must be ordered by step.
+fn widest<'a>(files: impl Iterator<Item = &'a DomainFile>) -> Vec<&'a DomainFile> {
+    let mut picked: Vec<&DomainFile> = vec![];
+    for file in files {
+        match picked.last().copied() {
+            None if file.steps.start == 0 => picked.push(file),
+            Some(last) if file.steps.start == last.steps.end => picked.push(file),
+            Some(last) if file.steps.start == last.steps.start => {
+                // ordered by end, so this one is wider
+                *picked.last_mut().unwrap() = file;
+            }
+            Some(last) if file.steps.start < last.steps.end => (),
+            _ => break,
+        }
+    }
+    picked
+}
+
+/// The frozen values and history of one domain.
+#[derive(Debug, Default)]
+pub struct DomainReader {
+    // ordered by step
+    values: Vec<(Range<u64>, Segment)>,
+    // the `.ef` and `.v` files of each range of steps
+    history: Vec<(Range<u64>, Segment, Segment)>,
+}
+
+impl DomainReader {
+    /// Returns true if no files of the domain are frozen.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.history.is_empty()
+    }
+
+    /// Returns the end of the txnums whose changes are in the history files.
+    pub fn frozen_txnums(&self) -> u64 {
+        self.history
+            .last()
+            .map_or(0, |(steps, _, _)| steps.end * STEP_SIZE)
+    }
+
+    /// Returns the latest frozen value of `key`, or `None` if no file holds
+    /// it. An empty value means the key was deleted.
+    pub fn latest(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        for (_, kv) in self.values.iter().rev() {
+            if let Some(mut getter) = seek_key(kv, key)? {
+                return getter.next_word().map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the value `key` had before its first change at or after
+    /// `txnum`, or `None` if the history files have no such change. An empty
+    /// value means the key did not exist.
+    pub fn before(&self, key: &[u8], txnum: u64) -> Result<Option<Vec<u8>>> {
+        for (steps, ef, v) in &self.history {
+            if steps.end * STEP_SIZE <= txnum {
+                continue;
+            }
+            let word = match seek_key(ef, key)? {
+                Some(mut getter) => getter.next_word()?,
+                None => continue,
+            };
+            let (txnums, _) = EliasFano::read(&word)?;
+            let changed = match txnums.seek(txnum)? {
+                Some(changed) => changed,
+                None => continue,
+            };
+            let offset = v
+                .idx
+                .word_offset(&[&changed.to_be_bytes()[..], key].concat())?
+                .ok_or_else(|| eyre!("Missing history of txnum {}", changed))?;
+            let mut getter = v.seg.getter();
+            getter.reset(offset);
+            return getter.next_word().map(Some);
+        }
+        Ok(None)
+    }
+}
+
+// Finds the word of `key` in a file of key words, each followed by a value
+// word. Returns a getter positioned at the value.
+fn seek_key<'a>(file: &'a Segment, key: &[u8]) -> Result<Option<Getter<'a>>> {
+    let offset = match file.idx.word_offset(key)? {
+        Some(offset) => offset,
+        None => return Ok(None),
+    };
+    let mut getter = file.seg.getter();
+    getter.reset(offset);
+    // the index maps absent keys to arbitrary words
+    Ok((getter.has_next() && getter.next_word()? == key).then(|| getter))
+}
+
+/// Decodes an account from the accounts domain. The nonce, balance, codehash,
+/// and incarnation are each stored as a length byte followed by that many big
+/// endian bytes. An empty value is an account that doesn't exist.
+pub fn decode_account(mut buf: &[u8]) -> Result<Option<Account>> {
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    let be = |b: &[u8]| b.iter().fold(0, |acc, b| acc << 8 | u64::from(*b));
+    let nonce = be(take_field(&mut buf, 8)?);
+    let balance = U256::from_big_endian(take_field(&mut buf, 32)?);
+    let codehash = match take_field(&mut buf, 32)? {
+        [] => H256::zero(),
+        hash => {
+            ensure!(hash.len() == 32, "Invalid codehash length {}", hash.len());
+            H256::from_slice(hash)
+        }
+    };
+    let incarnation = be(take_field(&mut buf, 8)?);
+    ensure!(buf.is_empty(), "Trailing bytes after account");
+    Ok(Some(Account {
+        nonce,
+        incarnation: incarnation.into(),
+        balance,
+        codehash,
+    }))
+}
+
+/// Decodes a value from the storage domain, stored as big endian bytes without
+/// leading zeroes. An empty value is a slot that was cleared.
+pub fn decode_storage(buf: &[u8]) -> Result<U256> {
+    ensure!(
+        buf.len() <= 32,
+        "Invalid storage value length {}",
+        buf.len()
+    );
+    Ok(U256::from_big_endian(buf))
+}
+
+fn take_field<'a>(buf: &mut &'a [u8], max: usize) -> Result<&'a [u8]> {
+    let (len, rest) = buf
+        .split_first()
+        .ok_or_else(|| eyre!("Truncated account"))?;
+    let len = usize::from(*len);
+    ensure!(len <= max && len <= rest.len(), "Invalid account field");
+    let (field, rest) = rest.split_at(len);
+    *buf = rest;
+    Ok(field)
+}
@@ -0,0 +1,253 @@
+//! Elias-Fano encoded monotone sequences, as serialized by erigon-lib's
+//! `recsplit/eliasfano32` and `recsplit/eliasfano16` packages.
+use crate::{error::ensure, Result};
+use eyre::eyre;
+
+const LOG2Q: u64 = 8;
+const Q: u64 = 1 << LOG2Q;
+const Q_MASK: u64 = Q - 1;
+const SUPER_Q: u64 = 1 << 14;
+const Q_PER_SUPER_Q: u64 = SUPER_Q / Q;
+const SUPER_Q_MASK: u64 = SUPER_Q - 1;
+// jumps are 32 bits wide in EliasFano, 16 in DoubleEliasFano
+const SUPER_Q_SIZE: u64 = 1 + Q_PER_SUPER_Q / 2;
+const SUPER_Q_SIZE_16: u64 = 1 + Q_PER_SUPER_Q / 4;
+
+/// A view of little-endian u64 words in a byte slice, which need not be
+/// aligned.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Words<'a>(pub &'a [u8]);
+
+impl<'a> Words<'a> {
+    pub fn get(&self, i: u64) -> u64 {
+        let i = i as usize * 8;
+        u64::from_le_bytes(self.0[i..i + 8].try_into().unwrap())
+    }
+
+    pub fn slice(&self, from: usize, to: usize) -> Self {
+        Self(&self.0[from * 8..to * 8])
+    }
+}
+
+/// Returns the position of the `k`th (from 0) set bit of `x`.
+pub(crate) fn select64(mut x: u64, k: u32) -> u32 {
+    for _ in 0..k {
+        x &= x - 1;
+    }
+    x.trailing_zeros()
+}
+
+/// A monotone sequence of `count` integers below `u`.
+#[derive(Clone, Debug)]
+pub struct EliasFano<'a> {
+    lower_bits: Words<'a>,
+    upper_bits: Words<'a>,
+    jump: Words<'a>,
+    lower_bits_mask: u64,
+    // one less than the number of values
+    count: u64,
+    l: u64,
+}
+
+impl<'a> EliasFano<'a> {
+    /// Reads a sequence from the start of `buf`, returning it along with the
+    /// number of bytes it occupies.
+    pub fn read(buf: &'a [u8]) -> Result<(Self, usize)> {
+        ensure!(buf.len() >= 16, "Truncated elias-fano header");
+        let count = u64::from_be_bytes(buf[..8].try_into()?);
+        let u = u64::from_be_bytes(buf[8..16].try_into()?);
+        let l = match u / (count + 1) {
+            0 => 0,
+            n => 63 - u64::from(n.leading_zeros()),
+        };
+        let words_lower = ((count + 1) * l + 63) / 64 + 1;
+        let words_upper = (count + 1 + (u >> l) + 63) / 64;
+        let mut words_jump = (count + 1) / SUPER_Q * SUPER_Q_SIZE;
+        if (count + 1) % SUPER_Q != 0 {
+            words_jump += 1 + (((count + 1) % SUPER_Q + Q - 1) / Q + 3) / 2;
+        }
+        let (lower, upper) = (words_lower as usize, (words_lower + words_upper) as usize);
+        let total = upper + words_jump as usize;
+        let data = Words(
+            buf.get(16..16 + total * 8)
+                .ok_or_else(|| eyre!("Truncated elias-fano data"))?,
+        );
+        let ef = Self {
+            lower_bits: data.slice(0, lower),
+            upper_bits: data.slice(lower, upper),
+            jump: data.slice(upper, total),
+            lower_bits_mask: (1 << l) - 1,
+            count,
+            l,
+        };
+        Ok((ef, 16 + total * 8))
+    }
+
+    /// The number of values in the sequence.
+    pub fn len(&self) -> u64 {
+        self.count + 1
+    }
+
+    /// A sequence always holds at least one value.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Returns the `i`th value of the sequence.
+    pub fn get(&self, i: u64) -> Result<u64> {
+        ensure!(i <= self.count, "Index {} out of bounds", i);
+        let pos = i * self.l;
+        let (idx, shift) = (pos / 64, pos % 64);
+        let mut lower = self.lower_bits.get(idx) >> shift;
+        if shift > 0 {
+            lower |= self.lower_bits.get(idx + 1) << (64 - shift);
+        }
+
+        let jump_super = i / SUPER_Q * SUPER_Q_SIZE;
+        let jump_inside = i % SUPER_Q / Q;
+        let (idx, shift) = (jump_super + 1 + (jump_inside >> 1), 32 * (jump_inside % 2));
+        let jump = self.jump.get(jump_super) + (self.jump.get(idx) >> shift & 0xffff_ffff);
+
+        let (curr, _, sel) = select(self.upper_bits, jump, i);
+        Ok((curr * 64 + sel - i) << self.l | (lower & self.lower_bits_mask))
+    }
+
+    /// Returns the first value of the sequence that is >= `v`.
+    pub fn seek(&self, v: u64) -> Result<Option<u64>> {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.get(mid)? < v {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        if lo == self.len() {
+            return Ok(None);
+        }
+        self.get(lo).map(Some)
+    }
+}
+
+/// A pair of monotone sequences encoded together, used by recsplit indices to
+/// map each bucket to the number of keys in the buckets before it and to the
+/// bit position of its Golomb-Rice codes.
+#[derive(Clone, Debug)]
+pub struct DoubleEliasFano<'a> {
+    lower_bits: Words<'a>,
+    upper_bits_cum_keys: Words<'a>,
+    upper_bits_position: Words<'a>,
+    jump: Words<'a>,
+    l_cum_keys: u64,
+    l_position: u64,
+    cum_keys_min_delta: u64,
+    position_min_delta: u64,
+}
+
+impl<'a> DoubleEliasFano<'a> {
+    /// Reads the sequences from the start of `buf`.
+    pub fn read(buf: &'a [u8]) -> Result<Self> {
+        ensure!(buf.len() >= 40, "Truncated double elias-fano header");
+        let be = |i: usize| u64::from_be_bytes(buf[i * 8..i * 8 + 8].try_into().unwrap());
+        let (num_buckets, u_cum_keys, u_position) = (be(0), be(1), be(2));
+        let (cum_keys_min_delta, position_min_delta) = (be(3), be(4));
+
+        let log2 = |u: u64| match u / (num_buckets + 1) {
+            0 => 0,
+            n => 63 - u64::from(n.leading_zeros()),
+        };
+        let (l_cum_keys, l_position) = (log2(u_cum_keys), log2(u_position));
+        ensure!(
+            l_cum_keys * 2 + l_position <= 56,
+            "Invalid double elias-fano parameters"
+        );
+
+        let words_lower = ((num_buckets + 1) * (l_cum_keys + l_position) + 63) / 64 + 1;
+        let words_cum_keys = (num_buckets + 1 + (u_cum_keys >> l_cum_keys) + 63) / 64;
+        let words_position = (num_buckets + 1 + (u_position >> l_position) + 63) / 64;
+        let mut words_jump = (num_buckets + 1) / SUPER_Q * SUPER_Q_SIZE_16 * 2;
+        if (num_buckets + 1) % SUPER_Q != 0 {
+            words_jump += (1 + (((num_buckets + 1) % SUPER_Q + Q - 1) / Q + 3) / 4) * 2;
+        }
+        let cum_keys = words_lower as usize;
+        let position = cum_keys + words_cum_keys as usize;
+        let jump = position + words_position as usize;
+        let total = jump + words_jump as usize;
+        let data = Words(
+            buf.get(40..40 + total * 8)
+                .ok_or_else(|| eyre!("Truncated double elias-fano data"))?,
+        );
+        Ok(Self {
+            lower_bits: data.slice(0, cum_keys),
+            upper_bits_cum_keys: data.slice(cum_keys, position),
+            upper_bits_position: data.slice(position, jump),
+            jump: data.slice(jump, total),
+            l_cum_keys,
+            l_position,
+            cum_keys_min_delta,
+            position_min_delta,
+        })
+    }
+
+    /// Returns the `i`th and `i+1`th values of the first sequence and the
+    /// `i`th value of the second: the number of keys before bucket `i`, the
+    /// number of keys before bucket `i+1`, and the bit position of bucket `i`.
+    pub fn get3(&self, i: u64) -> (u64, u64, u64) {
+        let pos = i * (self.l_cum_keys + self.l_position);
+        let (idx, shift) = (pos / 64, pos % 64);
+        let mut lower = self.lower_bits.get(idx) >> shift;
+        if shift > 0 {
+            lower |= self.lower_bits.get(idx + 1) << (64 - shift);
+        }
+
+        // 16 bit jumps, alternating between the two sequences
+        let jump_super = i / SUPER_Q * SUPER_Q_SIZE_16 * 2;
+        let idx16 = 4 * (jump_super + 2) + 2 * ((i & SUPER_Q_MASK) / Q);
+        let jump16 = |idx16: u64| self.jump.get(idx16 / 4) >> (16 * (idx16 % 4)) & 0xffff;
+        let jump_cum_keys = self.jump.get(jump_super) + jump16(idx16);
+        let jump_position = self.jump.get(jump_super + 1) + jump16(idx16 + 1);
+
+        let (mut curr, mut window, sel) = select(self.upper_bits_cum_keys, jump_cum_keys, i);
+        let cum_delta = i * self.cum_keys_min_delta;
+        let cum_keys = ((curr * 64 + sel - i) << self.l_cum_keys
+            | (lower & ((1 << self.l_cum_keys) - 1)))
+            + cum_delta;
+        lower >>= self.l_cum_keys;
+
+        let (curr_pos, _, sel_pos) = select(self.upper_bits_position, jump_position, i);
+        let position = ((curr_pos * 64 + sel_pos - i) << self.l_position
+            | (lower & ((1 << self.l_position) - 1)))
+            + i * self.position_min_delta;
+        lower >>= self.l_position;
+
+        // the next set bit of the upper bits after the ith gives the i+1th value
+        window &= u64::MAX << sel << 1;
+        while window == 0 {
+            curr += 1;
+            window = self.upper_bits_cum_keys.get(curr);
+        }
+        let cum_keys_next = ((curr * 64 + u64::from(window.trailing_zeros()) - i - 1)
+            << self.l_cum_keys
+            | (lower & ((1 << self.l_cum_keys) - 1)))
+            + cum_delta
+            + self.cum_keys_min_delta;
+
+        (cum_keys, cum_keys_next, position)
+    }
+}
+
+// Finds the ith set bit of `upper`, starting the search from the jump
+// position of i's block. Returns the word holding the bit, the remaining bits
+// of that word, and the position of the bit within it.
+fn select(upper: Words<'_>, jump: u64, i: u64) -> (u64, u64, u64) {
+    let mut curr = jump / 64;
+    let mut window = upper.get(curr) & (u64::MAX << (jump % 64));
+    let mut d = (i & Q_MASK) as u32;
+    while window.count_ones() <= d {
+        d -= window.count_ones();
+        curr += 1;
+        window = upper.get(curr);
+    }
+    (curr, window, u64::from(select64(window, d)))
+}
@@ -0,0 +1,507 @@
+//! Reader for Erigon's `.idx` files, which map the words of a `.seg` file to
+//! their offsets.
+//!
+//! An index is a RecSplit minimal perfect hash over the keys of the segment
+//! (e.g. tx hashes), along with a record for each key. Keys are hashed into
+//! buckets, and the keys of each bucket are recursively split until they can
+//! be mapped to distinct records by a bijection. The seeds of the splits and
+//! bijections are stored as Golomb-Rice codes, found from the bucket by a
+//! double Elias-Fano sequence.
+use eyre::eyre;
+use memmap2::Mmap;
+use std::{fs::File, path::Path};
+
+use crate::{
+    error::ensure,
+    snapshots::eliasfano::{DoubleEliasFano, EliasFano, Words},
+    Result,
+};
+
+// the index stores the offset of every word in order
+const FEATURE_ENUMS: u8 = 0b1;
+// the index stores a byte of each key's hash, to reject most absent keys
+const FEATURE_LESS_FALSE_POSITIVES: u8 = 0b10;
+
+// Golomb-Rice parameters of the bijections of leaves of each size
+const BIJ_MEMO: [u32; 25] = [
+    0, 0, 0, 1, 3, 4, 5, 7, 8, 10, 11, 12, 14, 15, 16, 18, 19, 21, 22, 23, 25, 26, 28, 29, 30,
+];
+
+/// A memory-mapped `.idx` file.
+#[derive(Debug)]
+pub struct Index {
+    mmap: Mmap,
+    base_data_id: u64,
+    key_count: u64,
+    bytes_per_rec: usize,
+    bucket_count: u64,
+    leaf_size: u16,
+    primary_aggr_bound: u16,
+    secondary_aggr_bound: u16,
+    salt: u32,
+    start_seed: Vec<u64>,
+    // the position of the offsets of the words, ordered by ordinal
+    offsets: Option<usize>,
+    // the position of the fingerprint byte of each record
+    existence: Option<usize>,
+    // for each subtree size: the Golomb-Rice parameter of its root, the number
+    // of nodes in it, and the number of fixed bits of its codes
+    golomb_rice: Vec<u32>,
+    // the positions of the Golomb-Rice codes and the double Elias-Fano
+    // sequence
+    gr_data: (usize, usize),
+    double_ef: usize,
+}
+
+impl Index {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        // safety: snapshot files are immutable once erigon has written them
+        let mmap = unsafe { Mmap::map(&file)? };
+        let data = &mmap[..];
+        let truncated = || eyre!("Truncated index {}", path.display());
+        let be = |pos: usize, len: usize| -> Result<u64> {
+            let bytes = data.get(pos..pos + len).ok_or_else(truncated)?;
+            Ok(bytes.iter().fold(0, |acc, b| acc << 8 | u64::from(*b)))
+        };
+
+        let base_data_id = be(0, 8)?;
+        let key_count = be(8, 8)?;
+        let bytes_per_rec = be(16, 1)? as usize;
+        let mut pos = 17 + key_count as usize * bytes_per_rec;
+        let bucket_count = be(pos, 8)?;
+        // the bucket size only matters when building the index
+        let leaf_size = be(pos + 10, 2)? as u16;
+        let salt = be(pos + 12, 4)? as u32;
+        pos += 16;
+        let start_seed_len = be(pos, 1)? as usize;
+        pos += 1;
+        let start_seed = (0..start_seed_len)
+            .map(|i| be(pos + i * 8, 8))
+            .collect::<Result<Vec<_>>>()?;
+        pos += start_seed_len * 8;
+
+        let features = be(pos, 1)? as u8;
+        ensure!(
+            features & !(FEATURE_ENUMS | FEATURE_LESS_FALSE_POSITIVES) == 0,
+            "Unsupported index features: {:#b}",
+            features
+        );
+        pos += 1;
+        let offsets = (features & FEATURE_ENUMS != 0 && key_count > 0).then(|| pos);
+        if offsets.is_some() {
+            pos += EliasFano::read(data.get(pos..).ok_or_else(truncated)?)?.1;
+        }
+        let existence =
+            (features & FEATURE_LESS_FALSE_POSITIVES != 0 && key_count > 0).then(|| pos + 8);
+        if existence.is_some() {
+            let len = be(pos, 8)?;
+            ensure!(len == key_count, "Invalid existence filter length: {}", len);
+            pos += 8 + len as usize;
+        }
+
+        ensure!(
+            (1..BIJ_MEMO.len() as u16).contains(&leaf_size),
+            "Unsupported leaf size: {}",
+            leaf_size
+        );
+        let primary_aggr_bound = leaf_size * 2.max((0.35 * leaf_size as f64 + 0.5).ceil() as u16);
+        let secondary_aggr_bound = if leaf_size < 7 {
+            primary_aggr_bound * 2
+        } else {
+            primary_aggr_bound * (0.21 * leaf_size as f64 + 0.9).ceil() as u16
+        };
+        // written as 4 bytes, of which the first 2 hold the size
+        let golomb_param_size = be(pos, 2)? as u16;
+        pos += 4;
+        let mut golomb_rice = Vec::with_capacity(golomb_param_size as usize);
+        for m in 0..golomb_param_size {
+            let params = match m {
+                0 => BIJ_MEMO[0] << 27 | BIJ_MEMO[0],
+                m if m <= leaf_size => BIJ_MEMO[m as usize] << 27 | 1 << 16 | BIJ_MEMO[m as usize],
+                m => golomb_rice_params(
+                    m,
+                    &golomb_rice,
+                    leaf_size,
+                    primary_aggr_bound,
+                    secondary_aggr_bound,
+                )?,
+            };
+            golomb_rice.push(params);
+        }
+
+        let gr_words = be(pos, 8)? as usize;
+        pos += 8;
+        let gr_data = (pos, pos + gr_words * 8);
+        ensure!(
+            gr_data.1 <= data.len(),
+            "Truncated index {}",
+            path.display()
+        );
+        let double_ef = gr_data.1;
+        DoubleEliasFano::read(&data[double_ef..])?;
+
+        Ok(Self {
+            mmap,
+            base_data_id,
+            key_count,
+            bytes_per_rec,
+            bucket_count,
+            leaf_size,
+            primary_aggr_bound,
+            secondary_aggr_bound,
+            salt,
+            start_seed,
+            offsets,
+            existence,
+            golomb_rice,
+            gr_data,
+            double_ef,
+        })
+    }
+
+    /// The id of the first word, e.g. the first block number of a headers
+    /// segment, or the first tx id of a transactions segment.
+    pub fn base_data_id(&self) -> u64 {
+        self.base_data_id
+    }
+
+    pub fn key_count(&self) -> u64 {
+        self.key_count
+    }
+
+    /// Returns the offset of the `i`th word of the segment.
+    pub fn ordinal_lookup(&self, i: u64) -> Result<u64> {
+        let pos = self
+            .offsets
+            .ok_or_else(|| eyre!("Index does not support lookups by ordinal"))?;
+        let (offsets, _) = EliasFano::read(&self.mmap[pos..])?;
+        offsets.get(i)
+    }
+
+    /// Returns the offset of the word of `key`, following the ordinal if the
+    /// index stores offsets by ordinal. Absent keys give false positives as
+    /// in [`Index::lookup`].
+    pub fn word_offset(&self, key: &[u8]) -> Result<Option<u64>> {
+        match (self.lookup(key)?, self.offsets) {
+            (Some(ordinal), Some(_)) => self.ordinal_lookup(ordinal).map(Some),
+            (record, _) => Ok(record),
+        }
+    }
+
+    /// Returns the record stored for `key`: the ordinal of the key's word if
+    /// the index stores offsets by ordinal, otherwise the offset of the word.
+    ///
+    /// The lookup is O(1), but a key that isn't in the index usually still
+    /// maps to some record, so callers must check the word they find, e.g.
+    /// that the first byte of a tx hash matches. `None` is only returned for
+    /// an empty index, or if the index keeps fingerprints of its keys and the
+    /// key's doesn't match.
+    pub fn lookup(&self, key: &[u8]) -> Result<Option<u64>> {
+        match self.key_count {
+            0 => return Ok(None),
+            1 => return Ok(Some(0)),
+            _ => (),
+        }
+        let (bucket_hash, fingerprint) = murmur3_x64_128(key, self.salt);
+        let bucket = remap(bucket_hash, self.bucket_count);
+        let ef = DoubleEliasFano::read(&self.mmap[self.double_ef..])?;
+        let (mut cum_keys, cum_keys_next, bit_pos) = ef.get3(bucket);
+        // the number of keys in the bucket
+        let mut m = (cum_keys_next - cum_keys) as u16;
+        let mut gr = GolombRiceReader::new(
+            Words(&self.mmap[self.gr_data.0..self.gr_data.1]),
+            bit_pos,
+            self.skip_bits(m),
+        );
+
+        let mut level = 0;
+        // fanout 2
+        while m > self.secondary_aggr_bound {
+            let d = gr.read_next(self.golomb_param(m))?;
+            let hmod = remap16(
+                remix(fingerprint.wrapping_add(self.seed(level)?).wrapping_add(d)),
+                m,
+            );
+            let split = ((m + 1) / 2 + self.secondary_aggr_bound - 1) / self.secondary_aggr_bound
+                * self.secondary_aggr_bound;
+            if hmod < split {
+                m = split;
+            } else {
+                gr.skip_subtree(self.skip_nodes(split), self.skip_bits(split))?;
+                m -= split;
+                cum_keys += u64::from(split);
+            }
+            level += 1;
+        }
+        if m > self.primary_aggr_bound {
+            let d = gr.read_next(self.golomb_param(m))?;
+            let hmod = remap16(
+                remix(fingerprint.wrapping_add(self.seed(level)?).wrapping_add(d)),
+                m,
+            );
+            let part = hmod / self.primary_aggr_bound;
+            m = self
+                .primary_aggr_bound
+                .min(m - part * self.primary_aggr_bound);
+            cum_keys += u64::from(self.primary_aggr_bound * part);
+            if part != 0 {
+                gr.skip_subtree(
+                    self.skip_nodes(self.primary_aggr_bound) * u64::from(part),
+                    self.skip_bits(self.primary_aggr_bound) * u64::from(part),
+                )?;
+            }
+            level += 1;
+        }
+        if m > self.leaf_size {
+            let d = gr.read_next(self.golomb_param(m))?;
+            let hmod = remap16(
+                remix(fingerprint.wrapping_add(self.seed(level)?).wrapping_add(d)),
+                m,
+            );
+            let part = hmod / self.leaf_size;
+            m = self.leaf_size.min(m - part * self.leaf_size);
+            cum_keys += u64::from(self.leaf_size * part);
+            if part != 0 {
+                gr.skip_subtree(
+                    u64::from(part),
+                    self.skip_bits(self.leaf_size) * u64::from(part),
+                )?;
+            }
+            level += 1;
+        }
+        let b = gr.read_next(self.golomb_param(m))?;
+        let hash = fingerprint.wrapping_add(self.seed(level)?).wrapping_add(b);
+        let rec = cum_keys + u64::from(remap16(remix(hash), m));
+        if let Some(pos) = self.existence {
+            if self.mmap.get(pos + rec as usize) != Some(&(bucket_hash as u8)) {
+                return Ok(None);
+            }
+        }
+        self.record(rec).map(Some)
+    }
+
+    fn record(&self, rec: u64) -> Result<u64> {
+        let start = 17 + rec as usize * self.bytes_per_rec;
+        let bytes = self
+            .mmap
+            .get(start..start + self.bytes_per_rec)
+            .ok_or_else(|| eyre!("Record {} out of bounds", rec))?;
+        Ok(bytes.iter().fold(0, |acc, b| acc << 8 | u64::from(*b)))
+    }
+
+    fn seed(&self, level: usize) -> Result<u64> {
+        self.start_seed
+            .get(level)
+            .copied()
+            .ok_or_else(|| eyre!("Index has too few seeds").into())
+    }
+
+    fn param(&self, m: u16) -> u32 {
+        self.golomb_rice.get(m as usize).copied().unwrap_or(0)
+    }
+
+    fn golomb_param(&self, m: u16) -> u32 {
+        self.param(m) >> 27
+    }
+
+    fn skip_bits(&self, m: u16) -> u64 {
+        u64::from(self.param(m) & 0xffff)
+    }
+
+    fn skip_nodes(&self, m: u16) -> u64 {
+        u64::from(self.param(m) >> 16 & 0x7ff)
+    }
+}
+
+// Computes the Golomb-Rice parameters of a subtree of size m from those of
+// its children, following erigon-lib's `computeGolombRice`.
+fn golomb_rice_params(
+    m: u16,
+    table: &[u32],
+    leaf_size: u16,
+    primary_aggr_bound: u16,
+    secondary_aggr_bound: u16,
+) -> Result<u32> {
+    let (fanout, unit) = if m > secondary_aggr_bound {
+        let unit = secondary_aggr_bound
+            * (((m + 1) / 2 + secondary_aggr_bound - 1) / secondary_aggr_bound);
+        (2, unit)
+    } else if m > primary_aggr_bound {
+        let fanout = (m + primary_aggr_bound - 1) / primary_aggr_bound;
+        (fanout, primary_aggr_bound)
+    } else {
+        ((m + leaf_size - 1) / leaf_size, leaf_size)
+    };
+    let mut k = vec![unit; fanout as usize];
+    k[fanout as usize - 1] = m - unit * (fanout - 1);
+
+    let sqrt_prod: f64 = k.iter().map(|&k| f64::from(k).sqrt()).product();
+    let p = f64::from(m).sqrt()
+        / ((2.0 * std::f64::consts::PI).powf((f64::from(fanout) - 1.0) / 2.0) * sqrt_prod);
+    let golden = (5f64.sqrt() + 1.0) / 2.0;
+    let param = (-golden.ln() / (-p).ln_1p()).log2().ceil() as u32;
+    ensure!(param <= 0x1f, "Invalid Golomb-Rice parameter for {}", m);
+
+    let child = |k: u16| table.get(k as usize).copied().unwrap_or(0);
+    let bits = param + k.iter().map(|&k| child(k) & 0xffff).sum::<u32>();
+    ensure!(bits <= 0xffff, "Invalid Golomb-Rice length for {}", m);
+    let nodes = 1 + k.iter().map(|&k| child(k) >> 16 & 0x7ff).sum::<u32>();
+    ensure!(
+        leaf_size < 3 || nodes <= 0x7ff,
+        "Invalid Golomb-Rice node count for {}",
+        m
+    );
+    Ok(param << 27 | nodes << 16 | bits)
+}
+
+// Reads the Golomb-Rice codes of a bucket. The fixed-width low bits of every
+// code in the bucket come first, followed by their unary-coded high bits.
+struct GolombRiceReader<'a> {
+    data: Words<'a>,
+    fixed: u64,
+    unary_window: u64,
+    unary_ptr: u64,
+    unary_valid: u64,
+}
+
+impl<'a> GolombRiceReader<'a> {
+    fn new(data: Words<'a>, bit_pos: u64, unary_offset: u64) -> Self {
+        let unary_pos = bit_pos + unary_offset;
+        Self {
+            data,
+            fixed: bit_pos,
+            unary_window: data.get(unary_pos / 64) >> (unary_pos % 64),
+            unary_ptr: unary_pos / 64 + 1,
+            unary_valid: 64 - unary_pos % 64,
+        }
+    }
+
+    fn next_unary_word(&mut self) -> Result<u64> {
+        ensure!(
+            (self.unary_ptr as usize + 1) * 8 <= self.data.0.len(),
+            "Golomb-Rice codes out of bounds"
+        );
+        let word = self.data.get(self.unary_ptr);
+        self.unary_ptr += 1;
+        Ok(word)
+    }
+
+    fn read_next(&mut self, log2golomb: u32) -> Result<u64> {
+        let mut result = 0;
+        if self.unary_window == 0 {
+            result += self.unary_valid;
+            self.unary_window = self.next_unary_word()?;
+            self.unary_valid = 64;
+            while self.unary_window == 0 {
+                result += 64;
+                self.unary_window = self.next_unary_word()?;
+            }
+        }
+        let pos = u64::from(self.unary_window.trailing_zeros());
+        self.unary_window = self.unary_window >> pos >> 1;
+        self.unary_valid -= pos + 1;
+        result += pos;
+        result <<= log2golomb;
+
+        let (idx, shift) = (self.fixed / 64, self.fixed % 64);
+        let mut fixed = self.data.get(idx) >> shift;
+        if shift + u64::from(log2golomb) > 64 {
+            fixed |= self.data.get(idx + 1) << (64 - shift);
+        }
+        result |= fixed & ((1 << log2golomb) - 1);
+        self.fixed += u64::from(log2golomb);
+        Ok(result)
+    }
+
+    fn skip_subtree(&mut self, nodes: u64, fixed_len: u64) -> Result<()> {
+        let mut missing = nodes as u32;
+        loop {
+            let count = self.unary_window.count_ones();
+            if count >= missing {
+                break;
+            }
+            self.unary_window = self.next_unary_word()?;
+            missing -= count;
+            self.unary_valid = 64;
+        }
+        let pos = u64::from(super::eliasfano::select64(self.unary_window, missing - 1));
+        self.unary_window = self.unary_window >> pos >> 1;
+        self.unary_valid -= pos + 1;
+        self.fixed += fixed_len;
+        Ok(())
+    }
+}
+
+// Maps a hash uniformly onto [0, n).
+fn remap(x: u64, n: u64) -> u64 {
+    ((u128::from(x) * u128::from(n)) >> 64) as u64
+}
+
+fn remap16(x: u64, n: u16) -> u16 {
+    (((x & 0xffff) * u64::from(n)) >> 16) as u16
+}
+
+// The finalizer of splitmix64.
+fn remix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// The 128 bit x64 variant of MurmurHash3, returned as its two 64 bit halves.
+pub fn murmur3_x64_128(data: &[u8], seed: u32) -> (u64, u64) {
+    const C1: u64 = 0x87c37b91114253d5;
+    const C2: u64 = 0x4cf5ad432745937f;
+    let mix_k1 = |k: u64| k.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    let mix_k2 = |k: u64| k.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    let (mut h1, mut h2) = (u64::from(seed), u64::from(seed));
+
+    let mut blocks = data.chunks_exact(16);
+    for block in &mut blocks {
+        let k1 = u64::from_le_bytes(block[..8].try_into().unwrap());
+        let k2 = u64::from_le_bytes(block[8..].try_into().unwrap());
+        h1 ^= mix_k1(k1);
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dce729);
+        h2 ^= mix_k2(k2);
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x38495ab5);
+    }
+    let tail = blocks.remainder();
+    let le = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |acc, b| acc << 8 | u64::from(*b))
+    };
+    if tail.len() > 8 {
+        h2 ^= mix_k2(le(&tail[8..]));
+    }
+    if !tail.is_empty() {
+        h1 ^= mix_k1(le(&tail[..tail.len().min(8)]));
+    }
+
+    let fmix = |mut k: u64| {
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xff51afd7ed558ccd);
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xc4ceb9fe1a85ec53);
+        k ^ (k >> 33)
+    };
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    h1 = fmix(h1);
+    h2 = fmix(h2);
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    (h1, h2)
+}
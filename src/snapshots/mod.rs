@@ -0,0 +1,8 @@
+//! Readers for the files Erigon freezes out of mdbx.
+mod blocks;
+pub mod decompress;
+pub mod domains;
+mod eliasfano;
+pub mod index;
+
+pub use blocks::{BlockSegments, BlockSnapshots, Segment};